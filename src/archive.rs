@@ -0,0 +1,72 @@
+use object::Object;
+
+use std::fmt::Write;
+
+// Library archives: object files bundled together so the linker can take
+// only the ones a program needs. The file is text, each member's object
+// file under a line naming it:
+//
+//     CHIP8-ARCHIVE 1
+//     MEMBER multiply.o8
+//     CHIP8-OBJECT 1
+//     ...
+//     MEMBER divide.o8
+//     CHIP8-OBJECT 1
+//     ...
+
+const MAGIC: &str = "CHIP8-ARCHIVE";
+const VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Archive {
+    // Each member's name and its object.
+    pub members: Vec<(String, Object)>,
+}
+
+pub fn is_archive(text: &str) -> bool {
+    text.starts_with(MAGIC)
+}
+
+impl Archive {
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{} {}", MAGIC, VERSION).unwrap();
+        for (name, object) in self.members.iter() {
+            writeln!(out, "MEMBER {}", name).unwrap();
+            out.push_str(&object.to_text());
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Archive, String> {
+        let mut lines = text.lines();
+        match lines.next().map(|ln| ln.split_whitespace().collect::<Vec<_>>()) {
+            Some(ref words) if words.len() == 2 && words[0] == MAGIC => {
+                if words[1] != VERSION.to_string() {
+                    return Err(format!("Archive version {} is not supported", words[1]));
+                }
+            }
+            _ => return Err("Not an archive".to_owned()),
+        }
+        let mut sections: Vec<(String, String)> = Vec::new();
+        for ln in lines {
+            match ln.strip_prefix("MEMBER ") {
+                Some(name) => sections.push((name.trim().to_owned(), String::new())),
+                None => match sections.last_mut() {
+                    Some(section) => {
+                        section.1.push_str(ln);
+                        section.1.push('\n');
+                    }
+                    None if ln.trim().is_empty() => {}
+                    None => return Err(format!("Unexpected line in archive: {}", ln)),
+                },
+            }
+        }
+        let mut members = Vec::new();
+        for (name, body) in sections {
+            let object = Object::parse(&body).map_err(|err| format!("{}: {}", name, err))?;
+            members.push((name, object));
+        }
+        Ok(Archive { members })
+    }
+}
@@ -0,0 +1,578 @@
+use instructions::*;
+use instructions::parameters::OpParam;
+use interpreter::{MEMORY_SIZE, PROGRAM_START};
+use callgraph;
+use expr::{is_variable_line, named_registers, Variables};
+use font::{is_font_line, Fonts};
+use functions::{is_function_line, Functions};
+use ir::Program;
+use lang;
+use octo;
+use optimize;
+use sprite::{self, is_sprite_line};
+use stdlib;
+use structured::{is_block_line, is_generated_label, Blocks};
+use warnings;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SourceLoc {
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.file)
+        } else {
+            write!(f, "{}:{}", self.file, self.line)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub loc: SourceLoc,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.loc, self.message)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Item {
+    Label(String),
+    Instr(Instruction),
+    // Raw bytes, such as sprites.
+    Data(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+pub struct SourceItem {
+    pub loc: SourceLoc,
+    pub text: String,
+    pub item: Item,
+}
+
+#[derive(Clone, Debug)]
+pub struct LineEntry {
+    pub addr: u16,
+    pub loc: SourceLoc,
+}
+
+pub struct Assembly {
+    pub items: Vec<SourceItem>,
+    pub labels: HashMap<OpParam, OpParam>,
+    pub code: Vec<u8>,
+    pub line_map: Vec<LineEntry>,
+    // Likely mistakes that do not stop the build.
+    pub warnings: Vec<Diagnostic>,
+    // The Octo `:const`s the program defines, in order.
+    pub constants: Vec<(String, i32)>,
+}
+
+impl Assembly {
+    pub fn loc_for_addr(&self, addr: u16) -> Option<&SourceLoc> {
+        self.line_map.iter().find(|entry| entry.addr == addr).map(|entry| &entry.loc)
+    }
+
+    pub fn addr_for_loc(&self, file: &str, line: usize) -> Option<u16> {
+        self.line_map
+            .iter()
+            .find(|entry| entry.loc.file == file && entry.loc.line == line)
+            .map(|entry| entry.addr)
+    }
+
+    pub fn label_addr(&self, name: &str) -> Option<u16> {
+        match self.labels.get(&OpParam::Label(name.to_uppercase())) {
+            Some(&OpParam::Variable(addr)) => Some(addr),
+            _ => None,
+        }
+    }
+
+    // The closest label at or before the given address, used to name routines.
+    pub fn label_for_addr(&self, addr: u16) -> Option<(&str, u16)> {
+        let mut best: Option<(&str, u16)> = None;
+        for (label, value) in self.labels.iter() {
+            if let (OpParam::Label(name), &OpParam::Variable(laddr)) = (label, value) {
+                if is_generated_label(name) {
+                    continue;
+                }
+                let better = match best {
+                    Some((_, baddr)) => laddr > baddr,
+                    None => true,
+                };
+                if laddr <= addr && better {
+                    best = Some((name.as_str(), laddr));
+                }
+            }
+        }
+        best
+    }
+}
+
+// How to turn parsed items into a ROM.
+#[derive(Clone, Debug)]
+pub struct Options {
+    // Run the `-O` peephole rewrites.
+    pub optimize: bool,
+    // How many return addresses the target interpreter's stack holds.
+    pub stack_limit: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            optimize: false,
+            stack_limit: callgraph::STACK_LIMIT,
+        }
+    }
+}
+
+// Which front end parses a source file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    Chip8,
+    Octo,
+    Lang,
+}
+
+impl Syntax {
+    pub fn parse(name: &str) -> Option<Syntax> {
+        match name.to_lowercase().as_str() {
+            "chip8" => Some(Syntax::Chip8),
+            "octo" => Some(Syntax::Octo),
+            "lang" => Some(Syntax::Lang),
+            _ => None,
+        }
+    }
+
+    // Octo sources conventionally end in `.8o` and the high-level language
+    // uses `.c8c`; everything else is this assembler's own dialect.
+    pub fn for_path(path: &str) -> Syntax {
+        let lower = path.to_lowercase();
+        if lower.ends_with(".8o") {
+            Syntax::Octo
+        } else if lower.ends_with(".c8c") {
+            Syntax::Lang
+        } else {
+            Syntax::Chip8
+        }
+    }
+}
+
+pub fn read_source(path: &str) -> io::Result<String> {
+    if stdlib::is_library_path(path) {
+        return stdlib::source(path).map(str::to_owned).ok_or_else(|| {
+            let names = stdlib::names().iter().map(|name| format!("<{}>", name)).collect::<Vec<_>>().join(", ");
+            io::Error::new(io::ErrorKind::NotFound, format!("the standard library has {}", names))
+        });
+    }
+    let mut source = String::new();
+    File::open(path)?.read_to_string(&mut source)?;
+    Ok(source)
+}
+
+// Matches `INCLUDE "path"`, returning the quoted path, or `INCLUDE <name>`
+// for a standard library file, returning `<name>`.
+pub fn include_path(ln: &str) -> Option<String> {
+    let code = ln.split("//").next().unwrap_or("").trim();
+    if !code.get(..7).is_some_and(|word| word.eq_ignore_ascii_case("INCLUDE")) {
+        return None;
+    }
+    let arg = code[7..].trim();
+    if arg.len() >= 2 && arg.starts_with('"') && arg.ends_with('"') {
+        Some(arg[1..arg.len() - 1].to_owned())
+    } else if arg.len() >= 2 && arg.starts_with('<') && arg.ends_with('>') {
+        Some(arg.to_owned())
+    } else {
+        None
+    }
+}
+
+// Included files are looked up next to the file that includes them, and
+// `<name>` is the standard library file of that name.
+pub fn resolve_include(from_file: &str, path: &str) -> String {
+    if let Some(name) = path.strip_prefix('<').and_then(|path| path.strip_suffix('>')) {
+        return stdlib::path(&name.trim().to_lowercase());
+    }
+    match Path::new(from_file).parent() {
+        Some(dir) if !Path::new(path).is_absolute() && !dir.as_os_str().is_empty() => {
+            dir.join(path).to_string_lossy().into_owned()
+        }
+        _ => path.to_owned(),
+    }
+}
+
+pub fn parse_source(file: &str, source: &str) -> (Vec<SourceItem>, Vec<Diagnostic>) {
+    parse_source_with(file, source, &mut read_source)
+}
+
+pub fn parse_source_with<F>(file: &str, source: &str, load: &mut F) -> (Vec<SourceItem>, Vec<Diagnostic>)
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    parse_source_as(file, source, Syntax::for_path(file), load)
+}
+
+pub fn parse_source_as<F>(file: &str, source: &str, syntax: Syntax, load: &mut F) -> (Vec<SourceItem>, Vec<Diagnostic>)
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    let (items, errors, _) = parse_with_constants(file, source, syntax, load);
+    (items, errors)
+}
+
+// Items, errors, and the Octo `:const`s defined, in order.
+type Parsed = (Vec<SourceItem>, Vec<Diagnostic>, Vec<(String, i32)>);
+
+// Parses a program along with the Octo `:const`s it and its includes define.
+pub fn parse_with_constants<F>(file: &str, source: &str, syntax: Syntax, load: &mut F) -> Parsed
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    match syntax {
+        Syntax::Chip8 => {
+            // Which registers the program names is only known once every
+            // included file is read, so expressions are compiled again
+            // without them.
+            let (items, errors, constants) = parse_program(file, source, load, &[]);
+            let named = named_registers(&items);
+            if named.is_empty() || !items.iter().any(|item| is_variable_line(&item.text)) {
+                return (items, errors, constants);
+            }
+            parse_program(file, source, load, &named)
+        }
+        Syntax::Octo => {
+            let mut constants = Vec::new();
+            let (mut items, errors) = octo::parse(file, source, &mut Blocks::new(), &mut constants);
+            octo::jump_to_main(&mut items);
+            (items, errors, constants)
+        }
+        Syntax::Lang => {
+            let (items, errors) = lang::parse(file, source);
+            (items, errors, Vec::new())
+        }
+    }
+}
+
+// What parsing one file hands on to the files it includes.
+struct ParseState {
+    include_stack: Vec<String>,
+    blocks: Blocks,
+    variables: Variables,
+    functions: Functions,
+    fonts: Fonts,
+    constants: Vec<(String, i32)>,
+}
+
+impl ParseState {
+    fn new(file: &str) -> ParseState {
+        ParseState {
+            include_stack: vec![file.to_owned()],
+            blocks: Blocks::new(),
+            variables: Variables::new(),
+            functions: Functions::new(),
+            fonts: Fonts::new(),
+            constants: Vec::new(),
+        }
+    }
+}
+
+fn parse_program<F>(file: &str, source: &str, load: &mut F, reserved: &[u8]) -> Parsed
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    let mut state = ParseState::new(file);
+    state.variables.reserve(reserved);
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    parse_into(file, source, load, &mut state, &mut items, &mut errors);
+    let end = SourceLoc {
+        file: file.to_owned(),
+        line: source.lines().count(),
+    };
+    state.functions.check_calls(&state.variables, &mut errors);
+    items.extend(state.variables.storage(&end));
+    items.extend(state.fonts.storage());
+    (items, errors, state.constants)
+}
+
+fn parse_into<F>(
+    file: &str,
+    source: &str,
+    load: &mut F,
+    state: &mut ParseState,
+    items: &mut Vec<SourceItem>,
+    errors: &mut Vec<Diagnostic>,
+) where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    let depth = state.blocks.depth();
+    for (idx, ln) in source.lines().enumerate() {
+        let loc = SourceLoc {
+            file: file.to_owned(),
+            line: idx + 1,
+        };
+        if let Some(path) = include_path(ln) {
+            let included = resolve_include(file, &path);
+            if state.include_stack.contains(&included) {
+                errors.push(Diagnostic {
+                    loc,
+                    message: format!("{} includes itself", included),
+                });
+                continue;
+            }
+            match load(&included) {
+                Ok(_) if Syntax::for_path(&included) == Syntax::Lang => errors.push(Diagnostic {
+                    loc,
+                    message: format!("{} is a whole program and cannot be included", included),
+                }),
+                Ok(ref text) if Syntax::for_path(&included) == Syntax::Octo => {
+                    let (octo_items, octo_errors) = octo::parse(&included, text, &mut state.blocks, &mut state.constants);
+                    items.extend(octo_items);
+                    errors.extend(octo_errors);
+                }
+                Ok(text) => {
+                    state.include_stack.push(included.clone());
+                    parse_into(&included, &text, load, state, items, errors);
+                    state.include_stack.pop();
+                }
+                Err(err) => errors.push(Diagnostic {
+                    loc,
+                    message: format!("Could not include {}: {}", included, err),
+                }),
+            }
+        } else if is_sprite_line(ln) {
+            match sprite::line(ln, &loc) {
+                Ok(sprite_items) => items.extend(sprite_items),
+                Err(message) => errors.push(Diagnostic { loc, message }),
+            }
+        } else if state.fonts.is_open() || is_font_line(ln) {
+            if let Err(message) = state.fonts.line(ln, &loc, items) {
+                errors.push(Diagnostic { loc, message });
+            }
+        } else if is_block_line(ln) {
+            if let Err(message) = state.blocks.line(ln, &loc, depth, items) {
+                errors.push(Diagnostic { loc, message });
+            }
+        } else if is_function_line(ln) {
+            if let Err(message) = state.functions.line(ln, &loc, &state.blocks, &mut state.variables, items) {
+                errors.push(Diagnostic { loc, message });
+            }
+        } else if is_variable_line(ln) {
+            if let Err(message) = state.variables.line(ln, &loc, items) {
+                errors.push(Diagnostic { loc, message });
+            }
+        } else if is_instr_line(ln) {
+            match Instruction::parse_args(ln) {
+                Ok(instr) => items.push(SourceItem {
+                    loc,
+                    text: ln.to_owned(),
+                    item: Item::Instr(instr),
+                }),
+                Err(ParseError(message)) => errors.push(Diagnostic { loc, message }),
+            }
+        } else if is_label(ln) {
+            let name = label_name(ln);
+            if !name.is_empty() {
+                items.push(SourceItem {
+                    loc,
+                    text: ln.to_owned(),
+                    item: Item::Label(name),
+                });
+            }
+        }
+    }
+    state.blocks.close_file(depth, errors);
+    state.fonts.close_file(file, errors);
+    state.functions.close_file(file, &mut state.variables, errors);
+}
+
+fn size(item: &Item) -> usize {
+    match *item {
+        Item::Instr(_) => 2,
+        Item::Data(ref bytes) => bytes.len(),
+        Item::Label(_) => 0,
+    }
+}
+
+// Fails at the first item that runs past the end of memory, since its
+// address would not fit in the 12 bits instructions have for one.
+pub fn check_fits(items: &[SourceItem]) -> Result<(), Diagnostic> {
+    let total: usize = items.iter().map(|item| size(&item.item)).sum();
+    let mut end = PROGRAM_START as usize;
+    for item in items {
+        end += size(&item.item);
+        if end > MEMORY_SIZE {
+            return Err(Diagnostic {
+                loc: item.loc.clone(),
+                message: format!(
+                    "The program takes {} bytes, more than the {} there is room for",
+                    total,
+                    MEMORY_SIZE - PROGRAM_START as usize
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+// The address each item is placed at. Addresses wrap for a program too big
+// for memory, which `check_fits` reports.
+pub fn addresses(items: &[SourceItem]) -> Vec<u16> {
+    let mut addrs = Vec::with_capacity(items.len());
+    let mut offset = PROGRAM_START;
+
+    for item in items {
+        addrs.push(offset);
+        offset = offset.wrapping_add(size(&item.item) as u16);
+    }
+    addrs
+}
+
+pub fn layout(items: &[SourceItem]) -> HashMap<OpParam, OpParam> {
+    let mut map = HashMap::new();
+
+    for (item, addr) in items.iter().zip(addresses(items)) {
+        if let Item::Label(ref name) = item.item {
+            map.insert(OpParam::Label(name.clone()), OpParam::Variable(addr));
+        }
+    }
+    map
+}
+
+// An error for every label defined a second time.
+pub fn duplicate_labels(items: &[SourceItem]) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+    let mut defined: HashMap<&str, &SourceLoc> = HashMap::new();
+    for item in items.iter() {
+        if let Item::Label(ref name) = item.item {
+            match defined.get(name.as_str()) {
+                Some(first) => errors.push(Diagnostic {
+                    loc: item.loc.clone(),
+                    message: format!("Label {} is already defined at {}", name, first),
+                }),
+                None => {
+                    defined.insert(name, &item.loc);
+                }
+            }
+        }
+    }
+    errors
+}
+
+// Assembles parsed items, failing if calls can nest more than `stack_limit`
+// deep.
+pub fn assemble_items(items: Vec<SourceItem>, stack_limit: usize) -> Result<Assembly, Vec<Diagnostic>> {
+    check_fits(&items).map_err(|err| vec![err])?;
+    let labels = layout(&items);
+    let mut errors = duplicate_labels(&items);
+    let mut code = Vec::new();
+    let mut line_map = Vec::new();
+
+    for item in items.iter() {
+        if let Item::Data(ref bytes) = item.item {
+            code.extend_from_slice(bytes);
+        }
+        if let Item::Instr(ref instr) = item.item {
+            let resolved = instr.resolve_labels(&labels);
+            let unresolved = resolved.label_refs();
+            if !unresolved.is_empty() {
+                errors.push(Diagnostic {
+                    loc: item.loc.clone(),
+                    message: format!("Unknown label {}", unresolved.join(", ")),
+                });
+                continue;
+            }
+            line_map.push(LineEntry {
+                addr: PROGRAM_START + code.len() as u16,
+                loc: item.loc.clone(),
+            });
+            let opc = resolved.to_opcode();
+            code.push(((opc & 0xFF00) >> 8) as u8);
+            code.push((opc & 0x00FF) as u8);
+        }
+    }
+
+    if errors.is_empty() {
+        if let Err(err) = callgraph::check_depth(&Program::new(items.clone()), stack_limit) {
+            errors.push(err);
+        }
+    }
+    if errors.is_empty() {
+        let warnings = warnings::check(&items);
+        Ok(Assembly {
+            items,
+            labels,
+            code,
+            line_map,
+            warnings,
+            constants: Vec::new(),
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn assemble_source(file: &str, source: &str) -> Result<Assembly, Vec<Diagnostic>> {
+    assemble_source_as(file, source, Syntax::for_path(file))
+}
+
+pub fn assemble_source_as(file: &str, source: &str, syntax: Syntax) -> Result<Assembly, Vec<Diagnostic>> {
+    assemble_source_opt(file, source, syntax, &Options::default())
+}
+
+pub fn assemble_source_opt(file: &str, source: &str, syntax: Syntax, options: &Options) -> Result<Assembly, Vec<Diagnostic>> {
+    let (items, errors, constants) = parse_with_constants(file, source, syntax, &mut read_source);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut assembly = assemble_items_opt(items, options)?;
+    assembly.constants = constants;
+    Ok(assembly)
+}
+
+// Assembles parsed items, optimizing them first if the options say to.
+pub fn assemble_items_opt(items: Vec<SourceItem>, options: &Options) -> Result<Assembly, Vec<Diagnostic>> {
+    if options.optimize {
+        // Warnings point at what was written, not at what -O made of it.
+        let warnings = warnings::check(&items);
+        let mut assembly = assemble_items(optimize::optimize(items), options.stack_limit)?;
+        assembly.warnings = warnings;
+        Ok(assembly)
+    } else {
+        assemble_items(items, options.stack_limit)
+    }
+}
+
+pub fn assemble_file(path: &str) -> Result<Assembly, Vec<Diagnostic>> {
+    assemble_file_as(path, Syntax::for_path(path))
+}
+
+pub fn assemble_file_as(path: &str, syntax: Syntax) -> Result<Assembly, Vec<Diagnostic>> {
+    assemble_file_opt(path, syntax, &Options::default())
+}
+
+pub fn assemble_file_opt(path: &str, syntax: Syntax, options: &Options) -> Result<Assembly, Vec<Diagnostic>> {
+    match read_source(path) {
+        Ok(source) => assemble_source_opt(path, &source, syntax, options),
+        Err(err) => Err(vec![Diagnostic {
+            loc: SourceLoc {
+                file: path.to_owned(),
+                line: 0,
+            },
+            message: format!("Could not read file: {}", err),
+        }]),
+    }
+}
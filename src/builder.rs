@@ -0,0 +1,309 @@
+use assembler::{assemble_items_opt, Diagnostic, Item, Options, SourceItem, SourceLoc};
+use instructions::bitops::{And, Or, Rand, ShiftLeft, ShiftRight, Xor};
+use instructions::display::{ClearScreen, Draw};
+use instructions::flow::{Call, Jump, Return, SkipIfEqual, SkipIfKey, SkipIfNotEqual, SkipIfNotKey};
+use instructions::loads::Load;
+use instructions::math::{Add, Sub, SubN};
+use instructions::parameters::OpParam;
+use instructions::Instruction;
+use {Diagnostics, Rom};
+
+// Programs written from Rust instead of as source text, for generated
+// levels and tables:
+//
+//     let mut prog = Builder::new();
+//     prog.label("loop");
+//     prog.ld(V0, 5);
+//     prog.drw(V0, V1, 5);
+//     prog.jp("loop");
+//     prog.label("table").data(&[1, 2, 4, 8]);
+//     let rom = prog.build(&Options::default())?;
+//
+// Each method adds the Instruction the parser makes for the same line, so
+// labels can be used before they are placed and `build` checks and lays
+// out the program just as `assemble` does. Label names are upper-cased like
+// parsed ones. Diagnostics give the file as BUILDER_NAME and the line as
+// the number of the call that added the item, counting from 1. A register
+// past VF, an address past 0xFFF or more than 15 rows is not added; the
+// call is reported by `build` instead.
+
+pub const BUILDER_NAME: &str = "<builder>";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reg(pub u8);
+
+pub const V0: Reg = Reg(0x0);
+pub const V1: Reg = Reg(0x1);
+pub const V2: Reg = Reg(0x2);
+pub const V3: Reg = Reg(0x3);
+pub const V4: Reg = Reg(0x4);
+pub const V5: Reg = Reg(0x5);
+pub const V6: Reg = Reg(0x6);
+pub const V7: Reg = Reg(0x7);
+pub const V8: Reg = Reg(0x8);
+pub const V9: Reg = Reg(0x9);
+pub const VA: Reg = Reg(0xA);
+pub const VB: Reg = Reg(0xB);
+pub const VC: Reg = Reg(0xC);
+pub const VD: Reg = Reg(0xD);
+pub const VE: Reg = Reg(0xE);
+pub const VF: Reg = Reg(0xF);
+
+// A byte operand: a register or a constant.
+pub trait Value {
+    fn param(self) -> Result<OpParam, String>;
+}
+
+impl Value for Reg {
+    fn param(self) -> Result<OpParam, String> {
+        if self.0 <= 0xF {
+            Ok(OpParam::Register(self.0))
+        } else {
+            Err(format!("Register 0x{:X} does not exist; registers are V0 to VF", self.0))
+        }
+    }
+}
+
+impl Value for u8 {
+    fn param(self) -> Result<OpParam, String> {
+        Ok(OpParam::Variable(u16::from(self)))
+    }
+}
+
+// A 12-bit address operand: a label or a number.
+pub trait Address {
+    fn param(self) -> Result<OpParam, String>;
+}
+
+impl Address for &str {
+    fn param(self) -> Result<OpParam, String> {
+        Ok(OpParam::Label(self.to_uppercase()))
+    }
+}
+
+impl Address for String {
+    fn param(self) -> Result<OpParam, String> {
+        self.as_str().param()
+    }
+}
+
+impl Address for u16 {
+    fn param(self) -> Result<OpParam, String> {
+        if self <= 0x0FFF {
+            Ok(OpParam::Variable(self))
+        } else {
+            Err(format!("Address 0x{:X} does not fit in 12 bits", self))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Builder {
+    items: Vec<SourceItem>,
+    calls: usize,
+    errors: Vec<Diagnostic>,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    // What has been added so far, as the parser would have produced it.
+    pub fn items(&self) -> &[SourceItem] {
+        &self.items
+    }
+
+    fn loc(&self) -> SourceLoc {
+        SourceLoc {
+            file: BUILDER_NAME.to_owned(),
+            line: self.calls,
+        }
+    }
+
+    fn push(&mut self, item: Item) -> &mut Builder {
+        self.calls += 1;
+        let loc = self.loc();
+        self.items.push(SourceItem {
+            loc,
+            text: String::new(),
+            item,
+        });
+        self
+    }
+
+    pub fn instr(&mut self, instr: Instruction) -> &mut Builder {
+        self.push(Item::Instr(instr))
+    }
+
+    // Adds the instruction, or records why its operands are out of range.
+    fn checked<F: FnOnce() -> Result<Instruction, String>>(&mut self, make: F) -> &mut Builder {
+        match make() {
+            Ok(instr) => self.instr(instr),
+            Err(message) => {
+                self.calls += 1;
+                let loc = self.loc();
+                self.errors.push(Diagnostic { loc, message });
+                self
+            }
+        }
+    }
+
+    pub fn label(&mut self, name: &str) -> &mut Builder {
+        self.push(Item::Label(name.to_uppercase()))
+    }
+
+    pub fn data(&mut self, bytes: &[u8]) -> &mut Builder {
+        self.push(Item::Data(bytes.to_vec()))
+    }
+
+    pub fn build(&self, options: &Options) -> Result<Rom, Diagnostics> {
+        let mut errors = self.errors.clone();
+        match assemble_items_opt(self.items.clone(), options) {
+            Ok(assembly) if errors.is_empty() => return Ok(Rom::from(assembly)),
+            Ok(_) => {}
+            Err(more) => errors.extend(more),
+        }
+        errors.sort_by_key(|err| err.loc.line);
+        Err(Diagnostics(errors))
+    }
+
+    pub fn cls(&mut self) -> &mut Builder {
+        self.instr(Instruction::ClearScreen(ClearScreen {}))
+    }
+
+    pub fn ret(&mut self) -> &mut Builder {
+        self.instr(Instruction::Return(Return {}))
+    }
+
+    pub fn jp<A: Address>(&mut self, target: A) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Jump(Jump::new(target.param()?))))
+    }
+
+    // JP V0, target
+    pub fn jp_v0<A: Address>(&mut self, target: A) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Jump(Jump::offset(target.param()?))))
+    }
+
+    pub fn call<A: Address>(&mut self, target: A) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Call(Call::new(target.param()?))))
+    }
+
+    pub fn se<V: Value>(&mut self, x: Reg, value: V) -> &mut Builder {
+        self.checked(|| Ok(Instruction::SkipIfEqual(SkipIfEqual::new(x.param()?, value.param()?))))
+    }
+
+    pub fn sne<V: Value>(&mut self, x: Reg, value: V) -> &mut Builder {
+        self.checked(|| Ok(Instruction::SkipIfNotEqual(SkipIfNotEqual::new(x.param()?, value.param()?))))
+    }
+
+    pub fn skp(&mut self, x: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::SkipIfKey(SkipIfKey::new(x.param()?))))
+    }
+
+    pub fn sknp(&mut self, x: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::SkipIfNotKey(SkipIfNotKey::new(x.param()?))))
+    }
+
+    fn load(&mut self, dest: Result<OpParam, String>, source: Result<OpParam, String>) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Load(Load::new(dest?, source?))))
+    }
+
+    pub fn ld<V: Value>(&mut self, x: Reg, value: V) -> &mut Builder {
+        self.load(x.param(), value.param())
+    }
+
+    pub fn ld_i<A: Address>(&mut self, target: A) -> &mut Builder {
+        self.load(Ok(OpParam::RegisterI), target.param())
+    }
+
+    // LD Vx, DT
+    pub fn ld_from_dt(&mut self, x: Reg) -> &mut Builder {
+        self.load(x.param(), Ok(OpParam::Timer))
+    }
+
+    // LD Vx, K
+    pub fn ld_key(&mut self, x: Reg) -> &mut Builder {
+        self.load(x.param(), Ok(OpParam::Keyboard))
+    }
+
+    // LD DT, Vx
+    pub fn ld_dt(&mut self, x: Reg) -> &mut Builder {
+        self.load(Ok(OpParam::Timer), x.param())
+    }
+
+    // LD ST, Vx
+    pub fn ld_st(&mut self, x: Reg) -> &mut Builder {
+        self.load(Ok(OpParam::AudioTimer), x.param())
+    }
+
+    // LD F, Vx
+    pub fn ld_f(&mut self, x: Reg) -> &mut Builder {
+        self.load(Ok(OpParam::Fontset), x.param())
+    }
+
+    // LD B, Vx
+    pub fn ld_b(&mut self, x: Reg) -> &mut Builder {
+        self.load(Ok(OpParam::Digits), x.param())
+    }
+
+    // LD [I], Vx
+    pub fn ld_mem(&mut self, x: Reg) -> &mut Builder {
+        self.load(Ok(OpParam::DerefI), x.param())
+    }
+
+    // LD Vx, [I]
+    pub fn ld_from_mem(&mut self, x: Reg) -> &mut Builder {
+        self.load(x.param(), Ok(OpParam::DerefI))
+    }
+
+    pub fn add<V: Value>(&mut self, x: Reg, value: V) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Add(Add::new(x.param()?, value.param()?))))
+    }
+
+    // ADD I, Vx
+    pub fn add_i(&mut self, x: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Add(Add::new(OpParam::RegisterI, x.param()?))))
+    }
+
+    pub fn or(&mut self, x: Reg, y: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Or(Or::new(x.param()?, y.param()?))))
+    }
+
+    pub fn and(&mut self, x: Reg, y: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::And(And::new(x.param()?, y.param()?))))
+    }
+
+    pub fn xor(&mut self, x: Reg, y: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Xor(Xor::new(x.param()?, y.param()?))))
+    }
+
+    pub fn sub(&mut self, x: Reg, y: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Sub(Sub::new(x.param()?, y.param()?))))
+    }
+
+    pub fn subn(&mut self, x: Reg, y: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::SubN(SubN::new(x.param()?, y.param()?))))
+    }
+
+    pub fn shr(&mut self, x: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::ShiftRight(ShiftRight::new(x.param()?, x.param()?))))
+    }
+
+    pub fn shl(&mut self, x: Reg) -> &mut Builder {
+        self.checked(|| Ok(Instruction::ShiftLeft(ShiftLeft::new(x.param()?, x.param()?))))
+    }
+
+    pub fn rnd(&mut self, x: Reg, mask: u8) -> &mut Builder {
+        self.checked(|| Ok(Instruction::Rand(Rand::new(x.param()?, mask.param()?))))
+    }
+
+    pub fn drw(&mut self, x: Reg, y: Reg, rows: u8) -> &mut Builder {
+        self.checked(|| {
+            if rows > 0xF {
+                return Err(format!("DRW draws up to 15 rows, not {}", rows));
+            }
+            Ok(Instruction::Draw(Draw::new(x.param()?, y.param()?, OpParam::Variable(u16::from(rows)))))
+        })
+    }
+}
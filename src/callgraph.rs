@@ -0,0 +1,169 @@
+use assembler::Diagnostic;
+use functions::deepest_chain;
+use ir::Program;
+use opcode::Op;
+use structured::is_generated_label;
+
+use std::collections::HashMap;
+
+// The routines of a program and the calls between them, for checking how
+// deep the return stack gets:
+//
+//     JP V0, MOVES        // targets: LEFT, RIGHT
+//
+// A routine is the entry point at 0x200 or the target of a CALL, together
+// with every block reachable from it without following calls. A `JP V0`
+// leaves the known code, unless a `targets:` comment on its line names the
+// labels it can land on; those are then part of the routine too.
+
+// Where the original COSMAC VIP interpreter kept its return stack there was
+// room for 12 addresses; later interpreters have 16.
+pub const VIP_STACK_LIMIT: usize = 12;
+pub const STACK_LIMIT: usize = 16;
+
+struct CallGraph {
+    // Each routine's name and the block it starts at, the entry point first.
+    routines: Vec<(String, usize)>,
+    callees: HashMap<String, Vec<String>>,
+    // The item of the first call from one routine to another.
+    sites: HashMap<(String, String), usize>,
+}
+
+// The labels a `JP V0` line says it can land on.
+fn jump_targets(text: &str) -> Vec<String> {
+    let comment = match text.find("//").or_else(|| text.find('#')) {
+        Some(start) => &text[start..],
+        None => return Vec::new(),
+    };
+    match comment.find("targets:") {
+        Some(start) => comment[start + "targets:".len()..]
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_uppercase())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// The routine's name: its first label written in the source, or its address.
+fn routine_name(program: &Program, block: usize) -> String {
+    match program.labels(&program.blocks[block]).into_iter().find(|name| !is_generated_label(name)) {
+        Some(name) => name.to_owned(),
+        None => format!("0x{:03X}", program.blocks[block].addr),
+    }
+}
+
+// The blocks of the routine starting at `start`.
+fn routine_blocks(program: &Program, start: usize) -> Vec<usize> {
+    let mut seen = vec![false; program.blocks.len()];
+    let mut pending = vec![start];
+    while let Some(idx) = pending.pop() {
+        if seen[idx] {
+            continue;
+        }
+        for reached in program.reachable(idx) {
+            seen[reached] = true;
+            for item in program.blocks[reached].items.clone() {
+                if let Some(Op::JumpV0(_)) = program.ops[item] {
+                    let targets = jump_targets(&program.items[item].text);
+                    pending.extend(targets.iter().filter_map(|name| program.block_for_label(name)));
+                }
+            }
+        }
+    }
+    (0..program.blocks.len()).filter(|&idx| seen[idx]).collect()
+}
+
+impl CallGraph {
+    fn new(program: &Program) -> CallGraph {
+        let mut graph = CallGraph {
+            routines: Vec::new(),
+            callees: HashMap::new(),
+            sites: HashMap::new(),
+        };
+        if program.blocks.is_empty() {
+            return graph;
+        }
+        let mut pending = vec![0];
+        while let Some(start) = pending.pop() {
+            if graph.routines.iter().any(|&(_, block)| block == start) {
+                continue;
+            }
+            let name = routine_name(program, start);
+            let mut callees = Vec::new();
+            for block in routine_blocks(program, start) {
+                for item in program.blocks[block].items.clone() {
+                    let target = match program.ops[item] {
+                        Some(Op::Call(addr)) => program.block_at(addr),
+                        _ => None,
+                    };
+                    if let Some(target) = target {
+                        let callee = routine_name(program, target);
+                        if !callees.contains(&callee) {
+                            graph.sites.insert((name.clone(), callee.clone()), item);
+                            callees.push(callee);
+                            pending.push(target);
+                        }
+                    }
+                }
+            }
+            graph.callees.insert(name.clone(), callees);
+            graph.routines.push((name, start));
+        }
+        graph
+    }
+
+    // The longest chain of calls from the entry point, starting with the
+    // entry point itself, or the first cycle of calls found.
+    fn deepest(&self) -> Option<Result<Vec<&str>, Vec<&str>>> {
+        let (entry, _) = self.routines.first()?;
+        let callees: HashMap<&str, Vec<&str>> = self
+            .callees
+            .iter()
+            .map(|(caller, list)| (caller.as_str(), list.iter().map(|name| name.as_str()).collect()))
+            .collect();
+        let mut chains = HashMap::new();
+        let result = deepest_chain(entry.as_str(), &callees, &mut chains, &mut Vec::new());
+        let owned = |names: Vec<&str>| names.into_iter().map(|name| self.name(name)).collect();
+        Some(result.map(owned).map_err(owned))
+    }
+
+    fn name(&self, name: &str) -> &str {
+        self.routines.iter().find(|(routine, _)| routine == name).map_or("", |(routine, _)| routine.as_str())
+    }
+
+    fn site(&self, caller: &str, callee: &str) -> usize {
+        self.sites[&(caller.to_owned(), callee.to_owned())]
+    }
+}
+
+// Fails when calls can nest deeper than the return stack holds.
+pub fn check_depth(program: &Program, limit: usize) -> Result<(), Diagnostic> {
+    let graph = CallGraph::new(program);
+    let chain = match graph.deepest() {
+        Some(Ok(chain)) => chain,
+        _ => return Ok(()),
+    };
+    let depth = chain.len() - 1;
+    if depth <= limit {
+        return Ok(());
+    }
+    let site = graph.site(chain[limit], chain[limit + 1]);
+    Err(Diagnostic {
+        loc: program.items[site].loc.clone(),
+        message: format!("Calls nest {} deep, more than the {} the stack holds: {}", depth, limit, chain.join(" -> ")),
+    })
+}
+
+// A warning when routines call each other in a cycle, whose depth cannot be
+// checked.
+pub fn recursion(program: &Program) -> Option<Diagnostic> {
+    let graph = CallGraph::new(program);
+    let cycle = graph.deepest()?.err()?;
+    let site = graph.site(cycle[cycle.len() - 2], cycle[cycle.len() - 1]);
+    Some(Diagnostic {
+        loc: program.items[site].loc.clone(),
+        message: format!("Calls recurse, so the stack depth cannot be checked: {}", cycle.join(" -> ")),
+    })
+}
+
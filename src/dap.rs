@@ -0,0 +1,695 @@
+use assembler::{self, Assembly};
+use interpreter::state::History;
+use interpreter::{Machine, MachineError, StepResult, MEMORY_SIZE};
+use json::JsonValue;
+use opcode::Op;
+use protocol::{read_message, write_message};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REF: i64 = 1;
+const STACK_REF: i64 = 2;
+
+// How many instructions to run between checks for a `pause` request.
+const POLL_INTERVAL: u64 = 256;
+
+// How many instructions `stepBack` and `reverseContinue` can undo.
+const HISTORY_DEPTH: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq)]
+enum RunMode {
+    Continue,
+    StepIn,
+    StepOver,
+    StepOut,
+}
+
+pub struct DebugSession<W: Write> {
+    out: W,
+    seq: i64,
+    assembly: Option<Assembly>,
+    machine: Machine,
+    stop_on_entry: bool,
+    breakpoint_lines: HashMap<String, Vec<usize>>,
+    breakpoints: HashSet<u16>,
+    key_release_at: Option<u64>,
+    history: History,
+    pending: VecDeque<JsonValue>,
+    input_closed: bool,
+    done: bool,
+}
+
+pub fn serve<R: Read + Send + 'static, W: Write>(input: R, output: W) -> io::Result<()> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(input);
+        while let Ok(Some(Ok(msg))) = read_message(&mut reader) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = DebugSession::new(output);
+    while !session.done {
+        let msg = match session.pending.pop_front() {
+            Some(msg) => msg,
+            None => match rx.recv() {
+                Ok(msg) => msg,
+                Err(_) => break,
+            },
+        };
+        session.handle(&msg, &rx)?;
+    }
+    Ok(())
+}
+
+fn same_file(left: &str, right: &str) -> bool {
+    if left == right {
+        return true;
+    }
+    match (fs::canonicalize(left), fs::canonicalize(right)) {
+        (Ok(l), Ok(r)) => l == r,
+        _ => false,
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let text = text.trim();
+    if text.starts_with("0x") || text.starts_with("0X") {
+        i64::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn format_byte(value: u8) -> String {
+    format!("0x{:02X} ({})", value, value)
+}
+
+fn format_addr(value: u16) -> String {
+    format!("0x{:03X}", value)
+}
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let triple = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                out.push(BASE64_CHARS[((triple >> (18 - 6 * idx)) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+impl<W: Write> DebugSession<W> {
+    pub fn new(out: W) -> DebugSession<W> {
+        DebugSession {
+            out,
+            seq: 1,
+            assembly: None,
+            machine: Machine::new(),
+            stop_on_entry: false,
+            breakpoint_lines: HashMap::new(),
+            breakpoints: HashSet::new(),
+            key_release_at: None,
+            history: History::new(HISTORY_DEPTH),
+            pending: VecDeque::new(),
+            input_closed: false,
+            done: false,
+        }
+    }
+
+    fn send(&mut self, mut fields: Vec<(&str, JsonValue)>) -> io::Result<()> {
+        fields.insert(0, ("seq", JsonValue::from(self.seq)));
+        self.seq += 1;
+        write_message(&mut self.out, &JsonValue::object(fields))
+    }
+
+    fn respond(&mut self, request: &JsonValue, body: JsonValue) -> io::Result<()> {
+        let fields = vec![
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").cloned().unwrap_or(JsonValue::Null)),
+            ("success", true.into()),
+            ("command", request.get("command").cloned().unwrap_or(JsonValue::Null)),
+            ("body", body),
+        ];
+        self.send(fields)
+    }
+
+    fn respond_error(&mut self, request: &JsonValue, message: &str) -> io::Result<()> {
+        let fields = vec![
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").cloned().unwrap_or(JsonValue::Null)),
+            ("success", false.into()),
+            ("command", request.get("command").cloned().unwrap_or(JsonValue::Null)),
+            ("message", message.into()),
+        ];
+        self.send(fields)
+    }
+
+    fn event(&mut self, name: &str, body: JsonValue) -> io::Result<()> {
+        self.send(vec![("type", "event".into()), ("event", name.into()), ("body", body)])
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) -> io::Result<()> {
+        let mut fields = vec![
+            ("reason", JsonValue::from(reason)),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ];
+        if let Some(text) = description {
+            fields.push(("description", text.clone().into()));
+            fields.push(("text", text.into()));
+        }
+        self.event("stopped", JsonValue::object(fields))
+    }
+
+    pub fn handle(&mut self, request: &JsonValue, rx: &Receiver<JsonValue>) -> io::Result<()> {
+        let args = request.get("arguments").cloned().unwrap_or(JsonValue::Object(Vec::new()));
+        let command = request.str_field("command").unwrap_or("").to_owned();
+        match command.as_str() {
+            "initialize" => {
+                let caps = JsonValue::object(vec![
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsEvaluateForHovers", true.into()),
+                    ("supportsSetVariable", true.into()),
+                    ("supportsReadMemoryRequest", true.into()),
+                    ("supportsDisassembleRequest", true.into()),
+                    ("supportsTerminateRequest", true.into()),
+                    ("supportsStepBack", true.into()),
+                ]);
+                self.respond(request, caps)
+            }
+            "launch" => self.launch(request, &args),
+            "setBreakpoints" => self.set_breakpoints(request, &args),
+            "setExceptionBreakpoints" => self.respond(request, JsonValue::object(vec![])),
+            "configurationDone" => {
+                self.respond(request, JsonValue::Null)?;
+                if self.stop_on_entry {
+                    self.stopped("entry", None)
+                } else {
+                    self.run(RunMode::Continue, rx)
+                }
+            }
+            "threads" => {
+                let thread = JsonValue::object(vec![("id", THREAD_ID.into()), ("name", "CHIP-8".into())]);
+                self.respond(request, JsonValue::object(vec![("threads", JsonValue::Array(vec![thread]))]))
+            }
+            "stackTrace" => self.stack_trace(request, &args),
+            "scopes" => {
+                let scope = |name: &str, reference: i64| {
+                    JsonValue::object(vec![
+                        ("name", name.into()),
+                        ("variablesReference", reference.into()),
+                        ("expensive", false.into()),
+                    ])
+                };
+                let scopes = vec![scope("Registers", REGISTERS_REF), scope("Stack", STACK_REF)];
+                self.respond(request, JsonValue::object(vec![("scopes", JsonValue::Array(scopes))]))
+            }
+            "variables" => {
+                let variables = self.variables(args.int_field("variablesReference").unwrap_or(0));
+                self.respond(request, JsonValue::object(vec![("variables", JsonValue::Array(variables))]))
+            }
+            "setVariable" => self.set_variable(request, &args),
+            "evaluate" => self.evaluate(request, &args),
+            "readMemory" => self.read_memory(request, &args),
+            "disassemble" => self.disassemble(request, &args),
+            "continue" => {
+                self.respond(request, JsonValue::object(vec![("allThreadsContinued", true.into())]))?;
+                self.run(RunMode::Continue, rx)
+            }
+            "next" => {
+                self.respond(request, JsonValue::Null)?;
+                self.run(RunMode::StepOver, rx)
+            }
+            "stepIn" => {
+                self.respond(request, JsonValue::Null)?;
+                self.run(RunMode::StepIn, rx)
+            }
+            "stepOut" => {
+                self.respond(request, JsonValue::Null)?;
+                self.run(RunMode::StepOut, rx)
+            }
+            "stepBack" => {
+                self.respond(request, JsonValue::Null)?;
+                self.step_back(false)
+            }
+            "reverseContinue" => {
+                self.respond(request, JsonValue::Null)?;
+                self.step_back(true)
+            }
+            "pause" => {
+                self.respond(request, JsonValue::Null)?;
+                self.stopped("pause", None)
+            }
+            "terminate" => {
+                self.respond(request, JsonValue::Null)?;
+                self.event("terminated", JsonValue::object(vec![]))
+            }
+            "disconnect" => {
+                self.done = true;
+                self.respond(request, JsonValue::Null)
+            }
+            _ => self.respond_error(request, &format!("Unsupported request: {}", command)),
+        }
+    }
+
+    fn launch(&mut self, request: &JsonValue, args: &JsonValue) -> io::Result<()> {
+        let program = match args.str_field("program") {
+            Some(program) => program.to_owned(),
+            None => return self.respond_error(request, "Missing `program` launch argument"),
+        };
+        let assembly = match assembler::assemble_file(&program) {
+            Ok(assembly) => assembly,
+            Err(errors) => {
+                let lines: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                return self.respond_error(request, &lines.join("\n"));
+            }
+        };
+
+        let mut machine = match args.int_field("seed") {
+            Some(seed) => Machine::with_seed(seed as u32),
+            None => Machine::new(),
+        };
+        if let Some(cycles) = args.int_field("cyclesPerFrame") {
+            machine.cycles_per_frame = cycles.max(1) as u32;
+        }
+        if let Err(err) = machine.load_rom(&assembly.code) {
+            return self.respond_error(request, &err.message);
+        }
+        if let Some(path) = args.str_field("state") {
+            let loaded = fs::read(path).map_err(|err| err.to_string()).and_then(|data| Machine::load_state(&data));
+            match loaded {
+                Ok(state) => machine = state,
+                Err(err) => return self.respond_error(request, &format!("Could not load {}: {}", path, err)),
+            }
+        }
+
+        self.stop_on_entry = args.get("stopOnEntry").and_then(|v| v.as_bool()).unwrap_or(false);
+        self.machine = machine;
+        self.history.clear();
+        self.assembly = Some(assembly);
+        self.respond(request, JsonValue::Null)?;
+        self.event("initialized", JsonValue::Null)
+    }
+
+    // Finds the first instruction at or after the requested line.
+    fn resolve_line(&self, path: &str, line: usize) -> Option<(usize, u16)> {
+        let assembly = self.assembly.as_ref()?;
+        assembly
+            .line_map
+            .iter()
+            .filter(|entry| entry.loc.line >= line && same_file(&entry.loc.file, path))
+            .min_by_key(|entry| entry.loc.line)
+            .map(|entry| (entry.loc.line, entry.addr))
+    }
+
+    fn set_breakpoints(&mut self, request: &JsonValue, args: &JsonValue) -> io::Result<()> {
+        let path = args
+            .get("source")
+            .and_then(|src| src.str_field("path"))
+            .unwrap_or("")
+            .to_owned();
+        let lines: Vec<usize> = args
+            .get("breakpoints")
+            .and_then(|bps| bps.as_array())
+            .map(|bps| bps.iter().filter_map(|bp| bp.int_field("line")).map(|l| l as usize).collect())
+            .unwrap_or_default();
+
+        let mut results = Vec::new();
+        for &line in lines.iter() {
+            let result = match self.resolve_line(&path, line) {
+                Some((actual, _)) => JsonValue::object(vec![("verified", true.into()), ("line", actual.into())]),
+                None => JsonValue::object(vec![
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "No instruction at or after this line".into()),
+                ]),
+            };
+            results.push(result);
+        }
+        self.breakpoint_lines.insert(path, lines);
+
+        let mut breakpoints = HashSet::new();
+        for (path, lines) in self.breakpoint_lines.iter() {
+            for &line in lines.iter() {
+                if let Some((_, addr)) = self.resolve_line(path, line) {
+                    breakpoints.insert(addr);
+                }
+            }
+        }
+        self.breakpoints = breakpoints;
+
+        self.respond(request, JsonValue::object(vec![("breakpoints", JsonValue::Array(results))]))
+    }
+
+    fn step_machine(&mut self) -> Result<StepResult, MachineError> {
+        self.history.push(&self.machine);
+        let result = self.machine.step();
+        if let Some(release_at) = self.key_release_at {
+            if self.machine.cycles >= release_at {
+                self.machine.keys = [false; 16];
+                self.key_release_at = None;
+            }
+        }
+        result
+    }
+
+    fn run(&mut self, mode: RunMode, rx: &Receiver<JsonValue>) -> io::Result<()> {
+        if self.assembly.is_none() {
+            return self.event("terminated", JsonValue::object(vec![]));
+        }
+        let start_depth = self.machine.stack.len();
+        let mut steps: u64 = 0;
+        loop {
+            match self.step_machine() {
+                Err(err) => {
+                    let text = format!("{} (at 0x{:03X})", err.message, err.pc);
+                    return self.stopped("exception", Some(text));
+                }
+                // A key pressed from the debug console is still on its way down and up.
+                Ok(StepResult::WaitingForKey) if self.key_release_at.is_some() || self.machine.key_wait.is_some() => {}
+                Ok(StepResult::WaitingForKey) => {
+                    return self.stopped("pause", Some("Waiting for a key press".to_owned()));
+                }
+                Ok(StepResult::Executed(_)) => {}
+            }
+            steps += 1;
+
+            let depth = self.machine.stack.len();
+            let finished = match mode {
+                RunMode::Continue => false,
+                RunMode::StepIn => true,
+                RunMode::StepOver => depth <= start_depth,
+                RunMode::StepOut => depth < start_depth,
+            };
+            if finished {
+                return self.stopped("step", None);
+            }
+            if self.breakpoints.contains(&self.machine.pc) {
+                return self.stopped("breakpoint", None);
+            }
+
+            if steps.is_multiple_of(POLL_INTERVAL) && !self.input_closed {
+                loop {
+                    match rx.try_recv() {
+                        Ok(msg) => {
+                            let command = msg.str_field("command").unwrap_or("").to_owned();
+                            if command == "pause" {
+                                self.respond(&msg, JsonValue::Null)?;
+                                return self.stopped("pause", None);
+                            } else if command == "disconnect" || command == "terminate" {
+                                self.pending.push_front(msg);
+                                return Ok(());
+                            }
+                            self.pending.push_back(msg);
+                        }
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => {
+                            self.input_closed = true;
+                            if self.pending.is_empty() {
+                                self.done = true;
+                                return Ok(());
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Undoes one instruction, or with `to_breakpoint` keeps going back until a
+    // breakpoint or the oldest recorded state.
+    fn step_back(&mut self, to_breakpoint: bool) -> io::Result<()> {
+        loop {
+            match self.history.pop() {
+                Some(machine) => self.machine = machine,
+                None => return self.stopped("step", Some("No earlier state was recorded".to_owned())),
+            }
+            self.key_release_at = None;
+            if !to_breakpoint {
+                return self.stopped("step", None);
+            }
+            if self.breakpoints.contains(&self.machine.pc) {
+                return self.stopped("breakpoint", None);
+            }
+        }
+    }
+
+    fn frame_name(&self, addr: u16) -> String {
+        let label = self.assembly.as_ref().and_then(|asm| asm.label_for_addr(addr));
+        match label {
+            Some((name, laddr)) if laddr == addr => name.to_owned(),
+            Some((name, laddr)) => format!("{}+0x{:X}", name, addr - laddr),
+            None => format_addr(addr),
+        }
+    }
+
+    fn stack_trace(&mut self, request: &JsonValue, args: &JsonValue) -> io::Result<()> {
+        // The innermost frame sits at the PC, callers at the CALL before each return address.
+        let mut addrs = vec![self.machine.pc];
+        for &ret in self.machine.stack.iter().rev() {
+            addrs.push(ret.wrapping_sub(2));
+        }
+
+        let start = args.int_field("startFrame").unwrap_or(0).max(0) as usize;
+        let levels = match args.int_field("levels") {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => addrs.len(),
+        };
+
+        let mut frames = Vec::new();
+        for (idx, &addr) in addrs.iter().enumerate().skip(start).take(levels) {
+            let mut fields = vec![
+                ("id", JsonValue::from(idx)),
+                ("name", self.frame_name(addr).into()),
+                ("line", 0usize.into()),
+                ("column", 1usize.into()),
+                ("instructionPointerReference", format_addr(addr).into()),
+            ];
+            let loc = self.assembly.as_ref().and_then(|asm| asm.loc_for_addr(addr));
+            if let Some(loc) = loc {
+                let name = Path::new(&loc.file)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| loc.file.clone());
+                fields[2] = ("line", loc.line.into());
+                fields.push((
+                    "source",
+                    JsonValue::object(vec![("name", name.into()), ("path", loc.file.clone().into())]),
+                ));
+            }
+            frames.push(JsonValue::object(fields));
+        }
+
+        let body = JsonValue::object(vec![
+            ("stackFrames", JsonValue::Array(frames)),
+            ("totalFrames", addrs.len().into()),
+        ]);
+        self.respond(request, body)
+    }
+
+    fn variables(&self, reference: i64) -> Vec<JsonValue> {
+        let var = |name: String, value: String, memory: Option<u16>| {
+            let mut fields = vec![
+                ("name", JsonValue::from(name)),
+                ("value", value.into()),
+                ("variablesReference", 0i64.into()),
+            ];
+            if let Some(addr) = memory {
+                fields.push(("memoryReference", format_addr(addr).into()));
+            }
+            JsonValue::object(fields)
+        };
+
+        let machine = &self.machine;
+        match reference {
+            REGISTERS_REF => {
+                let mut vars: Vec<JsonValue> = (0..16)
+                    .map(|reg| var(format!("V{:X}", reg), format_byte(machine.v[reg]), None))
+                    .collect();
+                vars.push(var("I".to_owned(), format_addr(machine.i), Some(machine.i)));
+                vars.push(var("PC".to_owned(), format_addr(machine.pc), Some(machine.pc)));
+                vars.push(var("DT".to_owned(), format_byte(machine.delay_timer), None));
+                vars.push(var("ST".to_owned(), format_byte(machine.sound_timer), None));
+                vars
+            }
+            STACK_REF => machine
+                .stack
+                .iter()
+                .enumerate()
+                .map(|(idx, &ret)| var(format!("#{}", idx), self.frame_name(ret), Some(ret)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_variable(&mut self, request: &JsonValue, args: &JsonValue) -> io::Result<()> {
+        let name = args.str_field("name").unwrap_or("").to_uppercase();
+        let value = match args.str_field("value").and_then(parse_number) {
+            Some(value) => value,
+            None => return self.respond_error(request, "Expected a decimal or 0x-prefixed number"),
+        };
+        let shown = match name.as_str() {
+            "I" => {
+                self.machine.i = (value & 0xFFFF) as u16;
+                format_addr(self.machine.i)
+            }
+            "PC" => {
+                self.machine.pc = (value & 0x0FFF) as u16;
+                format_addr(self.machine.pc)
+            }
+            "DT" => {
+                self.machine.delay_timer = value as u8;
+                format_byte(self.machine.delay_timer)
+            }
+            "ST" => {
+                self.machine.sound_timer = value as u8;
+                format_byte(self.machine.sound_timer)
+            }
+            reg if reg.len() == 2 && reg.starts_with('V') => match u8::from_str_radix(&reg[1..], 16) {
+                Ok(idx) => {
+                    self.machine.v[idx as usize] = value as u8;
+                    format_byte(value as u8)
+                }
+                Err(_) => return self.respond_error(request, &format!("Unknown register {}", name)),
+            },
+            _ => return self.respond_error(request, &format!("Cannot set {}", name)),
+        };
+        self.respond(request, JsonValue::object(vec![("value", shown.into())]))
+    }
+
+    fn evaluate(&mut self, request: &JsonValue, args: &JsonValue) -> io::Result<()> {
+        let raw = args.str_field("expression").unwrap_or("").trim().to_owned();
+        let expr = raw.to_uppercase();
+        let result = if expr.starts_with("SAVE ") {
+            let path = raw[5..].trim();
+            match fs::write(path, self.machine.save_state()) {
+                Ok(()) => format!("Saved the machine state to {}", path),
+                Err(err) => return self.respond_error(request, &format!("Could not write {}: {}", path, err)),
+            }
+        } else if expr.starts_with("LOAD ") {
+            let path = raw[5..].trim();
+            let loaded = fs::read(path).map_err(|err| err.to_string()).and_then(|data| Machine::load_state(&data));
+            match loaded {
+                Ok(machine) => {
+                    self.machine = machine;
+                    self.history.clear();
+                    self.key_release_at = None;
+                    format!("Loaded the machine state from {}", path)
+                }
+                Err(err) => return self.respond_error(request, &format!("Could not load {}: {}", path, err)),
+            }
+        } else if let Some(key_text) = expr.strip_prefix("KEY ") {
+            match u8::from_str_radix(key_text.trim(), 16) {
+                Ok(key) if key < 16 => {
+                    self.machine.keys[key as usize] = true;
+                    self.key_release_at = Some(self.machine.cycles + u64::from(self.machine.cycles_per_frame));
+                    format!("Pressed key {:X}", key)
+                }
+                _ => return self.respond_error(request, "Keys are hex digits 0-F"),
+            }
+        } else if expr == "SCREEN" {
+            self.machine.screen_ascii()
+        } else if expr.starts_with('[') && expr.ends_with(']') {
+            let inner = expr[1..expr.len() - 1].trim();
+            let addr = if inner == "I" {
+                Some(i64::from(self.machine.i))
+            } else {
+                parse_number(inner)
+            };
+            match addr {
+                Some(addr) if addr >= 0 && (addr as usize) < MEMORY_SIZE => {
+                    format_byte(self.machine.memory[addr as usize])
+                }
+                _ => return self.respond_error(request, &format!("Invalid address {}", inner)),
+            }
+        } else {
+            match self.variables(REGISTERS_REF).iter().find(|var| var.str_field("name") == Some(&expr)) {
+                Some(var) => var.str_field("value").unwrap_or("").to_owned(),
+                None => return self.respond_error(request, &format!("Cannot evaluate {}", expr)),
+            }
+        };
+        let body = JsonValue::object(vec![("result", result.into()), ("variablesReference", 0i64.into())]);
+        self.respond(request, body)
+    }
+
+    fn read_memory(&mut self, request: &JsonValue, args: &JsonValue) -> io::Result<()> {
+        let base = args.str_field("memoryReference").and_then(parse_number).unwrap_or(0);
+        // An address past either end of i64 is past the end of memory.
+        let start = base
+            .checked_add(args.int_field("offset").unwrap_or(0))
+            .map_or(MEMORY_SIZE, |start| start.clamp(0, MEMORY_SIZE as i64) as usize);
+        let count = args.int_field("count").unwrap_or(0).max(0) as usize;
+        let end = start.checked_add(count).map_or(MEMORY_SIZE, |end| end.min(MEMORY_SIZE));
+
+        let body = JsonValue::object(vec![
+            ("address", format_addr(start as u16).into()),
+            ("data", base64_encode(&self.machine.memory[start..end]).into()),
+            ("unreadableBytes", (count - (end - start)).into()),
+        ]);
+        self.respond(request, body)
+    }
+
+    fn disassemble(&mut self, request: &JsonValue, args: &JsonValue) -> io::Result<()> {
+        let base = args.str_field("memoryReference").and_then(parse_number).unwrap_or(0);
+        let skip = args.int_field("instructionOffset").unwrap_or(0).checked_mul(2);
+        let start = base
+            .checked_add(args.int_field("offset").unwrap_or(0))
+            .and_then(|start| start.checked_add(skip?));
+        // There are no more instructions than memory holds.
+        let count = args.int_field("instructionCount").unwrap_or(0).clamp(0, MEMORY_SIZE as i64 / 2);
+
+        let mut instructions = Vec::new();
+        for idx in 0..count {
+            let addr = match idx.checked_mul(2).and_then(|skip| start?.checked_add(skip)) {
+                Some(addr) if addr >= 0 && (addr as usize) + 1 < MEMORY_SIZE => addr as u16,
+                addr => {
+                    instructions.push(JsonValue::object(vec![
+                        ("address", format!("0x{:X}", addr.unwrap_or(0).max(0)).into()),
+                        ("instruction", "??".into()),
+                        ("presentationHint", "invalid".into()),
+                    ]));
+                    continue;
+                }
+            };
+            let opc = (u16::from(self.machine.memory[addr as usize]) << 8) | u16::from(self.machine.memory[addr as usize + 1]);
+            let mut fields = vec![
+                ("address", format_addr(addr).into()),
+                ("instructionBytes", format!("{:04X}", opc).into()),
+                ("instruction", Op::decode(opc).to_string().into()),
+            ];
+            if let Some(asm) = self.assembly.as_ref() {
+                if let Some((name, laddr)) = asm.label_for_addr(addr) {
+                    if laddr == addr {
+                        fields.push(("symbol", name.into()));
+                    }
+                }
+                if let Some(loc) = asm.loc_for_addr(addr) {
+                    fields.push(("location", JsonValue::object(vec![("path", loc.file.clone().into())])));
+                    fields.push(("line", loc.line.into()));
+                }
+            }
+            instructions.push(JsonValue::object(fields));
+        }
+        self.respond(request, JsonValue::object(vec![("instructions", JsonValue::Array(instructions))]))
+    }
+}
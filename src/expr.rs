@@ -0,0 +1,1397 @@
+use assembler::{Item, SourceItem, SourceLoc};
+use instructions::bitops::{And, Or, ShiftLeft, ShiftRight, Xor};
+use instructions::flow::{Call, Return};
+use instructions::loads::Load;
+use instructions::math::{Add, Sub};
+use instructions::parameters::OpParam;
+use instructions::*;
+use functions::{argument_label, arguments_label, frame_label, FRAME_SIZE};
+use structured::{Condition, GENERATED_PREFIX};
+
+use std::collections::HashMap;
+use std::fmt;
+
+// Typed variables and assignments compiled from expressions:
+//
+//     VAR SCORE : BYTE
+//     VAR LIVES : BYTE = 0x3
+//     SCORE = SCORE + (LIVES * 2) & 0xFF
+//
+// Variables live in memory after the program. Expressions are evaluated in
+// the registers of the pool, spilling intermediate results to memory when
+// the pool runs out. The pool is whatever of V1-VE the program never names,
+// so registers that hand-written code keeps state in are left alone and
+// saved around FUNC calls; `REGISTERS` picks the pool instead. V0 carries
+// every byte to and from memory and VF takes the flags, so neither is ever
+// allocated; an assignment also clobbers I.
+
+// Moves bytes between registers and memory, as `LD [I], V0` / `LD V0, [I]`
+// only need V0 itself.
+const TRANSFER: u8 = 0;
+const FLAGS: u8 = 0xF;
+const DEFAULT_POOL: [u8; 14] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 0xA, 0xB, 0xC, 0xD, 0xE];
+// A comparison holds both operands and its result at once.
+const MIN_POOL: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Type {
+    Byte,
+    Bool,
+    // A 12-bit address, stored as a `LD I, addr` / `RET` pair so that calling
+    // it loads I.
+    Addr,
+}
+
+impl Type {
+    pub fn parse(name: &str) -> Option<Type> {
+        match name.to_uppercase().as_str() {
+            "BYTE" => Some(Type::Byte),
+            "BOOL" => Some(Type::Bool),
+            "ADDR" => Some(Type::Addr),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Type::Byte => "BYTE",
+            Type::Bool => "BOOL",
+            Type::Addr => "ADDR",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    Complement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Mod,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Xor,
+    Or,
+    LogicalAnd,
+    LogicalOr,
+}
+
+// Operators by precedence, loosest first, as in C.
+const BINARY_OPS: [(&str, BinaryOp, u8); 18] = [
+    ("||", BinaryOp::LogicalOr, 1),
+    ("&&", BinaryOp::LogicalAnd, 2),
+    ("|", BinaryOp::Or, 3),
+    ("^", BinaryOp::Xor, 4),
+    ("&", BinaryOp::And, 5),
+    ("==", BinaryOp::Equal, 6),
+    ("!=", BinaryOp::NotEqual, 6),
+    ("<", BinaryOp::Less, 7),
+    (">", BinaryOp::Greater, 7),
+    ("<=", BinaryOp::LessEqual, 7),
+    (">=", BinaryOp::GreaterEqual, 7),
+    ("<<", BinaryOp::Shl, 8),
+    (">>", BinaryOp::Shr, 8),
+    ("+", BinaryOp::Add, 9),
+    ("-", BinaryOp::Sub, 9),
+    ("*", BinaryOp::Mul, 10),
+    ("/", BinaryOp::Div, 10),
+    ("%", BinaryOp::Mod, 10),
+];
+
+impl BinaryOp {
+    // The operator a symbol stands for, and its precedence.
+    pub fn parse(symbol: &str) -> Option<(BinaryOp, u8)> {
+        BINARY_OPS.iter().find(|entry| entry.0 == symbol).map(|&(_, op, prec)| (op, prec))
+    }
+
+    pub fn symbol(self) -> &'static str {
+        BINARY_OPS.iter().find(|entry| entry.1 == self).map_or("?", |entry| entry.0)
+    }
+
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Less
+                | BinaryOp::Greater
+                | BinaryOp::LessEqual
+                | BinaryOp::GreaterEqual
+                | BinaryOp::Equal
+                | BinaryOp::NotEqual
+        )
+    }
+
+    pub fn is_commutative(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Add
+                | BinaryOp::Mul
+                | BinaryOp::And
+                | BinaryOp::Or
+                | BinaryOp::Xor
+                | BinaryOp::LogicalAnd
+                | BinaryOp::LogicalOr
+                | BinaryOp::Equal
+                | BinaryOp::NotEqual
+        )
+    }
+
+    // The same comparison with its operands swapped.
+    fn mirror(self) -> BinaryOp {
+        match self {
+            BinaryOp::Less => BinaryOp::Greater,
+            BinaryOp::Greater => BinaryOp::Less,
+            BinaryOp::LessEqual => BinaryOp::GreaterEqual,
+            BinaryOp::GreaterEqual => BinaryOp::LessEqual,
+            op => op,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(u16),
+    Bool(bool),
+    Register(u8),
+    // A variable, or else a label standing for its address.
+    Name(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    // A FUNC call.
+    Call(String, Vec<Expr>),
+}
+
+impl Expr {
+    fn registers(&self, found: &mut Vec<u8>) {
+        match *self {
+            Expr::Register(num) => found.push(num),
+            Expr::Unary(_, ref inner) => inner.registers(found),
+            Expr::Binary(_, ref left, ref right) => {
+                left.registers(found);
+                right.registers(found);
+            }
+            Expr::Call(_, ref args) => {
+                for arg in args {
+                    arg.registers(found);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn parse_number(text: &str) -> Option<u16> {
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+pub fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn register_name(text: &str) -> Option<u8> {
+    let upper = text.to_uppercase();
+    match upper.strip_prefix('V') {
+        Some(digit) if digit.len() == 1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            tokens.push(chars[start..pos].iter().collect());
+        } else {
+            let pair: String = chars[pos..chars.len().min(pos + 2)].iter().collect();
+            if ["<<", ">>", "<=", ">=", "==", "!=", "&&", "||"].contains(&pair.as_str()) {
+                tokens.push(pair);
+                pos += 2;
+            } else if "+-*/%&|^~!<>(),".contains(c) {
+                tokens.push(c.to_string());
+                pos += 1;
+            } else {
+                return Err(format!("Unexpected {} in expression", c));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| token.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| "Expression ends too early".to_owned())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn binary(&mut self, min_prec: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some((op, prec)) = self.peek().and_then(BinaryOp::parse) {
+            if prec < min_prec {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(prec + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self.next()?;
+        let op = match token.as_str() {
+            "-" => UnaryOp::Neg,
+            "!" => UnaryOp::Not,
+            "~" => UnaryOp::Complement,
+            "(" => {
+                let inner = self.binary(0)?;
+                if self.next()? != ")" {
+                    return Err("Expected )".to_owned());
+                }
+                return Ok(inner);
+            }
+            _ if is_identifier(&token) && self.peek() == Some("(") => return self.call(token),
+            _ => return self.atom(token),
+        };
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn call(&mut self, name: String) -> Result<Expr, String> {
+        self.pos += 1;
+        let mut args = Vec::new();
+        if self.peek() == Some(")") {
+            self.pos += 1;
+        } else {
+            loop {
+                args.push(self.binary(0)?);
+                match self.next()?.as_str() {
+                    ")" => break,
+                    "," => {}
+                    other => return Err(format!("Expected , or ) in the arguments of {}, got {}", name, other)),
+                }
+            }
+        }
+        Ok(Expr::Call(name.to_uppercase(), args))
+    }
+
+    fn atom(&self, token: String) -> Result<Expr, String> {
+        let upper = token.to_uppercase();
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            match parse_number(&token) {
+                Some(value) if value <= 0xFFF => Ok(Expr::Number(value)),
+                _ => Err(format!("{} is not a number up to 0xFFF", token)),
+            }
+        } else if upper == "TRUE" || upper == "FALSE" {
+            Ok(Expr::Bool(upper == "TRUE"))
+        } else if let Some(num) = register_name(&token) {
+            Ok(Expr::Register(num))
+        } else if is_identifier(&token) {
+            Ok(Expr::Name(upper))
+        } else {
+            Err(format!("Unexpected {} in expression", token))
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+    if parser.tokens.is_empty() {
+        return Err("Expected an expression".to_owned());
+    }
+    let expr = parser.binary(0)?;
+    match parser.peek() {
+        Some(token) => Err(format!("Unexpected {} in expression", token)),
+        None => Ok(expr),
+    }
+}
+
+// Evaluates the parts of an expression that only involve byte and bool
+// constants, with the same wrapping arithmetic as the machine.
+pub fn fold(expr: Expr) -> Expr {
+    match expr {
+        Expr::Unary(op, inner) => match (op, fold(*inner)) {
+            (UnaryOp::Neg, Expr::Number(n)) if n <= 0xFF => Expr::Number(u16::from((n as u8).wrapping_neg())),
+            (UnaryOp::Complement, Expr::Number(n)) if n <= 0xFF => Expr::Number(u16::from(!(n as u8))),
+            (UnaryOp::Not, Expr::Bool(b)) => Expr::Bool(!b),
+            (op, inner) => Expr::Unary(op, Box::new(inner)),
+        },
+        Expr::Binary(op, left, right) => {
+            let (left, right) = (fold(*left), fold(*right));
+            match (&left, &right) {
+                (&Expr::Number(l), &Expr::Number(r)) if l <= 0xFF && r <= 0xFF => {
+                    if let Some(folded) = fold_bytes(op, l as u8, r as u8) {
+                        return folded;
+                    }
+                }
+                (&Expr::Bool(l), &Expr::Bool(r)) => {
+                    let value = match op {
+                        BinaryOp::LogicalAnd | BinaryOp::And => Some(l && r),
+                        BinaryOp::LogicalOr | BinaryOp::Or => Some(l || r),
+                        BinaryOp::Xor | BinaryOp::NotEqual => Some(l != r),
+                        BinaryOp::Equal => Some(l == r),
+                        _ => None,
+                    };
+                    if let Some(value) = value {
+                        return Expr::Bool(value);
+                    }
+                }
+                _ => {}
+            }
+            Expr::Binary(op, Box::new(left), Box::new(right))
+        }
+        Expr::Call(name, args) => Expr::Call(name, args.into_iter().map(fold).collect()),
+        expr => expr,
+    }
+}
+
+fn fold_bytes(op: BinaryOp, l: u8, r: u8) -> Option<Expr> {
+    let number = |value: u8| Some(Expr::Number(u16::from(value)));
+    match op {
+        BinaryOp::Add => number(l.wrapping_add(r)),
+        BinaryOp::Sub => number(l.wrapping_sub(r)),
+        BinaryOp::Mul => number(l.wrapping_mul(r)),
+        BinaryOp::Div if r != 0 => number(l / r),
+        BinaryOp::Mod if r != 0 => number(l % r),
+        BinaryOp::Shl => number(l.checked_shl(u32::from(r)).unwrap_or(0)),
+        BinaryOp::Shr => number(l.checked_shr(u32::from(r)).unwrap_or(0)),
+        BinaryOp::And => number(l & r),
+        BinaryOp::Or => number(l | r),
+        BinaryOp::Xor => number(l ^ r),
+        BinaryOp::Less => Some(Expr::Bool(l < r)),
+        BinaryOp::Greater => Some(Expr::Bool(l > r)),
+        BinaryOp::LessEqual => Some(Expr::Bool(l <= r)),
+        BinaryOp::GreaterEqual => Some(Expr::Bool(l >= r)),
+        BinaryOp::Equal => Some(Expr::Bool(l == r)),
+        BinaryOp::NotEqual => Some(Expr::Bool(l != r)),
+        _ => None,
+    }
+}
+
+// Registers needed to evaluate an expression without spilling, in the
+// manner of Sethi and Ullman.
+fn need(expr: &Expr) -> usize {
+    match *expr {
+        Expr::Number(_) | Expr::Bool(_) | Expr::Register(_) | Expr::Name(_) => 1,
+        Expr::Unary(_, ref inner) => need(inner).max(2),
+        Expr::Binary(op, ref left, ref right) => {
+            let (l, r) = (need(left), need(right));
+            let operands = if l == r { l + 1 } else { l.max(r) };
+            operands.max(if op.is_comparison() { MIN_POOL } else { 2 })
+        }
+        // Every argument is held until the call.
+        Expr::Call(_, ref args) => args.iter().enumerate().map(|(idx, arg)| need(arg) + idx).max().unwrap_or(1),
+    }
+}
+
+fn instr_load(dest: OpParam, source: OpParam) -> Instruction {
+    Instruction::Load(Load::new(dest, source))
+}
+
+fn reg(num: u8) -> OpParam {
+    OpParam::Register(num)
+}
+
+fn byte(value: u8) -> OpParam {
+    OpParam::Variable(u16::from(value))
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Value {
+    Const(u8),
+    // A register the program owns, which must not be changed.
+    Reg(u8),
+    // A register from the pool holding an intermediate result.
+    Temp(u8),
+}
+
+impl Value {
+    fn param(self) -> OpParam {
+        match self {
+            Value::Const(value) => byte(value),
+            Value::Reg(num) | Value::Temp(num) => reg(num),
+        }
+    }
+}
+
+// A FUNC's parameters and result. Arguments are passed in V1, V2, ... and
+// the result comes back in V0; inside the function the parameters stay in
+// those registers and the result is built in the register after them.
+#[derive(Clone, Debug)]
+pub struct Signature {
+    pub params: Vec<(String, Type)>,
+    pub result: Option<(String, Type)>,
+}
+
+impl Signature {
+    pub fn result_register(&self) -> u8 {
+        self.params.len() as u8 + 1
+    }
+
+    fn locals(&self) -> Vec<(String, u8, Type)> {
+        let mut locals: Vec<(String, u8, Type)> = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(idx, &(ref name, ty))| (name.clone(), idx as u8 + 1, ty))
+            .collect();
+        if let Some((ref name, ty)) = self.result {
+            locals.push((name.clone(), self.result_register(), ty));
+        }
+        locals
+    }
+}
+
+enum Target {
+    I,
+    Register(u8),
+    Local(u8, Type),
+    Variable(String, Type),
+}
+
+struct Codegen<'a> {
+    vars: &'a Variables,
+    // Registers the statement may allocate, and those it must keep intact.
+    pool: Vec<u8>,
+    live: Vec<u8>,
+    free: Vec<u8>,
+    spills: usize,
+    max_spills: usize,
+    addr_consts: Vec<String>,
+    calls: Vec<String>,
+    // The highest register each call site saves.
+    saves: Vec<u8>,
+    out: Vec<Instruction>,
+}
+
+impl<'a> Codegen<'a> {
+    fn emit(&mut self, instr: Instruction) {
+        self.out.push(instr);
+    }
+
+    fn temp(&mut self) -> Result<u8, String> {
+        self.free
+            .pop()
+            .ok_or_else(|| "The expression needs more registers than REGISTERS allows".to_owned())
+    }
+
+    fn release(&mut self, value: Value) {
+        if let Value::Temp(num) = value {
+            self.free.push(num);
+        }
+    }
+
+    // A temporary holding the value, which the caller may overwrite.
+    fn owned(&mut self, value: Value) -> Result<u8, String> {
+        match value {
+            Value::Temp(num) => Ok(num),
+            _ => {
+                let num = self.temp()?;
+                self.emit(instr_load(reg(num), value.param()));
+                Ok(num)
+            }
+        }
+    }
+
+    fn in_register(&mut self, value: Value) -> Result<Value, String> {
+        match value {
+            Value::Const(_) => Ok(Value::Temp(self.owned(value)?)),
+            _ => Ok(value),
+        }
+    }
+
+    fn read_byte(&mut self, addr: OpParam, dest: u8) {
+        self.emit(instr_load(OpParam::RegisterI, addr));
+        self.emit(instr_load(reg(TRANSFER), OpParam::DerefI));
+        self.emit(instr_load(reg(dest), reg(TRANSFER)));
+    }
+
+    fn write_byte(&mut self, addr: OpParam, value: Value) {
+        self.emit(instr_load(reg(TRANSFER), value.param()));
+        self.emit(instr_load(OpParam::RegisterI, addr));
+        self.emit(instr_load(OpParam::DerefI, reg(TRANSFER)));
+    }
+
+    fn next_byte(&mut self) {
+        self.emit(instr_load(reg(TRANSFER), byte(1)));
+        self.emit(Instruction::Add(Add::new(OpParam::RegisterI, reg(TRANSFER))));
+    }
+
+    fn spill(&mut self, num: u8) -> usize {
+        let slot = self.spills;
+        self.spills += 1;
+        self.max_spills = self.max_spills.max(self.spills);
+        let label = spill_label(&self.vars.function, slot);
+        self.write_byte(OpParam::Label(label), Value::Temp(num));
+        self.free.push(num);
+        slot
+    }
+
+    fn reload(&mut self, slot: usize) -> Result<u8, String> {
+        self.spills -= 1;
+        let num = self.temp()?;
+        let label = spill_label(&self.vars.function, slot);
+        self.read_byte(OpParam::Label(label), num);
+        Ok(num)
+    }
+
+    // Calls a FUNC, leaving its result in V0. The arguments are staged in
+    // memory and loaded into V1 up with one `LD Vn, [I]`; anything live in
+    // those registers is saved around the call, and the callee preserves
+    // the rest.
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<(), String> {
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(self.value(arg)?);
+        }
+        for (idx, value) in values.into_iter().enumerate() {
+            self.write_byte(OpParam::Label(argument_label(name, idx + 1)), value);
+            self.release(value);
+        }
+
+        let count = args.len() as u8;
+        let busy = self.pool.iter().filter(|num| !self.free.contains(num));
+        let highest = busy.chain(self.live.iter()).filter(|num| (1..=count).contains(*num)).max().cloned();
+        let save = highest.map(|num| {
+            let label = OpParam::Label(save_label(self.vars.call_saves.len() + self.saves.len()));
+            self.saves.push(num);
+            (label, num)
+        });
+        if let Some((ref label, num)) = save {
+            self.emit(instr_load(OpParam::RegisterI, label.clone()));
+            self.emit(instr_load(OpParam::DerefI, reg(num)));
+        }
+        if count > 0 {
+            self.emit(instr_load(OpParam::RegisterI, OpParam::Label(arguments_label(name))));
+            self.emit(instr_load(reg(count), OpParam::DerefI));
+        }
+        self.emit(Instruction::Call(Call::new(OpParam::Label(name.to_owned()))));
+        self.calls.push(name.to_owned());
+        // Parking the result where V0 was saved restores it into V0.
+        if let Some((label, num)) = save {
+            self.emit(instr_load(OpParam::RegisterI, label.clone()));
+            self.emit(instr_load(OpParam::DerefI, reg(TRANSFER)));
+            self.emit(instr_load(OpParam::RegisterI, label));
+            self.emit(instr_load(reg(num), OpParam::DerefI));
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, expr: &Expr) -> Result<Value, String> {
+        match *expr {
+            Expr::Number(value) => Ok(Value::Const(value as u8)),
+            Expr::Bool(value) => Ok(Value::Const(u8::from(value))),
+            Expr::Register(num) => Ok(Value::Reg(num)),
+            Expr::Name(ref name) if self.vars.locals.contains_key(name) => Ok(Value::Reg(self.vars.locals[name].0)),
+            Expr::Name(ref name) => {
+                let num = self.temp()?;
+                self.read_byte(OpParam::Label(name.clone()), num);
+                Ok(Value::Temp(num))
+            }
+            Expr::Unary(op, ref inner) => {
+                let value = self.value(inner)?;
+                self.unary(op, value)
+            }
+            Expr::Binary(op, ref left, ref right) => self.binary(op, left, right),
+            Expr::Call(ref name, ref args) => {
+                self.call(name, args)?;
+                let num = self.temp()?;
+                self.emit(instr_load(reg(num), reg(TRANSFER)));
+                Ok(Value::Temp(num))
+            }
+        }
+    }
+
+    fn unary(&mut self, op: UnaryOp, value: Value) -> Result<Value, String> {
+        if op == UnaryOp::Neg {
+            let value = self.in_register(value)?;
+            let num = self.temp()?;
+            self.emit(instr_load(reg(num), byte(0)));
+            self.emit(Instruction::Sub(Sub::new(reg(num), value.param())));
+            self.release(value);
+            return Ok(Value::Temp(num));
+        }
+        let num = self.owned(value)?;
+        let mask = self.temp()?;
+        self.emit(instr_load(reg(mask), byte(if op == UnaryOp::Not { 1 } else { 0xFF })));
+        self.emit(Instruction::Xor(Xor::new(reg(num), reg(mask))));
+        self.free.push(mask);
+        Ok(Value::Temp(num))
+    }
+
+    // Evaluates the operand needing more registers first; if the other one
+    // then needs more than are free, the first result waits in memory.
+    fn binary(&mut self, op: BinaryOp, left: &Expr, right: &Expr) -> Result<Value, String> {
+        let swapped = need(right) > need(left);
+        let (first, second) = if swapped { (right, left) } else { (left, right) };
+        let mut done = self.value(first)?;
+        let spilled = match done {
+            Value::Temp(num) if self.free.len() < need(second) => Some(self.spill(num)),
+            _ => None,
+        };
+        let other = self.value(second)?;
+        if let Some(slot) = spilled {
+            done = Value::Temp(self.reload(slot)?);
+        }
+        if swapped {
+            self.apply(op, other, done)
+        } else {
+            self.apply(op, done, other)
+        }
+    }
+
+    fn apply(&mut self, op: BinaryOp, left: Value, right: Value) -> Result<Value, String> {
+        // Keep constants on the right and reuse a temporary on the left.
+        let swap = match (left, right) {
+            (Value::Const(_), Value::Const(_)) => false,
+            (Value::Const(_), _) | (Value::Reg(_), Value::Temp(_)) => true,
+            _ => false,
+        };
+        if swap && (op.is_commutative() || op.is_comparison()) {
+            return self.apply(op.mirror(), right, left);
+        }
+
+        if op.is_comparison() {
+            return self.compare(op, left, right);
+        }
+        let identity = match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Or | BinaryOp::Xor | BinaryOp::Shl | BinaryOp::Shr => Some(0),
+            BinaryOp::And => Some(0xFF),
+            BinaryOp::Mul | BinaryOp::Div => Some(1),
+            _ => None,
+        };
+        if let (Value::Const(value), Some(identity)) = (right, identity) {
+            if value == identity {
+                return Ok(left);
+            }
+        }
+
+        let acc = self.owned(left)?;
+        match op {
+            BinaryOp::Add => self.emit(Instruction::Add(Add::new(reg(acc), right.param()))),
+            BinaryOp::Sub => match right {
+                Value::Const(value) => self.emit(Instruction::Add(Add::new(reg(acc), byte(value.wrapping_neg())))),
+                _ => self.emit(Instruction::Sub(Sub::new(reg(acc), right.param()))),
+            },
+            BinaryOp::And | BinaryOp::LogicalAnd | BinaryOp::Or | BinaryOp::LogicalOr | BinaryOp::Xor => {
+                let other = self.in_register(right)?;
+                let instr = match op {
+                    BinaryOp::And | BinaryOp::LogicalAnd => Instruction::And(And::new(reg(acc), other.param())),
+                    BinaryOp::Or | BinaryOp::LogicalOr => Instruction::Or(Or::new(reg(acc), other.param())),
+                    _ => Instruction::Xor(Xor::new(reg(acc), other.param())),
+                };
+                self.emit(instr);
+                self.release(other);
+                return Ok(Value::Temp(acc));
+            }
+            BinaryOp::Shl | BinaryOp::Shr => {
+                let count = match right {
+                    Value::Const(count) => count,
+                    _ => return Err(format!("Can only shift by a constant with {}", op.symbol())),
+                };
+                self.shift(acc, op == BinaryOp::Shl, count);
+            }
+            BinaryOp::Mul => {
+                let factor = match right {
+                    Value::Const(factor) => factor,
+                    _ => return Err("Can only multiply by a constant".to_owned()),
+                };
+                return self.multiply(acc, factor).map(Value::Temp);
+            }
+            BinaryOp::Div | BinaryOp::Mod => {
+                let divisor = match right {
+                    Value::Const(divisor) if divisor.is_power_of_two() => divisor,
+                    _ => return Err(format!("Can only {} by a constant power of two", if op == BinaryOp::Div { "divide" } else { "take the remainder" })),
+                };
+                if op == BinaryOp::Div {
+                    self.shift(acc, false, divisor.trailing_zeros() as u8);
+                } else {
+                    let mask = self.temp()?;
+                    self.emit(instr_load(reg(mask), byte(divisor - 1)));
+                    self.emit(Instruction::And(And::new(reg(acc), reg(mask))));
+                    self.free.push(mask);
+                }
+            }
+            _ => {}
+        }
+        self.release(right);
+        Ok(Value::Temp(acc))
+    }
+
+    fn shift(&mut self, acc: u8, left: bool, count: u8) {
+        if count >= 8 {
+            self.emit(instr_load(reg(acc), byte(0)));
+            return;
+        }
+        for _ in 0..count {
+            self.emit(if left {
+                Instruction::ShiftLeft(ShiftLeft::new(reg(acc), reg(acc)))
+            } else {
+                Instruction::ShiftRight(ShiftRight::new(reg(acc), reg(acc)))
+            });
+        }
+    }
+
+    // Shift and add, one addition per set bit of the factor.
+    fn multiply(&mut self, acc: u8, factor: u8) -> Result<u8, String> {
+        if factor == 0 || factor.is_power_of_two() {
+            self.shift(acc, true, if factor == 0 { 8 } else { factor.trailing_zeros() as u8 });
+            return Ok(acc);
+        }
+        let product = self.temp()?;
+        self.emit(instr_load(reg(product), byte(0)));
+        let mut rest = factor;
+        while rest != 0 {
+            if rest & 1 != 0 {
+                self.emit(Instruction::Add(Add::new(reg(product), reg(acc))));
+            }
+            rest >>= 1;
+            if rest != 0 {
+                self.shift(acc, true, 1);
+            }
+        }
+        self.free.push(acc);
+        Ok(product)
+    }
+
+    fn compare(&mut self, op: BinaryOp, left: Value, right: Value) -> Result<Value, String> {
+        let left = self.in_register(left)?;
+        let result = self.temp()?;
+        let condition = Condition::new(left.param(), op.symbol(), right.param())?;
+        self.emit(instr_load(reg(result), byte(0)));
+        for instr in condition.guard(instr_load(reg(result), byte(1))) {
+            self.emit(instr);
+        }
+        self.release(left);
+        self.release(right);
+        Ok(Value::Temp(result))
+    }
+
+    // Splits an address expression into its base and the bytes added to or
+    // (when `true`) subtracted from it.
+    fn address_parts(&self, expr: &Expr, offsets: &mut Vec<(bool, Expr)>) -> Result<Expr, String> {
+        match *expr {
+            Expr::Number(_) | Expr::Name(_) => Ok(expr.clone()),
+            Expr::Binary(BinaryOp::Add, ref left, ref right) => {
+                if self.vars.type_of(left)? == Type::Addr {
+                    let base = self.address_parts(left, offsets)?;
+                    offsets.push((false, (**right).clone()));
+                    Ok(base)
+                } else {
+                    let base = self.address_parts(right, offsets)?;
+                    offsets.push((false, (**left).clone()));
+                    Ok(base)
+                }
+            }
+            Expr::Binary(BinaryOp::Sub, ref left, ref right) => {
+                let base = self.address_parts(left, offsets)?;
+                offsets.push((true, (**right).clone()));
+                Ok(base)
+            }
+            _ => Err("An address can only be a label, an ADDR variable or one plus or minus bytes".to_owned()),
+        }
+    }
+
+    fn set_i(&mut self, expr: &Expr) -> Result<(), String> {
+        let mut offsets = Vec::new();
+        let base = self.address_parts(expr, &mut offsets)?;
+        if offsets.iter().any(|&(negative, _)| negative) {
+            return Err("I can only be moved forward; subtract in an ADDR variable instead".to_owned());
+        }
+        // Reading variables moves I, so the offsets come first.
+        let mut values = Vec::new();
+        for (_, offset) in offsets.iter() {
+            let value = self.value(offset)?;
+            values.push(self.in_register(value)?);
+        }
+        match base {
+            Expr::Name(ref name) if self.vars.types.contains_key(name) => {
+                self.emit(Instruction::Call(Call::new(OpParam::Label(name.clone()))));
+                self.calls.push(name.clone());
+            }
+            Expr::Name(ref name) => self.emit(instr_load(OpParam::RegisterI, OpParam::Label(name.clone()))),
+            Expr::Number(addr) => self.emit(instr_load(OpParam::RegisterI, OpParam::Variable(addr))),
+            _ => {}
+        }
+        for value in values {
+            self.emit(Instruction::Add(Add::new(OpParam::RegisterI, value.param())));
+            self.release(value);
+        }
+        Ok(())
+    }
+
+    fn set_address(&mut self, target: &str, expr: &Expr) -> Result<(), String> {
+        let mut offsets = Vec::new();
+        let base = self.address_parts(expr, &mut offsets)?;
+        let high = self.temp()?;
+        let low = self.temp()?;
+        match base {
+            Expr::Number(addr) => {
+                self.emit(instr_load(reg(high), byte(0xA0 | (addr >> 8) as u8)));
+                self.emit(instr_load(reg(low), byte(addr as u8)));
+            }
+            Expr::Name(ref name) => {
+                let source = if self.vars.types.contains_key(name) {
+                    name.clone()
+                } else {
+                    if !self.addr_consts.contains(name) {
+                        self.addr_consts.push(name.clone());
+                    }
+                    address_label(name)
+                };
+                self.read_byte(OpParam::Label(source.clone()), high);
+                self.next_byte();
+                self.emit(instr_load(reg(TRANSFER), OpParam::DerefI));
+                self.emit(instr_load(reg(low), reg(TRANSFER)));
+            }
+            _ => {}
+        }
+        // ADD sets VF to the carry and SUB to NOT borrow.
+        for (negative, offset) in offsets {
+            let value = self.value(&offset)?;
+            let value = self.in_register(value)?;
+            if negative {
+                self.emit(Instruction::Sub(Sub::new(reg(low), value.param())));
+                self.emit(Instruction::Add(Add::new(reg(high), reg(FLAGS))));
+                self.emit(Instruction::Add(Add::new(reg(high), byte(0xFF))));
+            } else {
+                self.emit(Instruction::Add(Add::new(reg(low), value.param())));
+                self.emit(Instruction::Add(Add::new(reg(high), reg(FLAGS))));
+            }
+            self.release(value);
+        }
+        self.write_byte(OpParam::Label(target.to_owned()), Value::Temp(high));
+        self.next_byte();
+        self.emit(instr_load(reg(TRANSFER), reg(low)));
+        self.emit(instr_load(OpParam::DerefI, reg(TRANSFER)));
+        Ok(())
+    }
+}
+
+fn spill_label(function: &Option<String>, slot: usize) -> String {
+    match *function {
+        Some(ref name) => format!("{}{}_SPILL{}", GENERATED_PREFIX, name, slot),
+        None => format!("{}SPILL{}", GENERATED_PREFIX, slot),
+    }
+}
+
+fn save_label(site: usize) -> String {
+    format!("{}CALL{}_SAVE", GENERATED_PREFIX, site)
+}
+
+fn address_label(name: &str) -> String {
+    format!("{}ADDR_{}", GENERATED_PREFIX, name)
+}
+
+fn keyword(code: &str) -> String {
+    code.split_whitespace().next().unwrap_or("").to_uppercase()
+}
+
+// The `NAME =` an assignment starts with, if the line is one.
+fn assignment_target(code: &str) -> Option<(&str, &str)> {
+    let eq = code.find('=')?;
+    let target = code[..eq].trim();
+    let rest = &code[eq + 1..];
+    if is_identifier(target) && !rest.starts_with('=') {
+        Some((target, rest))
+    } else {
+        None
+    }
+}
+
+// `NAME(...)` on its own.
+fn is_call(code: &str) -> bool {
+    let code = code.trim();
+    match code.find('(') {
+        Some(paren) => is_identifier(code[..paren].trim()) && code.ends_with(')'),
+        None => false,
+    }
+}
+
+// The registers of V1-VE that any line of the program mentions by name.
+pub fn named_registers(items: &[SourceItem]) -> Vec<u8> {
+    let mut named = Vec::new();
+    for item in items {
+        let code = item.text.split("//").next().unwrap_or("");
+        for word in code.split(|c: char| !c.is_ascii_alphanumeric()) {
+            match register_name(word) {
+                Some(num) if DEFAULT_POOL.contains(&num) && !named.contains(&num) => named.push(num),
+                _ => {}
+            }
+        }
+    }
+    named.sort();
+    named
+}
+
+pub fn is_variable_line(ln: &str) -> bool {
+    let code = ln.split("//").next().unwrap_or("");
+    ["VAR", "REGISTERS"].contains(&keyword(code).as_str()) || assignment_target(code).is_some() || is_call(code)
+}
+
+// The variables declared so far and the memory they and the compiled
+// assignments need, which is laid out after the program.
+pub struct Variables {
+    types: HashMap<String, Type>,
+    storage: Vec<SourceItem>,
+    pool: Vec<u8>,
+    // Registers the program names itself, kept out of the default pool.
+    reserved: Vec<u8>,
+    // Spill slots needed at the top level and in each FUNC.
+    spill_slots: Vec<(Option<String>, usize)>,
+    addr_consts: Vec<String>,
+    functions: Vec<(String, Signature)>,
+    // The FUNC being compiled and the names of its registers.
+    function: Option<String>,
+    locals: HashMap<String, (u8, Type)>,
+    calls: Vec<(Option<String>, String)>,
+    call_saves: Vec<u8>,
+}
+
+impl Default for Variables {
+    fn default() -> Variables {
+        Variables::new()
+    }
+}
+
+impl Variables {
+    pub fn new() -> Variables {
+        Variables {
+            types: HashMap::new(),
+            storage: Vec::new(),
+            pool: DEFAULT_POOL.to_vec(),
+            reserved: Vec::new(),
+            spill_slots: Vec::new(),
+            addr_consts: Vec::new(),
+            functions: Vec::new(),
+            function: None,
+            locals: HashMap::new(),
+            calls: Vec::new(),
+            call_saves: Vec::new(),
+        }
+    }
+
+    pub fn signature(&self, name: &str) -> Option<&Signature> {
+        self.functions.iter().find(|entry| entry.0 == name).map(|entry| &entry.1)
+    }
+
+    // Every call compiled so far, from the FUNC it is in (if any) to the FUNC
+    // or ADDR variable it calls.
+    pub fn calls(&self) -> &[(Option<String>, String)] {
+        &self.calls
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.types.contains_key(name) || self.signature(name).is_some()
+    }
+
+    pub fn declare_function(&mut self, name: &str, signature: Signature) -> Result<(), String> {
+        if self.is_declared(name) {
+            return Err(format!("{} is already declared", name));
+        }
+        self.functions.push((name.to_owned(), signature));
+        Ok(())
+    }
+
+    pub fn enter_function(&mut self, name: &str) {
+        self.locals = match self.signature(name) {
+            Some(signature) => signature
+                .locals()
+                .into_iter()
+                .map(|(local, num, ty)| (local, (num, ty)))
+                .collect(),
+            None => HashMap::new(),
+        };
+        self.function = Some(name.to_owned());
+    }
+
+    pub fn leave_function(&mut self) {
+        self.function = None;
+        self.locals.clear();
+    }
+
+    fn type_of(&self, expr: &Expr) -> Result<Type, String> {
+        match *expr {
+            Expr::Number(value) => Ok(if value <= 0xFF { Type::Byte } else { Type::Addr }),
+            Expr::Bool(_) => Ok(Type::Bool),
+            Expr::Register(_) => Ok(Type::Byte),
+            Expr::Name(ref name) if name == "I" => Err("I cannot be read in an expression".to_owned()),
+            Expr::Name(ref name) if self.signature(name).is_some() => Err(format!("{} is a FUNC; call it with {}()", name, name)),
+            Expr::Name(ref name) => match (self.locals.get(name), self.types.get(name)) {
+                (Some(&(_, ty)), _) | (None, Some(&ty)) => Ok(ty),
+                // Anything that is not a variable is taken to be a label.
+                (None, None) => Ok(Type::Addr),
+            },
+            Expr::Unary(op, ref inner) => match (op, self.type_of(inner)?) {
+                (UnaryOp::Not, Type::Bool) => Ok(Type::Bool),
+                (UnaryOp::Neg, Type::Byte) | (UnaryOp::Complement, Type::Byte) => Ok(Type::Byte),
+                (op, ty) => {
+                    let symbol = match op {
+                        UnaryOp::Neg => "-",
+                        UnaryOp::Not => "!",
+                        UnaryOp::Complement => "~",
+                    };
+                    Err(format!("Cannot apply {} to {}", symbol, ty))
+                }
+            },
+            Expr::Binary(op, ref left, ref right) => {
+                let types = (self.type_of(left)?, self.type_of(right)?);
+                let result = match (op, types) {
+                    (BinaryOp::Add, (Type::Addr, Type::Byte))
+                    | (BinaryOp::Add, (Type::Byte, Type::Addr))
+                    | (BinaryOp::Sub, (Type::Addr, Type::Byte)) => Some(Type::Addr),
+                    (BinaryOp::LogicalAnd, (Type::Bool, Type::Bool))
+                    | (BinaryOp::LogicalOr, (Type::Bool, Type::Bool))
+                    | (BinaryOp::And, (Type::Bool, Type::Bool))
+                    | (BinaryOp::Or, (Type::Bool, Type::Bool))
+                    | (BinaryOp::Xor, (Type::Bool, Type::Bool))
+                    | (BinaryOp::Equal, (Type::Bool, Type::Bool))
+                    | (BinaryOp::NotEqual, (Type::Bool, Type::Bool)) => Some(Type::Bool),
+                    (BinaryOp::LogicalAnd, _) | (BinaryOp::LogicalOr, _) => None,
+                    (op, (Type::Byte, Type::Byte)) if op.is_comparison() => Some(Type::Bool),
+                    (_, (Type::Byte, Type::Byte)) => Some(Type::Byte),
+                    _ => None,
+                };
+                result.ok_or_else(|| format!("Cannot apply {} to {} and {}", op.symbol(), types.0, types.1))
+            }
+            Expr::Call(ref name, ref args) => self
+                .check_call(name, args)?
+                .ok_or_else(|| format!("{} has no result", name)),
+        }
+    }
+
+    fn check_call(&self, name: &str, args: &[Expr]) -> Result<Option<Type>, String> {
+        let signature = self.signature(name).ok_or_else(|| format!("{} is not a FUNC declared above", name))?;
+        if args.len() != signature.params.len() {
+            return Err(format!("{} takes {} arguments, got {}", name, signature.params.len(), args.len()));
+        }
+        for (idx, (arg, &(_, expected))) in args.iter().zip(signature.params.iter()).enumerate() {
+            let ty = self.type_of(arg)?;
+            if ty != expected {
+                return Err(format!("Argument {} of {} must be {}, got {}", idx + 1, name, expected, ty));
+            }
+        }
+        Ok(signature.result.as_ref().map(|&(_, ty)| ty))
+    }
+
+    fn codegen(&self, used: &[u8]) -> Codegen<'_> {
+        let mut live = used.to_vec();
+        live.extend(self.locals.values().map(|&(num, _)| num));
+        live.extend(self.reserved.iter().cloned());
+        let pool: Vec<u8> = self.pool.iter().filter(|num| !live.contains(num)).cloned().collect();
+        Codegen {
+            vars: self,
+            free: pool.iter().rev().cloned().collect(),
+            pool,
+            live,
+            spills: 0,
+            max_spills: 0,
+            addr_consts: self.addr_consts.clone(),
+            calls: Vec::new(),
+            saves: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    // Keeps what compiling a statement allocated.
+    fn finish(&mut self, max_spills: usize, addr_consts: Vec<String>, calls: Vec<String>, saves: Vec<u8>) {
+        let scope = self.function.clone();
+        match self.spill_slots.iter_mut().find(|entry| entry.0 == scope) {
+            Some(entry) => entry.1 = entry.1.max(max_spills),
+            None => self.spill_slots.push((scope.clone(), max_spills)),
+        }
+        self.addr_consts = addr_consts;
+        self.calls.extend(calls.into_iter().map(|callee| (scope.clone(), callee)));
+        self.call_saves.extend(saves);
+    }
+
+    // `VAR NAME : TYPE` with an optional `= VALUE`.
+    pub fn declare(&mut self, decl: &str, loc: &SourceLoc, text: &str) -> Result<(), String> {
+        let (decl, init) = match decl.find('=') {
+            Some(eq) => (&decl[..eq], Some(decl[eq + 1..].trim())),
+            None => (decl, None),
+        };
+        let colon = decl.find(':').ok_or_else(|| "Expected VAR NAME : TYPE".to_owned())?;
+        let name = decl[..colon].trim().to_uppercase();
+        let ty_name = decl[colon + 1..].trim();
+        let ty = Type::parse(ty_name).ok_or_else(|| format!("Unknown type {}; use BYTE, BOOL or ADDR", ty_name))?;
+        if !is_identifier(&name) || register_name(&name).is_some() || name == "I" {
+            return Err(format!("{} is not a valid variable name", name));
+        }
+        if self.is_declared(&name) {
+            return Err(format!("{} is already declared", name));
+        }
+
+        let init = match init {
+            Some(init) => fold(parse(init)?),
+            None if ty == Type::Bool => Expr::Bool(false),
+            None => Expr::Number(0),
+        };
+        let mut items = vec![Item::Label(name.clone())];
+        match (ty, init) {
+            (Type::Byte, Expr::Number(value)) if value <= 0xFF => items.push(Item::Data(vec![value as u8])),
+            (Type::Bool, Expr::Bool(value)) => items.push(Item::Data(vec![u8::from(value)])),
+            (Type::Addr, Expr::Number(addr)) => items.extend(thunk(OpParam::Variable(addr))),
+            (Type::Addr, Expr::Name(ref label)) if !self.types.contains_key(label) => {
+                items.extend(thunk(OpParam::Label(label.clone())))
+            }
+            (ty, _) => return Err(format!("The initial value of a {} variable must be a constant {}", ty, ty)),
+        }
+        for item in items {
+            self.storage.push(SourceItem {
+                loc: loc.clone(),
+                text: text.to_owned(),
+                item,
+            });
+        }
+        self.types.insert(name, ty);
+        Ok(())
+    }
+
+    // `REGISTERS V1-V4, V7`: the registers later assignments may use.
+    pub fn set_registers(&mut self, list: &str) -> Result<(), String> {
+        let mut pool = Vec::new();
+        for part in list.split(',') {
+            let bounds: Vec<&str> = part.split('-').map(|bound| bound.trim()).collect();
+            let range = match bounds.as_slice() {
+                [single] => register_name(single).map(|num| (num, num)),
+                [first, last] => register_name(first).and_then(|first| register_name(last).map(|last| (first, last))),
+                _ => None,
+            };
+            let (first, last) = range.ok_or_else(|| format!("Expected registers like V1-V4, got {}", part.trim()))?;
+            for num in first..=last {
+                if num == TRANSFER || num == FLAGS {
+                    return Err("V0 and VF are reserved for compiled expressions".to_owned());
+                }
+                if !pool.contains(&num) {
+                    pool.push(num);
+                }
+            }
+        }
+        if pool.len() < MIN_POOL {
+            return Err(format!("Expressions need at least {} registers", MIN_POOL));
+        }
+        self.reserved.retain(|num| !pool.contains(num));
+        self.pool = pool;
+        Ok(())
+    }
+
+    // Keeps the registers the program names out of the pool, unless a later
+    // `REGISTERS` hands them over.
+    pub fn reserve(&mut self, regs: &[u8]) {
+        self.reserved = regs.to_vec();
+    }
+
+    fn check_pool(&self) -> Result<(), String> {
+        let free = self.pool.iter().filter(|num| !self.reserved.contains(num)).count();
+        if free < MIN_POOL {
+            return Err(format!(
+                "The program names all but {} of V1-VE itself, which leaves too few for expressions; pick the ones they may use with REGISTERS",
+                free
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn assign(&mut self, target: &str, text: &str) -> Result<Vec<Instruction>, String> {
+        self.check_pool()?;
+        let target = target.to_uppercase();
+        let target = if target == "I" {
+            Target::I
+        } else if let Some(&(num, ty)) = self.locals.get(&target) {
+            Target::Local(num, ty)
+        } else if let Some(num) = register_name(&target) {
+            Target::Register(num)
+        } else {
+            match self.types.get(&target) {
+                Some(&ty) => Target::Variable(target, ty),
+                None => return Err(format!("{} is not a variable; declare it with VAR", target)),
+            }
+        };
+
+        let expr = fold(parse(text)?);
+        let ty = self.type_of(&expr)?;
+        let expected = match target {
+            Target::I => Type::Addr,
+            Target::Register(_) if ty == Type::Bool => Type::Bool,
+            Target::Register(_) => Type::Byte,
+            Target::Local(_, ty) | Target::Variable(_, ty) => ty,
+        };
+        let coerced = expected == Type::Addr && matches!(expr, Expr::Number(_));
+        if ty != expected && !coerced {
+            return Err(format!("Cannot assign {} to {}", ty, expected));
+        }
+
+        let mut used = Vec::new();
+        expr.registers(&mut used);
+        if used.contains(&TRANSFER) || used.contains(&FLAGS) {
+            return Err("V0 and VF are used by compiled expressions and cannot be read in one".to_owned());
+        }
+        if let Target::Register(num) = target {
+            used.push(num);
+        }
+        let mut gen = self.codegen(&used);
+        match target {
+            Target::I => gen.set_i(&expr)?,
+            Target::Variable(ref name, Type::Addr) => gen.set_address(name, &expr)?,
+            Target::Variable(ref name, _) => {
+                let value = gen.value(&expr)?;
+                gen.write_byte(OpParam::Label(name.clone()), value);
+            }
+            Target::Register(num) | Target::Local(num, _) => match expr {
+                Expr::Name(ref name) if self.types.contains_key(name) => {
+                    gen.read_byte(OpParam::Label(name.clone()), num);
+                }
+                _ => {
+                    let value = gen.value(&expr)?;
+                    if !matches!(value, Value::Reg(src) | Value::Temp(src) if src == num) {
+                        gen.emit(instr_load(reg(num), value.param()));
+                    }
+                }
+            },
+        }
+        let Codegen { out, max_spills, addr_consts, calls, saves, .. } = gen;
+        self.finish(max_spills, addr_consts, calls, saves);
+        Ok(out)
+    }
+
+    // A FUNC called for its effect; any result is dropped.
+    pub fn call_statement(&mut self, text: &str) -> Result<Vec<Instruction>, String> {
+        let (name, args) = match fold(parse(text)?) {
+            Expr::Call(name, args) => (name, args),
+            _ => return Err(format!("Expected a FUNC call, got {}", text.trim())),
+        };
+        self.check_call(&name, &args)?;
+        self.check_pool()?;
+        let mut used = Vec::new();
+        for arg in args.iter() {
+            arg.registers(&mut used);
+        }
+        if used.contains(&TRANSFER) || used.contains(&FLAGS) {
+            return Err("V0 and VF are used by compiled expressions and cannot be read in one".to_owned());
+        }
+        let mut gen = self.codegen(&used);
+        gen.call(&name, &args)?;
+        let Codegen { out, max_spills, addr_consts, calls, saves, .. } = gen;
+        self.finish(max_spills, addr_consts, calls, saves);
+        Ok(out)
+    }
+
+    // Lowers one VAR, REGISTERS or assignment line of the assembly dialect.
+    pub fn line(&mut self, ln: &str, loc: &SourceLoc, items: &mut Vec<SourceItem>) -> Result<(), String> {
+        let code = ln.split("//").next().unwrap_or("").trim();
+        let word = keyword(code);
+        let rest = code[word.len().min(code.len())..].trim();
+        let instrs = match word.as_str() {
+            "VAR" => return self.declare(rest, loc, ln),
+            "REGISTERS" => return self.set_registers(rest),
+            _ => match assignment_target(code) {
+                Some((target, expr)) => self.assign(target, expr)?,
+                None if is_call(code) => self.call_statement(code)?,
+                None => return Err(format!("Could not parse {}", code)),
+            },
+        };
+        for instr in instrs {
+            items.push(SourceItem {
+                loc: loc.clone(),
+                text: ln.to_owned(),
+                item: Item::Instr(instr),
+            });
+        }
+        Ok(())
+    }
+
+    // Everything to place after the program: variables, spill slots, FUNC
+    // frames and arguments, call sites' saved registers and the addresses of
+    // labels copied into ADDR variables.
+    pub fn storage(&self, loc: &SourceLoc) -> Vec<SourceItem> {
+        let mut items = self.storage.clone();
+        let generated = |item: Item| SourceItem {
+            loc: loc.clone(),
+            text: String::new(),
+            item,
+        };
+        let mut reserve = |label: String, size: usize| {
+            items.push(generated(Item::Label(label)));
+            items.push(generated(Item::Data(vec![0; size])));
+        };
+        for &(ref scope, slots) in self.spill_slots.iter() {
+            for slot in 0..slots {
+                reserve(spill_label(scope, slot), 1);
+            }
+        }
+        for (name, signature) in self.functions.iter() {
+            reserve(frame_label(name), FRAME_SIZE);
+            // The first byte of the arguments is what lands in V0.
+            reserve(arguments_label(name), 1);
+            for idx in 1..=signature.params.len() {
+                reserve(argument_label(name, idx), 1);
+            }
+        }
+        for (site, &highest) in self.call_saves.iter().enumerate() {
+            reserve(save_label(site), usize::from(highest) + 1);
+        }
+        for label in self.addr_consts.iter() {
+            items.push(generated(Item::Label(address_label(label))));
+            items.push(generated(Item::Instr(instr_load(OpParam::RegisterI, OpParam::Label(label.clone())))));
+        }
+        items
+    }
+}
+
+// Calling the stored address loads it into I.
+fn thunk(addr: OpParam) -> Vec<Item> {
+    vec![
+        Item::Instr(instr_load(OpParam::RegisterI, addr)),
+        Item::Instr(Instruction::Return(Return {})),
+    ]
+}
@@ -0,0 +1,276 @@
+use assembler::{Diagnostic, Item, SourceItem, SourceLoc};
+use expr::{is_identifier, register_name};
+use instructions::display::Draw;
+use instructions::loads::Load;
+use instructions::math::Add;
+use instructions::parameters::OpParam;
+use instructions::Instruction;
+use structured::GENERATED_PREFIX;
+
+// Fonts of the program's own, for text the built-in hex digits cannot
+// spell. A FONT gives each glyph as a character and its rows, `#` for a lit
+// pixel and `.` for a dark one:
+//
+//     FONT SMALL 3x5
+//         H  #.# #.# ### #.# #.#
+//         I  ### .#. .#. .#. ###
+//     ENDFONT
+//
+//     TEXT "HI", V3, V4          // in the last FONT declared
+//     TEXT SMALL "HI", V3, V4
+//
+// TEXT compiles to an `LD I` and a `DRW` for each character, moving the X
+// register on by the glyph width plus one after each, so it ends up past
+// the text. Spaces only move it. A lower-case letter the font lacks is
+// drawn as upper case. Glyphs are stored after the program, and only the
+// ones some TEXT uses.
+
+const MAX_WIDTH: usize = 8;
+const MAX_HEIGHT: usize = 15;
+
+const KEYWORDS: [&str; 3] = ["FONT", "ENDFONT", "TEXT"];
+
+struct Glyph {
+    ch: char,
+    rows: Vec<u8>,
+    loc: SourceLoc,
+    text: String,
+    used: bool,
+}
+
+struct Font {
+    name: String,
+    width: usize,
+    height: usize,
+    glyphs: Vec<Glyph>,
+    loc: SourceLoc,
+}
+
+impl Font {
+    fn glyph(&self, ch: char) -> Option<usize> {
+        let find = |ch: char| self.glyphs.iter().position(|glyph| glyph.ch == ch);
+        find(ch).or_else(|| find(ch.to_ascii_uppercase()))
+    }
+}
+
+fn keyword(ln: &str) -> Option<String> {
+    let first = ln.split_whitespace().next()?.to_uppercase();
+    if KEYWORDS.contains(&first.as_str()) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+pub fn is_font_line(ln: &str) -> bool {
+    keyword(ln).is_some()
+}
+
+pub fn glyph_label(font: &str, ch: char) -> String {
+    format!("{}{}_{}", GENERATED_PREFIX, font, ch as u32)
+}
+
+// `3x5`: a glyph's width and height.
+fn size(text: &str) -> Result<(usize, usize), String> {
+    let lower = text.to_lowercase();
+    let (width, height) = lower.split_once('x').ok_or_else(|| format!("Expected a glyph size like 3x5, got {}", text))?;
+    match (width.parse::<usize>(), height.parse::<usize>()) {
+        (Ok(width), Ok(height)) if (1..=MAX_WIDTH).contains(&width) && (1..=MAX_HEIGHT).contains(&height) => Ok((width, height)),
+        (Ok(_), Ok(_)) => Err(format!("Glyphs can be up to {} wide and {} high, not {}", MAX_WIDTH, MAX_HEIGHT, text)),
+        _ => Err(format!("Expected a glyph size like 3x5, got {}", text)),
+    }
+}
+
+fn draw_register(text: &str) -> Result<u8, String> {
+    match register_name(text.trim()) {
+        Some(0xF) => Err("TEXT cannot draw at VF, which DRW changes".to_owned()),
+        Some(num) => Ok(num),
+        None => Err(format!("Expected a register, got {}", text.trim())),
+    }
+}
+
+// The fonts declared so far and the glyphs TEXT has drawn from them.
+pub struct Fonts {
+    fonts: Vec<Font>,
+    open: Option<Font>,
+}
+
+impl Default for Fonts {
+    fn default() -> Fonts {
+        Fonts::new()
+    }
+}
+
+impl Fonts {
+    pub fn new() -> Fonts {
+        Fonts { fonts: Vec::new(), open: None }
+    }
+
+    // Whether lines are glyphs of a FONT still to be ended.
+    pub fn is_open(&self) -> bool {
+        self.open.is_some()
+    }
+
+    pub fn line(&mut self, ln: &str, loc: &SourceLoc, items: &mut Vec<SourceItem>) -> Result<(), String> {
+        let code = ln.trim();
+        match keyword(code).as_deref() {
+            Some("ENDFONT") if self.open.is_some() => {
+                let font = self.open.take().unwrap();
+                if font.glyphs.is_empty() {
+                    return Err(format!("FONT {} has no glyphs", font.name));
+                }
+                self.fonts.push(font);
+                Ok(())
+            }
+            Some("ENDFONT") => Err("ENDFONT without a FONT".to_owned()),
+            Some("FONT") if self.open.is_some() => Err("FONT inside another FONT".to_owned()),
+            Some("FONT") => self.font(code.split("//").next().unwrap_or(""), loc),
+            Some("TEXT") if self.open.is_none() => self.text(code, loc, items),
+            _ => self.glyph(ln, loc),
+        }
+    }
+
+    fn font(&mut self, code: &str, loc: &SourceLoc) -> Result<(), String> {
+        let words: Vec<&str> = code.split_whitespace().collect();
+        let (name, size_text) = match words.as_slice() {
+            [_, name, size_text] => (name.to_uppercase(), *size_text),
+            _ => return Err("Expected FONT NAME WxH".to_owned()),
+        };
+        if !is_identifier(&name) {
+            return Err(format!("{} is not a valid font name", name));
+        }
+        if self.fonts.iter().any(|font| font.name == name) {
+            return Err(format!("FONT {} is already declared", name));
+        }
+        let (width, height) = size(size_text)?;
+        self.open = Some(Font {
+            name,
+            width,
+            height,
+            glyphs: Vec::new(),
+            loc: loc.clone(),
+        });
+        Ok(())
+    }
+
+    // `H  #.# #.# ### #.# #.#`: a character and its rows, top first.
+    fn glyph(&mut self, ln: &str, loc: &SourceLoc) -> Result<(), String> {
+        let font = match self.open {
+            Some(ref mut font) => font,
+            None => return Err(format!("Unexpected line: {}", ln.trim())),
+        };
+        let code = ln.split("//").next().unwrap_or("").trim();
+        if code.is_empty() {
+            return Ok(());
+        }
+        let words: Vec<&str> = code.split_whitespace().collect();
+        let ch = match words[0].chars().collect::<Vec<_>>().as_slice() {
+            [ch] if words.len() == font.height + 1 => *ch,
+            _ => return Err(format!("Expected a character and {} rows of {} pixels, got {}", font.height, font.width, code)),
+        };
+        if font.glyphs.iter().any(|glyph| glyph.ch == ch) {
+            return Err(format!("FONT {} already has a glyph for '{}'", font.name, ch));
+        }
+        let mut rows = Vec::new();
+        for row in words[1..].iter() {
+            if row.chars().count() != font.width || row.chars().any(|c| c != '#' && c != '.') {
+                return Err(format!("Expected {} pixels of # or ., got {}", font.width, row));
+            }
+            let bits = row.chars().enumerate().filter(|&(_, c)| c == '#').fold(0u8, |bits, (idx, _)| bits | 0x80 >> idx);
+            rows.push(bits);
+        }
+        font.glyphs.push(Glyph {
+            ch,
+            rows,
+            loc: loc.clone(),
+            text: ln.to_owned(),
+            used: false,
+        });
+        Ok(())
+    }
+
+    // `TEXT [FONT] "STRING", VX, VY`
+    fn text(&mut self, code: &str, loc: &SourceLoc, items: &mut Vec<SourceItem>) -> Result<(), String> {
+        let rest = code[4..].trim_start();
+        let quote = rest.find('"').ok_or("Expected TEXT \"STRING\", VX, VY")?;
+        let end = rest[quote + 1..].find('"').ok_or("The TEXT string is missing its closing quote")? + quote + 1;
+        let string = &rest[quote + 1..end];
+        let args = rest[end + 1..].split("//").next().unwrap_or("").trim();
+        let regs: Vec<&str> = args.strip_prefix(',').ok_or("Expected TEXT \"STRING\", VX, VY")?.split(',').collect();
+        let (x, y) = match regs.as_slice() {
+            [x, y] => (draw_register(x)?, draw_register(y)?),
+            _ => return Err("Expected TEXT \"STRING\", VX, VY".to_owned()),
+        };
+
+        let name = rest[..quote].trim().to_uppercase();
+        let font = if name.is_empty() {
+            self.fonts.last_mut().ok_or("TEXT needs a FONT declared before it")?
+        } else {
+            match self.fonts.iter_mut().find(|font| font.name == name) {
+                Some(font) => font,
+                None => return Err(format!("No FONT named {}", name)),
+            }
+        };
+
+        let step = font.width as u16 + 1;
+        let mut instrs = Vec::new();
+        let mut advance = 0;
+        for ch in string.chars() {
+            let glyph = match font.glyph(ch) {
+                Some(glyph) => glyph,
+                None if ch == ' ' => {
+                    advance += step;
+                    continue;
+                }
+                None => return Err(format!("FONT {} has no glyph for '{}'", font.name, ch)),
+            };
+            if advance > 0 {
+                instrs.push(Instruction::Add(Add::new(OpParam::Register(x), OpParam::Variable(advance & 0xFF))));
+            }
+            let label = glyph_label(&font.name, font.glyphs[glyph].ch);
+            font.glyphs[glyph].used = true;
+            instrs.push(Instruction::Load(Load::new(OpParam::RegisterI, OpParam::Label(label))));
+            instrs.push(Instruction::Draw(Draw::new(OpParam::Register(x), OpParam::Register(y), OpParam::Variable(font.height as u16))));
+            advance = step;
+        }
+        if advance > 0 {
+            instrs.push(Instruction::Add(Add::new(OpParam::Register(x), OpParam::Variable(advance & 0xFF))));
+        }
+        for instr in instrs {
+            items.push(SourceItem {
+                loc: loc.clone(),
+                text: code.to_owned(),
+                item: Item::Instr(instr),
+            });
+        }
+        Ok(())
+    }
+
+    // A FONT left open at the end of the file it started in.
+    pub fn close_file(&mut self, file: &str, errors: &mut Vec<Diagnostic>) {
+        if self.open.as_ref().is_some_and(|font| font.loc.file == file) {
+            let font = self.open.take().unwrap();
+            errors.push(Diagnostic {
+                loc: font.loc,
+                message: format!("FONT {} is missing its ENDFONT", font.name),
+            });
+        }
+    }
+
+    // The glyphs some TEXT drew, to place after the program.
+    pub fn storage(&self) -> Vec<SourceItem> {
+        let mut items = Vec::new();
+        for font in self.fonts.iter() {
+            for glyph in font.glyphs.iter().filter(|glyph| glyph.used) {
+                let item = |item: Item| SourceItem {
+                    loc: glyph.loc.clone(),
+                    text: glyph.text.clone(),
+                    item,
+                };
+                items.push(item(Item::Label(glyph_label(&font.name, glyph.ch))));
+                items.push(item(Item::Data(glyph.rows.clone())));
+            }
+        }
+        items
+    }
+}
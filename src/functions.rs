@@ -0,0 +1,314 @@
+use assembler::{Diagnostic, Item, SourceItem, SourceLoc};
+use expr::{is_identifier, Signature, Type, Variables};
+use instructions::flow::{Jump, Return};
+use instructions::loads::Load;
+use instructions::parameters::OpParam;
+use instructions::*;
+use structured::{Blocks, GENERATED_PREFIX};
+
+use std::collections::HashMap;
+
+// Functions with parameters, built on the variables and expressions of
+// expr.rs:
+//
+//     FUNC AVERAGE(A, B) -> MEAN : BYTE
+//         MEAN = A / 2 + B / 2
+//     ENDFUNC
+//
+//     SCORE = AVERAGE(SCORE, BONUS + 0x10)
+//
+// RETURN leaves early, and RETURN with an expression sets the result first.
+// A function must be declared before it is called.
+//
+// Parameters and results are BYTE unless given a type, and cannot be ADDR.
+// A call stages its arguments in memory and loads them into V1 up, the
+// result comes back in V0. Inside the function the parameters stay in those
+// registers and the result is the register after them. The function saves
+// V0-VE to its frame on entry and restores them on return, so only V0, VF, I
+// and the argument registers are changed by a call; the caller saves what it
+// still needs from the argument registers. Frames are static, so functions
+// cannot recurse.
+
+pub const MAX_PARAMS: usize = 6;
+pub const FRAME_SIZE: usize = 15;
+
+const KEYWORDS: [&str; 3] = ["FUNC", "ENDFUNC", "RETURN"];
+
+fn keyword(ln: &str) -> Option<String> {
+    let code = ln.split("//").next().unwrap_or("");
+    let first = code.split_whitespace().next()?.to_uppercase();
+    if KEYWORDS.contains(&first.as_str()) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+pub fn is_function_line(ln: &str) -> bool {
+    keyword(ln).is_some()
+}
+
+pub fn frame_label(name: &str) -> String {
+    format!("{}{}_FRAME", GENERATED_PREFIX, name)
+}
+
+pub fn arguments_label(name: &str) -> String {
+    format!("{}{}_ARGS", GENERATED_PREFIX, name)
+}
+
+pub fn argument_label(name: &str, idx: usize) -> String {
+    format!("{}{}_ARG{}", GENERATED_PREFIX, name, idx)
+}
+
+fn return_label(name: &str) -> String {
+    format!("{}{}_RETURN", GENERATED_PREFIX, name)
+}
+
+fn load(dest: OpParam, src: OpParam) -> Item {
+    Item::Instr(Instruction::Load(Load::new(dest, src)))
+}
+
+// `NAME [: TYPE]`
+fn parse_local(text: &str) -> Result<(String, Type), String> {
+    let (name, ty) = match text.find(':') {
+        Some(colon) => {
+            let ty_name = text[colon + 1..].trim();
+            let ty = Type::parse(ty_name).ok_or_else(|| format!("Unknown type {}", ty_name))?;
+            (text[..colon].trim(), ty)
+        }
+        None => (text.trim(), Type::Byte),
+    };
+    if !is_identifier(name) {
+        return Err(format!("Expected a name, got {}", name));
+    }
+    if ty == Type::Addr {
+        return Err(format!("{} cannot be ADDR; parameters and results are BYTE or BOOL", name));
+    }
+    Ok((name.to_uppercase(), ty))
+}
+
+// `NAME(A, B : BOOL) -> R : BYTE`
+fn parse_header(text: &str) -> Result<(String, Signature), String> {
+    let usage = "Expected FUNC NAME(PARAMS) [-> RESULT]";
+    let open = text.find('(').ok_or_else(|| usage.to_owned())?;
+    let close = text.find(')').ok_or_else(|| usage.to_owned())?;
+    let name = text[..open].trim();
+    if close < open || !is_identifier(name) {
+        return Err(usage.to_owned());
+    }
+
+    let mut params: Vec<(String, Type)> = Vec::new();
+    let list = text[open + 1..close].trim();
+    if !list.is_empty() {
+        for param in list.split(',') {
+            let (param, ty) = parse_local(param)?;
+            if params.iter().any(|entry| entry.0 == param) {
+                return Err(format!("Parameter {} is repeated", param));
+            }
+            params.push((param, ty));
+        }
+    }
+    if params.len() > MAX_PARAMS {
+        return Err(format!("{} takes {} parameters, the most is {}", name, params.len(), MAX_PARAMS));
+    }
+
+    let rest = text[close + 1..].trim();
+    let result = if rest.is_empty() {
+        None
+    } else if let Some(result) = rest.strip_prefix("->") {
+        let result = parse_local(result)?;
+        if params.iter().any(|entry| entry.0 == result.0) {
+            return Err(format!("Result {} has the name of a parameter", result.0));
+        }
+        Some(result)
+    } else {
+        return Err(usage.to_owned());
+    };
+    Ok((name.to_uppercase(), Signature { params, result }))
+}
+
+struct OpenFunction {
+    name: String,
+    loc: SourceLoc,
+    // Blocks open when the FUNC started, which must be open at ENDFUNC.
+    blocks: usize,
+}
+
+// The function being parsed and where each was declared. Files share one
+// instance, but a FUNC must end in the file that started it.
+pub struct Functions {
+    open: Option<OpenFunction>,
+    declared: HashMap<String, SourceLoc>,
+    order: Vec<String>,
+}
+
+impl Default for Functions {
+    fn default() -> Functions {
+        Functions::new()
+    }
+}
+
+impl Functions {
+    pub fn new() -> Functions {
+        Functions {
+            open: None,
+            declared: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    fn open(&mut self, header: &str, loc: &SourceLoc, blocks: &Blocks, variables: &mut Variables) -> Result<Vec<Item>, String> {
+        if let Some(ref open) = self.open {
+            return Err(format!("FUNC cannot be nested; {} has no ENDFUNC", open.name));
+        }
+        let (name, signature) = parse_header(header)?;
+        variables.declare_function(&name, signature)?;
+        variables.enter_function(&name);
+        self.declared.insert(name.clone(), loc.clone());
+        self.order.push(name.clone());
+        self.open = Some(OpenFunction {
+            name: name.clone(),
+            loc: loc.clone(),
+            blocks: blocks.depth(),
+        });
+        Ok(vec![
+            Item::Label(name.clone()),
+            load(OpParam::RegisterI, OpParam::Label(frame_label(&name))),
+            load(OpParam::DerefI, OpParam::Register(0xE)),
+        ])
+    }
+
+    fn return_from(&mut self, value: &str, variables: &mut Variables) -> Result<Vec<Item>, String> {
+        let name = match self.open {
+            Some(ref open) => open.name.clone(),
+            None => return Err("RETURN outside of a FUNC".to_owned()),
+        };
+        let mut items = Vec::new();
+        if !value.is_empty() {
+            let result = match variables.signature(&name).and_then(|signature| signature.result.clone()) {
+                Some((result, _)) => result,
+                None => return Err(format!("{} has no result to return", name)),
+            };
+            items.extend(variables.assign(&result, value)?.into_iter().map(Item::Instr));
+        }
+        items.push(Item::Instr(Instruction::Jump(Jump::new(OpParam::Label(return_label(&name))))));
+        Ok(items)
+    }
+
+    fn close(&mut self, blocks: &Blocks, variables: &mut Variables) -> Result<Vec<Item>, String> {
+        let (name, open_blocks) = match self.open {
+            Some(ref open) => (open.name.clone(), open.blocks),
+            None => return Err("ENDFUNC without FUNC".to_owned()),
+        };
+        if blocks.depth() > open_blocks {
+            return Err(format!("ENDFUNC with an IF or LOOP still open in {}", name));
+        }
+        let frame = OpParam::Label(frame_label(&name));
+        let mut items = vec![Item::Label(return_label(&name))];
+        if let Some(signature) = variables.signature(&name) {
+            if signature.result.is_some() {
+                // Parking the result where V0 was saved restores it into V0.
+                items.push(load(OpParam::Register(0), OpParam::Register(signature.result_register())));
+                items.push(load(OpParam::RegisterI, frame.clone()));
+                items.push(load(OpParam::DerefI, OpParam::Register(0)));
+            }
+        }
+        items.push(load(OpParam::RegisterI, frame));
+        items.push(load(OpParam::Register(0xE), OpParam::DerefI));
+        items.push(Item::Instr(Instruction::Return(Return {})));
+        variables.leave_function();
+        self.open = None;
+        Ok(items)
+    }
+
+    // Lowers one FUNC, RETURN or ENDFUNC line.
+    pub fn line(&mut self, ln: &str, loc: &SourceLoc, blocks: &Blocks, variables: &mut Variables, items: &mut Vec<SourceItem>) -> Result<(), String> {
+        let code = ln.split("//").next().unwrap_or("").trim();
+        let word = keyword(code).unwrap_or_default();
+        let rest = code[word.len()..].trim();
+        let lowered = match word.as_str() {
+            "FUNC" => self.open(rest, loc, blocks, variables)?,
+            "RETURN" => self.return_from(rest, variables)?,
+            _ if !rest.is_empty() => return Err("ENDFUNC takes no arguments".to_owned()),
+            _ => self.close(blocks, variables)?,
+        };
+        for item in lowered {
+            items.push(SourceItem {
+                loc: loc.clone(),
+                text: ln.to_owned(),
+                item,
+            });
+        }
+        Ok(())
+    }
+
+    // Reports a FUNC the file left open.
+    pub fn close_file(&mut self, file: &str, variables: &mut Variables, errors: &mut Vec<Diagnostic>) {
+        if self.open.as_ref().is_some_and(|open| open.loc.file == file) {
+            if let Some(open) = self.open.take() {
+                variables.leave_function();
+                errors.push(Diagnostic {
+                    loc: open.loc,
+                    message: format!("FUNC {} without ENDFUNC", open.name),
+                });
+            }
+        }
+    }
+
+    // Checks that no chain of calls between functions recurses. How deep the
+    // calls nest is checked with the rest of the program's calls, against
+    // the configured stack limit, once it is assembled.
+    pub fn check_calls(&self, variables: &Variables, errors: &mut Vec<Diagnostic>) {
+        let mut callees: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (caller, callee) in variables.calls() {
+            if let Some(caller) = caller {
+                let list = callees.entry(caller.as_str()).or_default();
+                if !list.contains(&callee.as_str()) {
+                    list.push(callee.as_str());
+                }
+            }
+        }
+
+        let mut chains: HashMap<&str, Vec<&str>> = HashMap::new();
+        for name in self.order.iter() {
+            if let Err(cycle) = deepest_chain(name, &callees, &mut chains, &mut Vec::new()) {
+                errors.push(Diagnostic {
+                    loc: self.declared[name].clone(),
+                    message: format!("{} is recursive, which its static frame cannot support: {}", name, cycle.join(" -> ")),
+                });
+                return;
+            }
+        }
+    }
+}
+
+// The longest chain of calls starting with a call to `name`, or the cycle
+// that makes it endless.
+pub fn deepest_chain<'a>(
+    name: &'a str,
+    callees: &HashMap<&'a str, Vec<&'a str>>,
+    chains: &mut HashMap<&'a str, Vec<&'a str>>,
+    path: &mut Vec<&'a str>,
+) -> Result<Vec<&'a str>, Vec<&'a str>> {
+    if let Some(chain) = chains.get(name) {
+        return Ok(chain.clone());
+    }
+    if let Some(start) = path.iter().position(|&entry| entry == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name);
+        return Err(cycle);
+    }
+    path.push(name);
+    let mut longest = Vec::new();
+    for &callee in callees.get(name).map_or(&[][..], |list| &list[..]) {
+        let chain = deepest_chain(callee, callees, chains, path)?;
+        if chain.len() > longest.len() {
+            longest = chain;
+        }
+    }
+    path.pop();
+    let mut chain = vec![name];
+    chain.extend(longest);
+    chains.insert(name, chain.clone());
+    Ok(chain)
+}
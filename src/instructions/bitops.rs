@@ -1,8 +1,14 @@
 use instructions::*;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Or {acc : OpParam, reg : OpParam}
 
+impl Or {
+    pub fn new(acc: OpParam, reg: OpParam) -> Or {
+        Or { acc, reg }
+    }
+}
+
 impl InstructionOps for Or {
     fn to_opcode(&self) -> u16 {
         match *self {
@@ -22,9 +28,15 @@ impl InstructionOps for Or {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct And {acc : OpParam, reg : OpParam}
 
+impl And {
+    pub fn new(acc: OpParam, reg: OpParam) -> And {
+        And { acc, reg }
+    }
+}
+
 impl InstructionOps for And {
     fn to_opcode(&self) -> u16 {
         match *self {
@@ -44,9 +56,15 @@ impl InstructionOps for And {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Xor {acc : OpParam, reg : OpParam}
 
+impl Xor {
+    pub fn new(acc: OpParam, reg: OpParam) -> Xor {
+        Xor { acc, reg }
+    }
+}
+
 impl InstructionOps for Xor {
     fn to_opcode(&self) -> u16 {
         match *self {
@@ -67,12 +85,18 @@ impl InstructionOps for Xor {
 }
 
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ShiftRight {
     acc : OpParam, 
     usually_unused : OpParam
 }
 
+impl ShiftRight {
+    pub fn new(acc: OpParam, usually_unused: OpParam) -> ShiftRight {
+        ShiftRight { acc, usually_unused }
+    }
+}
+
 impl InstructionOps for ShiftRight {
     fn parse_args(ln : &str) -> Result<ShiftRight, ParseError> {
         let (parsed_dest, parsed_source) = parse_args!(ln, 2);
@@ -94,12 +118,18 @@ impl InstructionOps for ShiftRight {
 }
 
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ShiftLeft {
     acc : OpParam, 
     usually_unused : OpParam
 }
 
+impl ShiftLeft {
+    pub fn new(acc: OpParam, usually_unused: OpParam) -> ShiftLeft {
+        ShiftLeft { acc, usually_unused }
+    }
+}
+
 impl InstructionOps for ShiftLeft {
     fn parse_args(ln : &str) -> Result<ShiftLeft, ParseError> {
         let (parsed_dest, parsed_source) = parse_args!(ln, 2);
@@ -120,12 +150,18 @@ impl InstructionOps for ShiftLeft {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Rand {
     reg : OpParam,
     mask : OpParam
 }
 
+impl Rand {
+    pub fn new(reg: OpParam, mask: OpParam) -> Rand {
+        Rand { reg, mask }
+    }
+}
+
 impl InstructionOps for Rand {
     fn parse_args(ln : &str) -> Result<Rand, ParseError> {
         let (parsed_dest, parsed_source) = parse_args!(ln, 2);
@@ -140,7 +176,7 @@ impl InstructionOps for Rand {
 
     fn to_opcode(&self) -> u16 {
         match *self {
-            Rand{reg : OpParam::Register(dreg), mask : OpParam::Variable(msk)} => 0xC000 | (dreg as u16 & 0xF) << 8 | (msk as u16 & 0xFF),
+            Rand{reg : OpParam::Register(dreg), mask : OpParam::Variable(msk)} => 0xC000 | (dreg as u16 & 0xF) << 8 | (msk & 0xFF),
             _ => panic!("Got invalid Rand parameter!")
         }
     }
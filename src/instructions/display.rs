@@ -1,6 +1,6 @@
 use instructions::*;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ClearScreen {}
 
 impl InstructionOps for ClearScreen {
@@ -18,13 +18,19 @@ impl InstructionOps for ClearScreen {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Draw {
     xreg : OpParam,
     yreg : OpParam,
     length : OpParam
 }
 
+impl Draw {
+    pub fn new(xreg: OpParam, yreg: OpParam, length: OpParam) -> Draw {
+        Draw { xreg, yreg, length }
+    }
+}
+
 impl InstructionOps for Draw {
     fn parse_args(ln : &str) -> Result<Draw, ParseError> {
         let (parsed_dest, parsed_source, parsed_len) = parse_args!(ln, 3);
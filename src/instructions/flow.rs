@@ -1,6 +1,6 @@
 use instructions::*;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Return {}
 
 impl InstructionOps for Return {
@@ -18,9 +18,20 @@ impl InstructionOps for Return {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Jump (OpParam, OpParam);
 
+impl Jump {
+    pub fn new(dest: OpParam) -> Jump {
+        Jump(dest, OpParam::Blank)
+    }
+
+    // JP V0, dest
+    pub fn offset(dest: OpParam) -> Jump {
+        Jump(OpParam::Register(0), dest)
+    }
+}
+
 impl InstructionOps for Jump {
     fn to_opcode(&self) -> u16 {
         match self {
@@ -50,16 +61,29 @@ impl InstructionOpsWithLabels for Jump {
         let new_right = labels.get(&self.1).unwrap_or(&self.1);
         Jump(new_left.clone(), new_right.clone())
     }
+
+    fn label_refs(&self) -> Vec<&str> {
+        [&self.0, &self.1].iter().filter_map(|param| match param {
+            OpParam::Label(lbl) => Some(lbl.as_str()),
+            _ => None
+        }).collect()
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Call (OpParam);
 
+impl Call {
+    pub fn new(dest: OpParam) -> Call {
+        Call(dest)
+    }
+}
+
 impl InstructionOps for Call {
     fn to_opcode(&self) -> u16 {
-        match self {
-            &Call(OpParam::Variable(addr)) => 0x2000 | (addr & 0x0FFF),
-            &Call(OpParam::Label(ref lbl)) => panic!("Label {} not correclty replaced!", lbl),
+        match *self {
+            Call(OpParam::Variable(addr)) => 0x2000 | (addr & 0x0FFF),
+            Call(OpParam::Label(ref lbl)) => panic!("Label {} not correclty replaced!", lbl),
             _ => panic!("Could not correclty parse Call.")
         }
     }
@@ -79,11 +103,24 @@ impl InstructionOpsWithLabels for Call {
         let new_left = labels.get(&self.0).unwrap_or(&self.0);
         Call(new_left.clone())
     }
+
+    fn label_refs(&self) -> Vec<&str> {
+        match self.0 {
+            OpParam::Label(ref lbl) => vec![lbl.as_str()],
+            _ => Vec::new()
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SkipIfEqual(OpParam, OpParam);
 
+impl SkipIfEqual {
+    pub fn new(reg: OpParam, value: OpParam) -> SkipIfEqual {
+        SkipIfEqual(reg, value)
+    }
+}
+
 impl InstructionOps for SkipIfEqual {
     fn to_opcode(&self) -> u16 {
         match *self {
@@ -106,9 +143,15 @@ impl InstructionOps for SkipIfEqual {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SkipIfNotEqual(OpParam, OpParam);
 
+impl SkipIfNotEqual {
+    pub fn new(reg: OpParam, value: OpParam) -> SkipIfNotEqual {
+        SkipIfNotEqual(reg, value)
+    }
+}
+
 impl InstructionOps for SkipIfNotEqual {
     fn to_opcode(&self) -> u16 {
         match *self {
@@ -131,9 +174,15 @@ impl InstructionOps for SkipIfNotEqual {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SkipIfKey (OpParam);
 
+impl SkipIfKey {
+    pub fn new(reg: OpParam) -> SkipIfKey {
+        SkipIfKey(reg)
+    }
+}
+
 impl InstructionOps for SkipIfKey {
     fn parse_args(ln : &str) -> Result<SkipIfKey, ParseError> {
         let parsed_reg = parse_args!(ln, 1);
@@ -151,9 +200,15 @@ impl InstructionOps for SkipIfKey {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SkipIfNotKey (OpParam);
 
+impl SkipIfNotKey {
+    pub fn new(reg: OpParam) -> SkipIfNotKey {
+        SkipIfNotKey(reg)
+    }
+}
+
 impl InstructionOps for SkipIfNotKey {
     fn parse_args(ln : &str) -> Result<SkipIfNotKey, ParseError> {
         let parsed_reg = parse_args!(ln, 1);
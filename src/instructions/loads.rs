@@ -1,17 +1,23 @@
 use instructions::*;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Load { 
     dest : OpParam,
     source :OpParam
 }
 
+impl Load {
+    pub fn new(dest: OpParam, source: OpParam) -> Load {
+        Load { dest, source }
+    }
+}
+
 impl InstructionOps for Load {
 
     fn to_opcode(&self) -> u16 {
         match (&self.dest, &self.source) {
             (&OpParam::Register(regnum), &OpParam::Variable(vnum)) => 0x6000 | ((regnum as u16 & 0x0F) << 8) | (vnum & 0x00FF),
-            (&OpParam::Register(dreg), &OpParam::Register(sreg)) => 0x8000 | ((dreg as u16 & 0x0F) << 8) | ((sreg as u16 &0x0F << 4)),
+            (&OpParam::Register(dreg), &OpParam::Register(sreg)) => 0x8000 | ((dreg as u16 & 0x0F) << 8) | ((sreg as u16 & 0x0F) << 4),
             (&OpParam::RegisterI, &OpParam::Variable(vnum)) => 0xA000 | (vnum & 0x0FFF),
             (&OpParam::Register(dreg), &OpParam::Timer) => 0xF007 | ((dreg as u16) & 0x0F) << 8, 
             (&OpParam::Register(dreg), &OpParam::Keyboard) => 0xF00A | ((dreg as u16) &0x0F) << 8, 
@@ -52,4 +58,11 @@ impl InstructionOpsWithLabels for Load {
         let nlabel = labels.get(&self.source).unwrap_or(&self.source);
         Load{dest : self.dest.clone(), source : nlabel.clone()}
     }
+
+    fn label_refs(&self) -> Vec<&str> {
+        match self.source {
+            OpParam::Label(ref lbl) => vec![lbl.as_str()],
+            _ => Vec::new()
+        }
+    }
 }
\ No newline at end of file
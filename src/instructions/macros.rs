@@ -14,12 +14,12 @@ macro_rules! parse_arg {
 #[macro_export]
 macro_rules! parse_args {
     ($ln:ident, 1) => {{
-        let mut without_comment = $ln.splitn(2, "//").next().unwrap_or("").trim();
+        let without_comment = $ln.splitn(2, "//").next().unwrap_or("").trim();
         let mut instruction_itr =  without_comment.splitn(2, ",");
         parse_arg!(instruction_itr)
     }};
     ($ln:ident, 2) => {{
-        let mut without_comment = $ln.splitn(2, "//").next().unwrap_or("").trim();
+        let without_comment = $ln.splitn(2, "//").next().unwrap_or("").trim();
         let mut instruction_itr =  without_comment.splitn(2, ",");
 
         let parsed_arg1 = parse_arg!(instruction_itr); 
@@ -28,7 +28,7 @@ macro_rules! parse_args {
         (parsed_arg1, parsed_arg2)
     }};
     ($ln:ident, 3) => {{
-        let mut without_comment = $ln.splitn(2, "//").next().unwrap_or("").trim();
+        let without_comment = $ln.splitn(2, "//").next().unwrap_or("").trim();
         let mut instruction_itr =  without_comment.splitn(3, ",");
 
         let parsed_arg1 = parse_arg!(instruction_itr); 
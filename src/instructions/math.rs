@@ -1,17 +1,23 @@
 use instructions::*;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Add {
     acc : OpParam, 
     to_add : OpParam
 }
 
+impl Add {
+    pub fn new(acc: OpParam, to_add: OpParam) -> Add {
+        Add { acc, to_add }
+    }
+}
+
 impl InstructionOps for Add {
     fn to_opcode(&self) -> u16 {
         match *self {
             Add{acc : OpParam::Register(dreg), to_add : OpParam::Variable(vl)} => 0x7000 | ((dreg as u16 & 0xF) << 8) | vl & 0xFF, 
             Add{acc : OpParam::Register(dreg), to_add : OpParam::Register(sreg)} => 0x8004 | ((dreg as u16 & 0xF) << 8) | ((sreg as u16 &0xF) << 4),
-            Add{acc : OpParam::RegisterI, to_add : OpParam::Register(sreg)} => 0xF01E | ((sreg as u16 & 0xF)) << 8,  
+            Add{acc : OpParam::RegisterI, to_add : OpParam::Register(sreg)} => 0xF01E | (sreg as u16 & 0xF) << 8,  
             _ => panic!("Got invalid Add parameter!")
         }
     }
@@ -34,9 +40,15 @@ impl InstructionOps for Add {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Sub {acc : OpParam, reg : OpParam}
 
+impl Sub {
+    pub fn new(acc: OpParam, reg: OpParam) -> Sub {
+        Sub { acc, reg }
+    }
+}
+
 impl InstructionOps for Sub {
     fn to_opcode(&self) -> u16 {
         match *self {
@@ -56,9 +68,15 @@ impl InstructionOps for Sub {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SubN {acc : OpParam, reg : OpParam}
 
+impl SubN {
+    pub fn new(acc: OpParam, reg: OpParam) -> SubN {
+        SubN { acc, reg }
+    }
+}
+
 impl InstructionOps for SubN {
     fn to_opcode(&self) -> u16 {
         match *self {
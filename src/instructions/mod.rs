@@ -17,7 +17,7 @@ use math::*;
 use parameters::*;
 
 #[derive(Debug)]
-pub struct ParseError(String);
+pub struct ParseError(pub String);
 
 pub trait InstructionOps: Sized {
     fn to_opcode(&self) -> u16;
@@ -27,9 +27,11 @@ pub trait InstructionOps: Sized {
 
 pub trait InstructionOpsWithLabels: InstructionOps {
     fn resolve_labels(&self, labels: &HashMap<OpParam, OpParam>) -> Self;
+
+    fn label_refs(&self) -> Vec<&str>;
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Instruction {
     Jump(flow::Jump),
     Call(flow::Call),
@@ -88,37 +90,37 @@ impl InstructionOps for Instruction {
     }
 
     fn parse_args(ln : &str) -> Result<Instruction, ParseError> {
-        let true_line = ln.splitn(2, "//").next().unwrap_or("").trim().to_uppercase();
+        let true_line = ln.split("//").next().unwrap_or("").trim().to_uppercase();
 
         let mut tln_itr = true_line.splitn(2, " ");
         let instr = tln_itr.next().unwrap_or("").trim();
         let args = tln_itr.next().unwrap_or("").trim();
 
         match instr {
-            "JP" => Jump::parse_args(args).map(|inner| Instruction::Jump(inner)),
-            "CALL" => Call::parse_args(args).map(|inner| Instruction::Call(inner)),
-            "RET" => Return::parse_args(args).map(|inner| Instruction::Return(inner)),
+            "JP" => Jump::parse_args(args).map(Instruction::Jump),
+            "CALL" => Call::parse_args(args).map(Instruction::Call),
+            "RET" => Return::parse_args(args).map(Instruction::Return),
             
-            "SE" => SkipIfEqual::parse_args(args).map(|inner| Instruction::SkipIfEqual(inner)),
-            "SNE" => SkipIfNotEqual::parse_args(args).map(|inner| Instruction::SkipIfNotEqual(inner)),
-            "SKP" => SkipIfKey::parse_args(args).map(|inner| Instruction::SkipIfKey(inner)),
-            "SKNP" => SkipIfNotKey::parse_args(args).map(|inner| Instruction::SkipIfNotKey(inner)),
+            "SE" => SkipIfEqual::parse_args(args).map(Instruction::SkipIfEqual),
+            "SNE" => SkipIfNotEqual::parse_args(args).map(Instruction::SkipIfNotEqual),
+            "SKP" => SkipIfKey::parse_args(args).map(Instruction::SkipIfKey),
+            "SKNP" => SkipIfNotKey::parse_args(args).map(Instruction::SkipIfNotKey),
             
-            "LD" => Load::parse_args(args).map(|inner| Instruction::Load(inner)),
+            "LD" => Load::parse_args(args).map(Instruction::Load),
             
-            "AND" => And::parse_args(args).map(|inner| Instruction::And(inner)),
-            "OR" => Or::parse_args(args).map(|inner| Instruction::Or(inner)),
-            "XOR" => Xor::parse_args(args).map(|inner| Instruction::Xor(inner)),
-            "RAND" => Rand::parse_args(args).map(|inner| Instruction::Rand(inner)),
-            "SHL" => ShiftLeft::parse_args(args).map(|inner| Instruction::ShiftLeft(inner)),
-            "SHR" => ShiftRight::parse_args(args).map(|inner| Instruction::ShiftRight(inner)),
-
-            "CLS" => ClearScreen::parse_args(args).map(|inner| Instruction::ClearScreen(inner)),
-            "DRW" => Draw::parse_args(args).map(|inner| Instruction::Draw(inner)),
+            "AND" => And::parse_args(args).map(Instruction::And),
+            "OR" => Or::parse_args(args).map(Instruction::Or),
+            "XOR" => Xor::parse_args(args).map(Instruction::Xor),
+            "RND" | "RAND" => Rand::parse_args(args).map(Instruction::Rand),
+            "SHL" => ShiftLeft::parse_args(args).map(Instruction::ShiftLeft),
+            "SHR" => ShiftRight::parse_args(args).map(Instruction::ShiftRight),
+
+            "CLS" => ClearScreen::parse_args(args).map(Instruction::ClearScreen),
+            "DRW" => Draw::parse_args(args).map(Instruction::Draw),
             
-            "ADD" => Add::parse_args(args).map(|inner| Instruction::Add(inner)),
-            "SUB" => Sub::parse_args(args).map(|inner| Instruction::Sub(inner)),
-            "SUBN" => SubN::parse_args(args).map(|inner| Instruction::SubN(inner)),
+            "ADD" => Add::parse_args(args).map(Instruction::Add),
+            "SUB" => Sub::parse_args(args).map(Instruction::Sub),
+            "SUBN" => SubN::parse_args(args).map(Instruction::SubN),
 
 
             _ => Err(ParseError(format!("Could not parse instruction from line: {}", ln)))
@@ -136,6 +138,15 @@ impl InstructionOpsWithLabels for Instruction {
             _ => self.clone()
         }
     }
+
+    fn label_refs(&self) -> Vec<&str> {
+        match self {
+            Instruction::Jump(obj) => obj.label_refs(),
+            Instruction::Call(obj) => obj.label_refs(),
+            Instruction::Load(obj) => obj.label_refs(),
+            _ => Vec::new()
+        }
+    }
 }
 
 pub fn is_instr_line(ln: &str) -> bool {
@@ -163,22 +174,10 @@ pub fn is_instr_line(ln: &str) -> bool {
 }
 
 pub fn is_label(ln: &str) -> bool {
-    !ln.starts_with("//") && !ln.trim().is_empty() && !is_instr_line(ln)
+    !ln.trim().starts_with("//") && !ln.trim().is_empty() && !is_instr_line(ln)
 }
 
-pub fn parse_labels(file: &str) -> HashMap<OpParam, OpParam> {
-    let mut map = HashMap::new();
-    let mut offset: u16 = 0x200;
-
-    for ln in file.lines() {
-        if is_instr_line(ln) {
-            offset += 2;
-        } else if is_label(ln) {
-            let comment_parsed = ln.splitn(2, "//").next().unwrap();
-            let nlabel = OpParam::Label(comment_parsed.replace(":", " ").trim().to_owned());
-            let nvariable = OpParam::Variable(offset);
-            map.insert(nlabel, nvariable);
-        }
-    }
-    map
+pub fn label_name(ln: &str) -> String {
+    let comment_parsed = ln.split("//").next().unwrap_or("");
+    comment_parsed.replace(":", " ").trim().to_uppercase()
 }
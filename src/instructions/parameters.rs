@@ -34,8 +34,8 @@ impl OpParam {
             "B" => OpParam::Digits, 
             "F" => OpParam::Fontset,
             regarg if regarg.starts_with("V") => {
-                match u8::from_str_radix(&regarg[1..2], 16) {
-                    Ok(regvl) => OpParam::Register(regvl),
+                match regarg.get(1..2).map(|digit| u8::from_str_radix(digit, 16)) {
+                    Some(Ok(regvl)) => OpParam::Register(regvl),
                     _ => OpParam::Label(regarg.replace(":", " ").trim().to_owned())
                 }
             },
@@ -0,0 +1,355 @@
+pub mod script;
+pub mod state;
+pub mod terminal;
+pub mod trace;
+
+use callgraph::STACK_LIMIT;
+use opcode::Op;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MEMORY_SIZE: usize = 0x1000;
+pub const PROGRAM_START: u16 = 0x200;
+pub const FONT_START: u16 = 0x000;
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 10;
+
+pub const FONTSET: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineError {
+    pub pc: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepResult {
+    Executed(Op),
+    WaitingForKey,
+}
+
+#[derive(Clone)]
+pub struct Machine {
+    pub memory: Vec<u8>,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub screen: Vec<bool>,
+    pub keys: [bool; 16],
+    pub key_wait: Option<u8>,
+    pub cycles: u64,
+    pub frames: u64,
+    pub cycles_per_frame: u32,
+    pub rng_state: u32,
+}
+
+impl Machine {
+    pub fn new() -> Machine {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() ^ d.as_secs() as u32)
+            .unwrap_or(0);
+        Machine::with_seed(seed)
+    }
+
+    pub fn with_seed(seed: u32) -> Machine {
+        let mut memory = vec![0; MEMORY_SIZE];
+        let font_start = FONT_START as usize;
+        memory[font_start..font_start + FONTSET.len()].copy_from_slice(&FONTSET);
+        Machine {
+            memory,
+            v: [0; 16],
+            i: 0,
+            pc: PROGRAM_START,
+            stack: Vec::new(),
+            delay_timer: 0,
+            sound_timer: 0,
+            screen: vec![false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            keys: [false; 16],
+            key_wait: None,
+            cycles: 0,
+            frames: 0,
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            // Xorshift gets stuck on zero, so nudge it.
+            rng_state: if seed == 0 { 0x1234_5678 } else { seed },
+        }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), MachineError> {
+        let start = PROGRAM_START as usize;
+        if start + rom.len() > MEMORY_SIZE {
+            return Err(MachineError {
+                pc: self.pc,
+                message: format!("ROM of {} bytes does not fit in memory", rom.len()),
+            });
+        }
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        Ok(())
+    }
+
+    pub fn fetch(&self) -> Result<u16, MachineError> {
+        let pc = self.pc as usize;
+        if pc + 1 >= MEMORY_SIZE {
+            return Err(self.error(format!("PC 0x{:03X} is outside of memory", pc)));
+        }
+        Ok((u16::from(self.memory[pc]) << 8) | u16::from(self.memory[pc + 1]))
+    }
+
+    pub fn current_op(&self) -> Result<Op, MachineError> {
+        self.fetch().map(Op::decode)
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.screen[y * SCREEN_WIDTH + x]
+    }
+
+    pub fn next_random(&mut self) -> u8 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+        (x >> 24) as u8
+    }
+
+    fn error(&self, message: String) -> MachineError {
+        MachineError { pc: self.pc, message }
+    }
+
+    fn read_byte(&self, addr: u16) -> Result<u8, MachineError> {
+        self.memory
+            .get(addr as usize)
+            .cloned()
+            .ok_or_else(|| self.error(format!("Read from 0x{:03X} is outside of memory", addr)))
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) -> Result<(), MachineError> {
+        if addr as usize >= MEMORY_SIZE {
+            return Err(self.error(format!("Write to 0x{:03X} is outside of memory", addr)));
+        }
+        self.memory[addr as usize] = value;
+        Ok(())
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.frames += 1;
+    }
+
+    pub fn step(&mut self) -> Result<StepResult, MachineError> {
+        let op = self.current_op()?;
+        let next = self.pc.wrapping_add(2);
+        let mut new_pc = next;
+        let mut waiting = false;
+
+        match op {
+            Op::ClearScreen => {
+                for px in self.screen.iter_mut() {
+                    *px = false;
+                }
+            }
+            Op::Return => {
+                new_pc = self
+                    .stack
+                    .pop()
+                    .ok_or_else(|| self.error("RET with an empty stack".to_owned()))?;
+            }
+            Op::Jump(addr) => new_pc = addr,
+            Op::Call(addr) => {
+                if self.stack.len() >= STACK_LIMIT {
+                    return Err(self.error(format!("Stack overflow: more than {} nested calls", STACK_LIMIT)));
+                }
+                self.stack.push(next);
+                new_pc = addr;
+            }
+            Op::SkipEqualImm(x, kk) => {
+                if self.v[x as usize] == kk {
+                    new_pc += 2;
+                }
+            }
+            Op::SkipNotEqualImm(x, kk) => {
+                if self.v[x as usize] != kk {
+                    new_pc += 2;
+                }
+            }
+            Op::SkipEqualReg(x, y) => {
+                if self.v[x as usize] == self.v[y as usize] {
+                    new_pc += 2;
+                }
+            }
+            Op::SkipNotEqualReg(x, y) => {
+                if self.v[x as usize] != self.v[y as usize] {
+                    new_pc += 2;
+                }
+            }
+            Op::LoadImm(x, kk) => self.v[x as usize] = kk,
+            Op::AddImm(x, kk) => self.v[x as usize] = self.v[x as usize].wrapping_add(kk),
+            Op::LoadReg(x, y) => self.v[x as usize] = self.v[y as usize],
+            Op::Or(x, y) => self.v[x as usize] |= self.v[y as usize],
+            Op::And(x, y) => self.v[x as usize] &= self.v[y as usize],
+            Op::Xor(x, y) => self.v[x as usize] ^= self.v[y as usize],
+            Op::AddReg(x, y) => {
+                let (res, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+                self.v[x as usize] = res;
+                self.v[0xF] = carry as u8;
+            }
+            Op::Sub(x, y) => {
+                let (res, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
+                self.v[x as usize] = res;
+                self.v[0xF] = (!borrow) as u8;
+            }
+            Op::SubN(x, y) => {
+                let (res, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
+                self.v[x as usize] = res;
+                self.v[0xF] = (!borrow) as u8;
+            }
+            Op::ShiftRight(x, _) => {
+                let flag = self.v[x as usize] & 0x1;
+                self.v[x as usize] >>= 1;
+                self.v[0xF] = flag;
+            }
+            Op::ShiftLeft(x, _) => {
+                let flag = self.v[x as usize] >> 7;
+                self.v[x as usize] <<= 1;
+                self.v[0xF] = flag;
+            }
+            Op::LoadI(addr) => self.i = addr,
+            Op::JumpV0(addr) => new_pc = addr.wrapping_add(u16::from(self.v[0])) & 0x0FFF,
+            Op::Rand(x, kk) => self.v[x as usize] = self.next_random() & kk,
+            Op::Draw(x, y, n) => {
+                let start_x = self.v[x as usize] as usize % SCREEN_WIDTH;
+                let start_y = self.v[y as usize] as usize % SCREEN_HEIGHT;
+                let mut collision = false;
+                for row in 0..n as usize {
+                    let py = start_y + row;
+                    if py >= SCREEN_HEIGHT {
+                        break;
+                    }
+                    let sprite = self.read_byte(self.i.wrapping_add(row as u16))?;
+                    for col in 0..8 {
+                        let px = start_x + col;
+                        if px >= SCREEN_WIDTH {
+                            break;
+                        }
+                        if sprite & (0x80 >> col) != 0 {
+                            let cell = &mut self.screen[py * SCREEN_WIDTH + px];
+                            collision |= *cell;
+                            *cell = !*cell;
+                        }
+                    }
+                }
+                self.v[0xF] = collision as u8;
+            }
+            Op::SkipKey(x) => {
+                if self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    new_pc += 2;
+                }
+            }
+            Op::SkipNotKey(x) => {
+                if !self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    new_pc += 2;
+                }
+            }
+            Op::LoadFromTimer(x) => self.v[x as usize] = self.delay_timer,
+            // Like the COSMAC VIP, the key is only taken once it is released again.
+            // Time keeps passing while the machine waits.
+            Op::WaitKey(x) => match self.key_wait {
+                Some(key) if !self.keys[key as usize] => {
+                    self.v[x as usize] = key;
+                    self.key_wait = None;
+                }
+                Some(_) => waiting = true,
+                None => {
+                    self.key_wait = self.keys.iter().position(|&k| k).map(|k| k as u8);
+                    waiting = true;
+                }
+            },
+            Op::LoadTimer(x) => self.delay_timer = self.v[x as usize],
+            Op::LoadAudioTimer(x) => self.sound_timer = self.v[x as usize],
+            Op::AddI(x) => self.i = self.i.wrapping_add(u16::from(self.v[x as usize])),
+            Op::LoadFont(x) => self.i = FONT_START + u16::from(self.v[x as usize] & 0xF) * 5,
+            Op::StoreDigits(x) => {
+                let value = self.v[x as usize];
+                let i = self.i;
+                self.write_byte(i, value / 100)?;
+                self.write_byte(i.wrapping_add(1), (value / 10) % 10)?;
+                self.write_byte(i.wrapping_add(2), value % 10)?;
+            }
+            Op::StoreRegs(x) => {
+                for reg in 0..=x {
+                    let value = self.v[reg as usize];
+                    let addr = self.i.wrapping_add(u16::from(reg));
+                    self.write_byte(addr, value)?;
+                }
+            }
+            Op::LoadRegs(x) => {
+                for reg in 0..=x {
+                    self.v[reg as usize] = self.read_byte(self.i.wrapping_add(u16::from(reg)))?;
+                }
+            }
+            Op::Sys(_) | Op::Unknown(_) => {
+                return Err(self.error(format!("Cannot execute {}", op)));
+            }
+        }
+
+        if !waiting {
+            self.pc = new_pc;
+        }
+        self.cycles += 1;
+        if self.cycles.is_multiple_of(u64::from(self.cycles_per_frame.max(1))) {
+            self.tick_timers();
+        }
+        if waiting {
+            Ok(StepResult::WaitingForKey)
+        } else {
+            Ok(StepResult::Executed(op))
+        }
+    }
+
+    // Runs until the next 60Hz timer tick.
+    pub fn run_frame(&mut self) -> Result<(), MachineError> {
+        let frame = self.frames;
+        while self.frames == frame {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn screen_ascii(&self) -> String {
+        let mut text = String::new();
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                text.push(if self.pixel(x, y) { '#' } else { '.' });
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine::new()
+    }
+}
@@ -0,0 +1,368 @@
+use assembler::{self, Assembly, Options, Syntax};
+use interpreter::{Machine, SCREEN_HEIGHT, SCREEN_WIDTH};
+use png;
+
+use std::fs;
+use std::path::Path;
+
+// Input scripts drive a program frame by frame so that ROMs which normally
+// need someone at the keyboard can be checked automatically:
+//
+//     program ../../src/roms/tapereader.chip8
+//     seed 7
+//     at 1 tap 3
+//     at 5 expect VB == 1
+//     at 5 expect screen blank.txt
+//
+// Everything scheduled for frame N happens, in script order, before frame N
+// runs. Paths are relative to the script.
+
+const DEFAULT_SEED: u32 = 1;
+// Scale used when writing a PNG golden that does not exist yet.
+const PNG_SCALE: usize = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Register(u8),
+    I,
+    Pc,
+    Dt,
+    St,
+    Sp,
+    Memory(Value),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(u16),
+    Label(String),
+    I,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Press(u8),
+    Release(u8),
+    Expect(Target, bool, Value),
+    ExpectScreen(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub frame: u64,
+    pub line: usize,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug)]
+pub struct Script {
+    pub path: String,
+    pub program: Option<String>,
+    pub seed: u32,
+    pub cycles_per_frame: Option<u32>,
+    pub steps: Vec<Step>,
+    // Whether to run the program's `-O` build.
+    pub optimize: bool,
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+pub fn parse_key(text: &str) -> Option<u8> {
+    let key = if text.len() == 1 {
+        u8::from_str_radix(text, 16).ok()
+    } else {
+        parse_number(text).map(|n| n as u8)
+    };
+    key.filter(|&k| k < 16)
+}
+
+fn parse_value(text: &str) -> Value {
+    if text.eq_ignore_ascii_case("I") {
+        return Value::I;
+    }
+    match parse_number(text) {
+        Some(n) => Value::Number(n as u16),
+        None => Value::Label(text.to_uppercase()),
+    }
+}
+
+fn parse_target(text: &str) -> Option<Target> {
+    let upper = text.to_uppercase();
+    if let Some(inner) = upper.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        return Some(Target::Memory(parse_value(inner.trim())));
+    }
+    match upper.as_str() {
+        "I" => Some(Target::I),
+        "PC" => Some(Target::Pc),
+        "DT" => Some(Target::Dt),
+        "ST" => Some(Target::St),
+        "SP" => Some(Target::Sp),
+        _ => upper
+            .strip_prefix('V')
+            .filter(|reg| reg.len() == 1)
+            .and_then(|reg| u8::from_str_radix(reg, 16).ok())
+            .map(Target::Register),
+    }
+}
+
+fn parse_action(words: &[&str]) -> Result<Action, String> {
+    match words {
+        ["press", key] => Ok(Action::Press(parse_key(key).ok_or_else(|| format!("Bad key {}", key))?)),
+        ["release", key] => Ok(Action::Release(parse_key(key).ok_or_else(|| format!("Bad key {}", key))?)),
+        ["expect", "screen", file] => Ok(Action::ExpectScreen(file.to_string())),
+        ["expect", target, op, value] => {
+            let target = parse_target(target).ok_or_else(|| format!("Cannot check {}", target))?;
+            let equal = match *op {
+                "==" => true,
+                "!=" => false,
+                _ => return Err(format!("Expected == or !=, got {}", op)),
+            };
+            Ok(Action::Expect(target, equal, parse_value(value)))
+        }
+        _ => Err(format!("Unknown script command: {}", words.join(" "))),
+    }
+}
+
+impl Script {
+    pub fn parse(path: &str, source: &str) -> Result<Script, String> {
+        let mut script = Script {
+            path: path.to_owned(),
+            program: None,
+            seed: DEFAULT_SEED,
+            cycles_per_frame: None,
+            steps: Vec::new(),
+            optimize: false,
+        };
+
+        for (idx, ln) in source.lines().enumerate() {
+            let line = idx + 1;
+            let ln = ln.split('#').next().unwrap_or("").trim();
+            let words: Vec<&str> = ln.split_whitespace().collect();
+            let err = |message: String| format!("{}:{}: {}", path, line, message);
+            match words.as_slice() {
+                [] => {}
+                ["program", file] => script.program = Some(file.to_string()),
+                ["seed", n] => script.seed = parse_number(n).ok_or_else(|| err(format!("Bad seed {}", n)))?,
+                ["cycles-per-frame", n] => {
+                    let cycles = parse_number(n).filter(|&c| c > 0);
+                    script.cycles_per_frame = Some(cycles.ok_or_else(|| err(format!("Bad cycle count {}", n)))?);
+                }
+                ["at", frame, rest @ ..] => {
+                    let frame = parse_number(frame).ok_or_else(|| err(format!("Bad frame number {}", frame)))?;
+                    let frame = u64::from(frame);
+                    // A tap holds the key for one frame, which is enough for LD Vx, K.
+                    if let ["tap", key] = rest {
+                        let key = parse_key(key).ok_or_else(|| err(format!("Bad key {}", key)))?;
+                        script.steps.push(Step { frame, line, action: Action::Press(key) });
+                        script.steps.push(Step { frame: frame + 1, line, action: Action::Release(key) });
+                        continue;
+                    }
+                    let action = parse_action(rest).map_err(err)?;
+                    script.steps.push(Step { frame, line, action });
+                }
+                _ => return Err(err(format!("Unknown script command: {}", ln))),
+            }
+        }
+
+        // Stable, so same-frame steps keep their script order.
+        script.steps.sort_by_key(|step| step.frame);
+        Ok(script)
+    }
+
+    fn relative(&self, file: &str) -> String {
+        match Path::new(&self.path).parent() {
+            Some(dir) => dir.join(file).to_string_lossy().into_owned(),
+            None => file.to_owned(),
+        }
+    }
+
+    // Runs the script and returns a description of every failed expectation.
+    pub fn run(&self) -> Vec<String> {
+        let program = match self.program {
+            Some(ref program) => self.relative(program),
+            None => return vec![format!("{}: no program given", self.path)],
+        };
+        let options = Options {
+            optimize: self.optimize,
+            ..Options::default()
+        };
+        let assembly = match assembler::assemble_file_opt(&program, Syntax::for_path(&program), &options) {
+            Ok(assembly) => assembly,
+            Err(errors) => return errors.iter().map(|err| err.to_string()).collect(),
+        };
+
+        let mut machine = Machine::with_seed(self.seed);
+        if let Some(cycles) = self.cycles_per_frame {
+            machine.cycles_per_frame = cycles;
+        }
+        if let Err(err) = machine.load_rom(&assembly.code) {
+            return vec![format!("{}: {}", program, err.message)];
+        }
+
+        let mut failures = Vec::new();
+        for step in self.steps.iter() {
+            while machine.frames < step.frame {
+                if let Err(err) = machine.run_frame() {
+                    failures.push(format!(
+                        "{}:{}: the program stopped at 0x{:03X} in frame {}: {}",
+                        self.path, step.line, err.pc, machine.frames, err.message
+                    ));
+                    return failures;
+                }
+            }
+            let result = match step.action {
+                Action::Press(key) => {
+                    machine.keys[key as usize] = true;
+                    Ok(())
+                }
+                Action::Release(key) => {
+                    machine.keys[key as usize] = false;
+                    Ok(())
+                }
+                Action::Expect(ref target, equal, ref value) => check(&machine, &assembly, target, equal, value),
+                Action::ExpectScreen(ref file) => self.check_screen(&machine, file),
+            };
+            if let Err(message) = result {
+                failures.push(format!("{}:{}: frame {}: {}", self.path, step.line, step.frame, message));
+            }
+        }
+        failures
+    }
+
+    fn check_screen(&self, machine: &Machine, file: &str) -> Result<(), String> {
+        let path = self.relative(file);
+        let is_png = path.to_lowercase().ends_with(".png");
+        let golden = match fs::read(&path) {
+            Ok(golden) => golden,
+            Err(_) => {
+                let contents = if is_png { screen_png(machine, PNG_SCALE) } else { machine.screen_ascii().into_bytes() };
+                fs::write(&path, contents).map_err(|err| format!("Could not write {}: {}", path, err))?;
+                return Err(format!("{} did not exist, so it was created from this run; check it and re-run", path));
+            }
+        };
+
+        let (expected, scale) = if is_png {
+            let image = png::decode(&golden).map_err(|err| format!("{}: {}", path, err))?;
+            let scale = image.width / SCREEN_WIDTH;
+            if scale == 0 || image.width != SCREEN_WIDTH * scale || image.height != SCREEN_HEIGHT * scale {
+                return Err(format!(
+                    "{} is {}x{}; screen goldens must be a multiple of {}x{}",
+                    path, image.width, image.height, SCREEN_WIDTH, SCREEN_HEIGHT
+                ));
+            }
+            let mut pixels = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT);
+            for y in 0..SCREEN_HEIGHT {
+                for x in 0..SCREEN_WIDTH {
+                    pixels.push(image.is_lit(x * scale, y * scale));
+                }
+            }
+            (pixels, scale)
+        } else {
+            let text = String::from_utf8_lossy(&golden);
+            let rows: Vec<&str> = text.lines().map(|ln| ln.trim_end()).filter(|ln| !ln.is_empty()).collect();
+            if rows.len() != SCREEN_HEIGHT || rows.iter().any(|row| row.chars().count() != SCREEN_WIDTH) {
+                return Err(format!("{} must hold {} rows of {} characters", path, SCREEN_HEIGHT, SCREEN_WIDTH));
+            }
+            let pixels = rows.iter().flat_map(|row| row.chars().map(|c| c != '.' && c != ' ')).collect();
+            (pixels, 1)
+        };
+
+        let mismatch = (0..SCREEN_HEIGHT)
+            .flat_map(|y| (0..SCREEN_WIDTH).map(move |x| (x, y)))
+            .find(|&(x, y)| expected[y * SCREEN_WIDTH + x] != machine.pixel(x, y));
+        let (x, y) = match mismatch {
+            Some(pos) => pos,
+            None => return Ok(()),
+        };
+
+        let actual_path = actual_path(&path);
+        let contents = if is_png { screen_png(machine, scale) } else { machine.screen_ascii().into_bytes() };
+        fs::write(&actual_path, contents).map_err(|err| format!("Could not write {}: {}", actual_path, err))?;
+        Err(format!(
+            "the screen does not match {} (first difference at {}, {}); this run's screen is in {}",
+            path, x, y, actual_path
+        ))
+    }
+}
+
+fn resolve(machine: &Machine, assembly: &Assembly, value: &Value) -> Result<u16, String> {
+    match *value {
+        Value::Number(n) => Ok(n),
+        Value::I => Ok(machine.i),
+        Value::Label(ref name) => assembly.label_addr(name).ok_or_else(|| format!("Unknown label {}", name)),
+    }
+}
+
+fn check(machine: &Machine, assembly: &Assembly, target: &Target, equal: bool, value: &Value) -> Result<(), String> {
+    let (name, actual) = match *target {
+        Target::Register(reg) => (format!("V{:X}", reg), u16::from(machine.v[reg as usize])),
+        Target::I => ("I".to_owned(), machine.i),
+        Target::Pc => ("PC".to_owned(), machine.pc),
+        Target::Dt => ("DT".to_owned(), u16::from(machine.delay_timer)),
+        Target::St => ("ST".to_owned(), u16::from(machine.sound_timer)),
+        Target::Sp => ("SP".to_owned(), machine.stack.len() as u16),
+        Target::Memory(ref addr) => {
+            let addr = resolve(machine, assembly, addr)?;
+            let byte = machine
+                .memory
+                .get(addr as usize)
+                .ok_or_else(|| format!("0x{:03X} is outside of memory", addr))?;
+            (format!("[0x{:03X}]", addr), u16::from(*byte))
+        }
+    };
+    let expected = resolve(machine, assembly, value)?;
+    if (actual == expected) == equal {
+        return Ok(());
+    }
+    let describe = |n: u16| match assembly.label_for_addr(n) {
+        Some((label, addr)) if addr == n && matches!(*target, Target::Pc | Target::I) => format!("0x{:02X} ({})", n, label),
+        _ => format!("0x{:02X}", n),
+    };
+    Err(format!(
+        "expected {} {} {}, got {}",
+        name,
+        if equal { "==" } else { "!=" },
+        describe(expected),
+        describe(actual)
+    ))
+}
+
+fn actual_path(path: &str) -> String {
+    match path.rfind('.') {
+        Some(dot) if !path[dot..].contains('/') => format!("{}.actual{}", &path[..dot], &path[dot..]),
+        _ => format!("{}.actual", path),
+    }
+}
+
+fn screen_png(machine: &Machine, scale: usize) -> Vec<u8> {
+    let (width, height) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+    let mut gray = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            gray.push(if machine.pixel(x / scale, y / scale) { 0xFF } else { 0x00 });
+        }
+    }
+    png::encode_gray(width, height, &gray)
+}
+
+// Runs a script file, against the `-O` build of its program if `optimize`
+// is set.
+pub fn run_file(path: &str, optimize: bool) -> Vec<String> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => return vec![format!("Could not read {}: {}", path, err)],
+    };
+    match Script::parse(path, &source) {
+        Ok(mut script) => {
+            script.optimize = optimize;
+            script.run()
+        }
+        Err(err) => vec![err],
+    }
+}
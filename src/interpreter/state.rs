@@ -0,0 +1,236 @@
+use callgraph::STACK_LIMIT;
+use interpreter::{Machine, MEMORY_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use std::collections::VecDeque;
+use std::mem;
+
+// Save state files start with the magic and a version byte; everything after
+// is big-endian. Bump the version whenever the layout changes and keep
+// reading the old one.
+pub const STATE_MAGIC: &[u8; 4] = b"C8SS";
+pub const STATE_VERSION: u8 = 1;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + count)
+            .ok_or_else(|| "Save state is truncated".to_owned())?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok((u64::from(self.u32()?) << 32) | u64::from(self.u32()?))
+    }
+}
+
+impl Machine {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = STATE_MAGIC.to_vec();
+        out.push(STATE_VERSION);
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.v);
+        out.extend_from_slice(&self.i.to_be_bytes());
+        out.extend_from_slice(&self.pc.to_be_bytes());
+        out.push(self.stack.len() as u8);
+        for &ret in self.stack.iter() {
+            out.extend_from_slice(&ret.to_be_bytes());
+        }
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        // One bit per pixel, row by row, most significant bit first.
+        for row in self.screen.chunks(8) {
+            out.push(row.iter().fold(0, |acc, &px| (acc << 1) | u8::from(px)));
+        }
+        let keys = self.keys.iter().rev().fold(0u16, |acc, &down| (acc << 1) | u16::from(down));
+        out.extend_from_slice(&keys.to_be_bytes());
+        out.push(self.key_wait.unwrap_or(0xFF));
+        out.extend_from_slice(&self.cycles.to_be_bytes());
+        out.extend_from_slice(&self.frames.to_be_bytes());
+        out.extend_from_slice(&self.cycles_per_frame.to_be_bytes());
+        out.extend_from_slice(&self.rng_state.to_be_bytes());
+        out
+    }
+
+    pub fn load_state(data: &[u8]) -> Result<Machine, String> {
+        if data.len() < 5 || &data[..4] != STATE_MAGIC {
+            return Err("Not a CHIP-8 save state".to_owned());
+        }
+        if data[4] != STATE_VERSION {
+            return Err(format!("Unsupported save state version {}", data[4]));
+        }
+
+        let mut reader = Reader { data, pos: 5 };
+        let mut machine = Machine::with_seed(0);
+        machine.memory = reader.bytes(MEMORY_SIZE)?.to_vec();
+        machine.v.copy_from_slice(reader.bytes(16)?);
+        machine.i = reader.u16()?;
+        machine.pc = reader.u16()?;
+        let depth = reader.u8()?;
+        if usize::from(depth) > STACK_LIMIT {
+            return Err(format!("Save state has {} return addresses, more than the stack holds", depth));
+        }
+        machine.stack = (0..depth).map(|_| reader.u16()).collect::<Result<_, _>>()?;
+        machine.delay_timer = reader.u8()?;
+        machine.sound_timer = reader.u8()?;
+        let screen = reader.bytes(SCREEN_WIDTH * SCREEN_HEIGHT / 8)?;
+        machine.screen = screen
+            .iter()
+            .flat_map(|&byte| (0..8).rev().map(move |bit| byte & (1 << bit) != 0))
+            .collect();
+        let keys = reader.u16()?;
+        for (idx, down) in machine.keys.iter_mut().enumerate() {
+            *down = keys & (1 << idx) != 0;
+        }
+        machine.key_wait = match reader.u8()? {
+            0xFF => None,
+            key if key < 16 => Some(key),
+            key => return Err(format!("Save state waits for key 0x{:02X}, which does not exist", key)),
+        };
+        machine.cycles = reader.u64()?;
+        machine.frames = reader.u64()?;
+        machine.cycles_per_frame = reader.u32()?;
+        machine.rng_state = reader.u32()?;
+        if reader.pos != data.len() {
+            return Err("Save state has trailing data".to_owned());
+        }
+        Ok(machine)
+    }
+}
+
+// The positions where `old` differs from `new`, with the values in `old`.
+fn differences<T: Copy + PartialEq>(new: &[T], old: &[T]) -> Vec<(usize, T)> {
+    new.iter().zip(old.iter()).enumerate().filter(|&(_, (n, o))| n != o).map(|(idx, (_, &o))| (idx, o)).collect()
+}
+
+// What takes a recorded state back to the one recorded before it: the
+// memory bytes and pixels that differ, and everything else whole.
+#[derive(Clone)]
+struct Undo {
+    memory: Vec<(usize, u8)>,
+    screen: Vec<(usize, bool)>,
+    rest: Machine,
+}
+
+impl Undo {
+    // The undo that turns `newer` into `older`.
+    fn between(newer: &Machine, older: &Machine) -> Undo {
+        Undo {
+            memory: differences(&newer.memory, &older.memory),
+            screen: differences(&newer.screen, &older.screen),
+            rest: Machine {
+                memory: Vec::new(),
+                screen: Vec::new(),
+                stack: older.stack.clone(),
+                ..*older
+            },
+        }
+    }
+
+    fn apply(&self, machine: &mut Machine) {
+        for &(addr, byte) in self.memory.iter() {
+            machine.memory[addr] = byte;
+        }
+        for &(idx, px) in self.screen.iter() {
+            machine.screen[idx] = px;
+        }
+        let memory = mem::take(&mut machine.memory);
+        let screen = mem::take(&mut machine.screen);
+        *machine = Machine {
+            memory,
+            screen,
+            ..self.rest.clone()
+        };
+    }
+}
+
+// The most recent machine states. Only the newest is kept whole; each older
+// one is kept as what changed since, so a long history of single steps
+// stays small. Once full, recording a new state drops the oldest one.
+#[derive(Clone)]
+pub struct History {
+    newest: Option<Machine>,
+    // Oldest first; the last one undoes `newest`.
+    undos: VecDeque<Undo>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History {
+            newest: None,
+            undos: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, machine: &Machine) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(newest) = self.newest.take() {
+            if self.undos.len() + 1 == self.capacity {
+                self.undos.pop_front();
+            }
+            if self.capacity > 1 {
+                self.undos.push_back(Undo::between(machine, &newest));
+            }
+        }
+        self.newest = Some(machine.clone());
+    }
+
+    pub fn pop(&mut self) -> Option<Machine> {
+        let newest = self.newest.take()?;
+        if let Some(undo) = self.undos.pop_back() {
+            let mut older = newest.clone();
+            undo.apply(&mut older);
+            self.newest = Some(older);
+        }
+        Some(newest)
+    }
+
+    // `back(0)` is the most recent state.
+    pub fn back(&self, count: usize) -> Option<Machine> {
+        if count >= self.len() {
+            return None;
+        }
+        let mut machine = self.newest.clone()?;
+        for undo in self.undos.iter().rev().take(count) {
+            undo.apply(&mut machine);
+        }
+        Some(machine)
+    }
+
+    pub fn len(&self) -> usize {
+        self.newest.as_ref().map_or(0, |_| self.undos.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.undos.clear();
+    }
+}
@@ -0,0 +1,99 @@
+use interpreter::script::parse_key;
+use interpreter::state::History;
+use interpreter::Machine;
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+
+// A front end for playing a program in a terminal, one command per line:
+//
+//     (empty)       run one frame
+//     run N         run N frames
+//     press K       hold key K down (0-F)
+//     release K     let key K go
+//     back [N]      rewind N frames, 1 if not given
+//     save FILE     write a save state of the machine as it is now
+//     quit
+//
+// The screen and the machine's frame and PC are shown after every command.
+// When the program stops with an error the machine is left as it was, so
+// `back` can return to the frames just before the crash.
+
+// A minute at 60 frames a second.
+pub const REWIND_FRAMES: usize = 3600;
+
+pub fn play<R: BufRead, W: Write>(machine: Machine, input: R, mut out: W) -> io::Result<()> {
+    let mut machine = machine;
+    let mut history = History::new(REWIND_FRAMES);
+    let mut stopped: Option<String> = None;
+    show(&mut out, &machine, None)?;
+    for ln in input.lines() {
+        let ln = ln?;
+        let words: Vec<&str> = ln.split_whitespace().collect();
+        let mut note = None;
+        match words.as_slice() {
+            [] | ["run"] | ["run", _] => {
+                let frames = match words.get(1).map(|count| count.parse::<u64>()) {
+                    None => Ok(1),
+                    Some(Ok(frames)) => Ok(frames),
+                    Some(Err(_)) => Err(format!("run takes a number of frames, not {}", words[1])),
+                };
+                match (frames, stopped.as_ref()) {
+                    (Err(message), _) => note = Some(message),
+                    (Ok(_), Some(message)) => note = Some(format!("{}; use back to rewind", message)),
+                    (Ok(frames), None) => {
+                        for _ in 0..frames {
+                            history.push(&machine);
+                            if let Err(err) = machine.run_frame() {
+                                let message = format!("Stopped at 0x{:03X} in frame {}: {}", err.pc, machine.frames, err.message);
+                                stopped = Some(message.clone());
+                                note = Some(message);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            ["press", key] | ["release", key] => match parse_key(key) {
+                Some(key) => machine.keys[key as usize] = words[0] == "press",
+                None => note = Some(format!("{} is not a key; keys are 0 to F", key)),
+            },
+            ["back"] | ["back", _] => {
+                let count = match words.get(1) {
+                    Some(count) => count.parse::<usize>().ok().filter(|&count| count > 0),
+                    None => Some(1),
+                };
+                match count {
+                    Some(count) if count <= history.len() => {
+                        for _ in 0..count {
+                            machine = history.pop().unwrap();
+                        }
+                        stopped = None;
+                    }
+                    Some(_) => note = Some(format!("Only {} frames can be rewound", history.len())),
+                    None => note = Some(format!("back takes a number of frames, not {}", words[1])),
+                }
+            }
+            ["save", path] => {
+                note = Some(match fs::write(path, machine.save_state()) {
+                    Ok(()) => format!("Saved the state to {}", path),
+                    Err(err) => format!("Could not write {}: {}", path, err),
+                });
+            }
+            ["quit"] => break,
+            _ => note = Some(format!("Unknown command: {}", ln.trim())),
+        }
+        show(&mut out, &machine, note.as_deref())?;
+    }
+    Ok(())
+}
+
+fn show<W: Write>(out: &mut W, machine: &Machine, note: Option<&str>) -> io::Result<()> {
+    write!(out, "{}", machine.screen_ascii())?;
+    writeln!(out, "Frame {}, PC 0x{:03X}", machine.frames, machine.pc)?;
+    if let Some(note) = note {
+        writeln!(out, "{}", note)?;
+    }
+    out.flush()
+}
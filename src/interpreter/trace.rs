@@ -0,0 +1,299 @@
+use assembler::Assembly;
+use instructions::parameters::OpParam;
+use interpreter::Machine;
+use json::JsonValue;
+use opcode::Op;
+use structured::is_generated_label;
+
+use std::io;
+use std::io::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    JsonLines,
+}
+
+impl TraceFormat {
+    pub fn parse(name: &str) -> Option<TraceFormat> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "json" | "jsonl" => Some(TraceFormat::JsonLines),
+            _ => None,
+        }
+    }
+
+    pub fn for_path(path: &str) -> TraceFormat {
+        if path.ends_with(".jsonl") || path.ends_with(".json") {
+            TraceFormat::JsonLines
+        } else {
+            TraceFormat::Text
+        }
+    }
+}
+
+// Half-open address ranges; an empty filter traces everything.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    pub ranges: Vec<(u16, u16)>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, addr: u16) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|&(start, end)| addr >= start && addr < end)
+    }
+
+    pub fn add_range(&mut self, spec: &str) -> Result<(), String> {
+        let mut parts = spec.splitn(2, ':');
+        let start = parse_addr(parts.next().unwrap_or(""));
+        let end = parse_addr(parts.next().unwrap_or(""));
+        match (start, end) {
+            (Some(start), Some(end)) if start < end => {
+                self.ranges.push((start, end));
+                Ok(())
+            }
+            _ => Err(format!("Expected an address range like 0x200:0x240, got {}", spec)),
+        }
+    }
+
+    // A label covers everything from it up to the next label.
+    pub fn add_label(&mut self, assembly: &Assembly, name: &str) -> Result<(), String> {
+        let start = assembly
+            .label_addr(name)
+            .ok_or_else(|| format!("Unknown label {}", name))?;
+        let end = assembly
+            .labels
+            .iter()
+            .filter(|(label, _)| match label {
+                OpParam::Label(name) => !is_generated_label(name),
+                _ => true,
+            })
+            .filter_map(|(_, value)| match *value {
+                OpParam::Variable(addr) if addr > start => Some(addr),
+                _ => None,
+            })
+            .min()
+            .unwrap_or(0x1000);
+        self.ranges.push((start, end));
+        Ok(())
+    }
+}
+
+fn parse_addr(text: &str) -> Option<u16> {
+    let text = text.trim();
+    if text.starts_with("0x") || text.starts_with("0X") {
+        u16::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub op: Op,
+    pub location: Option<String>,
+    // The disassembly with its address written as a label, like `JP LOOP`.
+    pub asm_label: Option<String>,
+    pub changed: Vec<(String, u16)>,
+    pub i: u16,
+    // I as a label, when it points into the program.
+    pub i_label: Option<String>,
+    pub stack_depth: usize,
+}
+
+// An address as the label it falls under: `LOOP`, or `LOOP+0x4` past it.
+fn label_relative(assembly: &Assembly, addr: u16) -> Option<String> {
+    assembly.label_for_addr(addr).map(|(name, laddr)| {
+        if laddr == addr {
+            name.to_owned()
+        } else {
+            format!("{}+0x{:X}", name, addr - laddr)
+        }
+    })
+}
+
+impl TraceRecord {
+    // Builds the record for the instruction that took `before` to `after`.
+    pub fn new(before: &Machine, after: &Machine, opcode: u16, assembly: Option<&Assembly>) -> TraceRecord {
+        let mut changed = Vec::new();
+        for reg in 0..16 {
+            if before.v[reg] != after.v[reg] {
+                changed.push((format!("V{:X}", reg), u16::from(after.v[reg])));
+            }
+        }
+        if before.i != after.i {
+            changed.push(("I".to_owned(), after.i));
+        }
+        if before.delay_timer != after.delay_timer {
+            changed.push(("DT".to_owned(), u16::from(after.delay_timer)));
+        }
+        if before.sound_timer != after.sound_timer {
+            changed.push(("ST".to_owned(), u16::from(after.sound_timer)));
+        }
+
+        let op = Op::decode(opcode);
+        let location = assembly.and_then(|asm| label_relative(asm, before.pc));
+        let target = match op {
+            Op::Jump(addr) | Op::Call(addr) | Op::LoadI(addr) | Op::JumpV0(addr) => Some(addr),
+            _ => None,
+        };
+        let asm_label = target.and_then(|addr| {
+            let name = label_relative(assembly?, addr)?;
+            Some(op.to_string().replace(&format!("0x{:03X}", addr), &name))
+        });
+        let i_label = assembly.and_then(|asm| label_relative(asm, after.i));
+
+        TraceRecord {
+            cycle: before.cycles,
+            pc: before.pc,
+            opcode,
+            op,
+            location,
+            asm_label,
+            changed,
+            i: after.i,
+            i_label,
+            stack_depth: after.stack.len(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let changed: Vec<String> = self
+            .changed
+            .iter()
+            .map(|(name, value)| format!("{}=0x{:02X}", name, value))
+            .collect();
+        format!(
+            "{:>8}  0x{:03X}  {:04X}  {:<18} {:<20} I=0x{:03X} SP={} {}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            self.op.to_string(),
+            self.location.clone().unwrap_or_default(),
+            self.i,
+            self.stack_depth,
+            changed.join(" ")
+        )
+        .trim_end()
+        .to_owned()
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let changed = self
+            .changed
+            .iter()
+            .map(|(name, value)| (name.clone(), JsonValue::from(*value)))
+            .collect();
+        JsonValue::object(vec![
+            ("cycle", JsonValue::Number(self.cycle as f64)),
+            ("pc", self.pc.into()),
+            ("opcode", self.opcode.into()),
+            ("asm", self.op.to_string().into()),
+            ("asm_label", self.asm_label.clone().into()),
+            ("label", self.location.clone().into()),
+            ("changed", JsonValue::Object(changed)),
+            ("i", self.i.into()),
+            ("i_label", self.i_label.clone().into()),
+            ("sp", self.stack_depth.into()),
+        ])
+    }
+}
+
+pub struct Tracer<W: Write> {
+    out: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W, format: TraceFormat, filter: TraceFilter) -> Tracer<W> {
+        Tracer { out, format, filter }
+    }
+
+    pub fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if !self.filter.matches(record.pc) {
+            return Ok(());
+        }
+        match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", record.to_text()),
+            TraceFormat::JsonLines => writeln!(self.out, "{}", record.to_json()),
+        }
+    }
+}
+
+// The parts of a record two traces are compared on. When both records carry
+// a label, addresses in the disassembly and I are compared as labels, and
+// the PC is left out, so builds that only moved code around still line up.
+fn compared(record: &JsonValue, labelled: bool) -> Vec<(&'static str, JsonValue)> {
+    let field = |name: &str| record.get(name).cloned().unwrap_or(JsonValue::Null);
+    let symbolic = |value: JsonValue, name: &str| match record.get(name) {
+        Some(label) if labelled && label.as_str().is_some() => label.clone(),
+        _ => value,
+    };
+    let changed = match record.get("changed") {
+        Some(JsonValue::Object(changed)) => {
+            let changed = changed
+                .iter()
+                .map(|(reg, value)| match reg.as_str() {
+                    "I" => (reg.clone(), symbolic(value.clone(), "i_label")),
+                    _ => (reg.clone(), value.clone()),
+                })
+                .collect();
+            JsonValue::Object(changed)
+        }
+        _ => field("changed"),
+    };
+    let mut fields = vec![
+        ("label", field("label")),
+        ("asm", symbolic(field("asm"), "asm_label")),
+        ("changed", changed),
+        ("sp", field("sp")),
+    ];
+    if !labelled {
+        fields.push(("pc", field("pc")));
+    }
+    fields
+}
+
+// Compares two JSON-lines traces and describes the first record where they
+// diverge; cycle counts are ignored.
+pub fn diff_traces(left: &str, right: &str) -> Result<Option<String>, String> {
+    let parse = |text: &str| -> Result<Vec<JsonValue>, String> {
+        text.lines()
+            .filter(|ln| !ln.trim().is_empty())
+            .map(|ln| JsonValue::parse(ln).map_err(|err| err.0))
+            .collect()
+    };
+    let left = parse(left)?;
+    let right = parse(right)?;
+
+    for (idx, (l, r)) in left.iter().zip(right.iter()).enumerate() {
+        let labelled = l.str_field("label").is_some() && r.str_field("label").is_some();
+        let differing: Vec<&str> = compared(l, labelled)
+            .into_iter()
+            .zip(compared(r, labelled))
+            .filter(|(l, r)| l != r)
+            .map(|((name, _), _)| name)
+            .collect();
+        if !differing.is_empty() {
+            return Ok(Some(format!(
+                "Traces diverge at record {} ({}):\n< {}\n> {}",
+                idx + 1,
+                differing.join(", "),
+                l,
+                r
+            )));
+        }
+    }
+    if left.len() != right.len() {
+        return Ok(Some(format!(
+            "Traces agree for {} records, then one ends ({} vs {} records)",
+            left.len().min(right.len()),
+            left.len(),
+            right.len()
+        )));
+    }
+    Ok(None)
+}
@@ -0,0 +1,354 @@
+use assembler::{addresses, layout, Item, SourceItem};
+use instructions::{InstructionOps, InstructionOpsWithLabels};
+use opcode::Op;
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::ops::Range;
+
+// The assembled items split into basic blocks, with the control-flow graph
+// between them. A block starts at a label, at the target of a jump or skip,
+// and after a jump, skip or RET; skips are two-way branches, to the next
+// instruction and to the one after it. Edges are found by address, so
+// `JP 0x200` works as well as `JP MAIN`.
+
+// How control gets from one block to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    // Falling through, which includes a skip that did not skip.
+    Next,
+    // A skip that did.
+    Skip,
+    Jump,
+}
+
+#[derive(Clone, Debug)]
+pub struct Block {
+    // The items of the block: its labels, then instructions or data.
+    pub items: Range<usize>,
+    pub addr: u16,
+    pub succs: Vec<(usize, Edge)>,
+    pub preds: Vec<usize>,
+    // Whether control can also leave for code no block covers, through RET,
+    // `JP V0`, a jump out of the program or running off its end.
+    pub exits: bool,
+}
+
+// A set of registers: bit N is VN, and bit 16 is I.
+pub type Regs = u32;
+
+pub const REG_I: Regs = 1 << 16;
+pub const ALL_REGS: Regs = 0x1FFFF;
+
+pub fn reg(x: u8) -> Regs {
+    1 << x
+}
+
+// V0 up to and including Vx.
+fn regs_upto(x: u8) -> Regs {
+    (2 << x) - 1
+}
+
+// The registers an instruction reads and the ones it writes. A CALL may
+// read anything and is not counted as writing anything.
+pub fn effects(op: &Op) -> (Regs, Regs) {
+    let vf = reg(0xF);
+    match *op {
+        Op::ClearScreen | Op::Jump(_) => (0, 0),
+        Op::Return | Op::Call(_) | Op::Sys(_) | Op::Unknown(_) => (ALL_REGS, 0),
+        Op::SkipEqualImm(x, _) | Op::SkipNotEqualImm(x, _) | Op::SkipKey(x) | Op::SkipNotKey(x) => (reg(x), 0),
+        Op::SkipEqualReg(x, y) | Op::SkipNotEqualReg(x, y) => (reg(x) | reg(y), 0),
+        Op::LoadImm(x, _) | Op::Rand(x, _) | Op::LoadFromTimer(x) | Op::WaitKey(x) => (0, reg(x)),
+        Op::AddImm(x, _) => (reg(x), reg(x)),
+        Op::LoadReg(x, y) => (reg(y), reg(x)),
+        Op::Or(x, y) | Op::And(x, y) | Op::Xor(x, y) => (reg(x) | reg(y), reg(x)),
+        Op::AddReg(x, y) | Op::Sub(x, y) | Op::SubN(x, y) => (reg(x) | reg(y), reg(x) | vf),
+        Op::ShiftRight(x, _) | Op::ShiftLeft(x, _) => (reg(x), reg(x) | vf),
+        Op::LoadI(_) => (0, REG_I),
+        Op::JumpV0(_) => (reg(0), 0),
+        Op::Draw(x, y, _) => (reg(x) | reg(y) | REG_I, vf),
+        Op::LoadTimer(x) | Op::LoadAudioTimer(x) => (reg(x), 0),
+        Op::AddI(x) => (reg(x) | REG_I, REG_I),
+        Op::LoadFont(x) => (reg(x), REG_I),
+        Op::StoreDigits(x) => (reg(x) | REG_I, 0),
+        Op::StoreRegs(x) => (regs_upto(x) | REG_I, 0),
+        Op::LoadRegs(x) => (REG_I, regs_upto(x)),
+    }
+}
+
+pub struct Program {
+    pub items: Vec<SourceItem>,
+    pub addrs: Vec<u16>,
+    // The decoded instruction of each item, when its labels resolve.
+    pub ops: Vec<Option<Op>>,
+    pub blocks: Vec<Block>,
+}
+
+// Where control can go after an instruction at `addr`: the successors by
+// address, and whether it can also leave.
+fn flow(op: Option<&Op>, addr: u16) -> (Vec<(u16, Edge)>, bool) {
+    match op {
+        Some(&Op::Jump(target)) => (vec![(target, Edge::Jump)], false),
+        Some(&Op::JumpV0(_)) | Some(&Op::Return) | None => (Vec::new(), true),
+        Some(op) if op.is_skip() => (vec![(addr + 2, Edge::Next), (addr + 4, Edge::Skip)], false),
+        Some(_) => (vec![(addr + 2, Edge::Next)], false),
+    }
+}
+
+fn ends_block(op: Option<&Op>) -> bool {
+    match op {
+        Some(op) => op.is_skip() || matches!(op, Op::Jump(_) | Op::JumpV0(_) | Op::Return),
+        None => true,
+    }
+}
+
+impl Program {
+    pub fn new(items: Vec<SourceItem>) -> Program {
+        let addrs = addresses(&items);
+        let labels = layout(&items);
+        let ops: Vec<Option<Op>> = items
+            .iter()
+            .map(|item| match item.item {
+                Item::Instr(ref instr) => {
+                    let resolved = instr.resolve_labels(&labels);
+                    if resolved.label_refs().is_empty() {
+                        Some(Op::decode(resolved.to_opcode()))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut targets = HashSet::new();
+        for (idx, item) in items.iter().enumerate() {
+            if let Item::Instr(_) = item.item {
+                for (target, edge) in flow(ops[idx].as_ref(), addrs[idx]).0 {
+                    if edge != Edge::Next {
+                        targets.insert(target);
+                    }
+                }
+            }
+        }
+
+        let mut starts = Vec::new();
+        let mut prev: Option<&Item> = None;
+        let mut ended = false;
+        for (idx, item) in items.iter().enumerate() {
+            let starts_here = match (prev, &item.item) {
+                (None, _) => true,
+                (Some(&Item::Label(_)), _) => false,
+                (_, &Item::Label(_)) => true,
+                (Some(&Item::Data(_)), &Item::Data(_)) => false,
+                (Some(&Item::Data(_)), _) | (_, &Item::Data(_)) => true,
+                (_, &Item::Instr(_)) => ended || targets.contains(&addrs[idx]),
+            };
+            if starts_here {
+                starts.push(idx);
+            }
+            ended = match item.item {
+                Item::Instr(_) => ends_block(ops[idx].as_ref()),
+                Item::Data(_) => false,
+                Item::Label(_) => ended,
+            };
+            prev = Some(&item.item);
+        }
+
+        let mut blocks: Vec<Block> = starts
+            .iter()
+            .enumerate()
+            .map(|(idx, &start)| {
+                let end = starts.get(idx + 1).cloned().unwrap_or(items.len());
+                Block {
+                    items: start..end,
+                    addr: addrs[start],
+                    succs: Vec::new(),
+                    preds: Vec::new(),
+                    exits: false,
+                }
+            })
+            .collect();
+
+        let mut program = Program {
+            items,
+            addrs,
+            ops,
+            blocks: Vec::new(),
+        };
+        for idx in 0..blocks.len() {
+            let (succs, exits) = program.successors(&blocks, idx);
+            blocks[idx].succs = succs;
+            blocks[idx].exits = exits;
+        }
+        for idx in 0..blocks.len() {
+            for (succ, _) in blocks[idx].succs.clone() {
+                if !blocks[succ].preds.contains(&idx) {
+                    blocks[succ].preds.push(idx);
+                }
+            }
+        }
+        program.blocks = blocks;
+        program
+    }
+
+    // The registers live after each item. Whatever leaves the known code,
+    // and any data that gets run, is assumed to need every register.
+    pub fn live_out(&self) -> Vec<Regs> {
+        let uses = |item: usize| match (&self.items[item].item, self.ops[item]) {
+            (&Item::Instr(_), Some(ref op)) => effects(op),
+            (&Item::Instr(_), None) | (&Item::Data(_), _) => (ALL_REGS, 0),
+            (&Item::Label(_), _) => (0, 0),
+        };
+        let live_in = |block: &Block, out: Regs| {
+            block.items.clone().rev().fold(out, |live, item| {
+                let (read, written) = uses(item);
+                (live & !written) | read
+            })
+        };
+
+        let mut block_in = vec![0; self.blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for idx in (0..self.blocks.len()).rev() {
+                let live = live_in(&self.blocks[idx], self.block_live_out(idx, &block_in));
+                if live != block_in[idx] {
+                    block_in[idx] = live;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut live = vec![0; self.items.len()];
+        for (idx, block) in self.blocks.iter().enumerate() {
+            let mut out = self.block_live_out(idx, &block_in);
+            for item in block.items.clone().rev() {
+                live[item] = out;
+                let (read, written) = uses(item);
+                out = (out & !written) | read;
+            }
+        }
+        live
+    }
+
+    fn block_live_out(&self, idx: usize, block_in: &[Regs]) -> Regs {
+        let block = &self.blocks[idx];
+        let exits = if block.exits { ALL_REGS } else { 0 };
+        block.succs.iter().fold(exits, |live, &(succ, _)| live | block_in[succ])
+    }
+
+    pub fn into_items(self) -> Vec<SourceItem> {
+        self.items
+    }
+
+    fn successors(&self, blocks: &[Block], idx: usize) -> (Vec<(usize, Edge)>, bool) {
+        let block = &blocks[idx];
+        if self.is_data(block) {
+            return (Vec::new(), false);
+        }
+        let last = block.items.clone().rev().find(|&item| matches!(self.items[item].item, Item::Instr(_)));
+        let (targets, mut exits) = match last {
+            Some(item) => flow(self.ops[item].as_ref(), self.addrs[item]),
+            // Only labels, at the end of the program.
+            None => (vec![(block.addr, Edge::Next)], false),
+        };
+        let mut succs = Vec::new();
+        for (addr, edge) in targets {
+            match blocks.iter().position(|block| block.addr == addr && !self.is_empty(block)) {
+                Some(succ) => succs.push((succ, edge)),
+                None => exits = true,
+            }
+        }
+        (succs, exits)
+    }
+
+    fn is_empty(&self, block: &Block) -> bool {
+        self.items[block.items.clone()].iter().all(|item| matches!(item.item, Item::Label(_)))
+    }
+
+    pub fn is_data(&self, block: &Block) -> bool {
+        self.items[block.items.clone()].iter().any(|item| matches!(item.item, Item::Data(_)))
+    }
+
+    pub fn labels(&self, block: &Block) -> Vec<&str> {
+        self.items[block.items.clone()]
+            .iter()
+            .filter_map(|item| match item.item {
+                Item::Label(ref name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The block holding the code or data at an address.
+    pub fn block_at(&self, addr: u16) -> Option<usize> {
+        self.blocks.iter().position(|block| block.addr == addr && !self.is_empty(block))
+    }
+
+    // The block that starts with the given label.
+    pub fn block_for_label(&self, name: &str) -> Option<usize> {
+        let name = name.to_uppercase();
+        self.blocks.iter().position(|block| self.labels(block).contains(&name.as_str()))
+    }
+
+    // The blocks reachable from `start` without following calls, in order.
+    pub fn reachable(&self, start: usize) -> Vec<usize> {
+        let mut seen = vec![false; self.blocks.len()];
+        let mut pending = vec![start];
+        while let Some(idx) = pending.pop() {
+            if !seen[idx] {
+                seen[idx] = true;
+                pending.extend(self.blocks[idx].succs.iter().map(|&(succ, _)| succ));
+            }
+        }
+        (0..self.blocks.len()).filter(|&idx| seen[idx]).collect()
+    }
+
+    // A Graphviz graph of the given blocks, with one node per block listing
+    // its labels and instructions.
+    pub fn to_dot(&self, name: &str, blocks: &[usize]) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph \"{}\" {{", escape(name)).unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        let mut exits = false;
+        for &idx in blocks {
+            let block = &self.blocks[idx];
+            let mut text = String::new();
+            for item in block.items.clone() {
+                let addr = self.addrs[item];
+                match self.items[item].item {
+                    Item::Label(ref name) => text.push_str(&format!("{}:\\l", escape(name))),
+                    Item::Instr(_) => {
+                        let instr = match self.ops[item] {
+                            Some(ref op) => op.to_string(),
+                            None => self.items[item].text.trim().to_owned(),
+                        };
+                        text.push_str(&format!("0x{:03X}  {}\\l", addr, escape(&instr)));
+                    }
+                    Item::Data(ref bytes) => text.push_str(&format!("0x{:03X}  {} bytes of data\\l", addr, bytes.len())),
+                }
+            }
+            writeln!(out, "    b{} [label=\"{}\"];", idx, text).unwrap();
+            for &(succ, edge) in block.succs.iter() {
+                match edge {
+                    Edge::Skip => writeln!(out, "    b{} -> b{} [label=\"skip\"];", idx, succ).unwrap(),
+                    _ => writeln!(out, "    b{} -> b{};", idx, succ).unwrap(),
+                }
+            }
+            if block.exits {
+                writeln!(out, "    b{} -> exit [style=dashed];", idx).unwrap();
+                exits = true;
+            }
+        }
+        if exits {
+            writeln!(out, "    exit [shape=plaintext];").unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,96 @@
+use expr::{BinaryOp, UnaryOp};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeName {
+    Byte,
+    Bool,
+    Struct(String),
+}
+
+// A type as written in a declaration: `byte`, `Pos`, or an array such as
+// `byte grid[16]` with its length.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeSpec {
+    pub name: TypeName,
+    pub len: Option<Expr>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Init {
+    Expr(Expr),
+    // The bytes of an array or struct, in memory order.
+    List(Vec<Expr>),
+}
+
+#[derive(Clone, Debug)]
+pub struct VarDecl {
+    pub line: usize,
+    pub name: String,
+    pub ty: TypeSpec,
+    pub init: Option<Init>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub name: String,
+    pub ty: TypeSpec,
+}
+
+#[derive(Clone, Debug)]
+pub struct Param {
+    pub name: String,
+    pub ty: TypeName,
+}
+
+#[derive(Clone, Debug)]
+pub struct FuncDecl {
+    pub line: usize,
+    pub name: String,
+    pub params: Vec<Param>,
+    // None for `void`.
+    pub result: Option<TypeName>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Decl {
+    Const { line: usize, name: String, value: Expr },
+    Var(VarDecl),
+    Struct { line: usize, name: String, fields: Vec<Field> },
+    Sprite { line: usize, name: String, rows: Vec<Expr> },
+    Func(FuncDecl),
+}
+
+#[derive(Clone, Debug)]
+pub enum StmtKind {
+    Var(VarDecl),
+    // `target = value`, or `target op= value` when there is an operator.
+    Assign { target: Expr, op: Option<BinaryOp>, value: Expr },
+    // A call made for its effect.
+    Call(Expr),
+    If { cond: Expr, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+    While { cond: Expr, body: Vec<Stmt> },
+    For { init: Option<Box<Stmt>>, cond: Option<Expr>, step: Option<Box<Stmt>>, body: Vec<Stmt> },
+    Break,
+    Continue,
+    Return(Option<Expr>),
+    Asm(Vec<(usize, String)>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Stmt {
+    pub line: usize,
+    pub kind: StmtKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(u16),
+    Bool(bool),
+    Name(String),
+    Index(Box<Expr>, Box<Expr>),
+    Field(Box<Expr>, String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
@@ -0,0 +1,389 @@
+use assembler::{Item, SourceItem, SourceLoc};
+use expr::{BinaryOp, UnaryOp};
+use instructions::bitops::{And, Or, Rand, ShiftLeft, ShiftRight, Xor};
+use instructions::display::{ClearScreen, Draw};
+use instructions::flow::{Call, Jump, Return, SkipIfKey, SkipIfNotEqual};
+use instructions::loads::Load;
+use instructions::math::{Add, Sub, SubN};
+use instructions::parameters::OpParam;
+use instructions::*;
+use lang::ir::{Address, Function, Op, Operand, Program, Temp};
+use structured::{Condition, GENERATED_PREFIX};
+
+const TRANSFER: OpParam = OpParam::Register(0);
+const FLAGS: OpParam = OpParam::Register(0xF);
+const HALT: &str = "@HALT";
+
+fn reg(temp: Temp) -> OpParam {
+    OpParam::Register(temp as u8 + 1)
+}
+
+fn byte(value: u8) -> OpParam {
+    OpParam::Variable(u16::from(value))
+}
+
+fn param(operand: &Operand) -> OpParam {
+    match *operand {
+        Operand::Temp(temp) => reg(temp),
+        Operand::Const(value) => byte(value),
+    }
+}
+
+fn load(dest: OpParam, src: OpParam) -> Instruction {
+    Instruction::Load(Load::new(dest, src))
+}
+
+fn jump(label: &str) -> Instruction {
+    Instruction::Jump(Jump::new(OpParam::Label(label.to_owned())))
+}
+
+// The label for a byte inside a variable: the variable's own label, or one
+// placed that many bytes into its data.
+fn offset_label(label: &str, offset: u16) -> String {
+    if offset == 0 {
+        label.to_owned()
+    } else if label.starts_with(GENERATED_PREFIX) {
+        format!("{}+{}", label, offset)
+    } else {
+        format!("{}{}+{}", GENERATED_PREFIX, label, offset)
+    }
+}
+
+struct Codegen<'a> {
+    file: &'a str,
+    lines: Vec<&'a str>,
+    items: Vec<SourceItem>,
+    line: usize,
+    func: String,
+    next_label: usize,
+    // Labels needed inside variables, and the registers each function saves
+    // around its calls.
+    offsets: Vec<(String, u16)>,
+    saves: Vec<(String, usize, Temp)>,
+}
+
+// Encodes a lowered program as assembler items: a call to main, then the
+// functions, then memory.
+pub fn generate(program: &Program, file: &str, source: &str) -> Vec<SourceItem> {
+    let mut gen = Codegen {
+        file,
+        lines: source.lines().collect(),
+        items: Vec::new(),
+        line: 1,
+        func: String::new(),
+        next_label: 0,
+        offsets: Vec::new(),
+        saves: Vec::new(),
+    };
+    if let Some(&(line, _)) = program.functions.iter().find(|function| function.name == "MAIN").and_then(|main| main.ops.first()) {
+        gen.line = line;
+    }
+    gen.emit(Instruction::Call(Call::new(OpParam::Label("MAIN".to_owned()))));
+    gen.label(HALT);
+    gen.emit(jump(HALT));
+    for function in program.functions.iter() {
+        gen.function(function);
+    }
+
+    let saves = gen.saves.clone();
+    for (func, line, live) in saves {
+        gen.line = line;
+        gen.label(&save_label(&func));
+        gen.data(vec![0; live + 1]);
+    }
+    for &(line, ref label, ref bytes) in program.data.iter() {
+        gen.line = line;
+        let mut cuts: Vec<u16> = gen.offsets.iter().filter(|entry| entry.0 == *label).map(|entry| entry.1).collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        let mut start = 0;
+        gen.label(label);
+        for cut in cuts {
+            gen.data(bytes[start..cut as usize].to_vec());
+            gen.label(&offset_label(label, cut));
+            start = cut as usize;
+        }
+        gen.data(bytes[start..].to_vec());
+    }
+    gen.items
+}
+
+fn save_label(func: &str) -> String {
+    format!("{}{}:SAVE", GENERATED_PREFIX, func)
+}
+
+impl<'a> Codegen<'a> {
+    fn push(&mut self, item: Item) {
+        self.items.push(SourceItem {
+            loc: SourceLoc {
+                file: self.file.to_owned(),
+                line: self.line,
+            },
+            text: self.lines.get(self.line.wrapping_sub(1)).map_or(String::new(), |ln| ln.to_string()),
+            item,
+        });
+    }
+
+    fn emit(&mut self, instr: Instruction) {
+        self.push(Item::Instr(instr));
+    }
+
+    fn emit_all(&mut self, instrs: Vec<Instruction>) {
+        for instr in instrs {
+            self.emit(instr);
+        }
+    }
+
+    fn label(&mut self, name: &str) {
+        self.push(Item::Label(name.to_owned()));
+    }
+
+    fn data(&mut self, bytes: Vec<u8>) {
+        if !bytes.is_empty() {
+            self.push(Item::Data(bytes));
+        }
+    }
+
+    fn fresh_label(&mut self) -> String {
+        self.next_label += 1;
+        format!("{}{}:OP{}", GENERATED_PREFIX, self.func, self.next_label)
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.func = function.name.clone();
+        self.next_label = 0;
+        for &(line, ref op) in function.ops.iter() {
+            self.line = line;
+            self.op(op);
+        }
+    }
+
+    // Points I at an address.
+    fn address(&mut self, addr: &Address) {
+        if addr.offset != 0 {
+            self.offsets.push((addr.label.clone(), addr.offset));
+        }
+        let label = offset_label(&addr.label, addr.offset);
+        self.emit(load(OpParam::RegisterI, OpParam::Label(label)));
+        if let Some(index) = addr.index {
+            self.emit(Instruction::Add(Add::new(OpParam::RegisterI, reg(index))));
+        }
+    }
+
+    // A register holding the operand; constants go through V0.
+    fn in_register(&mut self, operand: &Operand) -> OpParam {
+        match *operand {
+            Operand::Temp(temp) => reg(temp),
+            Operand::Const(value) => {
+                self.emit(load(TRANSFER, byte(value)));
+                TRANSFER
+            }
+        }
+    }
+
+    fn op(&mut self, op: &Op) {
+        match *op {
+            Op::Label(ref name) => self.label(name),
+            Op::Jump(ref target) => self.emit(jump(target)),
+            Op::JumpUnless { op, left, ref right, ref target } => {
+                let condition = Condition::new(reg(left), op.symbol(), param(right)).expect("comparisons are lowered to registers");
+                self.emit_all(condition.skip());
+                self.emit(jump(target));
+            }
+            Op::Move { dst, ref src } => {
+                if *src != Operand::Temp(dst) {
+                    self.emit(load(reg(dst), param(src)));
+                }
+            }
+            Op::Load { dst, ref addr } => {
+                self.address(addr);
+                self.emit(load(TRANSFER, OpParam::DerefI));
+                self.emit(load(reg(dst), TRANSFER));
+            }
+            Op::Store { ref addr, src } => {
+                self.address(addr);
+                self.emit(load(TRANSFER, reg(src)));
+                self.emit(load(OpParam::DerefI, TRANSFER));
+            }
+            Op::Unary { op, dst } => {
+                let (mask, instr) = match op {
+                    UnaryOp::Neg => (0, Instruction::SubN(SubN::new(reg(dst), TRANSFER))),
+                    UnaryOp::Not => (1, Instruction::Xor(Xor::new(reg(dst), TRANSFER))),
+                    UnaryOp::Complement => (0xFF, Instruction::Xor(Xor::new(reg(dst), TRANSFER))),
+                };
+                self.emit(load(TRANSFER, byte(mask)));
+                self.emit(instr);
+            }
+            Op::Binary { op, dst, ref src, scratch } => self.binary(op, dst, src, scratch),
+            Op::Call { ref func, live, dst } => {
+                // V0 to the newest live temporary are saved; the result is
+                // parked where V0 was saved, so restoring puts it in V0.
+                let save = OpParam::Label(save_label(&self.func));
+                if live > 0 {
+                    let (func, line) = (self.func.clone(), self.line);
+                    match self.saves.iter_mut().find(|entry| entry.0 == func) {
+                        Some(entry) => entry.2 = entry.2.max(live),
+                        None => self.saves.push((func, line, live)),
+                    }
+                    self.emit(load(OpParam::RegisterI, save.clone()));
+                    self.emit(load(OpParam::DerefI, reg(live - 1)));
+                }
+                self.emit(Instruction::Call(Call::new(OpParam::Label(func.clone()))));
+                if live > 0 {
+                    self.emit(load(OpParam::RegisterI, save.clone()));
+                    self.emit(load(OpParam::DerefI, TRANSFER));
+                    self.emit(load(OpParam::RegisterI, save));
+                    self.emit(load(reg(live - 1), OpParam::DerefI));
+                }
+                if let Some(dst) = dst {
+                    self.emit(load(reg(dst), TRANSFER));
+                }
+            }
+            Op::Return(ref value) => {
+                if let Some(ref value) = *value {
+                    self.emit(load(TRANSFER, param(value)));
+                }
+                self.emit(Instruction::Return(Return {}));
+            }
+            Op::Clear => self.emit(Instruction::ClearScreen(ClearScreen {})),
+            Op::Draw { ref sprite, height, x, y, dst } => {
+                self.emit(load(OpParam::RegisterI, OpParam::Label(sprite.clone())));
+                self.emit(Instruction::Draw(Draw::new(reg(x), reg(y), byte(height))));
+                if let Some(dst) = dst {
+                    self.emit(load(reg(dst), FLAGS));
+                }
+            }
+            Op::Digit { digit, x, y, dst } => {
+                self.emit(load(OpParam::Fontset, reg(digit)));
+                self.emit(Instruction::Draw(Draw::new(reg(x), reg(y), byte(5))));
+                if let Some(dst) = dst {
+                    self.emit(load(reg(dst), FLAGS));
+                }
+            }
+            Op::Key(key) => {
+                self.emit(load(TRANSFER, byte(1)));
+                self.emit(Instruction::SkipIfKey(SkipIfKey::new(reg(key))));
+                self.emit(load(TRANSFER, byte(0)));
+                self.emit(load(reg(key), TRANSFER));
+            }
+            Op::WaitKey(dst) => self.emit(load(reg(dst), OpParam::Keyboard)),
+            Op::Random { dst, mask } => self.emit(Instruction::Rand(Rand::new(reg(dst), byte(mask)))),
+            Op::GetDelay(dst) => self.emit(load(reg(dst), OpParam::Timer)),
+            Op::SetDelay(src) => self.emit(load(OpParam::Timer, reg(src))),
+            Op::SetSound(src) => self.emit(load(OpParam::AudioTimer, reg(src))),
+            Op::Asm(ref items) => {
+                for item in items.iter() {
+                    self.push(item.clone());
+                }
+            }
+        }
+    }
+
+    // Runs `body` while `counter` is not zero, counting it down.
+    fn count_down(&mut self, counter: OpParam, body: Vec<Instruction>) {
+        let start = self.fresh_label();
+        let end = self.fresh_label();
+        self.label(&start);
+        self.emit(Instruction::SkipIfNotEqual(SkipIfNotEqual::new(counter.clone(), byte(0))));
+        self.emit(jump(&end));
+        self.emit_all(body);
+        self.emit(Instruction::Add(Add::new(counter, byte(0xFF))));
+        self.emit(jump(&start));
+        self.label(&end);
+    }
+
+    fn binary(&mut self, op: BinaryOp, dst: Temp, src: &Operand, scratch: Temp) {
+        let d = reg(dst);
+        let shift = |op: BinaryOp| match op {
+            BinaryOp::Shl => Instruction::ShiftLeft(ShiftLeft::new(reg(dst), reg(dst))),
+            _ => Instruction::ShiftRight(ShiftRight::new(reg(dst), reg(dst))),
+        };
+        match (op, src) {
+            (BinaryOp::Add, &Operand::Const(0)) | (BinaryOp::Sub, &Operand::Const(0)) => {}
+            (BinaryOp::Add, &Operand::Const(value)) => self.emit(Instruction::Add(Add::new(d, byte(value)))),
+            (BinaryOp::Sub, &Operand::Const(value)) => self.emit(Instruction::Add(Add::new(d, byte(value.wrapping_neg())))),
+            (BinaryOp::Add, &Operand::Temp(src)) => self.emit(Instruction::Add(Add::new(d, reg(src)))),
+            (BinaryOp::Sub, &Operand::Temp(src)) => self.emit(Instruction::Sub(Sub::new(d, reg(src)))),
+            (BinaryOp::And, src) | (BinaryOp::LogicalAnd, src) => {
+                let s = self.in_register(src);
+                self.emit(Instruction::And(And::new(d, s)));
+            }
+            (BinaryOp::Or, src) | (BinaryOp::LogicalOr, src) => {
+                let s = self.in_register(src);
+                self.emit(Instruction::Or(Or::new(d, s)));
+            }
+            (BinaryOp::Xor, src) => {
+                let s = self.in_register(src);
+                self.emit(Instruction::Xor(Xor::new(d, s)));
+            }
+            (BinaryOp::Shl, &Operand::Const(count)) | (BinaryOp::Shr, &Operand::Const(count)) => {
+                if count >= 8 {
+                    self.emit(load(d, byte(0)));
+                } else {
+                    for _ in 0..count {
+                        self.emit(shift(op));
+                    }
+                }
+            }
+            (BinaryOp::Shl, &Operand::Temp(count)) | (BinaryOp::Shr, &Operand::Temp(count)) => {
+                self.count_down(reg(count), vec![shift(op)]);
+            }
+            (BinaryOp::Mul, &Operand::Const(value)) => {
+                // Shift and add, one bit of the constant at a time.
+                self.emit(load(reg(scratch), byte(0)));
+                let mut rest = value;
+                while rest != 0 {
+                    if rest & 1 == 1 {
+                        self.emit(Instruction::Add(Add::new(reg(scratch), d.clone())));
+                    }
+                    rest >>= 1;
+                    if rest != 0 {
+                        self.emit(Instruction::ShiftLeft(ShiftLeft::new(d.clone(), d.clone())));
+                    }
+                }
+                self.emit(load(d, reg(scratch)));
+            }
+            (BinaryOp::Mul, &Operand::Temp(src)) => {
+                self.emit(load(reg(scratch), byte(0)));
+                self.count_down(reg(src), vec![Instruction::Add(Add::new(reg(scratch), d.clone()))]);
+                self.emit(load(d, reg(scratch)));
+            }
+            (BinaryOp::Div, &Operand::Const(value)) if value.is_power_of_two() => {
+                for _ in 0..value.trailing_zeros() {
+                    self.emit(shift(BinaryOp::Shr));
+                }
+            }
+            (BinaryOp::Mod, &Operand::Const(value)) if value.is_power_of_two() => {
+                self.emit(load(TRANSFER, byte(value - 1)));
+                self.emit(Instruction::And(And::new(d, TRANSFER)));
+            }
+            (BinaryOp::Div, src) | (BinaryOp::Mod, src) => {
+                // Repeated subtraction. Dividing by zero gives 0, with the
+                // whole dividend left over.
+                let divisor = self.in_register(src);
+                let start = self.fresh_label();
+                let end = self.fresh_label();
+                self.emit(load(reg(scratch), byte(0)));
+                self.emit(Instruction::SkipIfNotEqual(SkipIfNotEqual::new(divisor.clone(), byte(0))));
+                self.emit(jump(&end));
+                self.label(&start);
+                let too_small = Condition::new(d.clone(), "<", divisor.clone()).expect("both sides are registers");
+                self.emit_all(too_small.guard(jump(&end)));
+                self.emit(Instruction::Sub(Sub::new(d.clone(), divisor)));
+                self.emit(Instruction::Add(Add::new(reg(scratch), byte(1))));
+                self.emit(jump(&start));
+                self.label(&end);
+                if op == BinaryOp::Div {
+                    self.emit(load(d, reg(scratch)));
+                }
+            }
+            (op, src) => {
+                // A comparison, worked out as 0 or 1.
+                let condition = Condition::new(d.clone(), op.symbol(), param(src)).expect("comparisons are lowered to registers");
+                self.emit(load(reg(scratch), byte(0)));
+                self.emit_all(condition.guard(load(reg(scratch), byte(1))));
+                self.emit(load(d, reg(scratch)));
+            }
+        }
+    }
+}
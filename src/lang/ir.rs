@@ -0,0 +1,68 @@
+use assembler::Item;
+use expr::{BinaryOp, UnaryOp};
+
+// The language's intermediate representation: a list of operations per
+// function over numbered temporaries. Temporaries are used in stack order -
+// an operation's operands are always the newest ones - so temporary N can
+// simply live in register V(N + 1).
+pub type Temp = usize;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Temp(Temp),
+    Const(u8),
+}
+
+// The byte at `label + offset`, plus the value of `index` when there is one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Address {
+    pub label: String,
+    pub offset: u16,
+    pub index: Option<Temp>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Op {
+    Label(String),
+    Jump(String),
+    // Jumps to the target unless `left op right` holds; `op` is a comparison.
+    JumpUnless { op: BinaryOp, left: Temp, right: Operand, target: String },
+    Move { dst: Temp, src: Operand },
+    Load { dst: Temp, addr: Address },
+    Store { addr: Address, src: Temp },
+    // dst = op dst
+    Unary { op: UnaryOp, dst: Temp },
+    // dst = dst op src, for everything but `&&` and `||`. `scratch` is free
+    // for operators that need another register.
+    Binary { op: BinaryOp, dst: Temp, src: Operand, scratch: Temp },
+    // The arguments are already stored in the callee's parameters. The
+    // temporaries below `live` are kept across the call.
+    Call { func: String, live: usize, dst: Option<Temp> },
+    Return(Option<Operand>),
+    Clear,
+    // dst = whether the sprite hit anything.
+    Draw { sprite: String, height: u8, x: Temp, y: Temp, dst: Option<Temp> },
+    Digit { digit: Temp, x: Temp, y: Temp, dst: Option<Temp> },
+    // Replaces the key number with whether that key is down.
+    Key(Temp),
+    WaitKey(Temp),
+    Random { dst: Temp, mask: u8 },
+    GetDelay(Temp),
+    SetDelay(Temp),
+    SetSound(Temp),
+    Asm(Vec<Item>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    // Each operation with the source line it came from.
+    pub ops: Vec<(usize, Op)>,
+}
+
+// A lowered program: its functions, then labelled bytes of memory.
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub data: Vec<(usize, String, Vec<u8>)>,
+}
@@ -0,0 +1,131 @@
+use expr::parse_number;
+
+// Source lines with their line numbers.
+pub type Lines = Vec<(usize, String)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Ident(String),
+    Number(u16),
+    Symbol(&'static str),
+    // The lines of an `asm { ... }` block, untouched.
+    Asm(Lines),
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+}
+
+// Longest first, so that `<<=` is not read as `<<` and `=`.
+const SYMBOLS: [&str; 35] = [
+    "<<=", ">>=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "+", "-",
+    "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "=", "(", ")", "{", "}",
+];
+const PUNCTUATION: [&str; 5] = ["[", "]", ",", ";", "."];
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>, (usize, String)> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    while pos < chars.len() {
+        let c = chars[pos];
+        let rest: String = chars[pos..chars.len().min(pos + 3)].iter().collect();
+        if c == '\n' {
+            line += 1;
+            pos += 1;
+        } else if c.is_whitespace() {
+            pos += 1;
+        } else if rest.starts_with("//") {
+            while pos < chars.len() && chars[pos] != '\n' {
+                pos += 1;
+            }
+        } else if rest.starts_with("/*") {
+            let start = line;
+            pos += 2;
+            while pos < chars.len() && !(chars[pos] == '*' && chars.get(pos + 1) == Some(&'/')) {
+                if chars[pos] == '\n' {
+                    line += 1;
+                }
+                pos += 1;
+            }
+            if pos >= chars.len() {
+                return Err((start, "Comment is never closed".to_owned()));
+            }
+            pos += 2;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            let word: String = chars[start..pos].iter().collect();
+            let kind = if c.is_ascii_digit() {
+                match parse_number(&word) {
+                    Some(value) => TokenKind::Number(value),
+                    None => return Err((line, format!("{} is not a number", word))),
+                }
+            } else if word == "asm" {
+                let (lines, end, end_line) = asm_block(&chars, pos, line)?;
+                pos = end;
+                let token = Token { kind: TokenKind::Asm(lines), line };
+                line = end_line;
+                tokens.push(token);
+                continue;
+            } else {
+                TokenKind::Ident(word)
+            };
+            tokens.push(Token { kind, line });
+        } else if let Some(symbol) = SYMBOLS.iter().chain(PUNCTUATION.iter()).find(|symbol| rest.starts_with(*symbol)) {
+            tokens.push(Token { kind: TokenKind::Symbol(symbol), line });
+            pos += symbol.len();
+        } else {
+            return Err((line, format!("Unexpected {}", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+// Reads the braces after `asm`: each line up to the closing brace, with `;`
+// separating instructions on one line. Returns the lines, where reading
+// should go on and the line it is on.
+fn asm_block(chars: &[char], pos: usize, line: usize) -> Result<(Lines, usize, usize), (usize, String)> {
+    let start = line;
+    let mut pos = pos;
+    let mut line = line;
+    while pos < chars.len() && chars[pos].is_whitespace() {
+        if chars[pos] == '\n' {
+            line += 1;
+        }
+        pos += 1;
+    }
+    if chars.get(pos) != Some(&'{') {
+        return Err((line, "Expected { after asm".to_owned()));
+    }
+    pos += 1;
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    loop {
+        let c = match chars.get(pos) {
+            Some(&c) => c,
+            None => return Err((start, "asm block is never closed".to_owned())),
+        };
+        pos += 1;
+        match c {
+            '}' | '\n' | ';' => {
+                let code = current.split("//").next().unwrap_or("").trim().to_owned();
+                if !code.is_empty() {
+                    lines.push((line, code));
+                }
+                current.clear();
+                if c == '}' {
+                    return Ok((lines, pos, line));
+                } else if c == '\n' {
+                    line += 1;
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+}
@@ -0,0 +1,924 @@
+use assembler::Item;
+use expr::{BinaryOp, UnaryOp};
+use functions::deepest_chain;
+use instructions::*;
+use interpreter::{MEMORY_SIZE, PROGRAM_START};
+use lang::ast::*;
+use lang::ir::{Address, Function, Op, Operand, Program, Temp};
+use structured::GENERATED_PREFIX;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+// V1-VE hold temporaries; V0 moves bytes to and from memory and VF takes flags.
+const MAX_TEMPS: usize = 14;
+const MAX_SPRITE_HEIGHT: usize = 15;
+// The most a single value can take: all the memory a program has.
+const MAX_SIZE: usize = MEMORY_SIZE - PROGRAM_START as usize;
+
+const BUILTINS: [&str; 9] = ["clear", "draw", "digit", "key", "wait_key", "random", "delay", "set_delay", "sound"];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Ty {
+    Byte,
+    Bool,
+    Struct(String),
+    Array(Box<Ty>, usize),
+}
+
+impl Ty {
+    fn is_scalar(&self) -> bool {
+        matches!(*self, Ty::Byte | Ty::Bool)
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Ty::Byte => write!(f, "byte"),
+            Ty::Bool => write!(f, "bool"),
+            Ty::Struct(ref name) => write!(f, "{}", name),
+            Ty::Array(ref elem, len) => write!(f, "{}[{}]", elem, len),
+        }
+    }
+}
+
+struct StructDef {
+    fields: Vec<(String, Ty, u16)>,
+    size: u16,
+}
+
+#[derive(Clone, Debug)]
+enum Symbol {
+    Const(u16, Ty),
+    Var { label: String, ty: Ty },
+    Sprite { label: String, height: u8 },
+    // Each parameter has a name, a type and a label in memory.
+    Func { label: String, params: Vec<(String, Ty, String)>, result: Option<Ty> },
+}
+
+type LowerResult<T> = Result<T, String>;
+
+struct Lowerer {
+    structs: HashMap<String, StructDef>,
+    globals: HashMap<String, Symbol>,
+    labels: Vec<String>,
+    data: Vec<(usize, String, Vec<u8>)>,
+    errors: Vec<(usize, String)>,
+    // The function being lowered.
+    func: String,
+    result: Option<Ty>,
+    scopes: Vec<HashMap<String, Symbol>>,
+    // The continue and break targets of the enclosing loops.
+    loops: Vec<(String, String)>,
+    ops: Vec<(usize, Op)>,
+    line: usize,
+    depth: usize,
+    next_label: usize,
+}
+
+// Lowers a parsed program to IR, checking its types. Every error is kept,
+// with the line it is on.
+pub fn lower(decls: &[Decl]) -> Result<Program, Vec<(usize, String)>> {
+    let mut lowerer = Lowerer {
+        structs: HashMap::new(),
+        globals: HashMap::new(),
+        labels: Vec::new(),
+        data: Vec::new(),
+        errors: Vec::new(),
+        func: String::new(),
+        result: None,
+        scopes: Vec::new(),
+        loops: Vec::new(),
+        ops: Vec::new(),
+        line: 0,
+        depth: 0,
+        next_label: 0,
+    };
+    for decl in decls {
+        if let Err(message) = lowerer.declare(decl) {
+            lowerer.errors.push((decl_line(decl), message));
+        }
+    }
+    let mut functions = Vec::new();
+    for decl in decls {
+        if let Decl::Func(ref func) = *decl {
+            if let Some(function) = lowerer.function(func) {
+                functions.push(function);
+            }
+        }
+    }
+    match lowerer.globals.get("main") {
+        Some(&Symbol::Func { ref params, result: None, .. }) if params.is_empty() => {}
+        Some(_) => lowerer.errors.push((1, "main must be declared as void main()".to_owned())),
+        None => lowerer.errors.push((1, "The program has no main function".to_owned())),
+    }
+    if lowerer.errors.is_empty() {
+        lowerer.check_calls(&functions, decls);
+    }
+    if lowerer.errors.is_empty() {
+        Ok(Program { functions, data: lowerer.data })
+    } else {
+        lowerer.errors.sort_by_key(|error| error.0);
+        Err(lowerer.errors)
+    }
+}
+
+fn decl_line(decl: &Decl) -> usize {
+    match *decl {
+        Decl::Const { line, .. } | Decl::Struct { line, .. } | Decl::Sprite { line, .. } => line,
+        Decl::Var(ref var) => var.line,
+        Decl::Func(ref func) => func.line,
+    }
+}
+
+fn fold(op: BinaryOp, left: u8, right: u8) -> u8 {
+    match op {
+        BinaryOp::Add => left.wrapping_add(right),
+        BinaryOp::Sub => left.wrapping_sub(right),
+        BinaryOp::Mul => left.wrapping_mul(right),
+        BinaryOp::Div => left.checked_div(right).unwrap_or(0),
+        BinaryOp::Mod => left.checked_rem(right).unwrap_or(left),
+        BinaryOp::Shl => left.checked_shl(u32::from(right)).unwrap_or(0),
+        BinaryOp::Shr => left.checked_shr(u32::from(right)).unwrap_or(0),
+        BinaryOp::And | BinaryOp::LogicalAnd => left & right,
+        BinaryOp::Or | BinaryOp::LogicalOr => left | right,
+        BinaryOp::Xor => left ^ right,
+        BinaryOp::Less => u8::from(left < right),
+        BinaryOp::Greater => u8::from(left > right),
+        BinaryOp::LessEqual => u8::from(left <= right),
+        BinaryOp::GreaterEqual => u8::from(left >= right),
+        BinaryOp::Equal => u8::from(left == right),
+        BinaryOp::NotEqual => u8::from(left != right),
+    }
+}
+
+// The type of `left op right`, if the operator applies to those types.
+fn binary_type(op: BinaryOp, left: &Ty, right: &Ty) -> LowerResult<Ty> {
+    let result = match (op, left, right) {
+        (BinaryOp::Equal, l, r) | (BinaryOp::NotEqual, l, r) if l == r && l.is_scalar() => Some(Ty::Bool),
+        (op, &Ty::Byte, &Ty::Byte) if op.is_comparison() => Some(Ty::Bool),
+        (BinaryOp::LogicalAnd, &Ty::Bool, &Ty::Bool) | (BinaryOp::LogicalOr, &Ty::Bool, &Ty::Bool) => Some(Ty::Bool),
+        (BinaryOp::And, &Ty::Bool, &Ty::Bool) | (BinaryOp::Or, &Ty::Bool, &Ty::Bool) | (BinaryOp::Xor, &Ty::Bool, &Ty::Bool) => {
+            Some(Ty::Bool)
+        }
+        (BinaryOp::LogicalAnd, _, _) | (BinaryOp::LogicalOr, _, _) => None,
+        (op, &Ty::Byte, &Ty::Byte) if !op.is_comparison() => Some(Ty::Byte),
+        _ => None,
+    };
+    result.ok_or_else(|| format!("Cannot apply {} to {} and {}", op.symbol(), left, right))
+}
+
+impl Lowerer {
+    // Every type is checked to fit in memory when it is resolved.
+    fn size(&self, ty: &Ty) -> u16 {
+        self.checked_size(ty).unwrap_or(u16::MAX)
+    }
+
+    fn checked_size(&self, ty: &Ty) -> Option<u16> {
+        let size = match *ty {
+            Ty::Byte | Ty::Bool => 1,
+            Ty::Struct(ref name) => self.structs.get(name).map_or(0, |def| def.size),
+            Ty::Array(ref elem, len) => self.checked_size(elem)?.checked_mul(u16::try_from(len).ok()?)?,
+        };
+        Some(size).filter(|&size| size as usize <= MAX_SIZE)
+    }
+
+    fn resolve(&self, spec: &TypeSpec) -> LowerResult<Ty> {
+        let ty = match spec.name {
+            TypeName::Byte => Ty::Byte,
+            TypeName::Bool => Ty::Bool,
+            TypeName::Struct(ref name) if self.structs.contains_key(name) => Ty::Struct(name.clone()),
+            TypeName::Struct(ref name) => return Err(format!("Unknown type {}", name)),
+        };
+        let ty = match spec.len {
+            Some(ref len) => match self.constant(len) {
+                Some((len, Ty::Byte)) if len > 0 => Ty::Array(Box::new(ty), len as usize),
+                _ => return Err("An array length must be a constant above 0".to_owned()),
+            },
+            None => ty,
+        };
+        match self.checked_size(&ty) {
+            Some(_) => Ok(ty),
+            None => Err(format!("{} does not fit in memory", ty)),
+        }
+    }
+
+    // Picks a label nothing else uses yet.
+    fn unique_label(&mut self, label: String) -> String {
+        let mut candidate = label.clone();
+        let mut suffix = 1;
+        while self.labels.contains(&candidate) {
+            suffix += 1;
+            candidate = format!("{}{}", label, suffix);
+        }
+        self.labels.push(candidate.clone());
+        candidate
+    }
+
+    fn declare_global(&mut self, name: &str, symbol: Symbol) -> LowerResult<()> {
+        if BUILTINS.contains(&name) {
+            return Err(format!("{} is a built-in function", name));
+        }
+        if self.globals.contains_key(name) {
+            return Err(format!("{} is already declared", name));
+        }
+        self.globals.insert(name.to_owned(), symbol);
+        Ok(())
+    }
+
+    // Labels for everything in memory are taken from the upper-cased name;
+    // names that differ only in case clash.
+    fn global_label(&mut self, name: &str) -> LowerResult<String> {
+        let label = name.to_uppercase();
+        if self.labels.contains(&label) {
+            return Err(format!("{} clashes with another name that differs only in case", name));
+        }
+        self.labels.push(label.clone());
+        Ok(label)
+    }
+
+    // Registers a top-level declaration, so that it can be used anywhere.
+    fn declare(&mut self, decl: &Decl) -> LowerResult<()> {
+        match *decl {
+            Decl::Const { ref name, ref value, .. } => {
+                let (value, ty) = self.constant(value).ok_or_else(|| format!("The value of {} must be a constant", name))?;
+                self.declare_global(name, Symbol::Const(value, ty))
+            }
+            Decl::Struct { ref name, ref fields, .. } => {
+                if self.structs.contains_key(name) {
+                    return Err(format!("Struct {} is already declared", name));
+                }
+                let mut def = StructDef { fields: Vec::new(), size: 0 };
+                for field in fields {
+                    if def.fields.iter().any(|entry| entry.0 == field.name) {
+                        return Err(format!("{} has two fields called {}", name, field.name));
+                    }
+                    let ty = self.resolve(&field.ty)?;
+                    let size = self.size(&ty);
+                    def.fields.push((field.name.clone(), ty, def.size));
+                    def.size = def
+                        .size
+                        .checked_add(size)
+                        .filter(|&size| size as usize <= MAX_SIZE)
+                        .ok_or_else(|| format!("{} does not fit in memory", name))?;
+                }
+                self.structs.insert(name.clone(), def);
+                Ok(())
+            }
+            Decl::Sprite { line, ref name, ref rows } => {
+                if rows.is_empty() || rows.len() > MAX_SPRITE_HEIGHT {
+                    return Err(format!("A sprite has 1 to {} rows", MAX_SPRITE_HEIGHT));
+                }
+                let bytes = self.constant_bytes(rows)?;
+                let label = self.global_label(name)?;
+                self.data.push((line, label.clone(), bytes));
+                self.declare_global(name, Symbol::Sprite { label, height: rows.len() as u8 })
+            }
+            Decl::Var(ref var) => {
+                let ty = self.resolve(&var.ty)?;
+                let mut bytes = vec![0; self.size(&ty) as usize];
+                match (&var.init, &ty) {
+                    (&None, _) => {}
+                    (&Some(Init::Expr(ref value)), ty) if ty.is_scalar() => match self.constant(value) {
+                        Some((value, ref found)) if found == ty && value <= 0xFF => bytes[0] = value as u8,
+                        _ => return Err(format!("{} must start as a constant {}", var.name, ty)),
+                    },
+                    (&Some(Init::List(ref values)), ty) if !ty.is_scalar() => {
+                        if values.len() > bytes.len() {
+                            return Err(format!("{} holds {} bytes, not {}", var.name, bytes.len(), values.len()));
+                        }
+                        let values = self.constant_bytes(values)?;
+                        bytes[..values.len()].copy_from_slice(&values);
+                    }
+                    _ => return Err(format!("{} is a {}, which cannot start with that value", var.name, ty)),
+                }
+                let label = self.global_label(&var.name)?;
+                self.data.push((var.line, label.clone(), bytes));
+                self.declare_global(&var.name, Symbol::Var { label, ty })
+            }
+            Decl::Func(ref func) => {
+                let label = self.global_label(&func.name)?;
+                let mut params: Vec<(String, Ty, String)> = Vec::new();
+                for param in func.params.iter() {
+                    if params.iter().any(|entry| entry.0 == param.name) {
+                        return Err(format!("Parameter {} is repeated", param.name));
+                    }
+                    let ty = self.resolve(&TypeSpec { name: param.ty.clone(), len: None })?;
+                    if !ty.is_scalar() {
+                        return Err(format!("Parameter {} must be a byte or a bool", param.name));
+                    }
+                    let slot = self.local_label(&label, &param.name);
+                    self.data.push((func.line, slot.clone(), vec![0]));
+                    params.push((param.name.clone(), ty, slot));
+                }
+                let result = match func.result {
+                    Some(ref name) => {
+                        let ty = self.resolve(&TypeSpec { name: name.clone(), len: None })?;
+                        if !ty.is_scalar() {
+                            return Err(format!("{} must return a byte or a bool", func.name));
+                        }
+                        Some(ty)
+                    }
+                    None => None,
+                };
+                self.declare_global(&func.name, Symbol::Func { label, params, result })
+            }
+        }
+    }
+
+    fn constant_bytes(&self, values: &[Expr]) -> LowerResult<Vec<u8>> {
+        values
+            .iter()
+            .map(|value| match self.constant(value) {
+                Some((value, _)) if value <= 0xFF => Ok(value as u8),
+                _ => Err("Expected a constant byte".to_owned()),
+            })
+            .collect()
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    // The value of an expression made only of numbers and constants.
+    fn constant(&self, expr: &Expr) -> Option<(u16, Ty)> {
+        match *expr {
+            Expr::Number(value) => Some((value, Ty::Byte)),
+            Expr::Bool(value) => Some((u16::from(value), Ty::Bool)),
+            Expr::Name(ref name) => match self.lookup(name) {
+                Some(&Symbol::Const(value, ref ty)) => Some((value, ty.clone())),
+                _ => None,
+            },
+            Expr::Unary(op, ref inner) => {
+                let (value, ty) = self.constant(inner)?;
+                let value = u8::try_from(value).ok()?;
+                match (op, ty) {
+                    (UnaryOp::Neg, Ty::Byte) => Some((u16::from(value.wrapping_neg()), Ty::Byte)),
+                    (UnaryOp::Complement, Ty::Byte) => Some((u16::from(!value), Ty::Byte)),
+                    (UnaryOp::Not, Ty::Bool) => Some((u16::from(value == 0), Ty::Bool)),
+                    _ => None,
+                }
+            }
+            Expr::Binary(op, ref left, ref right) => {
+                let (left, left_ty) = self.constant(left)?;
+                let (right, right_ty) = self.constant(right)?;
+                let ty = binary_type(op, &left_ty, &right_ty).ok()?;
+                let value = fold(op, u8::try_from(left).ok()?, u8::try_from(right).ok()?);
+                Some((u16::from(value), ty))
+            }
+            _ => None,
+        }
+    }
+
+    fn emit(&mut self, op: Op) {
+        self.ops.push((self.line, op));
+    }
+
+    fn fresh_label(&mut self, kind: &str) -> String {
+        self.next_label += 1;
+        format!("{}{}:{}{}", GENERATED_PREFIX, self.func, kind, self.next_label)
+    }
+
+    fn push(&mut self) -> LowerResult<Temp> {
+        if self.depth >= MAX_TEMPS {
+            return Err(format!("This needs more than {} registers; split it into smaller statements", MAX_TEMPS));
+        }
+        self.depth += 1;
+        Ok(self.depth - 1)
+    }
+
+    fn pop(&mut self, count: usize) {
+        self.depth -= count;
+    }
+
+    fn top(&self) -> Temp {
+        self.depth - 1
+    }
+
+    fn function(&mut self, func: &FuncDecl) -> Option<Function> {
+        let (label, params, result) = match self.globals.get(&func.name) {
+            Some(Symbol::Func { label, params, result }) => (label.clone(), params.clone(), result.clone()),
+            _ => return None,
+        };
+        self.func = label.clone();
+        self.result = result.clone();
+        self.ops = Vec::new();
+        self.line = func.line;
+        self.next_label = 0;
+        self.scopes = vec![HashMap::new()];
+        for (name, ty, label) in params {
+            self.scopes[0].insert(name, Symbol::Var { label, ty });
+        }
+        self.emit(Op::Label(label.clone()));
+        self.stmts(&func.body);
+        self.line = func.line;
+        let value = result.map(|_| Operand::Const(0));
+        self.emit(Op::Return(value));
+        self.scopes.clear();
+        Some(Function { name: label, ops: std::mem::take(&mut self.ops) })
+    }
+
+    fn local_label(&mut self, func: &str, name: &str) -> String {
+        let label = format!("{}{}.{}", GENERATED_PREFIX, func, name.to_uppercase());
+        self.unique_label(label)
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.line = stmt.line;
+            self.depth = 0;
+            if let Err(message) = self.stmt(stmt) {
+                self.errors.push((stmt.line, message));
+            }
+        }
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> LowerResult<()> {
+        match stmt.kind {
+            StmtKind::Var(ref var) => self.local(var),
+            StmtKind::Assign { ref target, op, ref value } => self.assign(target, op, value),
+            StmtKind::Call(Expr::Call(ref name, ref args)) => {
+                if self.call(name, args)?.is_some() {
+                    self.pop(1);
+                }
+                Ok(())
+            }
+            StmtKind::Call(_) => Err("Expected a call".to_owned()),
+            StmtKind::If { ref cond, ref then, ref otherwise } => {
+                let else_label = self.fresh_label("ELSE");
+                self.branch_unless(cond, &else_label)?;
+                self.stmts(then);
+                if otherwise.is_empty() {
+                    self.emit(Op::Label(else_label));
+                } else {
+                    let end_label = self.fresh_label("END");
+                    self.emit(Op::Jump(end_label.clone()));
+                    self.emit(Op::Label(else_label));
+                    self.stmts(otherwise);
+                    self.emit(Op::Label(end_label));
+                }
+                Ok(())
+            }
+            StmtKind::While { ref cond, ref body } => {
+                let start = self.fresh_label("LOOP");
+                let end = self.fresh_label("DONE");
+                self.emit(Op::Label(start.clone()));
+                self.branch_unless(cond, &end)?;
+                self.loop_body(body, &start, &end);
+                self.emit(Op::Jump(start));
+                self.emit(Op::Label(end));
+                Ok(())
+            }
+            StmtKind::For { ref init, ref cond, ref step, ref body } => {
+                if let Some(ref init) = *init {
+                    self.stmt(init)?;
+                }
+                let start = self.fresh_label("LOOP");
+                let next = self.fresh_label("NEXT");
+                let end = self.fresh_label("DONE");
+                self.emit(Op::Label(start.clone()));
+                if let Some(ref cond) = *cond {
+                    self.branch_unless(cond, &end)?;
+                }
+                self.loop_body(body, &next, &end);
+                self.emit(Op::Label(next));
+                if let Some(ref step) = *step {
+                    self.line = step.line;
+                    self.depth = 0;
+                    self.stmt(step)?;
+                }
+                self.emit(Op::Jump(start));
+                self.emit(Op::Label(end));
+                Ok(())
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let (next, end) = self.loops.last().cloned().ok_or_else(|| "break and continue must be inside a loop".to_owned())?;
+                let target = if matches!(stmt.kind, StmtKind::Break) { end } else { next };
+                self.emit(Op::Jump(target));
+                Ok(())
+            }
+            StmtKind::Return(ref value) => {
+                let operand = match (value.as_ref(), self.result.clone()) {
+                    (None, None) => None,
+                    (Some(value), Some(ty)) => {
+                        let (operand, found) = self.operand(value)?;
+                        if found != ty {
+                            return Err(format!("{} returns a {}, not a {}", self.func, ty, found));
+                        }
+                        Some(operand)
+                    }
+                    (None, Some(ty)) => return Err(format!("{} must return a {}", self.func, ty)),
+                    (Some(_), None) => return Err(format!("{} is void and cannot return a value", self.func)),
+                };
+                self.emit(Op::Return(operand));
+                Ok(())
+            }
+            StmtKind::Asm(ref lines) => {
+                let mut items = Vec::new();
+                for &(line, ref code) in lines.iter() {
+                    if is_instr_line(code) {
+                        match Instruction::parse_args(code) {
+                            Ok(instr) => items.push(Item::Instr(instr)),
+                            Err(ParseError(message)) => self.errors.push((line, message)),
+                        }
+                    } else if code.ends_with(':') {
+                        items.push(Item::Label(label_name(code)));
+                    } else {
+                        self.errors.push((line, format!("Expected an instruction or a label, got {}", code)));
+                    }
+                }
+                self.emit(Op::Asm(items));
+                Ok(())
+            }
+        }
+    }
+
+    fn loop_body(&mut self, body: &[Stmt], next: &str, end: &str) {
+        self.loops.push((next.to_owned(), end.to_owned()));
+        self.stmts(body);
+        self.loops.pop();
+    }
+
+    // Locals are static, like FUNC frames: each has a fixed place in memory,
+    // and one without an initial value keeps what it held last.
+    fn local(&mut self, var: &VarDecl) -> LowerResult<()> {
+        if BUILTINS.contains(&var.name.as_str()) {
+            return Err(format!("{} is a built-in function", var.name));
+        }
+        if self.scopes.last().is_some_and(|scope| scope.contains_key(&var.name)) {
+            return Err(format!("{} is already declared", var.name));
+        }
+        let ty = self.resolve(&var.ty)?;
+        let func = self.func.clone();
+        let label = self.local_label(&func, &var.name);
+        self.data.push((var.line, label.clone(), vec![0; self.size(&ty) as usize]));
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(var.name.clone(), Symbol::Var { label: label.clone(), ty: ty.clone() });
+        }
+        match (&var.init, &ty) {
+            (&None, _) => Ok(()),
+            (&Some(Init::Expr(ref value)), _) => self.assign(&Expr::Name(var.name.clone()), None, value),
+            (&Some(Init::List(ref values)), ty) if !ty.is_scalar() => {
+                if values.len() > self.size(ty) as usize {
+                    return Err(format!("{} holds {} bytes, not {}", var.name, self.size(ty), values.len()));
+                }
+                for (offset, value) in values.iter().enumerate() {
+                    self.value(value)?;
+                    let addr = Address { label: label.clone(), offset: offset as u16, index: None };
+                    self.emit(Op::Store { addr, src: self.top() });
+                    self.pop(1);
+                }
+                Ok(())
+            }
+            (_, ty) => Err(format!("A {} cannot start with a list", ty)),
+        }
+    }
+
+    fn assign(&mut self, target: &Expr, op: Option<BinaryOp>, value: &Expr) -> LowerResult<()> {
+        let (addr, ty) = self.place(target)?;
+        if !ty.is_scalar() {
+            return Err(format!("A {} cannot be assigned at once; assign its elements or fields", ty));
+        }
+        let found = match op {
+            Some(op) => {
+                let dst = self.push()?;
+                self.emit(Op::Load { dst, addr: addr.clone() });
+                let (src, right) = self.operand(value)?;
+                let found = binary_type(op, &ty, &right)?;
+                self.binary(op, dst, src)?;
+                found
+            }
+            None => self.value(value)?,
+        };
+        if found != ty {
+            return Err(format!("Cannot assign a {} to a {}", found, ty));
+        }
+        self.emit(Op::Store { addr, src: self.top() });
+        Ok(())
+    }
+
+    // Applies a binary operator to the newest temporary, releasing `src`.
+    fn binary(&mut self, op: BinaryOp, dst: Temp, src: Operand) -> LowerResult<()> {
+        let scratch = self.push()?;
+        self.pop(1);
+        self.emit(Op::Binary { op, dst, src: src.clone(), scratch });
+        if let Operand::Temp(_) = src {
+            self.pop(1);
+        }
+        Ok(())
+    }
+
+    // Where a variable, element or field is in memory, and its type. An index
+    // that is not constant is left in a new temporary.
+    fn place(&mut self, expr: &Expr) -> LowerResult<(Address, Ty)> {
+        match *expr {
+            Expr::Name(ref name) => match self.lookup(name) {
+                Some(Symbol::Var { label, ty }) => Ok((Address { label: label.clone(), offset: 0, index: None }, ty.clone())),
+                Some(_) => Err(format!("{} is not a variable", name)),
+                None => Err(format!("{} is not declared", name)),
+            },
+            Expr::Field(ref base, ref field) => {
+                let (mut addr, ty) = self.place(base)?;
+                let name = match ty {
+                    Ty::Struct(ref name) => name.clone(),
+                    _ => return Err(format!("A {} has no fields", ty)),
+                };
+                let (ty, offset) = match self.structs[&name].fields.iter().find(|entry| entry.0 == *field) {
+                    Some(&(_, ref ty, offset)) => (ty.clone(), offset),
+                    None => return Err(format!("{} has no field {}", name, field)),
+                };
+                addr.offset += offset;
+                Ok((addr, ty))
+            }
+            Expr::Index(ref base, ref index) => {
+                let (mut addr, ty) = self.place(base)?;
+                let (elem, len) = match ty {
+                    Ty::Array(elem, len) => (*elem, len),
+                    ty => return Err(format!("A {} cannot be indexed", ty)),
+                };
+                let size = self.size(&elem);
+                if let Some((index, _)) = self.constant(index) {
+                    if index as usize >= len {
+                        return Err(format!("Index {} is past the end of a {}[{}]", index, elem, len));
+                    }
+                    addr.offset += index * size;
+                    return Ok((addr, elem));
+                }
+                if self.value(index)? != Ty::Byte {
+                    return Err("An index must be a byte".to_owned());
+                }
+                let offset = self.top();
+                if size > 1 {
+                    self.binary(BinaryOp::Mul, offset, Operand::Const(size as u8))?;
+                }
+                match addr.index {
+                    Some(outer) => self.binary(BinaryOp::Add, outer, Operand::Temp(offset))?,
+                    None => addr.index = Some(offset),
+                }
+                Ok((addr, elem))
+            }
+            _ => Err("Expected a variable, an element or a field".to_owned()),
+        }
+    }
+
+    // Lowers an expression to a constant if it is one, or else to a new
+    // temporary.
+    fn operand(&mut self, expr: &Expr) -> LowerResult<(Operand, Ty)> {
+        match self.constant(expr) {
+            Some((value, ty)) if value <= 0xFF => Ok((Operand::Const(value as u8), ty)),
+            _ => {
+                let ty = self.value(expr)?;
+                Ok((Operand::Temp(self.top()), ty))
+            }
+        }
+    }
+
+    // Lowers an expression to a new temporary.
+    fn value(&mut self, expr: &Expr) -> LowerResult<Ty> {
+        if let Some((value, ty)) = self.constant(expr) {
+            if value > 0xFF {
+                return Err(format!("{} does not fit in a byte", value));
+            }
+            let dst = self.push()?;
+            self.emit(Op::Move { dst, src: Operand::Const(value as u8) });
+            return Ok(ty);
+        }
+        match *expr {
+            Expr::Name(ref name) => match self.lookup(name) {
+                Some(&Symbol::Sprite { .. }) => Err(format!("Sprite {} can only be drawn", name)),
+                Some(&Symbol::Func { .. }) => Err(format!("Call {} with {}()", name, name)),
+                _ => self.load(expr),
+            },
+            Expr::Number(_) | Expr::Bool(_) => Err("Constant out of range".to_owned()),
+            Expr::Index(..) | Expr::Field(..) => self.load(expr),
+            Expr::Unary(op, ref inner) => {
+                let ty = self.value(inner)?;
+                let expected = if op == UnaryOp::Not { Ty::Bool } else { Ty::Byte };
+                if ty != expected {
+                    let symbol = match op {
+                        UnaryOp::Neg => "-",
+                        UnaryOp::Not => "!",
+                        UnaryOp::Complement => "~",
+                    };
+                    return Err(format!("Cannot apply {} to a {}", symbol, ty));
+                }
+                self.emit(Op::Unary { op, dst: self.top() });
+                Ok(ty)
+            }
+            Expr::Binary(op @ BinaryOp::LogicalAnd, ref left, ref right) | Expr::Binary(op @ BinaryOp::LogicalOr, ref left, ref right) => {
+                let left_ty = self.value(left)?;
+                let dst = self.top();
+                let end = self.fresh_label(if op == BinaryOp::LogicalAnd { "AND" } else { "OR" });
+                // `&&` stops at false and `||` at true.
+                let keep_going = if op == BinaryOp::LogicalAnd { BinaryOp::NotEqual } else { BinaryOp::Equal };
+                self.emit(Op::JumpUnless { op: keep_going, left: dst, right: Operand::Const(0), target: end.clone() });
+                self.pop(1);
+                let right_ty = self.value(right)?;
+                self.emit(Op::Label(end));
+                binary_type(op, &left_ty, &right_ty)
+            }
+            Expr::Binary(op, ref left, ref right) => {
+                // Constants go on the right, where they can be immediates.
+                let (left, right) = if self.constant(left).is_some() && op.is_commutative() { (right, left) } else { (left, right) };
+                let left_ty = self.value(left)?;
+                let dst = self.top();
+                let (src, right_ty) = self.operand(right)?;
+                let ty = binary_type(op, &left_ty, &right_ty)?;
+                self.binary(op, dst, src)?;
+                Ok(ty)
+            }
+            Expr::Call(ref name, ref args) => self.call(name, args)?.ok_or_else(|| format!("{} does not return a value", name)),
+        }
+    }
+
+    fn load(&mut self, expr: &Expr) -> LowerResult<Ty> {
+        let (addr, ty) = self.place(expr)?;
+        if !ty.is_scalar() {
+            return Err(format!("A {} cannot be used as a value; use one of its elements or fields", ty));
+        }
+        let dst = match addr.index {
+            Some(index) => index,
+            None => self.push()?,
+        };
+        self.emit(Op::Load { dst, addr });
+        Ok(ty)
+    }
+
+    fn byte_args(&mut self, name: &str, args: &[Expr], count: usize) -> LowerResult<Temp> {
+        if args.len() != count {
+            return Err(format!("{} takes {} arguments, got {}", name, count, args.len()));
+        }
+        let first = self.depth;
+        for arg in args {
+            let ty = self.value(arg)?;
+            if ty != Ty::Byte {
+                return Err(format!("The arguments of {} are bytes, got a {}", name, ty));
+            }
+        }
+        Ok(first)
+    }
+
+    // Lowers a call; a result is left in a new temporary.
+    fn call(&mut self, name: &str, args: &[Expr]) -> LowerResult<Option<Ty>> {
+        match name {
+            "clear" => {
+                self.byte_args(name, args, 0)?;
+                self.emit(Op::Clear);
+                return Ok(None);
+            }
+            "draw" => {
+                let (sprite, height) = match args.first() {
+                    Some(Expr::Name(ref sprite)) => match self.lookup(sprite) {
+                        Some(&Symbol::Sprite { ref label, height }) => (label.clone(), height),
+                        _ => return Err(format!("{} is not a sprite", sprite)),
+                    },
+                    _ => return Err("draw takes a sprite, x and y".to_owned()),
+                };
+                let x = self.byte_args(name, &args[1..], 2)?;
+                self.emit(Op::Draw { sprite, height, x, y: x + 1, dst: Some(x) });
+                self.pop(1);
+                return Ok(Some(Ty::Bool));
+            }
+            "digit" => {
+                let digit = self.byte_args(name, args, 3)?;
+                self.emit(Op::Digit { digit, x: digit + 1, y: digit + 2, dst: Some(digit) });
+                self.pop(2);
+                return Ok(Some(Ty::Bool));
+            }
+            "key" => {
+                let key = self.byte_args(name, args, 1)?;
+                self.emit(Op::Key(key));
+                return Ok(Some(Ty::Bool));
+            }
+            "wait_key" | "delay" => {
+                self.byte_args(name, args, 0)?;
+                let dst = self.push()?;
+                self.emit(if name == "delay" { Op::GetDelay(dst) } else { Op::WaitKey(dst) });
+                return Ok(Some(Ty::Byte));
+            }
+            "random" => {
+                let mask = match args {
+                    [mask] => self.constant(mask).filter(|&(mask, ref ty)| mask <= 0xFF && *ty == Ty::Byte),
+                    _ => None,
+                };
+                let mask = mask.ok_or_else(|| "random takes a constant mask".to_owned())?.0 as u8;
+                let dst = self.push()?;
+                self.emit(Op::Random { dst, mask });
+                return Ok(Some(Ty::Byte));
+            }
+            "set_delay" | "sound" => {
+                let src = self.byte_args(name, args, 1)?;
+                self.emit(if name == "sound" { Op::SetSound(src) } else { Op::SetDelay(src) });
+                self.pop(1);
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        let (func, params, result) = match self.lookup(name) {
+            Some(Symbol::Func { label, params, result }) => (label.clone(), params.clone(), result.clone()),
+            Some(_) => return Err(format!("{} is not a function", name)),
+            None => return Err(format!("{} is not declared", name)),
+        };
+        if args.len() != params.len() {
+            return Err(format!("{} takes {} arguments, got {}", name, params.len(), args.len()));
+        }
+        let live = self.depth;
+        for (arg, (param, ty, _)) in args.iter().zip(params.iter()) {
+            let found = self.value(arg)?;
+            if found != *ty {
+                return Err(format!("{} of {} is a {}, got a {}", param, name, ty, found));
+            }
+        }
+        // Arguments are only stored once all of them are worked out, as
+        // they may call the same function.
+        for (idx, (_, _, label)) in params.into_iter().enumerate() {
+            self.emit(Op::Store { addr: Address { label, offset: 0, index: None }, src: live + idx });
+        }
+        self.pop(args.len());
+        let dst = match result {
+            Some(_) => Some(self.push()?),
+            None => None,
+        };
+        self.emit(Op::Call { func, live, dst });
+        Ok(result)
+    }
+
+    // Jumps to `target` unless the condition holds.
+    fn branch_unless(&mut self, cond: &Expr, target: &str) -> LowerResult<()> {
+        if let Some((value, ty)) = self.constant(cond) {
+            if ty != Ty::Bool {
+                return Err(format!("A condition must be a bool, got a {}", ty));
+            }
+            if value == 0 {
+                self.emit(Op::Jump(target.to_owned()));
+            }
+            return Ok(());
+        }
+        match *cond {
+            Expr::Binary(BinaryOp::LogicalAnd, ref left, ref right) => {
+                self.branch_unless(left, target)?;
+                self.branch_unless(right, target)
+            }
+            Expr::Binary(op, ref left, ref right) if op.is_comparison() => {
+                let left_ty = self.value(left)?;
+                let dst = self.top();
+                let (src, right_ty) = self.operand(right)?;
+                binary_type(op, &left_ty, &right_ty)?;
+                self.emit(Op::JumpUnless { op, left: dst, right: src.clone(), target: target.to_owned() });
+                self.pop(if let Operand::Temp(_) = src { 2 } else { 1 });
+                Ok(())
+            }
+            _ => {
+                let ty = self.value(cond)?;
+                if ty != Ty::Bool {
+                    return Err(format!("A condition must be a bool, got a {}", ty));
+                }
+                self.emit(Op::JumpUnless { op: BinaryOp::NotEqual, left: self.top(), right: Operand::Const(0), target: target.to_owned() });
+                self.pop(1);
+                Ok(())
+            }
+        }
+    }
+
+    // Frames are static, so no function may call itself, even indirectly.
+    // Whether the calls fit in the stack is checked on the assembled program.
+    fn check_calls(&mut self, functions: &[Function], decls: &[Decl]) {
+        let mut callees: HashMap<&str, Vec<&str>> = HashMap::new();
+        for function in functions {
+            let list = callees.entry(function.name.as_str()).or_default();
+            for (_, op) in function.ops.iter() {
+                if let Op::Call { ref func, .. } = *op {
+                    if !list.contains(&func.as_str()) {
+                        list.push(func.as_str());
+                    }
+                }
+            }
+        }
+        let line_of = |label: &str| {
+            decls
+                .iter()
+                .find_map(|decl| match *decl {
+                    Decl::Func(ref func) if func.name.to_uppercase() == label => Some(func.line),
+                    _ => None,
+                })
+                .unwrap_or(1)
+        };
+        let mut chains = HashMap::new();
+        for function in functions {
+            if let Err(cycle) = deepest_chain(&function.name, &callees, &mut chains, &mut Vec::new()) {
+                let message = format!("{} is recursive, which its static locals cannot support: {}", cycle[0], cycle.join(" -> "));
+                self.errors.push((line_of(cycle[0]), message));
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+pub mod ast;
+pub mod codegen;
+pub mod ir;
+pub mod lexer;
+pub mod lower;
+pub mod parser;
+
+use assembler::{Diagnostic, SourceItem, SourceLoc};
+
+// A small typed imperative language, compiled through its own syntax tree
+// and IR down to assembler items:
+//
+//     struct Pos { byte x; byte y; }
+//     const SPEED = 2;
+//     sprite dot = {0x80};
+//     Pos ball = {10, 4};
+//     byte trail[8];
+//
+//     byte clamp(byte value, byte limit) {
+//         if (value > limit) return limit;
+//         return value;
+//     }
+//
+//     void main() {
+//         byte i;
+//         for (i = 0; i < 8; i += 1) {
+//             trail[i] = clamp(ball.x + i * SPEED, 60);
+//             draw(dot, trail[i], ball.y);
+//         }
+//         asm { LD V0, K }
+//     }
+//
+// Values are bytes or bools; arrays and structs live in memory. Numbers may
+// be decimal, 0x hex or 0b binary. Globals are labelled with their
+// upper-cased names, so asm blocks and test scripts can refer to them.
+//
+// Built-ins: clear(), draw(sprite, x, y) and digit(d, x, y) (both returning
+// whether anything was hit), key(k), wait_key(), random(mask), delay(),
+// set_delay(t) and sound(t).
+
+fn diagnostics(file: &str, errors: Vec<(usize, String)>) -> Vec<Diagnostic> {
+    errors
+        .into_iter()
+        .map(|(line, message)| Diagnostic {
+            loc: SourceLoc {
+                file: file.to_owned(),
+                line,
+            },
+            message,
+        })
+        .collect()
+}
+
+pub fn parse(file: &str, source: &str) -> (Vec<SourceItem>, Vec<Diagnostic>) {
+    let decls = match lexer::tokenize(source).and_then(parser::parse) {
+        Ok(decls) => decls,
+        Err(error) => return (Vec::new(), diagnostics(file, vec![error])),
+    };
+    match lower::lower(&decls) {
+        Ok(program) => (codegen::generate(&program, file, source), Vec::new()),
+        Err(errors) => (Vec::new(), diagnostics(file, errors)),
+    }
+}
@@ -0,0 +1,411 @@
+use expr::{BinaryOp, UnaryOp};
+use lang::ast::*;
+use lang::lexer::{Token, TokenKind};
+
+const KEYWORDS: [&str; 15] = [
+    "byte", "bool", "void", "struct", "const", "sprite", "if", "else", "while", "for", "break", "continue", "return", "true",
+    "false",
+];
+
+const ASSIGN_OPS: [(&str, Option<BinaryOp>); 11] = [
+    ("=", None),
+    ("+=", Some(BinaryOp::Add)),
+    ("-=", Some(BinaryOp::Sub)),
+    ("*=", Some(BinaryOp::Mul)),
+    ("/=", Some(BinaryOp::Div)),
+    ("%=", Some(BinaryOp::Mod)),
+    ("&=", Some(BinaryOp::And)),
+    ("|=", Some(BinaryOp::Or)),
+    ("^=", Some(BinaryOp::Xor)),
+    ("<<=", Some(BinaryOp::Shl)),
+    (">>=", Some(BinaryOp::Shr)),
+];
+
+type ParseResult<T> = Result<T, (usize, String)>;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+pub fn parse(tokens: Vec<Token>) -> ParseResult<Vec<Decl>> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let mut decls = Vec::new();
+    while parser.pos < parser.tokens.len() {
+        decls.push(parser.decl()?);
+    }
+    Ok(decls)
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        match self.tokens.get(self.pos).or_else(|| self.tokens.last()) {
+            Some(token) => token.line,
+            None => 1,
+        }
+    }
+
+    fn error<T>(&self, message: String) -> ParseResult<T> {
+        Err((self.line(), message))
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|token| &token.kind)
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<&TokenKind> {
+        self.tokens.get(self.pos + ahead).map(|token| &token.kind)
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(&TokenKind::Symbol(found)) if found == symbol)
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(TokenKind::Ident(found)) if found == word)
+    }
+
+    fn describe(&self) -> String {
+        match self.peek() {
+            Some(TokenKind::Ident(word)) => word.clone(),
+            Some(TokenKind::Number(value)) => value.to_string(),
+            Some(TokenKind::Symbol(symbol)) => symbol.to_string(),
+            Some(TokenKind::Asm(_)) => "asm".to_owned(),
+            None => "the end of the file".to_owned(),
+        }
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if self.is_symbol(symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        if self.is_word(word) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> ParseResult<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            self.error(format!("Expected {}, got {}", symbol, self.describe()))
+        }
+    }
+
+    fn name(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Some(TokenKind::Ident(word)) if !KEYWORDS.contains(&word.as_str()) => {
+                let word = word.clone();
+                self.pos += 1;
+                Ok(word)
+            }
+            _ => self.error(format!("Expected a name, got {}", self.describe())),
+        }
+    }
+
+    // Whether a declaration starts here: a type followed by a name.
+    fn at_declaration(&self) -> bool {
+        match (self.peek(), self.peek_at(1)) {
+            (Some(TokenKind::Ident(word)), _) if word == "byte" || word == "bool" => true,
+            (Some(TokenKind::Ident(word)), Some(TokenKind::Ident(_))) => !KEYWORDS.contains(&word.as_str()),
+            _ => false,
+        }
+    }
+
+    fn type_name(&mut self) -> ParseResult<TypeName> {
+        if self.eat_word("byte") {
+            Ok(TypeName::Byte)
+        } else if self.eat_word("bool") {
+            Ok(TypeName::Bool)
+        } else {
+            Ok(TypeName::Struct(self.name()?))
+        }
+    }
+
+    fn decl(&mut self) -> ParseResult<Decl> {
+        let line = self.line();
+        if self.eat_word("const") {
+            let name = self.name()?;
+            self.expect("=")?;
+            let value = self.expr()?;
+            self.expect(";")?;
+            return Ok(Decl::Const { line, name, value });
+        }
+        if self.eat_word("struct") {
+            let name = self.name()?;
+            self.expect("{")?;
+            let mut fields = Vec::new();
+            while !self.eat("}") {
+                let ty = self.type_name()?;
+                let field = self.name()?;
+                let len = self.array_len()?;
+                self.expect(";")?;
+                fields.push(Field { name: field, ty: TypeSpec { name: ty, len } });
+            }
+            self.eat(";");
+            return Ok(Decl::Struct { line, name, fields });
+        }
+        if self.eat_word("sprite") {
+            let name = self.name()?;
+            self.expect("=")?;
+            let rows = self.list()?;
+            self.expect(";")?;
+            return Ok(Decl::Sprite { line, name, rows });
+        }
+
+        let result = if self.eat_word("void") { None } else { Some(self.type_name()?) };
+        let name = self.name()?;
+        if self.eat("(") {
+            let mut params = Vec::new();
+            if !self.eat(")") {
+                loop {
+                    let ty = self.type_name()?;
+                    params.push(Param { name: self.name()?, ty });
+                    if self.eat(")") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            let body = self.block()?;
+            return Ok(Decl::Func(FuncDecl { line, name, params, result, body }));
+        }
+        match result {
+            Some(ty) => Ok(Decl::Var(self.var_rest(line, ty, name)?)),
+            None => self.error(format!("Only functions can be void; expected ( after {}", name)),
+        }
+    }
+
+    fn array_len(&mut self) -> ParseResult<Option<Expr>> {
+        if self.eat("[") {
+            let len = self.expr()?;
+            self.expect("]")?;
+            Ok(Some(len))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // `{a, b, c}`, with an optional trailing comma.
+    fn list(&mut self) -> ParseResult<Vec<Expr>> {
+        self.expect("{")?;
+        let mut values = Vec::new();
+        while !self.eat("}") {
+            values.push(self.expr()?);
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        Ok(values)
+    }
+
+    // What follows the type and name of a variable.
+    fn var_rest(&mut self, line: usize, ty: TypeName, name: String) -> ParseResult<VarDecl> {
+        let len = self.array_len()?;
+        let init = if self.eat("=") {
+            if self.is_symbol("{") {
+                Some(Init::List(self.list()?))
+            } else {
+                Some(Init::Expr(self.expr()?))
+            }
+        } else {
+            None
+        };
+        self.expect(";")?;
+        Ok(VarDecl { line, name, ty: TypeSpec { name: ty, len }, init })
+    }
+
+    fn block(&mut self) -> ParseResult<Vec<Stmt>> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return self.error("Expected }, got the end of the file".to_owned());
+            }
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+
+    // A block, or a single statement standing for one.
+    fn body(&mut self) -> ParseResult<Vec<Stmt>> {
+        if self.is_symbol("{") {
+            self.block()
+        } else {
+            Ok(vec![self.stmt()?])
+        }
+    }
+
+    fn stmt(&mut self) -> ParseResult<Stmt> {
+        let line = self.line();
+        if let Some(TokenKind::Asm(lines)) = self.peek() {
+            let lines = lines.clone();
+            self.pos += 1;
+            return Ok(Stmt { line, kind: StmtKind::Asm(lines) });
+        }
+        let kind = if self.at_declaration() {
+            let ty = self.type_name()?;
+            let name = self.name()?;
+            StmtKind::Var(self.var_rest(line, ty, name)?)
+        } else if self.eat_word("if") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            let then = self.body()?;
+            let otherwise = if self.eat_word("else") { self.body()? } else { Vec::new() };
+            StmtKind::If { cond, then, otherwise }
+        } else if self.eat_word("while") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            StmtKind::While { cond, body: self.body()? }
+        } else if self.eat_word("for") {
+            self.expect("(")?;
+            let init = if self.is_symbol(";") {
+                None
+            } else if self.at_declaration() {
+                return self.error("Declare the loop variable before the for".to_owned());
+            } else {
+                Some(Box::new(self.simple()?))
+            };
+            self.expect(";")?;
+            let cond = if self.is_symbol(";") { None } else { Some(self.expr()?) };
+            self.expect(";")?;
+            let step = if self.is_symbol(")") { None } else { Some(Box::new(self.simple()?)) };
+            self.expect(")")?;
+            StmtKind::For { init, cond, step, body: self.body()? }
+        } else if self.eat_word("break") {
+            self.expect(";")?;
+            StmtKind::Break
+        } else if self.eat_word("continue") {
+            self.expect(";")?;
+            StmtKind::Continue
+        } else if self.eat_word("return") {
+            let value = if self.is_symbol(";") { None } else { Some(self.expr()?) };
+            self.expect(";")?;
+            StmtKind::Return(value)
+        } else {
+            let stmt = self.simple()?;
+            self.expect(";")?;
+            return Ok(stmt);
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    // An assignment or a call, without the semicolon.
+    fn simple(&mut self) -> ParseResult<Stmt> {
+        let line = self.line();
+        let target = self.postfix()?;
+        let op = match self.peek() {
+            Some(&TokenKind::Symbol(symbol)) => ASSIGN_OPS.iter().find(|entry| entry.0 == symbol).map(|entry| entry.1),
+            _ => None,
+        };
+        let kind = match (op, target) {
+            (Some(op), target) => {
+                self.pos += 1;
+                StmtKind::Assign { target, op, value: self.expr()? }
+            }
+            (None, call @ Expr::Call(..)) => StmtKind::Call(call),
+            _ => return self.error(format!("Expected an assignment or a call, got {}", self.describe())),
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    fn expr(&mut self) -> ParseResult<Expr> {
+        self.binary(0)
+    }
+
+    fn operator(&self) -> Option<(BinaryOp, u8)> {
+        match self.peek() {
+            Some(&TokenKind::Symbol(symbol)) => BinaryOp::parse(symbol),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, min_prec: u8) -> ParseResult<Expr> {
+        let mut left = self.unary()?;
+        while let Some((op, prec)) = self.operator() {
+            if prec < min_prec {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(prec + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> ParseResult<Expr> {
+        let op = if self.eat("-") {
+            UnaryOp::Neg
+        } else if self.eat("!") {
+            UnaryOp::Not
+        } else if self.eat("~") {
+            UnaryOp::Complement
+        } else {
+            return self.postfix();
+        };
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn postfix(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat("[") {
+                let index = self.expr()?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat(".") {
+                expr = Expr::Field(Box::new(expr), self.name()?);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
+        if self.eat("(") {
+            let inner = self.expr()?;
+            self.expect(")")?;
+            return Ok(inner);
+        }
+        if let Some(&TokenKind::Number(value)) = self.peek() {
+            self.pos += 1;
+            return Ok(Expr::Number(value));
+        }
+        if self.eat_word("true") {
+            return Ok(Expr::Bool(true));
+        }
+        if self.eat_word("false") {
+            return Ok(Expr::Bool(false));
+        }
+        let name = match self.peek() {
+            Some(TokenKind::Ident(word)) if !KEYWORDS.contains(&word.as_str()) => self.name()?,
+            _ => return self.error(format!("Expected an expression, got {}", self.describe())),
+        };
+        if !self.eat("(") {
+            return Ok(Expr::Name(name));
+        }
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.expr()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        Ok(Expr::Call(name, args))
+    }
+}
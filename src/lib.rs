@@ -0,0 +1,122 @@
+// The assembler, linker, interpreter and tools around them as a library.
+// The `chip8-rust-compiler` binary is one client of it; build scripts and
+// tests can be others:
+//
+//     extern crate chip8_rust_compiler;
+//     use chip8_rust_compiler::{assemble, Options};
+//
+//     let rom = assemble("LD V0, 0x5\nLOOP:\nJP LOOP\n", &Options::default())?;
+//     fs::write("game.c8", &rom.code)?;
+//
+// `assemble` and `assemble_file` cover the common case, and `Builder`
+// makes programs from Rust without writing source; the modules below give
+// everything the binary uses, down to single instructions.
+
+pub mod instructions;
+pub mod archive;
+pub mod assembler;
+pub mod builder;
+pub mod callgraph;
+pub mod dap;
+pub mod expr;
+pub mod font;
+pub mod functions;
+pub mod interpreter;
+pub mod ir;
+pub mod json;
+pub mod lang;
+pub mod linker;
+pub mod listing;
+pub mod lsp;
+pub mod object;
+pub mod octo;
+pub mod opcode;
+pub mod optimize;
+pub mod output;
+pub mod png;
+pub mod protocol;
+pub mod sprite;
+pub mod stdlib;
+pub mod structured;
+pub mod symbols;
+pub mod warnings;
+use instructions::*;
+
+use assembler::Assembly;
+use structured::is_generated_label;
+
+use std::error::Error;
+use std::fmt;
+
+pub use assembler::{Diagnostic, Options, Syntax};
+pub use builder::Builder;
+pub use instructions::parameters::OpParam;
+pub use instructions::{Instruction, InstructionOps};
+
+// The file name diagnostics give for source passed to `assemble`.
+pub const SOURCE_NAME: &str = "<source>";
+
+// An assembled program.
+#[derive(Clone, Debug)]
+pub struct Rom {
+    // The bytes to load at 0x200.
+    pub code: Vec<u8>,
+    // The labels written in the source, by address.
+    pub labels: Vec<(String, u16)>,
+    // Likely mistakes that did not stop the build.
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl From<Assembly> for Rom {
+    fn from(assembly: Assembly) -> Rom {
+        let mut labels: Vec<(String, u16)> = assembly
+            .labels
+            .iter()
+            .filter_map(|entry| match entry {
+                (OpParam::Label(name), &OpParam::Variable(addr)) if !is_generated_label(name) => Some((name.clone(), addr)),
+                _ => None,
+            })
+            .collect();
+        labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        Rom {
+            code: assembly.code,
+            labels,
+            warnings: assembly.warnings,
+        }
+    }
+}
+
+impl Rom {
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|label| label.0.eq_ignore_ascii_case(name)).map(|label| label.1)
+    }
+}
+
+// Everything that stopped a program from assembling, one per line when
+// printed.
+#[derive(Clone, Debug)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.0.iter().map(|diagnostic| diagnostic.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Error for Diagnostics {}
+
+// Assembles native source. INCLUDEs are found from the working directory,
+// and diagnostics name the source SOURCE_NAME.
+pub fn assemble(source: &str, options: &Options) -> Result<Rom, Diagnostics> {
+    assembler::assemble_source_opt(SOURCE_NAME, source, Syntax::Chip8, options)
+        .map(Rom::from)
+        .map_err(Diagnostics)
+}
+
+// Assembles a file in the syntax its extension gives.
+pub fn assemble_file(path: &str, options: &Options) -> Result<Rom, Diagnostics> {
+    assembler::assemble_file_opt(path, Syntax::for_path(path), options)
+        .map(Rom::from)
+        .map_err(Diagnostics)
+}
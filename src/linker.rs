@@ -0,0 +1,136 @@
+use interpreter::{MEMORY_SIZE, PROGRAM_START};
+use archive::Archive;
+use object::Object;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+// Puts object files together into a ROM. Modules are placed one after the
+// other from 0x200 in the order given, so the first one holds the entry
+// point. Every import has to be exported by exactly one module.
+//
+// Archive members are only linked when they export something a module
+// already in the program imports, and come after the modules given.
+
+pub struct Linked {
+    pub code: Vec<u8>,
+    // Where each module went and the address of every exported symbol.
+    pub map: String,
+}
+
+// The archive members the objects need, directly or through other members,
+// named `ARCHIVE(MEMBER)`. Archives are searched in order, and a member
+// is taken for the first symbol it can provide.
+pub fn pull_members(objects: &[(String, Object)], archives: &[(String, Archive)]) -> Vec<(String, Object)> {
+    let mut defined: HashSet<&str> = HashSet::new();
+    let mut wanted: Vec<&str> = Vec::new();
+    fn add<'a>(object: &'a Object, defined: &mut HashSet<&'a str>, wanted: &mut Vec<&'a str>) {
+        defined.extend(object.exports.iter().map(|(name, _)| name.as_str()));
+        wanted.extend(object.imports.iter().map(|name| name.as_str()));
+    }
+    for (_, object) in objects.iter() {
+        add(object, &mut defined, &mut wanted);
+    }
+    let mut pulled: Vec<(usize, usize)> = Vec::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (archive_idx, (_, archive)) in archives.iter().enumerate() {
+            for (member_idx, (_, object)) in archive.members.iter().enumerate() {
+                let needed = object.exports.iter().any(|(name, _)| !defined.contains(name.as_str()) && wanted.contains(&name.as_str()));
+                if needed && !pulled.contains(&(archive_idx, member_idx)) {
+                    pulled.push((archive_idx, member_idx));
+                    add(object, &mut defined, &mut wanted);
+                    changed = true;
+                }
+            }
+        }
+    }
+    pulled
+        .into_iter()
+        .map(|(archive_idx, member_idx)| {
+            let (path, archive) = &archives[archive_idx];
+            let (name, object) = &archive.members[member_idx];
+            (format!("{}({})", path, name), object.clone())
+        })
+        .collect()
+}
+
+// Links objects, each with the path it was read from, or returns every
+// problem found.
+pub fn link(objects: &[(String, Object)]) -> Result<Linked, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut bases = Vec::new();
+    let mut next = PROGRAM_START as usize;
+    for (_, object) in objects.iter() {
+        bases.push(next as u16);
+        next += object.code.len();
+    }
+    if next > MEMORY_SIZE {
+        errors.push(format!("The modules take {} bytes, more than the {} there is room for", next - PROGRAM_START as usize, MEMORY_SIZE - PROGRAM_START as usize));
+        return Err(errors);
+    }
+
+    let mut symbols: HashMap<&str, (u16, &str)> = HashMap::new();
+    for ((path, object), &base) in objects.iter().zip(bases.iter()) {
+        for &(ref name, offset) in object.exports.iter() {
+            let addr = match base.checked_add(offset) {
+                Some(addr) if usize::from(offset) <= object.code.len() => addr,
+                _ => {
+                    errors.push(format!("{} exports {} at {:04X}, outside its code", path, name, offset));
+                    continue;
+                }
+            };
+            match symbols.get(name.as_str()) {
+                Some(&(_, first)) => errors.push(format!("{} is exported by both {} and {}", name, first, path)),
+                None => {
+                    symbols.insert(name, (addr, path));
+                }
+            }
+        }
+    }
+
+    for (path, object) in objects.iter() {
+        for name in object.imports.iter() {
+            if !symbols.contains_key(name.as_str()) {
+                errors.push(format!("{} imports {}, which no module exports", path, name));
+            }
+        }
+    }
+
+    let mut code = Vec::new();
+    for ((_, object), &base) in objects.iter().zip(bases.iter()) {
+        let mut module = object.code.clone();
+        for reloc in object.relocs.iter() {
+            let at = usize::from(reloc.offset);
+            let opc = u16::from(module[at]) << 8 | u16::from(module[at + 1]);
+            let addr = match reloc.symbol {
+                Some(ref name) => match symbols.get(name.as_str()) {
+                    Some(&(addr, _)) => addr,
+                    None => continue,
+                },
+                None => base + (opc & 0x0FFF),
+            };
+            let opc = (opc & 0xF000) | (addr & 0x0FFF);
+            module[at] = (opc >> 8) as u8;
+            module[at + 1] = opc as u8;
+        }
+        code.extend(module);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut map = String::new();
+    writeln!(map, "MODULES").unwrap();
+    for ((path, object), &base) in objects.iter().zip(bases.iter()) {
+        writeln!(map, "0x{:03X}  {:>5}  {}  ({})", base, object.code.len(), path, object.source).unwrap();
+    }
+    let mut sorted: Vec<(u16, &str, &str)> = symbols.into_iter().map(|(name, (addr, path))| (addr, name, path)).collect();
+    sorted.sort();
+    writeln!(map, "\nSYMBOLS").unwrap();
+    for (addr, name, path) in sorted {
+        writeln!(map, "0x{:03X}  {}  {}", addr, name, path).unwrap();
+    }
+    Ok(Linked { code, map })
+}
@@ -0,0 +1,114 @@
+use assembler::{addresses, Assembly, Item};
+use interpreter::PROGRAM_START;
+use opcode::Op;
+use structured::is_generated_label;
+
+use std::fmt::Write;
+
+// A side-by-side listing of an assembled program for `--listing`:
+//
+//     ADDR   BYTES     LINE  SOURCE
+//     0x200  6004         2  LD V0, 0x4
+//     0x202  A20A         3  SCORE = SCORE + V0
+//     0x204  F065              LD V0, [I]
+//
+// Each source line is shown once, next to its first instruction; the other
+// instructions a line expands to follow it, indented and disassembled. The
+// lines of an included file are indented under a line naming it, and the
+// labels and their addresses come last.
+
+// How many bytes of data go on one line.
+const DATA_PER_LINE: usize = 4;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn row(out: &mut String, addr: Option<u16>, bytes: &[u8], line: Option<usize>, text: &str) {
+    let addr = addr.map_or(String::new(), |addr| format!("0x{:03X}", addr));
+    let line = line.map_or(String::new(), |line| line.to_string());
+    let row = format!("{:<5}  {:<8}  {:>4}  {}", addr, hex(bytes), line, text);
+    writeln!(out, "{}", row.trim_end()).unwrap();
+}
+
+pub fn listing(assembly: &Assembly) -> String {
+    let items = &assembly.items;
+    let addrs = addresses(items);
+    let mut out = String::new();
+    writeln!(out, "ADDR   BYTES     LINE  SOURCE").unwrap();
+
+    // The files being listed, the outermost first.
+    let mut files: Vec<&str> = Vec::new();
+    let mut idx = 0;
+    while idx < items.len() {
+        let item = &items[idx];
+        let file = item.loc.file.as_str();
+        if files.last() != Some(&file) {
+            match files.iter().position(|&other| other == file) {
+                Some(depth) => files.truncate(depth + 1),
+                None => {
+                    if !files.is_empty() {
+                        row(&mut out, None, &[], None, &format!("{}; {}", "    ".repeat(files.len() - 1), file));
+                    }
+                    files.push(file);
+                }
+            }
+        }
+        let indent = "    ".repeat(files.len() - 1);
+
+        // Data from one line, such as Octo's byte by byte, shares its rows.
+        let mut end = idx + 1;
+        if let Item::Data(_) = item.item {
+            while end < items.len() && items[end].loc == item.loc && matches!(items[end].item, Item::Data(_)) {
+                end += 1;
+            }
+        }
+        let len: usize = items[idx..end]
+            .iter()
+            .map(|item| match item.item {
+                Item::Instr(_) => 2,
+                Item::Data(ref data) => data.len(),
+                Item::Label(_) => 0,
+            })
+            .sum();
+        let start = usize::from(addrs[idx] - PROGRAM_START);
+        let bytes = &assembly.code[start..start + len];
+        let first = idx == 0 || items[idx - 1].loc != item.loc;
+        let text = if first && !item.text.trim().is_empty() {
+            format!("{}{}", indent, item.text.trim_end())
+        } else {
+            match item.item {
+                Item::Label(ref name) if !is_generated_label(name) => format!("{}{}:", indent, name),
+                Item::Instr(_) if !first => format!("{}  {}", indent, Op::decode(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))),
+                _ => String::new(),
+            }
+        };
+        let line = if first { Some(item.loc.line) } else { None };
+        let at = idx;
+        idx = end;
+        if bytes.is_empty() && text.is_empty() {
+            continue;
+        }
+        let mut chunks = bytes.chunks(DATA_PER_LINE);
+        row(&mut out, Some(addrs[at]), chunks.next().unwrap_or(&[]), line, &text);
+        for (offset, chunk) in chunks.enumerate() {
+            let addr = addrs[at] + ((offset + 1) * DATA_PER_LINE) as u16;
+            row(&mut out, Some(addr), chunk, None, "");
+        }
+    }
+
+    let mut symbols: Vec<(u16, &str)> = items
+        .iter()
+        .zip(addrs.iter())
+        .filter_map(|(item, &addr)| match item.item {
+            Item::Label(ref name) if !is_generated_label(name) => Some((addr, name.as_str())),
+            _ => None,
+        })
+        .collect();
+    symbols.sort();
+    writeln!(out, "\nSYMBOLS").unwrap();
+    for (addr, name) in symbols {
+        writeln!(out, "0x{:03X}  {}", addr, name).unwrap();
+    }
+    out
+}
@@ -0,0 +1,696 @@
+use assembler::{self, Diagnostic, Item, SourceItem};
+use callgraph;
+use instructions::parameters::OpParam;
+use instructions::*;
+use json::JsonValue;
+use octo;
+use opcode::Op;
+use protocol::{read_message, write_message};
+use structured::is_generated_label;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+const MNEMONICS: [(&str, &str); 32] = [
+    ("CLS", "Clear the display"),
+    ("RET", "Return from a subroutine"),
+    ("JP", "Jump to an address, optionally offset by V0"),
+    ("CALL", "Call a subroutine"),
+    ("SE", "Skip the next instruction if equal"),
+    ("SNE", "Skip the next instruction if not equal"),
+    ("LD", "Load a value into a register, I, a timer or memory"),
+    ("OR", "Bitwise OR two registers"),
+    ("AND", "Bitwise AND two registers"),
+    ("XOR", "Bitwise XOR two registers"),
+    ("ADD", "Add to a register or I"),
+    ("SUB", "Subtract a register, setting VF to NOT borrow"),
+    ("SUBN", "Reverse subtract a register, setting VF to NOT borrow"),
+    ("SHR", "Shift a register right, setting VF to the lost bit"),
+    ("SHL", "Shift a register left, setting VF to the lost bit"),
+    ("RND", "Load a masked random byte"),
+    ("DRW", "Draw a sprite from I"),
+    ("SKP", "Skip the next instruction if a key is pressed"),
+    ("SKNP", "Skip the next instruction if a key is not pressed"),
+    ("INCLUDE", "Assemble another source file in place"),
+    ("IF", "Run an instruction or block when a condition holds"),
+    ("ELSE", "Start the branch taken when the IF condition fails"),
+    ("END", "Close an IF block"),
+    ("LOOP", "Start a loop"),
+    ("AGAIN", "Jump back to the start of the loop"),
+    ("WHILE", "Leave the loop unless a condition holds"),
+    ("BREAK", "Leave the loop"),
+    ("VAR", "Declare a BYTE, BOOL or ADDR variable"),
+    ("REGISTERS", "Choose the registers compiled expressions may use"),
+    ("FUNC", "Start a function with parameters and a result"),
+    ("ENDFUNC", "End a function"),
+    ("RETURN", "Leave a function, optionally setting its result"),
+];
+
+const OPERANDS: [(&str, &str); 7] = [
+    ("I", "Address register"),
+    ("[I]", "Memory at I"),
+    ("DT", "Delay timer"),
+    ("ST", "Sound timer"),
+    ("K", "Wait for a key press"),
+    ("F", "Font sprite for a digit"),
+    ("B", "Decimal digits at I"),
+];
+
+pub struct LanguageServer<W: Write> {
+    out: W,
+    documents: HashMap<String, String>,
+    published: HashSet<String>,
+}
+
+pub fn serve<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<()> {
+    let mut server = LanguageServer::new(output);
+    while let Some(msg) = read_message(&mut input)? {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(err) => {
+                server.respond_error(&JsonValue::Null, -32700, &format!("Parse error: {}", err.0))?;
+                continue;
+            }
+        };
+        if msg.str_field("method") == Some("exit") {
+            break;
+        }
+        server.handle(&msg)?;
+    }
+    Ok(())
+}
+
+pub fn uri_to_path(uri: &str) -> String {
+    let raw = uri.trim_start_matches("file://");
+    let bytes = raw.as_bytes();
+    let mut out = Vec::new();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' {
+            let hex = raw.get(idx + 1..idx + 3).map(|hex| u8::from_str_radix(hex, 16));
+            if let Some(Ok(byte)) = hex {
+                out.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        out.push(bytes[idx]);
+        idx += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+pub fn path_to_uri(path: &str) -> String {
+    let absolute = if Path::new(path).is_absolute() {
+        path.to_owned()
+    } else {
+        env::current_dir()
+            .map(|dir| dir.join(path).to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_owned())
+    };
+    let mut uri = String::from("file://");
+    for c in absolute.chars() {
+        match c {
+            ' ' => uri.push_str("%20"),
+            '#' => uri.push_str("%23"),
+            '%' => uri.push_str("%25"),
+            '?' => uri.push_str("%3F"),
+            c => uri.push(c),
+        }
+    }
+    uri
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+// Positions count UTF-16 code units, as LSP clients do by default.
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+// Columns of every whole-word, case-insensitive occurrence of `word` before any comment.
+fn find_word(text: &str, word: &str) -> Vec<usize> {
+    let code: Vec<char> = text.split("//").next().unwrap_or("").chars().collect();
+    let word: Vec<char> = word.chars().map(|c| c.to_uppercase().next().unwrap_or(c)).collect();
+    let mut found = Vec::new();
+    let mut column = 0;
+    for start in 0..code.len() {
+        let end = start + word.len();
+        let matches = end <= code.len()
+            && code[start..end].iter().zip(word.iter()).all(|(&c, &w)| c.to_uppercase().next() == Some(w));
+        let before = start == 0 || !is_word_char(code[start - 1]);
+        let after = end >= code.len() || !is_word_char(code[end]);
+        if !word.is_empty() && matches && before && after {
+            found.push(column);
+        }
+        column += code[start].len_utf16();
+    }
+    found
+}
+
+// The number of whole characters in the first `character` UTF-16 units.
+fn char_index(chars: &[char], character: usize) -> usize {
+    let mut index = 0;
+    let mut column = 0;
+    while index < chars.len() && column + chars[index].len_utf16() <= character {
+        column += chars[index].len_utf16();
+        index += 1;
+    }
+    index
+}
+
+fn word_at(text: &str, character: usize) -> Option<(String, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let index = char_index(&chars, character);
+    let mut start = index;
+    while start > 0 && is_word_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = index;
+    while end < chars.len() && is_word_char(chars[end]) {
+        end += 1;
+    }
+    if start == end {
+        None
+    } else {
+        let word: String = chars[start..end].iter().collect();
+        let before: String = chars[..start].iter().collect();
+        Some((word, utf16_len(&before)))
+    }
+}
+
+fn range(line: usize, start: usize, end: usize) -> JsonValue {
+    let pos = |character: usize| {
+        JsonValue::object(vec![("line", line.into()), ("character", character.into())])
+    };
+    JsonValue::object(vec![("start", pos(start)), ("end", pos(end))])
+}
+
+fn location(file: &str, line: usize, start: usize, end: usize) -> JsonValue {
+    JsonValue::object(vec![("uri", path_to_uri(file).into()), ("range", range(line, start, end))])
+}
+
+// A name the assembler reads back as a label: not a mnemonic, which
+// `is_instr_line` matches by prefix, and not an operand or register, which
+// `OpParam::parse` matches by prefix too.
+fn is_valid_label(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    let reserved = MNEMONICS.iter().any(|&(m, _)| m == upper) || is_instr_line(&format!("{}:", name));
+    !name.is_empty()
+        && name.chars().all(is_word_char)
+        && !name.chars().next().unwrap().is_ascii_digit()
+        && !reserved
+        && OpParam::parse(&upper) == OpParam::Label(upper.clone())
+}
+
+type Program = (Vec<SourceItem>, Vec<Diagnostic>);
+
+// A label occurrence: the file and zero-based line and column it starts at.
+struct Occurrence {
+    file: String,
+    line: usize,
+    column: usize,
+    is_definition: bool,
+}
+
+impl<W: Write> LanguageServer<W> {
+    pub fn new(out: W) -> LanguageServer<W> {
+        LanguageServer {
+            out,
+            documents: HashMap::new(),
+            published: HashSet::new(),
+        }
+    }
+
+    fn respond(&mut self, request: &JsonValue, result: JsonValue) -> io::Result<()> {
+        let msg = JsonValue::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", request.get("id").cloned().unwrap_or(JsonValue::Null)),
+            ("result", result),
+        ]);
+        write_message(&mut self.out, &msg)
+    }
+
+    fn respond_error(&mut self, request: &JsonValue, code: i64, message: &str) -> io::Result<()> {
+        let error = JsonValue::object(vec![("code", code.into()), ("message", message.into())]);
+        let msg = JsonValue::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", request.get("id").cloned().unwrap_or(JsonValue::Null)),
+            ("error", error),
+        ]);
+        write_message(&mut self.out, &msg)
+    }
+
+    fn notify(&mut self, method: &str, params: JsonValue) -> io::Result<()> {
+        let msg = JsonValue::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ]);
+        write_message(&mut self.out, &msg)
+    }
+
+    pub fn handle(&mut self, msg: &JsonValue) -> io::Result<()> {
+        let params = msg.get("params").cloned().unwrap_or(JsonValue::Null);
+        let method = msg.str_field("method").unwrap_or("").to_owned();
+        let is_request = msg.get("id").is_some();
+
+        match method.as_str() {
+            "initialize" => {
+                let capabilities = JsonValue::object(vec![
+                    ("textDocumentSync", 1i64.into()),
+                    ("hoverProvider", true.into()),
+                    ("definitionProvider", true.into()),
+                    ("referencesProvider", true.into()),
+                    ("documentSymbolProvider", true.into()),
+                    ("renameProvider", true.into()),
+                    (
+                        "completionProvider",
+                        JsonValue::object(vec![("triggerCharacters", vec![" ", ","].into())]),
+                    ),
+                ]);
+                let info = JsonValue::object(vec![("name", "chip8-rust-compiler".into())]);
+                self.respond(msg, JsonValue::object(vec![("capabilities", capabilities), ("serverInfo", info)]))
+            }
+            "shutdown" => self.respond(msg, JsonValue::Null),
+            "textDocument/didOpen" => {
+                let doc = params.get("textDocument").cloned().unwrap_or(JsonValue::Null);
+                let path = uri_to_path(doc.str_field("uri").unwrap_or(""));
+                let text = doc.str_field("text").unwrap_or("").to_owned();
+                self.documents.insert(path, text);
+                self.publish_diagnostics()
+            }
+            "textDocument/didChange" => {
+                let path = self.doc_path(&params);
+                let changes = params.get("contentChanges").and_then(|c| c.as_array()).cloned();
+                if let Some(text) = changes.and_then(|c| c.last().and_then(|c| c.str_field("text").map(|t| t.to_owned()))) {
+                    self.documents.insert(path, text);
+                }
+                self.publish_diagnostics()
+            }
+            "textDocument/didClose" => {
+                let path = self.doc_path(&params);
+                self.documents.remove(&path);
+                self.publish_diagnostics()
+            }
+            "textDocument/hover" => {
+                let result = self.hover(&params);
+                self.respond(msg, result)
+            }
+            "textDocument/definition" => {
+                let result = self.definition(&params);
+                self.respond(msg, result)
+            }
+            "textDocument/references" => {
+                let result = self.references(&params);
+                self.respond(msg, result)
+            }
+            "textDocument/completion" => {
+                let result = self.completion(&params);
+                self.respond(msg, result)
+            }
+            "textDocument/documentSymbol" => {
+                let result = self.document_symbols(&params);
+                self.respond(msg, result)
+            }
+            "textDocument/rename" => match self.rename(&params) {
+                Ok(result) => self.respond(msg, result),
+                Err(message) => self.respond_error(msg, -32602, &message),
+            },
+            _ if is_request => self.respond_error(msg, -32601, &format!("Unsupported method: {}", method)),
+            _ => Ok(()),
+        }
+    }
+
+    fn doc_path(&self, params: &JsonValue) -> String {
+        let uri = params.get("textDocument").and_then(|doc| doc.str_field("uri")).unwrap_or("");
+        uri_to_path(uri)
+    }
+
+    fn position(&self, params: &JsonValue) -> (String, usize, usize) {
+        let pos = params.get("position").cloned().unwrap_or(JsonValue::Null);
+        let line = pos.int_field("line").unwrap_or(0).max(0) as usize;
+        let character = pos.int_field("character").unwrap_or(0).max(0) as usize;
+        (self.doc_path(params), line, character)
+    }
+
+    fn load(&self, path: &str) -> io::Result<String> {
+        match self.documents.get(path) {
+            Some(text) => Ok(text.clone()),
+            None => assembler::read_source(path),
+        }
+    }
+
+    fn parse(&self, root: &str) -> Program {
+        let text = self.load(root).unwrap_or_default();
+        assembler::parse_source_with(root, &text, &mut |path: &str| self.load(path))
+    }
+
+    // Open documents that no other open document includes are assembled as programs.
+    fn programs(&self) -> Vec<Program> {
+        let mut parsed: Vec<(String, Program)> = Vec::new();
+        let mut paths: Vec<&String> = self.documents.keys().collect();
+        paths.sort();
+        for path in paths {
+            parsed.push((path.clone(), self.parse(path)));
+        }
+
+        let mut included = HashSet::new();
+        for (root, (items, _)) in parsed.iter() {
+            for item in items.iter() {
+                if &item.loc.file != root {
+                    included.insert(item.loc.file.clone());
+                }
+            }
+        }
+        parsed
+            .into_iter()
+            .filter(|(root, _)| !included.contains(root))
+            .map(|(_, program)| program)
+            .collect()
+    }
+
+    fn program_for(&self, path: &str) -> Vec<SourceItem> {
+        self.programs()
+            .into_iter()
+            .map(|(items, _)| items)
+            .find(|items| items.iter().any(|item| item.loc.file == path))
+            .unwrap_or_else(|| self.parse(path).0)
+    }
+
+    fn line_text(&self, path: &str, line: usize) -> String {
+        self.load(path)
+            .ok()
+            .and_then(|text| text.lines().nth(line).map(|l| l.to_owned()))
+            .unwrap_or_default()
+    }
+
+    fn publish_diagnostics(&mut self) -> io::Result<()> {
+        let mut by_file: BTreeMap<String, Vec<JsonValue>> = BTreeMap::new();
+        for path in self.documents.keys() {
+            by_file.insert(path.clone(), Vec::new());
+        }
+        for (items, errors) in self.programs() {
+            // Errors are severity 1 and warnings 2.
+            let mut found: Vec<(Diagnostic, i64)> = errors.into_iter().map(|err| (err, 1)).collect();
+            match assembler::assemble_items(items, callgraph::STACK_LIMIT) {
+                Ok(assembly) => found.extend(assembly.warnings.into_iter().map(|warning| (warning, 2))),
+                Err(more) => found.extend(more.into_iter().map(|err| (err, 1))),
+            }
+            for (err, severity) in found {
+                let line = err.loc.line.saturating_sub(1);
+                let width = utf16_len(&self.line_text(&err.loc.file, line));
+                let diag = JsonValue::object(vec![
+                    ("range", range(line, 0, width)),
+                    ("severity", severity.into()),
+                    ("source", "chip8".into()),
+                    ("message", err.message.into()),
+                ]);
+                by_file.entry(err.loc.file).or_default().push(diag);
+            }
+        }
+
+        // Files that had diagnostics before need an empty list to clear them.
+        for path in self.published.iter() {
+            by_file.entry(path.clone()).or_default();
+        }
+        self.published = by_file.iter().filter(|(_, diags)| !diags.is_empty()).map(|(p, _)| p.clone()).collect();
+
+        for (path, diags) in by_file {
+            let params = JsonValue::object(vec![
+                ("uri", path_to_uri(&path).into()),
+                ("diagnostics", JsonValue::Array(diags)),
+            ]);
+            self.notify("textDocument/publishDiagnostics", params)?;
+        }
+        Ok(())
+    }
+
+    fn occurrences(&self, items: &[SourceItem], name: &str) -> Vec<Occurrence> {
+        let mut found = Vec::new();
+        for item in items.iter() {
+            let (matches, is_definition) = match item.item {
+                Item::Label(ref label) => (label == name, true),
+                Item::Instr(ref instr) => (instr.label_refs().contains(&name), false),
+                Item::Data(_) => (false, false),
+            };
+            if !matches {
+                continue;
+            }
+            for column in find_word(&item.text, name) {
+                found.push(Occurrence {
+                    file: item.loc.file.clone(),
+                    line: item.loc.line - 1,
+                    column,
+                    is_definition,
+                });
+            }
+        }
+        found
+    }
+
+    // The files a program was parsed from, in the order they were first used.
+    fn files(items: &[SourceItem]) -> Vec<String> {
+        let mut files: Vec<String> = Vec::new();
+        for item in items.iter() {
+            if !files.contains(&item.loc.file) {
+                files.push(item.loc.file.clone());
+            }
+        }
+        files
+    }
+
+    // Octo `:const`s are replaced as the file is parsed, so they are found in
+    // the text: every whole token outside a `#` comment, defined where it
+    // follows `:const`.
+    fn constant_occurrences(&self, items: &[SourceItem], name: &str) -> Vec<Occurrence> {
+        let mut found = Vec::new();
+        for file in Self::files(items) {
+            let text = self.load(&file).unwrap_or_default();
+            if !octo::constants(&text).iter().any(|(known, _)| known == name) {
+                continue;
+            }
+            let mut previous = String::new();
+            for (line, ln) in text.lines().enumerate() {
+                let code = ln.split('#').next().unwrap_or("");
+                let mut column = 0;
+                for word in code.split(|c: char| c.is_whitespace()) {
+                    if word == name {
+                        found.push(Occurrence {
+                            file: file.clone(),
+                            line,
+                            column,
+                            is_definition: previous == ":const",
+                        });
+                    }
+                    if !word.is_empty() {
+                        previous = word.to_owned();
+                    }
+                    column += utf16_len(word) + 1;
+                }
+            }
+        }
+        found
+    }
+
+    // The label or constant under the cursor and everywhere it appears.
+    fn symbol_at(&self, params: &JsonValue) -> Option<(String, Vec<Occurrence>)> {
+        let (path, line, character) = self.position(params);
+        let (word, _) = word_at(&self.line_text(&path, line), character)?;
+        let name = word.to_uppercase();
+        let items = self.program_for(&path);
+        let known = items.iter().any(|item| match item.item {
+            Item::Label(ref label) => *label == name,
+            _ => false,
+        });
+        if known {
+            return Some((name.clone(), self.occurrences(&items, &name)));
+        }
+        let constants = self.constant_occurrences(&items, &word);
+        if constants.is_empty() {
+            None
+        } else {
+            Some((word, constants))
+        }
+    }
+
+    fn hover(&self, params: &JsonValue) -> JsonValue {
+        let (path, line, character) = self.position(params);
+        let items = self.program_for(&path);
+        let labels = assembler::layout(&items);
+        let addrs = assembler::addresses(&items);
+
+        if let Some((word, _)) = word_at(&self.line_text(&path, line), character) {
+            if let Some(&OpParam::Variable(addr)) = labels.get(&OpParam::Label(word.to_uppercase())) {
+                let text = format!("`{}` = `0x{:03X}`", word.to_uppercase(), addr);
+                return JsonValue::object(vec![("contents", JsonValue::object(vec![
+                    ("kind", "markdown".into()),
+                    ("value", text.into()),
+                ]))]);
+            }
+            for file in Self::files(&items) {
+                let constants = octo::constants(&self.load(&file).unwrap_or_default());
+                if let Some((_, value)) = constants.into_iter().rev().find(|(name, _)| *name == word) {
+                    let text = format!("`{}` = `{}`", word, value);
+                    return JsonValue::object(vec![("contents", JsonValue::object(vec![
+                        ("kind", "markdown".into()),
+                        ("value", text.into()),
+                    ]))]);
+                }
+            }
+        }
+
+        let found = items
+            .iter()
+            .zip(addrs.iter())
+            .find(|(item, _)| item.loc.file == path && item.loc.line == line + 1);
+        if let Some((item, &addr)) = found {
+            if let Item::Instr(ref instr) = item.item {
+                let resolved = instr.resolve_labels(&labels);
+                if resolved.label_refs().is_empty() {
+                    let opc = resolved.to_opcode();
+                    let op = Op::decode(opc);
+                    let text = format!(
+                        "```chip8\n{}\n```\n`0x{:04X}` at `0x{:03X}`\n\n{}",
+                        op,
+                        opc,
+                        addr,
+                        op.describe()
+                    );
+                    return JsonValue::object(vec![("contents", JsonValue::object(vec![
+                        ("kind", "markdown".into()),
+                        ("value", text.into()),
+                    ]))]);
+                }
+            }
+        }
+        JsonValue::Null
+    }
+
+    fn definition(&self, params: &JsonValue) -> JsonValue {
+        let (name, occurrences) = match self.symbol_at(params) {
+            Some(found) => found,
+            None => return JsonValue::Null,
+        };
+        occurrences
+            .iter()
+            .find(|occ| occ.is_definition)
+            .map(|occ| location(&occ.file, occ.line, occ.column, occ.column + utf16_len(&name)))
+            .unwrap_or(JsonValue::Null)
+    }
+
+    fn references(&self, params: &JsonValue) -> JsonValue {
+        let include_declaration = params
+            .get("context")
+            .and_then(|ctx| ctx.get("includeDeclaration"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        let (name, occurrences) = match self.symbol_at(params) {
+            Some(found) => found,
+            None => return JsonValue::Array(Vec::new()),
+        };
+        let refs = occurrences
+            .iter()
+            .filter(|occ| include_declaration || !occ.is_definition)
+            .map(|occ| location(&occ.file, occ.line, occ.column, occ.column + utf16_len(&name)))
+            .collect();
+        JsonValue::Array(refs)
+    }
+
+    fn rename(&self, params: &JsonValue) -> Result<JsonValue, String> {
+        let new_name = params.str_field("newName").unwrap_or("").trim().to_owned();
+        if !is_valid_label(&new_name) {
+            return Err(format!("`{}` is not a valid label name", new_name));
+        }
+        let (name, occurrences) = self.symbol_at(params).ok_or_else(|| "No label or constant at this position".to_owned())?;
+        let (path, _, _) = self.position(params);
+        let upper = new_name.to_uppercase();
+        let taken = upper != name.to_uppercase()
+            && self.program_for(&path).iter().any(|item| match item.item {
+                Item::Label(ref label) => *label == upper,
+                _ => false,
+            });
+        if taken {
+            return Err(format!("`{}` is already a label", new_name));
+        }
+
+        let mut changes: BTreeMap<String, Vec<JsonValue>> = BTreeMap::new();
+        for occ in occurrences {
+            let edit = JsonValue::object(vec![
+                ("range", range(occ.line, occ.column, occ.column + utf16_len(&name))),
+                ("newText", new_name.clone().into()),
+            ]);
+            changes.entry(path_to_uri(&occ.file)).or_default().push(edit);
+        }
+        let changes = changes.into_iter().map(|(uri, edits)| (uri, JsonValue::Array(edits))).collect();
+        Ok(JsonValue::object(vec![("changes", JsonValue::Object(changes))]))
+    }
+
+    fn completion(&self, params: &JsonValue) -> JsonValue {
+        let (path, line, character) = self.position(params);
+        let text = self.line_text(&path, line);
+        let chars: Vec<char> = text.chars().collect();
+        let before: String = chars[..char_index(&chars, character)].iter().collect();
+        let item = |label: &str, kind: i64, detail: &str| {
+            JsonValue::object(vec![
+                ("label", label.into()),
+                ("kind", kind.into()),
+                ("detail", detail.into()),
+            ])
+        };
+
+        let mut items = Vec::new();
+        if before.trim_start().chars().all(is_word_char) {
+            for &(mnemonic, detail) in MNEMONICS.iter() {
+                items.push(item(mnemonic, 14, detail));
+            }
+            return JsonValue::Array(items);
+        }
+
+        for reg in 0..16 {
+            items.push(item(&format!("V{:X}", reg), 6, "Register"));
+        }
+        for &(operand, detail) in OPERANDS.iter() {
+            items.push(item(operand, 6, detail));
+        }
+        let labels = assembler::layout(&self.program_for(&path));
+        let mut names: Vec<(&String, u16)> = labels
+            .iter()
+            .filter_map(|(label, value)| match (label, value) {
+                (OpParam::Label(name), &OpParam::Variable(addr)) if !is_generated_label(name) => Some((name, addr)),
+                _ => None,
+            })
+            .collect();
+        names.sort();
+        for (name, addr) in names {
+            items.push(item(name, 18, &format!("Label at 0x{:03X}", addr)));
+        }
+        JsonValue::Array(items)
+    }
+
+    fn document_symbols(&self, params: &JsonValue) -> JsonValue {
+        let path = self.doc_path(params);
+        let items = self.program_for(&path);
+        let symbols = items
+            .iter()
+            .filter(|item| item.loc.file == path)
+            .filter_map(|item| match item.item {
+                Item::Label(ref name) if !is_generated_label(name) => {
+                    let column = find_word(&item.text, name).first().cloned().unwrap_or(0);
+                    Some(JsonValue::object(vec![
+                        ("name", name.clone().into()),
+                        ("kind", 12i64.into()),
+                        ("location", location(&path, item.loc.line - 1, column, column + utf16_len(name))),
+                    ]))
+                }
+                _ => None,
+            })
+            .collect();
+        JsonValue::Array(symbols)
+    }
+}
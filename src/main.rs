@@ -1,48 +1,467 @@
-pub mod instructions;
-use instructions::*;
+extern crate chip8_rust_compiler;
+
+use chip8_rust_compiler::assembler::{self, Options, Syntax};
+use chip8_rust_compiler::archive::{self, Archive};
+use chip8_rust_compiler::object::{self, Object};
+use chip8_rust_compiler::output::{self, OutputFormat};
+use chip8_rust_compiler::sprite::{self, SpriteSize};
+use chip8_rust_compiler::symbols::{self, SymbolFormat};
+use chip8_rust_compiler::interpreter::{script, terminal};
+use chip8_rust_compiler::interpreter::trace::{self, TraceFilter, TraceFormat, TraceRecord, Tracer};
+use chip8_rust_compiler::interpreter::state::History;
+use chip8_rust_compiler::interpreter::{Machine, StepResult};
+use chip8_rust_compiler::{callgraph, dap, ir, linker, listing, lsp};
 
 use std::env::*;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io;
 use std::io::prelude::*;
+use std::process;
+
+const DEFAULT_RUN_CYCLES: u64 = 100_000;
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn read_or_fail(path: &str) -> String {
+    assembler::read_source(path).unwrap_or_else(|err| fail(&format!("Could not read {}: {}", path, err)))
+}
+
+// Writes to a file, or to stdout for `-`.
+fn write_or_fail(path: &str, text: &str) {
+    let written = if path == "-" {
+        io::stdout().write_all(text.as_bytes())
+    } else {
+        fs::write(path, text)
+    };
+    written.unwrap_or_else(|err| fail(&format!("Could not write {}: {}", path, err)));
+}
+
+// How a `--run` should save its final state: the file, and how many frames
+// to rewind first.
+struct SaveState {
+    path: String,
+    rewind: usize,
+}
+
+fn run_program(assembly: &assembler::Assembly, machine: Machine, max_cycles: u64, tracer: Option<Tracer<Box<dyn Write>>>, save: Option<SaveState>) {
+    let mut machine = machine;
+    let mut tracer = tracer;
+    // The machine as it was at the start of each recent frame.
+    let mut history = History::new(save.as_ref().map_or(0, |save| save.rewind + 1));
+    let mut last_frame = None;
+    let mut error = None;
+    let max_cycles = machine.cycles + max_cycles;
+    while machine.cycles < max_cycles {
+        if last_frame != Some(machine.frames) {
+            history.push(&machine);
+            last_frame = Some(machine.frames);
+        }
+        // Only a trace needs the machine as it was before the step.
+        let before = tracer.as_ref().map(|_| machine.clone());
+        match machine.step() {
+            Ok(StepResult::Executed(_)) => {
+                if let (Some(tracer), Some(before)) = (tracer.as_mut(), before) {
+                    let opcode = before.fetch().unwrap_or(0);
+                    let record = TraceRecord::new(&before, &machine, opcode, Some(assembly));
+                    tracer.record(&record).unwrap_or_else(|err| fail(&format!("Could not write trace: {}", err)));
+                }
+            }
+            Ok(StepResult::WaitingForKey) => {
+                eprintln!("Stopped at 0x{:03X} after {} cycles: waiting for a key press", machine.pc, machine.cycles);
+                break;
+            }
+            Err(err) => {
+                error = Some(format!("Stopped at 0x{:03X} after {} cycles: {}", err.pc, machine.cycles, err.message));
+                break;
+            }
+        }
+    }
+    drop(tracer);
+
+    if let Some(save) = save {
+        let rewound = match history.len().checked_sub(1) {
+            Some(oldest) if save.rewind > 0 => history.back(save.rewind.min(oldest)),
+            _ => None,
+        };
+        let state = rewound.as_ref().unwrap_or(&machine);
+        if let Err(err) = File::create(&save.path).and_then(|mut fobj| fobj.write_all(&state.save_state())) {
+            fail(&format!("Could not write {}: {}", save.path, err));
+        }
+        eprintln!("Saved the state at 0x{:03X} after {} cycles to {}", state.pc, state.cycles, save.path);
+    }
+    match error {
+        Some(message) => fail(&message),
+        None if machine.cycles >= max_cycles => {
+            eprintln!("Stopped at 0x{:03X} after {} cycles", machine.pc, machine.cycles)
+        }
+        None => {}
+    }
+}
 
 fn main() {
     let run_args : Vec<String> = args().collect();
     let mut idx = 1;
     let mut inp_file = "roms/tapereader.chip8";
     let mut out_file = "a.c8";
+    let mut out_given = false;
+    let mut dap_mode = false;
+    let mut lsp_mode = false;
+    let mut run_mode = false;
+    let mut play_mode = false;
+    let mut seed = None;
+    let mut max_cycles = DEFAULT_RUN_CYCLES;
+    let mut trace_file: Option<String> = None;
+    let mut trace_format: Option<TraceFormat> = None;
+    let mut trace_ranges: Vec<String> = Vec::new();
+    let mut trace_labels: Vec<String> = Vec::new();
+    let mut trace_diff: Option<(String, String)> = None;
+    let mut test_scripts: Vec<String> = Vec::new();
+    let mut load_state: Option<String> = None;
+    let mut save_state: Option<String> = None;
+    let mut rewind = 0;
+    let mut syntax: Option<Syntax> = None;
+    let mut options = Options::default();
+    let mut out_format: Option<OutputFormat> = None;
+    let mut listing_file: Option<String> = None;
+    let mut object_mode = false;
+    let mut link_files: Vec<String> = Vec::new();
+    let mut archive_files: Vec<String> = Vec::new();
+    let mut map_file: Option<String> = None;
+    let mut symbols_file: Option<String> = None;
+    let mut symbols_format: Option<SymbolFormat> = None;
+    let mut line_map_file: Option<String> = None;
+    let mut dot_file: Option<String> = None;
+    let mut sprite_file: Option<String> = None;
+    let mut sprite_size: Option<String> = None;
+    let mut sprite_planes = 1;
+    let mut dot_label: Option<String> = None;
     while idx < run_args.len() {
         let cur_arg = &run_args[idx];
         if cur_arg == "-o" || cur_arg == "--output" {
             idx += 1;
             out_file = &run_args[idx];
+            out_given = true;
+        }
+        else if cur_arg == "--dap" {
+            dap_mode = true;
+        }
+        else if cur_arg == "--lsp" {
+            lsp_mode = true;
+        }
+        else if cur_arg == "--run" {
+            run_mode = true;
+        }
+        else if cur_arg == "--play" {
+            run_mode = true;
+            play_mode = true;
+        }
+        else if cur_arg == "--seed" {
+            idx += 1;
+            seed = Some(run_args[idx].parse().unwrap_or_else(|_| fail("--seed takes a number")));
+        }
+        else if cur_arg == "--cycles" {
+            idx += 1;
+            max_cycles = run_args[idx].parse().unwrap_or_else(|_| fail("--cycles takes a number"));
+        }
+        else if cur_arg == "--trace" {
+            idx += 1;
+            run_mode = true;
+            trace_file = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--trace-format" {
+            idx += 1;
+            trace_format = Some(TraceFormat::parse(&run_args[idx]).unwrap_or_else(|| fail("--trace-format is text or json")));
+        }
+        else if cur_arg == "--trace-range" {
+            idx += 1;
+            trace_ranges.push(run_args[idx].clone());
+        }
+        else if cur_arg == "--trace-label" {
+            idx += 1;
+            trace_labels.push(run_args[idx].clone());
+        }
+        else if cur_arg == "--trace-diff" {
+            trace_diff = Some((run_args[idx + 1].clone(), run_args[idx + 2].clone()));
+            idx += 2;
+        }
+        else if cur_arg == "--load-state" {
+            idx += 1;
+            run_mode = true;
+            load_state = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--save-state" {
+            idx += 1;
+            run_mode = true;
+            save_state = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--rewind" {
+            idx += 1;
+            rewind = run_args[idx].parse().unwrap_or_else(|_| fail("--rewind takes a number of frames"));
+        }
+        else if cur_arg == "--syntax" {
+            idx += 1;
+            syntax = Some(Syntax::parse(&run_args[idx]).unwrap_or_else(|| fail("--syntax is chip8, octo or lang")));
+        }
+        else if cur_arg == "-O" || cur_arg == "--optimize" {
+            options.optimize = true;
+        }
+        else if cur_arg == "--stack-limit" {
+            idx += 1;
+            options.stack_limit = match run_args[idx].as_str() {
+                "vip" => callgraph::VIP_STACK_LIMIT,
+                limit => limit.parse().unwrap_or_else(|_| fail("--stack-limit takes a number or vip")),
+            };
+        }
+        else if cur_arg == "--format" {
+            idx += 1;
+            out_format = Some(OutputFormat::parse(&run_args[idx]).unwrap_or_else(|| fail("--format is raw, ihex, srec, dump, c, rust or octo")));
+        }
+        else if cur_arg == "-c" || cur_arg == "--object" {
+            object_mode = true;
+        }
+        else if cur_arg == "--link" {
+            idx += 1;
+            link_files.push(run_args[idx].clone());
+        }
+        else if cur_arg == "--archive" {
+            idx += 1;
+            archive_files.push(run_args[idx].clone());
+        }
+        else if cur_arg == "--map" {
+            idx += 1;
+            map_file = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--listing" {
+            idx += 1;
+            listing_file = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--symbols" {
+            idx += 1;
+            symbols_file = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--symbols-format" {
+            idx += 1;
+            symbols_format = Some(SymbolFormat::parse(&run_args[idx]).unwrap_or_else(|| fail("--symbols-format is plain, json or sym")));
+        }
+        else if cur_arg == "--line-map" {
+            idx += 1;
+            line_map_file = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--dot" {
+            idx += 1;
+            dot_file = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--dot-label" {
+            idx += 1;
+            dot_label = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--sprite-import" {
+            idx += 1;
+            sprite_file = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--sprite-size" {
+            idx += 1;
+            sprite_size = Some(run_args[idx].clone());
+        }
+        else if cur_arg == "--sprite-planes" {
+            idx += 1;
+            sprite_planes = run_args[idx].parse().unwrap_or_else(|_| fail("--sprite-planes takes 1 or 2"));
+        }
+        else if cur_arg == "--test" {
+            idx += 1;
+            test_scripts.push(run_args[idx].clone());
         }
         else {
             inp_file = cur_arg;
         }
         idx += 1;
     }
-    
-    let mut inp_fobj = File::open(inp_file).unwrap();
-    let mut source = String::new();
-    let _read_result = inp_fobj.read_to_string(&mut source).unwrap();
 
+    if dap_mode {
+        dap::serve(io::stdin(), io::stdout()).unwrap_or_else(|err| fail(&format!("Debug adapter stopped: {}", err)));
+        return;
+    }
+    if lsp_mode {
+        let stdin = io::stdin();
+        lsp::serve(stdin.lock(), io::stdout()).unwrap_or_else(|err| fail(&format!("Language server stopped: {}", err)));
+        return;
+    }
+    if !test_scripts.is_empty() {
+        let mut failed = 0;
+        for path in test_scripts.iter() {
+            let failures = script::run_file(path, options.optimize);
+            if failures.is_empty() {
+                println!("PASS {}", path);
+            } else {
+                failed += 1;
+                println!("FAIL {}", path);
+                for failure in failures {
+                    println!("    {}", failure);
+                }
+            }
+        }
+        if failed > 0 {
+            println!("{} of {} scripts failed", failed, test_scripts.len());
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some((left, right)) = trace_diff {
+        match trace::diff_traces(&read_or_fail(&left), &read_or_fail(&right)) {
+            Ok(None) => println!("Traces are identical"),
+            Ok(Some(report)) => {
+                println!("{}", report);
+                process::exit(1);
+            }
+            Err(err) => fail(&format!("Could not parse trace: {}", err)),
+        }
+        return;
+    }
 
-    let label_map = parse_labels(&source);
-    println!("Labels: {:?}", label_map);
-    let mut code : Vec<u8> = Vec::new();
+    if let Some(path) = sprite_file {
+        let size = sprite_size.unwrap_or_else(|| fail("--sprite-import needs a --sprite-size such as 8x6"));
+        let size = SpriteSize::parse(&size, sprite_planes).unwrap_or_else(|err| fail(&err));
+        let sprites = sprite::import(&path, size).unwrap_or_else(|err| fail(&err));
+        write_or_fail(if out_given { out_file } else { "-" }, &sprite::to_octo(&path, size, &sprites));
+        return;
+    }
 
-    for ln in source.lines() {
-        if !is_instr_line(ln) {
-            continue;
+    let format = out_format.unwrap_or_else(|| OutputFormat::for_path(out_file));
+    if !archive_files.is_empty() {
+        let members = archive_files
+            .iter()
+            .map(|path| {
+                let object = Object::parse(&read_or_fail(path)).unwrap_or_else(|err| fail(&format!("{}: {}", path, err)));
+                let name = path.rsplit(['/', '\\']).next().unwrap_or(path).to_owned();
+                (name, object)
+            })
+            .collect();
+        write_or_fail(out_file, &Archive { members }.to_text());
+        return;
+    }
+    if !link_files.is_empty() {
+        let mut objects: Vec<(String, Object)> = Vec::new();
+        let mut archives: Vec<(String, Archive)> = Vec::new();
+        for path in link_files.iter() {
+            let text = read_or_fail(path);
+            let parsed = if archive::is_archive(&text) {
+                Archive::parse(&text).map(|archive| archives.push((path.clone(), archive)))
+            } else {
+                Object::parse(&text).map(|object| objects.push((path.clone(), object)))
+            };
+            parsed.unwrap_or_else(|err| fail(&format!("{}: {}", path, err)));
+        }
+        let members = linker::pull_members(&objects, &archives);
+        objects.extend(members);
+        let linked = linker::link(&objects).unwrap_or_else(|errors| fail(&errors.join("\n")));
+        if let Some(path) = map_file {
+            write_or_fail(&path, &linked.map);
         }
-        let instr = Instruction::parse_args(ln).unwrap().resolve_labels(&label_map);
-        let opc = instr.to_opcode();
-        code.push(((opc & 0xFF00) >> 8) as u8);
-        code.push( (opc & 0x00FF) as u8);
+        let mut out_fobj = File::create(out_file).unwrap();
+        out_fobj.write_all(&output::encode(&linked.code, format, out_file)).unwrap();
+        return;
     }
 
-    let mut out_fobj = File::create(out_file).unwrap();
+    let syntax = syntax.unwrap_or_else(|| Syntax::for_path(inp_file));
+    if object_mode {
+        match object::compile_file(inp_file, syntax, &options) {
+            Ok(object) => write_or_fail(out_file, &object.to_text()),
+            Err(errors) => {
+                for err in errors {
+                    eprintln!("{}", err);
+                }
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    let assembly = match assembler::assemble_file_opt(inp_file, syntax, &options) {
+        Ok(assembly) => assembly,
+        Err(errors) => {
+            for err in errors {
+                eprintln!("{}", err);
+            }
+            process::exit(1);
+        }
+    };
+    for warning in assembly.warnings.iter() {
+        eprintln!("{}: warning: {}", warning.loc, warning.message);
+    }
+
+    if let Some(path) = listing_file {
+        write_or_fail(&path, &listing::listing(&assembly));
+    }
+    if let Some(path) = symbols_file {
+        let format = symbols_format.unwrap_or_else(|| SymbolFormat::for_path(&path));
+        write_or_fail(&path, &symbols::symbols(&assembly, format));
+    }
+    if let Some(path) = line_map_file {
+        write_or_fail(&path, &symbols::line_map(&assembly, path.ends_with(".json")));
+    }
+
+    if let Some(path) = dot_file {
+        let program = ir::Program::new(assembly.items.clone());
+        let (name, blocks) = match dot_label {
+            Some(label) => match program.block_for_label(&label) {
+                Some(start) => (label.to_uppercase(), program.reachable(start)),
+                None => fail(&format!("Unknown label {}", label)),
+            },
+            None => (inp_file.to_owned(), (0..program.blocks.len()).collect()),
+        };
+        write_or_fail(&path, &program.to_dot(&name, &blocks));
+        return;
+    }
+
+    if run_mode {
+        let tracer = trace_file.map(|path| {
+            let mut filter = TraceFilter::default();
+            for range in trace_ranges.iter() {
+                filter.add_range(range).unwrap_or_else(|err| fail(&err));
+            }
+            for label in trace_labels.iter() {
+                filter.add_label(&assembly, label).unwrap_or_else(|err| fail(&err));
+            }
+            let format = trace_format.unwrap_or_else(|| TraceFormat::for_path(&path));
+            let out: Box<dyn Write> = if path == "-" {
+                Box::new(io::stdout())
+            } else {
+                let fobj = File::create(&path).unwrap_or_else(|err| fail(&format!("Could not create {}: {}", path, err)));
+                Box::new(io::BufWriter::new(fobj))
+            };
+            Tracer::new(out, format, filter)
+        });
+        let machine = match load_state {
+            Some(path) => {
+                let data = fs::read(&path).unwrap_or_else(|err| fail(&format!("Could not read {}: {}", path, err)));
+                Machine::load_state(&data).unwrap_or_else(|err| fail(&format!("Could not load {}: {}", path, err)))
+            }
+            None => {
+                let mut machine = match seed {
+                    Some(seed) => Machine::with_seed(seed),
+                    None => Machine::new(),
+                };
+                if let Err(err) = machine.load_rom(&assembly.code) {
+                    fail(&err.message);
+                }
+                machine
+            }
+        };
+        if play_mode {
+            let stdin = io::stdin();
+            terminal::play(machine, stdin.lock(), io::stdout()).unwrap_or_else(|err| fail(&err.to_string()));
+            return;
+        }
+        let save = save_state.map(|path| SaveState { path, rewind });
+        run_program(&assembly, machine, max_cycles, tracer, save);
+        return;
+    }
+
+    println!("Labels: {:?}", assembly.labels);
 
-    let _write_result = out_fobj.write_all(&mut code).unwrap();
+    let mut out_fobj = File::create(out_file).unwrap();
 
-}
\ No newline at end of file
+    out_fobj.write_all(&output::encode(&assembly.code, format, out_file)).unwrap();
+}
@@ -0,0 +1,249 @@
+use assembler::{self, check_fits, duplicate_labels, layout, Diagnostic, Item, Options, SourceItem, SourceLoc, Syntax};
+use instructions::parameters::OpParam;
+use instructions::{InstructionOps, InstructionOpsWithLabels};
+use interpreter::PROGRAM_START;
+use opcode::Op;
+use optimize;
+use structured::is_generated_label;
+
+use std::fmt::Write;
+
+// Object files, assembled from one source file for `--link` to put together
+// with others:
+//
+//     DRAW::              // exported, so other modules can use it
+//         CALL CLEAR      // imported: CLEAR is not defined here
+//
+// A native label written with `::` is exported; every label of an Octo file
+// is. A label used but not defined is imported. The code is kept as if the
+// module started at address 0, and each 12-bit address field that holds a
+// label (`JP`, `JP V0`, `CALL`, `LD I`) gets a relocation: either the
+// module's own address added once it is placed, or the address of an
+// imported symbol. The file is text:
+//
+//     CHIP8-OBJECT 1
+//     SOURCE game/draw.chip8
+//     CODE 0006
+//     2000 1202 00EE
+//     EXPORT DRAW 0000
+//     IMPORT CLEAR
+//     RELOC 0000 CLEAR
+//     RELOC 0002
+
+const MAGIC: &str = "CHIP8-OBJECT";
+const VERSION: u32 = 1;
+
+// Bytes on a line of the CODE section.
+const CODE_PER_LINE: usize = 16;
+
+// A 12-bit address field to fill in when linking.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reloc {
+    // Where the instruction starts, from the start of the module.
+    pub offset: u16,
+    // The imported symbol to put there, or none to add the module's address.
+    pub symbol: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object {
+    pub source: String,
+    pub code: Vec<u8>,
+    // Exported labels and their offsets in the module.
+    pub exports: Vec<(String, u16)>,
+    pub imports: Vec<String>,
+    pub relocs: Vec<Reloc>,
+}
+
+fn is_exported(item: &SourceItem) -> bool {
+    match item.item {
+        Item::Label(ref name) if !is_generated_label(name) => {
+            Syntax::for_path(&item.loc.file) == Syntax::Octo || item.text.split("//").next().unwrap_or("").trim().ends_with("::")
+        }
+        _ => false,
+    }
+}
+
+fn has_address_field(op: &Op) -> bool {
+    matches!(*op, Op::Jump(_) | Op::Call(_) | Op::LoadI(_) | Op::JumpV0(_))
+}
+
+// Assembles parsed items into an object.
+pub fn compile_items(source: &str, items: Vec<SourceItem>) -> Result<Object, Vec<Diagnostic>> {
+    check_fits(&items).map_err(|err| vec![err])?;
+    let mut errors = duplicate_labels(&items);
+    let mut labels = layout(&items);
+    let local = labels.clone();
+    let mut imports: Vec<String> = Vec::new();
+    for item in items.iter() {
+        if let Item::Instr(ref instr) = item.item {
+            for name in instr.label_refs() {
+                if !local.contains_key(&OpParam::Label(name.to_owned())) && !imports.iter().any(|import| import == name) {
+                    imports.push(name.to_owned());
+                }
+            }
+        }
+    }
+    for name in imports.iter() {
+        labels.insert(OpParam::Label(name.clone()), OpParam::Variable(0));
+    }
+
+    let mut object = Object {
+        source: source.to_owned(),
+        code: Vec::new(),
+        exports: Vec::new(),
+        imports,
+        relocs: Vec::new(),
+    };
+    for item in items.iter() {
+        let offset = object.code.len() as u16;
+        match item.item {
+            Item::Label(ref name) => {
+                if is_exported(item) {
+                    object.exports.push((name.clone(), offset));
+                }
+            }
+            Item::Data(ref bytes) => object.code.extend_from_slice(bytes),
+            Item::Instr(ref instr) => {
+                let mut opc = instr.resolve_labels(&labels).to_opcode();
+                if let Some(&name) = instr.label_refs().first() {
+                    if !has_address_field(&Op::decode(opc)) {
+                        errors.push(Diagnostic {
+                            loc: item.loc.clone(),
+                            message: format!("Label {} can only be linked into a JP, CALL or LD I address", name),
+                        });
+                    } else if object.imports.iter().any(|import| import == name) {
+                        object.relocs.push(Reloc {
+                            offset,
+                            symbol: Some(name.to_owned()),
+                        });
+                    } else if let Some(&OpParam::Variable(addr)) = local.get(&OpParam::Label(name.to_owned())) {
+                        // The module fits in memory, so its labels are all
+                        // at PROGRAM_START or after.
+                        opc = (opc & 0xF000) | (addr - PROGRAM_START);
+                        object.relocs.push(Reloc { offset, symbol: None });
+                    }
+                }
+                object.code.push((opc >> 8) as u8);
+                object.code.push(opc as u8);
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(object)
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn compile_file(path: &str, syntax: Syntax, options: &Options) -> Result<Object, Vec<Diagnostic>> {
+    let source = assembler::read_source(path).map_err(|err| {
+        vec![Diagnostic {
+            loc: SourceLoc {
+                file: path.to_owned(),
+                line: 0,
+            },
+            message: format!("Could not read file: {}", err),
+        }]
+    })?;
+    let (items, errors) = assembler::parse_source_as(path, &source, syntax, &mut assembler::read_source);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let items = if options.optimize { optimize::optimize(items) } else { items };
+    compile_items(path, items)
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text, 16).map_err(|_| format!("{} is not a hex number", text))
+}
+
+impl Object {
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{} {}", MAGIC, VERSION).unwrap();
+        writeln!(out, "SOURCE {}", self.source).unwrap();
+        writeln!(out, "CODE {:04X}", self.code.len()).unwrap();
+        for chunk in self.code.chunks(CODE_PER_LINE) {
+            let words: Vec<String> = chunk.chunks(2).map(|word| word.iter().map(|byte| format!("{:02X}", byte)).collect()).collect();
+            writeln!(out, "{}", words.join(" ")).unwrap();
+        }
+        for &(ref name, offset) in self.exports.iter() {
+            writeln!(out, "EXPORT {} {:04X}", name, offset).unwrap();
+        }
+        for name in self.imports.iter() {
+            writeln!(out, "IMPORT {}", name).unwrap();
+        }
+        for reloc in self.relocs.iter() {
+            match reloc.symbol {
+                Some(ref name) => writeln!(out, "RELOC {:04X} {}", reloc.offset, name).unwrap(),
+                None => writeln!(out, "RELOC {:04X}", reloc.offset).unwrap(),
+            }
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Object, String> {
+        let mut lines = text.lines();
+        match lines.next().map(|ln| ln.split_whitespace().collect::<Vec<_>>()) {
+            Some(ref words) if words.len() == 2 && words[0] == MAGIC => {
+                if words[1] != VERSION.to_string() {
+                    return Err(format!("Object file version {} is not supported", words[1]));
+                }
+            }
+            _ => return Err("Not an object file".to_owned()),
+        }
+        let mut object = Object {
+            source: String::new(),
+            code: Vec::new(),
+            exports: Vec::new(),
+            imports: Vec::new(),
+            relocs: Vec::new(),
+        };
+        let mut code_len = 0;
+        for ln in lines {
+            let words: Vec<&str> = ln.split_whitespace().collect();
+            if object.code.len() < code_len {
+                for word in words {
+                    let value = parse_hex(word)?;
+                    if word.len() == 4 {
+                        object.code.push((value >> 8) as u8);
+                    }
+                    object.code.push(value as u8);
+                }
+                continue;
+            }
+            match words.as_slice() {
+                ["SOURCE", rest @ ..] => object.source = rest.join(" "),
+                ["CODE", len] => code_len = usize::from(parse_hex(len)?),
+                ["EXPORT", name, offset] => object.exports.push(((*name).to_owned(), parse_hex(offset)?)),
+                ["IMPORT", name] => object.imports.push((*name).to_owned()),
+                ["RELOC", offset] => object.relocs.push(Reloc {
+                    offset: parse_hex(offset)?,
+                    symbol: None,
+                }),
+                ["RELOC", offset, name] => object.relocs.push(Reloc {
+                    offset: parse_hex(offset)?,
+                    symbol: Some((*name).to_owned()),
+                }),
+                [] => {}
+                _ => return Err(format!("Unexpected line in object file: {}", ln)),
+            }
+        }
+        if object.code.len() != code_len {
+            return Err(format!("Expected {} bytes of code, found {}", code_len, object.code.len()));
+        }
+        for reloc in object.relocs.iter() {
+            if usize::from(reloc.offset) + 2 > object.code.len() {
+                return Err(format!("Relocation at {:04X} is outside the code", reloc.offset));
+            }
+        }
+        // A label can sit just past the last byte, so the end of the code is allowed.
+        for &(ref name, offset) in object.exports.iter() {
+            if usize::from(offset) > object.code.len() {
+                return Err(format!("Export {} at {:04X} is outside the code", name, offset));
+            }
+        }
+        Ok(object)
+    }
+}
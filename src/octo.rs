@@ -0,0 +1,526 @@
+use assembler::{Diagnostic, Item, SourceItem, SourceLoc};
+use instructions::bitops::{And, Or, Rand, ShiftLeft, ShiftRight, Xor};
+use instructions::display::{ClearScreen, Draw};
+use instructions::flow::{Call, Jump, Return};
+use instructions::loads::Load;
+use instructions::math::{Add, Sub, SubN};
+use instructions::parameters::OpParam;
+use instructions::*;
+use structured::{Blocks, Condition};
+
+use std::collections::{HashMap, VecDeque};
+
+// A front end for Octo (https://github.com/JohnEarnest/Octo) source. Octo is
+// free-form: statements are runs of whitespace-separated tokens and `#` starts
+// a comment. Labels are upper-cased like everywhere else in the assembler, and
+// XO-CHIP and SUPER-CHIP extensions are rejected.
+
+// Guards against macros that expand themselves forever.
+const MAX_EXPANSION: usize = 10_000;
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Parser<'a> {
+    file: &'a str,
+    lines: Vec<&'a str>,
+    tokens: VecDeque<Token>,
+    consts: HashMap<String, i32>,
+    // Every `:const` in the order it was defined, for symbol files.
+    defined: &'a mut Vec<(String, i32)>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    blocks: &'a mut Blocks,
+    depth: usize,
+    items: Vec<SourceItem>,
+    errors: Vec<Diagnostic>,
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (idx, ln) in source.lines().enumerate() {
+        let code = ln.split('#').next().unwrap_or("");
+        for word in code.split_whitespace() {
+            tokens.push_back(Token {
+                text: word.to_owned(),
+                line: idx + 1,
+            });
+        }
+    }
+    tokens
+}
+
+fn parse_literal(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i32::from_str_radix(bin, 2).ok()?
+    } else if digits.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+// The `:const`s a source defines, in order, for the language server.
+// Constants defined inside macros are not found.
+pub fn constants(source: &str) -> Vec<(String, i32)> {
+    let mut consts: Vec<(String, i32)> = Vec::new();
+    let mut tokens = tokenize(source).into_iter();
+    while let Some(token) = tokens.next() {
+        if token.text != ":const" {
+            continue;
+        }
+        if let (Some(name), Some(value)) = (tokens.next(), tokens.next()) {
+            let known = consts.iter().rev().find(|known| known.0 == value.text).map(|known| known.1);
+            if let Some(value) = parse_literal(&value.text).or(known) {
+                consts.push((name.text, value));
+            }
+        }
+    }
+    consts
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn reg(num: u8) -> OpParam {
+    OpParam::Register(num)
+}
+
+pub fn parse(
+    file: &str,
+    source: &str,
+    blocks: &mut Blocks,
+    constants: &mut Vec<(String, i32)>,
+) -> (Vec<SourceItem>, Vec<Diagnostic>) {
+    let depth = blocks.depth();
+    let mut parser = Parser {
+        file,
+        lines: source.lines().collect(),
+        tokens: tokenize(source),
+        consts: HashMap::new(),
+        defined: constants,
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        expansions: 0,
+        blocks,
+        depth,
+        items: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    while let Some(token) = parser.tokens.pop_front() {
+        let line = token.line;
+        if let Err(message) = parser.statement(token) {
+            parser.errors.push(Diagnostic {
+                loc: parser.loc(line),
+                message,
+            });
+            // Resynchronise at the next line rather than misreading the rest
+            // of this one.
+            while parser.tokens.front().is_some_and(|token| token.line == line) {
+                parser.tokens.pop_front();
+            }
+        }
+    }
+    parser.blocks.close_file(depth, &mut parser.errors);
+    (parser.items, parser.errors)
+}
+
+impl<'a> Parser<'a> {
+    fn loc(&self, line: usize) -> SourceLoc {
+        SourceLoc {
+            file: self.file.to_owned(),
+            line,
+        }
+    }
+
+    fn emit(&mut self, line: usize, item: Item) {
+        let text = self.lines.get(line - 1).cloned().unwrap_or("").to_owned();
+        self.items.push(SourceItem {
+            loc: self.loc(line),
+            text,
+            item,
+        });
+    }
+
+    fn emit_instr(&mut self, line: usize, instr: Instruction) {
+        self.emit(line, Item::Instr(instr));
+    }
+
+    fn next(&mut self, what: &str) -> Result<Token, String> {
+        self.tokens.pop_front().ok_or_else(|| format!("Expected {} before the end of the file", what))
+    }
+
+    fn register(&self, token: &Token) -> Option<u8> {
+        if let Some(&num) = self.aliases.get(&token.text) {
+            return Some(num);
+        }
+        let lower = token.text.to_lowercase();
+        match lower.strip_prefix('v') {
+            Some(digit) if digit.len() == 1 => u8::from_str_radix(digit, 16).ok(),
+            _ => None,
+        }
+    }
+
+    fn expect_register(&mut self, what: &str) -> Result<u8, String> {
+        let token = self.next(what)?;
+        self.register(&token)
+            .ok_or_else(|| format!("Expected a register for {}, got {}", what, token.text))
+    }
+
+    fn number(&self, token: &Token) -> Option<i32> {
+        parse_literal(&token.text).or_else(|| self.consts.get(&token.text).cloned())
+    }
+
+    fn byte(&self, token: &Token) -> Result<u16, String> {
+        match self.number(token) {
+            Some(value) if (-128..=255).contains(&value) => Ok((value & 0xFF) as u16),
+            Some(value) => Err(format!("{} does not fit in a byte", value)),
+            None => Err(format!("Expected a number, got {}", token.text)),
+        }
+    }
+
+    // A register or a byte constant.
+    fn operand(&self, token: &Token) -> Result<OpParam, String> {
+        match self.register(token) {
+            Some(num) => Ok(reg(num)),
+            None => self.byte(token).map(OpParam::Variable),
+        }
+    }
+
+    fn address(&self, token: &Token) -> Result<OpParam, String> {
+        match self.number(token) {
+            Some(value) if (0..=0xFFF).contains(&value) => Ok(OpParam::Variable(value as u16)),
+            Some(value) => Err(format!("{} is not a 12-bit address", value)),
+            None if is_identifier(&token.text) => Ok(OpParam::Label(token.text.to_uppercase())),
+            None => Err(format!("Expected an address or label, got {}", token.text)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let left = self.expect_register("a condition")?;
+        let op = self.next("a comparison")?;
+        if op.text == "key" || op.text == "-key" {
+            return Condition::new(reg(left), &op.text, OpParam::Blank);
+        }
+        let right = self.next("a value to compare with")?;
+        let right = self.operand(&right)?;
+        Condition::new(reg(left), &op.text, right)
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), String> {
+        let line = token.line;
+        match token.text.as_str() {
+            ":" | ":proc" => {
+                let name = self.next("a label name")?;
+                if !is_identifier(&name.text) {
+                    return Err(format!("{} is not a valid label name", name.text));
+                }
+                self.emit(line, Item::Label(name.text.to_uppercase()));
+            }
+            ":const" => {
+                let name = self.next("a constant name")?;
+                let value = self.next("a constant value")?;
+                let value = self
+                    .number(&value)
+                    .ok_or_else(|| format!("Expected a number for {}, got {}", name.text, value.text))?;
+                self.defined.push((name.text.clone(), value));
+                self.consts.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next("an alias name")?;
+                let num = self.expect_register("the alias")?;
+                self.aliases.insert(name.text, num);
+            }
+            ":macro" => self.define_macro()?,
+            ":byte" => {
+                let value = self.next("a byte")?;
+                let value = self.byte(&value)?;
+                self.emit(line, Item::Data(vec![value as u8]));
+            }
+            ":breakpoint" => {
+                self.next("a breakpoint name")?;
+            }
+            ":monitor" => {
+                self.next("a monitor address")?;
+                self.next("a monitor length")?;
+            }
+            "clear" => self.emit_instr(line, Instruction::ClearScreen(ClearScreen {})),
+            "return" | ";" => self.emit_instr(line, Instruction::Return(Return {})),
+            "bcd" => {
+                let num = self.expect_register("bcd")?;
+                self.emit_instr(line, Instruction::Load(Load::new(OpParam::Digits, reg(num))));
+            }
+            "save" => {
+                let num = self.expect_register("save")?;
+                self.emit_instr(line, Instruction::Load(Load::new(OpParam::DerefI, reg(num))));
+            }
+            "load" => {
+                let num = self.expect_register("load")?;
+                self.emit_instr(line, Instruction::Load(Load::new(reg(num), OpParam::DerefI)));
+            }
+            "sprite" => {
+                let x = self.expect_register("the sprite's x position")?;
+                let y = self.expect_register("the sprite's y position")?;
+                let height = self.next("the sprite height")?;
+                let height = match self.number(&height) {
+                    Some(value) if (0..=15).contains(&value) => value as u16,
+                    _ => return Err(format!("Sprite height must be 0 to 15, got {}", height.text)),
+                };
+                let draw = Draw::new(reg(x), reg(y), OpParam::Variable(height));
+                self.emit_instr(line, Instruction::Draw(draw));
+            }
+            "jump" => {
+                let dest = self.next("a jump target")?;
+                let dest = self.address(&dest)?;
+                self.emit_instr(line, Instruction::Jump(Jump::new(dest)));
+            }
+            "jump0" => {
+                let dest = self.next("a jump target")?;
+                let dest = self.address(&dest)?;
+                self.emit_instr(line, Instruction::Jump(Jump::offset(dest)));
+            }
+            "loop" => {
+                let loc = self.loc(line);
+                for item in self.blocks.open_loop(&loc) {
+                    self.emit(line, item);
+                }
+            }
+            "again" => {
+                for item in self.blocks.again(self.depth)? {
+                    self.emit(line, item);
+                }
+            }
+            "while" => {
+                let condition = self.condition()?;
+                for item in self.blocks.exit_unless(&condition, self.depth)? {
+                    self.emit(line, item);
+                }
+            }
+            "if" => self.if_statement(line)?,
+            "else" => {
+                for item in self.blocks.else_branch(self.depth)? {
+                    self.emit(line, item);
+                }
+            }
+            "end" => {
+                for item in self.blocks.end_if(self.depth)? {
+                    self.emit(line, item);
+                }
+            }
+            "i" => self.i_statement(line)?,
+            "delay" | "buzzer" => {
+                let op = self.next(":=")?;
+                if op.text != ":=" {
+                    return Err(format!("Expected := after {}, got {}", token.text, op.text));
+                }
+                let num = self.expect_register(&token.text)?;
+                let timer = if token.text == "delay" { OpParam::Timer } else { OpParam::AudioTimer };
+                self.emit_instr(line, Instruction::Load(Load::new(timer, reg(num))));
+            }
+            text if text.starts_with(':') => return Err(format!("{} is not supported", text)),
+            _ => {
+                if let Some(num) = self.register(&token) {
+                    return self.register_statement(line, num);
+                }
+                if let Some(value) = parse_literal(&token.text).or_else(|| self.consts.get(&token.text).cloned()) {
+                    let value = self.byte(&Token { text: value.to_string(), line })?;
+                    self.emit(line, Item::Data(vec![value as u8]));
+                    return Ok(());
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand_macro(&token);
+                }
+                if is_identifier(&token.text) {
+                    let call = Call::new(OpParam::Label(token.text.to_uppercase()));
+                    self.emit_instr(line, Instruction::Call(call));
+                    return Ok(());
+                }
+                return Err(format!("Unexpected {}", token.text));
+            }
+        }
+        Ok(())
+    }
+
+    fn if_statement(&mut self, line: usize) -> Result<(), String> {
+        let condition = self.condition()?;
+        let keyword = self.next("then or begin")?;
+        match keyword.text.as_str() {
+            "begin" => {
+                let loc = self.loc(line);
+                for item in self.blocks.open_if(&condition, &loc) {
+                    self.emit(line, item);
+                }
+                Ok(())
+            }
+            "then" => {
+                // The guarded statement has to come out as exactly one instruction.
+                let body = self.next("a statement after then")?;
+                let start = self.items.len();
+                self.statement(body)?;
+                let lowered: Vec<SourceItem> = self.items.drain(start..).collect();
+                let instr = match lowered.as_slice() {
+                    [SourceItem { item: Item::Instr(ref instr), .. }] => instr.clone(),
+                    _ => return Err("then must be followed by a statement that is a single instruction".to_owned()),
+                };
+                for instr in condition.guard(instr) {
+                    self.emit_instr(line, instr);
+                }
+                Ok(())
+            }
+            other => Err(format!("Expected then or begin, got {}", other)),
+        }
+    }
+
+    fn i_statement(&mut self, line: usize) -> Result<(), String> {
+        let op = self.next(":= or +=")?;
+        match op.text.as_str() {
+            ":=" => {
+                let value = self.next("a value for i")?;
+                if value.text == "hex" {
+                    let num = self.expect_register("hex")?;
+                    self.emit_instr(line, Instruction::Load(Load::new(OpParam::Fontset, reg(num))));
+                } else if value.text == "bighex" || value.text == "long" {
+                    return Err(format!("i := {} is not supported", value.text));
+                } else {
+                    let addr = self.address(&value)?;
+                    self.emit_instr(line, Instruction::Load(Load::new(OpParam::RegisterI, addr)));
+                }
+            }
+            "+=" => {
+                let num = self.expect_register("i +=")?;
+                self.emit_instr(line, Instruction::Add(Add::new(OpParam::RegisterI, reg(num))));
+            }
+            other => return Err(format!("Expected := or += after i, got {}", other)),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, line: usize, num: u8) -> Result<(), String> {
+        let dest = reg(num);
+        let op = self.next("an operator")?;
+        let value = self.next("a value")?;
+        let source = self.register(&value).map(reg);
+        let needs_register = |name: &str| format!("{} needs a register on the right, got {}", name, value.text);
+        let instr = match (op.text.as_str(), source) {
+            (":=", _) if value.text == "random" => {
+                let mask = self.next("a mask for random")?;
+                let mask = self.byte(&mask)?;
+                Instruction::Rand(Rand::new(dest, OpParam::Variable(mask)))
+            }
+            (":=", _) if value.text == "delay" => Instruction::Load(Load::new(dest, OpParam::Timer)),
+            (":=", _) if value.text == "key" => Instruction::Load(Load::new(dest, OpParam::Keyboard)),
+            (":=", Some(src)) => Instruction::Load(Load::new(dest, src)),
+            (":=", None) => Instruction::Load(Load::new(dest, OpParam::Variable(self.byte(&value)?))),
+            ("+=", Some(src)) => Instruction::Add(Add::new(dest, src)),
+            ("+=", None) => Instruction::Add(Add::new(dest, OpParam::Variable(self.byte(&value)?))),
+            ("-=", Some(src)) => Instruction::Sub(Sub::new(dest, src)),
+            ("-=", None) => {
+                let value = self.byte(&value)?;
+                Instruction::Add(Add::new(dest, OpParam::Variable(value.wrapping_neg() & 0xFF)))
+            }
+            ("=-", Some(src)) => Instruction::SubN(SubN::new(dest, src)),
+            ("|=", Some(src)) => Instruction::Or(Or::new(dest, src)),
+            ("&=", Some(src)) => Instruction::And(And::new(dest, src)),
+            ("^=", Some(src)) => Instruction::Xor(Xor::new(dest, src)),
+            (">>=", Some(src)) => Instruction::ShiftRight(ShiftRight::new(dest, src)),
+            ("<<=", Some(src)) => Instruction::ShiftLeft(ShiftLeft::new(dest, src)),
+            (name @ "=-", None) | (name @ "|=", None) | (name @ "&=", None) | (name @ "^=", None)
+            | (name @ ">>=", None) | (name @ "<<=", None) => return Err(needs_register(name)),
+            (other, _) => return Err(format!("Unknown operator {}", other)),
+        };
+        self.emit_instr(line, instr);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.next("a macro name")?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next("{ to start the macro body")?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut nesting = 0;
+        loop {
+            let token = self.next("} to end the macro body")?;
+            if token.text == "{" {
+                nesting += 1;
+            } else if token.text == "}" {
+                if nesting == 0 {
+                    break;
+                }
+                nesting -= 1;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    // Replaces a macro invocation with its body, arguments substituted, and
+    // leaves it in the token stream to be parsed next.
+    fn expand_macro(&mut self, name: &Token) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSION {
+            return Err(format!("Macro {} keeps expanding", name.text));
+        }
+        let count = self.macros[&name.text].params.len();
+        let mut args = HashMap::new();
+        for idx in 0..count {
+            let arg = self.next(&format!("argument {} of {}", idx + 1, name.text))?;
+            args.insert(self.macros[&name.text].params[idx].clone(), arg.text);
+        }
+        let expansion: Vec<Token> = self.macros[&name.text]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: args.get(&token.text).cloned().unwrap_or_else(|| token.text.clone()),
+                // Expanded code is reported at the invocation.
+                line: name.line,
+            })
+            .collect();
+        for token in expansion.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+}
+
+// Execution starts at 0x200, so Octo jumps to `main` when it is not first.
+pub fn jump_to_main(items: &mut Vec<SourceItem>) {
+    let main = items
+        .iter()
+        .position(|item| matches!(item.item, Item::Label(ref name) if name == "MAIN"));
+    if let Some(idx) = main {
+        let preceded = items[..idx].iter().any(|item| !matches!(item.item, Item::Label(_)));
+        if preceded {
+            let loc = items[idx].loc.clone();
+            let text = items[idx].text.clone();
+            let jump = Jump::new(OpParam::Label("MAIN".to_owned()));
+            items.insert(0, SourceItem { loc, text, item: Item::Instr(Instruction::Jump(jump)) });
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    ClearScreen,
+    Return,
+    Sys(u16),
+    Jump(u16),
+    Call(u16),
+    SkipEqualImm(u8, u8),
+    SkipNotEqualImm(u8, u8),
+    SkipEqualReg(u8, u8),
+    LoadImm(u8, u8),
+    AddImm(u8, u8),
+    LoadReg(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddReg(u8, u8),
+    Sub(u8, u8),
+    ShiftRight(u8, u8),
+    SubN(u8, u8),
+    ShiftLeft(u8, u8),
+    SkipNotEqualReg(u8, u8),
+    LoadI(u16),
+    JumpV0(u16),
+    Rand(u8, u8),
+    Draw(u8, u8, u8),
+    SkipKey(u8),
+    SkipNotKey(u8),
+    LoadFromTimer(u8),
+    WaitKey(u8),
+    LoadTimer(u8),
+    LoadAudioTimer(u8),
+    AddI(u8),
+    LoadFont(u8),
+    StoreDigits(u8),
+    StoreRegs(u8),
+    LoadRegs(u8),
+    Unknown(u16),
+}
+
+impl Op {
+    pub fn decode(opc: u16) -> Op {
+        let x = ((opc >> 8) & 0xF) as u8;
+        let y = ((opc >> 4) & 0xF) as u8;
+        let n = (opc & 0xF) as u8;
+        let kk = (opc & 0xFF) as u8;
+        let nnn = opc & 0x0FFF;
+
+        match opc >> 12 {
+            0x0 => match opc {
+                0x00E0 => Op::ClearScreen,
+                0x00EE => Op::Return,
+                _ => Op::Sys(nnn),
+            },
+            0x1 => Op::Jump(nnn),
+            0x2 => Op::Call(nnn),
+            0x3 => Op::SkipEqualImm(x, kk),
+            0x4 => Op::SkipNotEqualImm(x, kk),
+            0x5 if n == 0 => Op::SkipEqualReg(x, y),
+            0x6 => Op::LoadImm(x, kk),
+            0x7 => Op::AddImm(x, kk),
+            0x8 => match n {
+                0x0 => Op::LoadReg(x, y),
+                0x1 => Op::Or(x, y),
+                0x2 => Op::And(x, y),
+                0x3 => Op::Xor(x, y),
+                0x4 => Op::AddReg(x, y),
+                0x5 => Op::Sub(x, y),
+                0x6 => Op::ShiftRight(x, y),
+                0x7 => Op::SubN(x, y),
+                0xE => Op::ShiftLeft(x, y),
+                _ => Op::Unknown(opc),
+            },
+            0x9 if n == 0 => Op::SkipNotEqualReg(x, y),
+            0xA => Op::LoadI(nnn),
+            0xB => Op::JumpV0(nnn),
+            0xC => Op::Rand(x, kk),
+            0xD => Op::Draw(x, y, n),
+            0xE => match kk {
+                0x9E => Op::SkipKey(x),
+                0xA1 => Op::SkipNotKey(x),
+                _ => Op::Unknown(opc),
+            },
+            0xF => match kk {
+                0x07 => Op::LoadFromTimer(x),
+                0x0A => Op::WaitKey(x),
+                0x15 => Op::LoadTimer(x),
+                0x18 => Op::LoadAudioTimer(x),
+                0x1E => Op::AddI(x),
+                0x29 => Op::LoadFont(x),
+                0x33 => Op::StoreDigits(x),
+                0x55 => Op::StoreRegs(x),
+                0x65 => Op::LoadRegs(x),
+                _ => Op::Unknown(opc),
+            },
+            _ => Op::Unknown(opc),
+        }
+    }
+
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Op::SkipEqualImm(..)
+                | Op::SkipNotEqualImm(..)
+                | Op::SkipEqualReg(..)
+                | Op::SkipNotEqualReg(..)
+                | Op::SkipKey(_)
+                | Op::SkipNotKey(_)
+        )
+    }
+
+    pub fn describe(&self) -> String {
+        match *self {
+            Op::ClearScreen => "Clear the display.".to_owned(),
+            Op::Return => "Return from a subroutine.".to_owned(),
+            Op::Sys(addr) => format!("Call the machine code routine at 0x{:03X} (ignored by modern interpreters).", addr),
+            Op::Jump(addr) => format!("Jump to 0x{:03X}.", addr),
+            Op::Call(addr) => format!("Call the subroutine at 0x{:03X}.", addr),
+            Op::SkipEqualImm(x, kk) => format!("Skip the next instruction if V{:X} == 0x{:02X}.", x, kk),
+            Op::SkipNotEqualImm(x, kk) => format!("Skip the next instruction if V{:X} != 0x{:02X}.", x, kk),
+            Op::SkipEqualReg(x, y) => format!("Skip the next instruction if V{:X} == V{:X}.", x, y),
+            Op::SkipNotEqualReg(x, y) => format!("Skip the next instruction if V{:X} != V{:X}.", x, y),
+            Op::LoadImm(x, kk) => format!("Set V{:X} to 0x{:02X}.", x, kk),
+            Op::AddImm(x, kk) => format!("Add 0x{:02X} to V{:X} without touching VF.", kk, x),
+            Op::LoadReg(x, y) => format!("Set V{:X} to V{:X}.", x, y),
+            Op::Or(x, y) => format!("Set V{:X} to V{:X} OR V{:X}.", x, x, y),
+            Op::And(x, y) => format!("Set V{:X} to V{:X} AND V{:X}.", x, x, y),
+            Op::Xor(x, y) => format!("Set V{:X} to V{:X} XOR V{:X}.", x, x, y),
+            Op::AddReg(x, y) => format!("Add V{:X} to V{:X}; VF is set to the carry.", y, x),
+            Op::Sub(x, y) => format!("Set V{:X} to V{:X} - V{:X}; VF is set to NOT borrow.", x, x, y),
+            Op::SubN(x, y) => format!("Set V{:X} to V{:X} - V{:X}; VF is set to NOT borrow.", x, y, x),
+            Op::ShiftRight(x, _) => format!("Shift V{:X} right by one; VF is set to the bit shifted out.", x),
+            Op::ShiftLeft(x, _) => format!("Shift V{:X} left by one; VF is set to the bit shifted out.", x),
+            Op::LoadI(addr) => format!("Set I to 0x{:03X}.", addr),
+            Op::JumpV0(addr) => format!("Jump to 0x{:03X} + V0.", addr),
+            Op::Rand(x, kk) => format!("Set V{:X} to a random byte AND 0x{:02X}.", x, kk),
+            Op::Draw(x, y, n) => format!(
+                "Draw the {}-byte sprite at I at (V{:X}, V{:X}); VF is set on collision.",
+                n, x, y
+            ),
+            Op::SkipKey(x) => format!("Skip the next instruction if the key in V{:X} is pressed.", x),
+            Op::SkipNotKey(x) => format!("Skip the next instruction if the key in V{:X} is not pressed.", x),
+            Op::LoadFromTimer(x) => format!("Set V{:X} to the delay timer.", x),
+            Op::WaitKey(x) => format!("Wait for a key press and store the key in V{:X}.", x),
+            Op::LoadTimer(x) => format!("Set the delay timer to V{:X}.", x),
+            Op::LoadAudioTimer(x) => format!("Set the sound timer to V{:X}.", x),
+            Op::AddI(x) => format!("Add V{:X} to I.", x),
+            Op::LoadFont(x) => format!("Point I at the font sprite for the digit in V{:X}.", x),
+            Op::StoreDigits(x) => format!("Store the decimal digits of V{:X} at I, I+1 and I+2.", x),
+            Op::StoreRegs(x) => format!("Store V0 through V{:X} in memory starting at I.", x),
+            Op::LoadRegs(x) => format!("Load V0 through V{:X} from memory starting at I.", x),
+            Op::Unknown(opc) => format!("0x{:04X} is not a CHIP-8 instruction.", opc),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Op::ClearScreen => write!(f, "CLS"),
+            Op::Return => write!(f, "RET"),
+            Op::Sys(addr) => write!(f, "SYS 0x{:03X}", addr),
+            Op::Jump(addr) => write!(f, "JP 0x{:03X}", addr),
+            Op::Call(addr) => write!(f, "CALL 0x{:03X}", addr),
+            Op::SkipEqualImm(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            Op::SkipNotEqualImm(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            Op::SkipEqualReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Op::LoadImm(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            Op::AddImm(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            Op::LoadReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Op::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Op::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Op::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Op::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Op::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Op::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Op::SubN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Op::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Op::SkipNotEqualReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Op::LoadI(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            Op::JumpV0(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Op::Rand(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Op::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, 0x{:X}", x, y, n),
+            Op::SkipKey(x) => write!(f, "SKP V{:X}", x),
+            Op::SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            Op::LoadFromTimer(x) => write!(f, "LD V{:X}, DT", x),
+            Op::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Op::LoadTimer(x) => write!(f, "LD DT, V{:X}", x),
+            Op::LoadAudioTimer(x) => write!(f, "LD ST, V{:X}", x),
+            Op::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Op::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Op::StoreDigits(x) => write!(f, "LD B, V{:X}", x),
+            Op::StoreRegs(x) => write!(f, "LD [I], V{:X}", x),
+            Op::LoadRegs(x) => write!(f, "LD V{:X}, [I]", x),
+            Op::Unknown(opc) => write!(f, "0x{:04X}", opc),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use assembler::{Item, SourceItem};
+use instructions::flow::Jump;
+use instructions::math::Add;
+use instructions::parameters::OpParam;
+use instructions::{Instruction, InstructionOpsWithLabels};
+use interpreter::PROGRAM_START;
+use ir::{reg, Program};
+use opcode::Op;
+
+// Peephole rewrites for `-O`, repeated until none applies:
+//
+//     LD Vx, Vx                 removed
+//     LD Vy, kk; ADD Vx, Vy     ADD Vx, kk, when Vy and VF are dead after
+//     JP A ... A: JP B          JP B
+//     CALL F; RET               JP F
+//     JP X; <unlabelled code>   the unreachable code removed
+//
+// Liveness comes from the control-flow graph, so a rewrite never changes a
+// register, including VF, that something may still read. An instruction
+// right after a skip is never removed or merged, since the skip would then
+// pass over a different one. Removing code moves everything after it, so
+// programs that address their own code by number, or jump through `JP V0`,
+// only get the rewrites that keep every instruction in place.
+
+// What to do with an item.
+enum Edit {
+    Keep,
+    Remove,
+    Replace(Instruction),
+}
+
+pub fn optimize(items: Vec<SourceItem>) -> Vec<SourceItem> {
+    let mut items = items;
+    loop {
+        let program = Program::new(items);
+        let edits = rewrites(&program);
+        items = program.into_items();
+        if edits.iter().all(|edit| matches!(edit, Edit::Keep)) {
+            return items;
+        }
+        items = items
+            .into_iter()
+            .zip(edits)
+            .filter_map(|(item, edit)| match edit {
+                Edit::Keep => Some(item),
+                Edit::Remove => None,
+                Edit::Replace(instr) => Some(SourceItem {
+                    item: Item::Instr(instr),
+                    ..item
+                }),
+            })
+            .collect();
+    }
+}
+
+// Whether instructions can be removed without breaking an address the
+// program computes or spells out.
+fn can_move(program: &Program) -> bool {
+    let end = program.addrs.last().map_or(PROGRAM_START, |&addr| addr + 2);
+    program.items.iter().zip(program.ops.iter()).all(|(item, op)| {
+        let numeric = match item.item {
+            Item::Instr(ref instr) => instr.label_refs().is_empty(),
+            _ => false,
+        };
+        match *op {
+            Some(Op::JumpV0(_)) => false,
+            Some(Op::Jump(addr)) | Some(Op::Call(addr)) | Some(Op::LoadI(addr)) if numeric => !(PROGRAM_START..end).contains(&addr),
+            _ => true,
+        }
+    })
+}
+
+fn rewrites(program: &Program) -> Vec<Edit> {
+    let items = &program.items;
+    let ops = &program.ops;
+    let live = program.live_out();
+    let can_move = can_move(program);
+    let mut edits: Vec<Edit> = items.iter().map(|_| Edit::Keep).collect();
+
+    // The instruction items in order, and whether each is skipped over by
+    // the one before.
+    let instrs: Vec<usize> = (0..items.len()).filter(|&idx| matches!(items[idx].item, Item::Instr(_))).collect();
+    let mut pinned = vec![false; items.len()];
+    for pair in instrs.windows(2) {
+        pinned[pair[1]] = ops[pair[0]].is_some_and(|op| op.is_skip());
+    }
+    // The instruction right after another in the same block, with nothing
+    // but the two of them in between.
+    let next_in_block = |idx: usize| {
+        let block = program.blocks.iter().find(|block| block.items.contains(&idx))?;
+        let next = idx + 1;
+        if block.items.contains(&next) && matches!(items[next].item, Item::Instr(_)) {
+            Some(next)
+        } else {
+            None
+        }
+    };
+
+    for &idx in instrs.iter() {
+        if !matches!(edits[idx], Edit::Keep) {
+            continue;
+        }
+        match ops[idx] {
+            Some(Op::LoadReg(x, y)) if x == y && can_move && !pinned[idx] => edits[idx] = Edit::Remove,
+            Some(Op::LoadImm(y, kk)) if can_move && !pinned[idx] => {
+                if let Some(next) = next_in_block(idx) {
+                    if let Some(Op::AddReg(x, src)) = ops[next] {
+                        let dead = reg(y) | reg(0xF);
+                        if src == y && x != y && live[next] & dead == 0 {
+                            edits[idx] = Edit::Remove;
+                            edits[next] = Edit::Replace(Instruction::Add(Add::new(OpParam::Register(x), OpParam::Variable(u16::from(kk)))));
+                        }
+                    }
+                }
+            }
+            Some(Op::Jump(_)) => {
+                if let Some(target) = thread(program, idx) {
+                    edits[idx] = Edit::Replace(target);
+                }
+            }
+            Some(Op::Call(addr)) => {
+                let ret = (idx + 1..items.len()).find(|&other| !matches!(items[other].item, Item::Label(_)));
+                if let Some(ret) = ret.filter(|&ret| ops[ret] == Some(Op::Return)) {
+                    let dest = match items[idx].item {
+                        Item::Instr(ref instr) => match instr.label_refs().first() {
+                            Some(label) => OpParam::Label((*label).to_owned()),
+                            None => OpParam::Variable(addr),
+                        },
+                        _ => unreachable!(),
+                    };
+                    edits[idx] = Edit::Replace(Instruction::Jump(Jump::new(dest)));
+                    // The RET stays when something else can still reach it.
+                    if can_move && next_in_block(idx) == Some(ret) {
+                        edits[ret] = Edit::Remove;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if can_move {
+        for (idx, block) in program.blocks.iter().enumerate() {
+            if idx > 0 && block.preds.is_empty() && program.labels(block).is_empty() && !program.is_data(block) {
+                for item in block.items.clone() {
+                    edits[item] = Edit::Remove;
+                }
+            }
+        }
+    }
+    edits
+}
+
+// For a jump to a block that only jumps on, a jump straight to the end of
+// the chain.
+fn thread(program: &Program, idx: usize) -> Option<Instruction> {
+    let target_of = |item: usize| match program.ops[item] {
+        Some(Op::Jump(addr)) => Some(addr),
+        _ => None,
+    };
+    let mut seen = vec![idx];
+    let mut at = idx;
+    while let Some(target) = target_of(at) {
+        let first = program
+            .blocks
+            .iter()
+            .filter(|block| block.addr == target)
+            .flat_map(|block| block.items.clone())
+            .find(|&item| matches!(program.items[item].item, Item::Instr(_)));
+        match first {
+            Some(first) if !seen.contains(&first) && target_of(first).is_some() => {
+                seen.push(first);
+                at = first;
+            }
+            _ => break,
+        }
+    }
+    if target_of(at) == target_of(idx) {
+        return None;
+    }
+    match program.items[at].item {
+        Item::Instr(ref instr) => Some(instr.clone()),
+        _ => None,
+    }
+}
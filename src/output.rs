@@ -0,0 +1,174 @@
+use interpreter::PROGRAM_START;
+use json::JsonValue;
+
+use std::fmt::Write;
+
+// The ways an assembled ROM can be written out. Raw bytes are what CHIP-8
+// interpreters load; the others wrap the same bytes for programmers, other
+// tools or source code:
+//
+//     raw      the bytes as they are loaded at 0x200
+//     ihex     Intel HEX, 16 bytes a record, at address 0x200
+//     srec     Motorola S-records, S1 records at 0x200
+//     dump     a hex dump with addresses
+//     c        a C array, `const unsigned char NAME[]`
+//     rust     a Rust array, `pub const NAME: [u8; N]`
+//     octo     Octo's cartridge JSON: the program as bytes and its options
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Raw,
+    IntelHex,
+    SRecord,
+    HexDump,
+    C,
+    Rust,
+    Octo,
+}
+
+// Bytes in a record or a line of text.
+const BYTES_PER_LINE: usize = 16;
+const BYTES_PER_ARRAY_LINE: usize = 12;
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Option<OutputFormat> {
+        match name {
+            "raw" => Some(OutputFormat::Raw),
+            "ihex" => Some(OutputFormat::IntelHex),
+            "srec" => Some(OutputFormat::SRecord),
+            "dump" => Some(OutputFormat::HexDump),
+            "c" => Some(OutputFormat::C),
+            "rust" => Some(OutputFormat::Rust),
+            "octo" => Some(OutputFormat::Octo),
+            _ => None,
+        }
+    }
+
+    pub fn for_path(path: &str) -> OutputFormat {
+        let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "hex" | "ihex" => OutputFormat::IntelHex,
+            "srec" | "s19" => OutputFormat::SRecord,
+            "txt" => OutputFormat::HexDump,
+            "c" | "h" => OutputFormat::C,
+            "rs" => OutputFormat::Rust,
+            "json" => OutputFormat::Octo,
+            _ => OutputFormat::Raw,
+        }
+    }
+}
+
+// The name for an array holding the ROM, or for other data read from a
+// file: the file name without its extension, upper-cased, with anything
+// else turned into underscores.
+pub fn array_name(path: &str) -> String {
+    let file = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    let stem = file.split('.').next().unwrap_or(file);
+    let name: String = stem.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect();
+    if name.chars().next().is_none_or(|c| c.is_ascii_digit()) {
+        format!("ROM_{}", name)
+    } else {
+        name
+    }
+}
+
+// The bytes of a record line, and the checksum over them.
+fn record(bytes: &[u8]) -> (String, u8) {
+    let text = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    (text, sum)
+}
+
+fn intel_hex(code: &[u8]) -> String {
+    let mut out = String::new();
+    for (idx, chunk) in code.chunks(BYTES_PER_LINE).enumerate() {
+        let addr = PROGRAM_START as usize + idx * BYTES_PER_LINE;
+        let mut bytes = vec![chunk.len() as u8, (addr >> 8) as u8, addr as u8, 0x00];
+        bytes.extend_from_slice(chunk);
+        let (text, sum) = record(&bytes);
+        writeln!(out, ":{}{:02X}", text, sum.wrapping_neg()).unwrap();
+    }
+    writeln!(out, ":00000001FF").unwrap();
+    out
+}
+
+fn s_records(code: &[u8]) -> String {
+    let mut out = String::new();
+    let mut line = |kind: &str, addr: usize, data: &[u8]| {
+        let mut bytes = vec![(data.len() + 3) as u8, (addr >> 8) as u8, addr as u8];
+        bytes.extend_from_slice(data);
+        let (text, sum) = record(&bytes);
+        writeln!(out, "{}{}{:02X}", kind, text, !sum).unwrap();
+    };
+    line("S0", 0, &[]);
+    let chunks = code.chunks(BYTES_PER_LINE);
+    let count = chunks.len();
+    for (idx, chunk) in chunks.enumerate() {
+        line("S1", PROGRAM_START as usize + idx * BYTES_PER_LINE, chunk);
+    }
+    line("S5", count, &[]);
+    line("S9", PROGRAM_START as usize, &[]);
+    out
+}
+
+fn hex_dump(code: &[u8]) -> String {
+    let mut out = String::new();
+    for (idx, chunk) in code.chunks(BYTES_PER_LINE).enumerate() {
+        let bytes: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+        writeln!(out, "0x{:03X}  {}", PROGRAM_START as usize + idx * BYTES_PER_LINE, bytes.join(" ")).unwrap();
+    }
+    out
+}
+
+fn array_lines(code: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in code.chunks(BYTES_PER_ARRAY_LINE) {
+        let bytes: Vec<String> = chunk.iter().map(|byte| format!("0x{:02X},", byte)).collect();
+        writeln!(out, "    {}", bytes.join(" ")).unwrap();
+    }
+    out
+}
+
+// Octo keeps a program's source and its settings as JSON inside cartridge
+// images. The ROM goes in as a `main` made of its bytes, with the settings
+// matching this assembler's interpreter.
+fn octo_bundle(code: &[u8]) -> String {
+    let mut program = String::from(": main\n");
+    for chunk in code.chunks(BYTES_PER_LINE) {
+        let bytes: Vec<String> = chunk.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        writeln!(program, "{}", bytes.join(" ")).unwrap();
+    }
+    let options = JsonValue::object(vec![
+        ("tickrate", 10u16.into()),
+        ("fillColor", "#FFFFFF".into()),
+        ("backgroundColor", "#000000".into()),
+        ("shiftQuirks", true.into()),
+        ("loadStoreQuirks", true.into()),
+        ("vfOrderQuirks", false.into()),
+        ("logicQuirks", false.into()),
+        ("jumpQuirks", false.into()),
+        ("maxSize", 3584u16.into()),
+    ]);
+    let bundle = JsonValue::object(vec![("program", program.into()), ("options", options)]);
+    format!("{}\n", bundle)
+}
+
+// The file contents for a ROM in the given format; `path` names arrays.
+pub fn encode(code: &[u8], format: OutputFormat, path: &str) -> Vec<u8> {
+    let name = array_name(path);
+    let text = match format {
+        OutputFormat::Raw => return code.to_vec(),
+        OutputFormat::IntelHex => intel_hex(code),
+        OutputFormat::SRecord => s_records(code),
+        OutputFormat::HexDump => hex_dump(code),
+        OutputFormat::C => format!(
+            "const unsigned char {}[{}] = {{\n{}}};\n",
+            name,
+            code.len(),
+            array_lines(code)
+        ),
+        OutputFormat::Rust => format!("pub const {}: [u8; {}] = [\n{}];\n", name, code.len(), array_lines(code)),
+        OutputFormat::Octo => octo_bundle(code),
+    };
+    text.into_bytes()
+}
@@ -0,0 +1,435 @@
+// Just enough PNG to read and write the small monochrome images used for
+// screen goldens and sprites. Images are written as 8-bit grayscale inside
+// uncompressed deflate blocks; reading handles any non-interlaced PNG.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // RGBA, row by row.
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Image {
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        self.pixels[y * self.width + x]
+    }
+
+    // A pixel counts as lit when it is opaque and closer to white than black.
+    pub fn is_lit(&self, x: usize, y: usize) -> bool {
+        let [r, g, b, a] = self.pixel(x, y);
+        let luma = (u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114) / 1000;
+        a >= 0x80 && luma >= 0x80
+    }
+}
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Encodes `gray` (one byte per pixel, row by row) as a grayscale PNG.
+pub fn encode_gray(width: usize, height: usize, gray: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in gray.chunks(width.max(1)).take(height) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        zlib.push(if last { 1 } else { 0 });
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &zlib);
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+pub fn decode(data: &[u8]) -> Result<Image, String> {
+    if data.len() < 8 || data[..8] != SIGNATURE {
+        return Err("Not a PNG file".to_owned());
+    }
+
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut transparent: Option<Vec<u8>> = None;
+    let mut idat = Vec::new();
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data
+            .get(pos + 8..pos + 8 + len)
+            .ok_or_else(|| "Truncated PNG chunk".to_owned())?;
+        match kind {
+            b"IHDR" if len >= 13 => header = Some(body.to_vec()),
+            b"PLTE" => palette = body.chunks(3).filter(|c| c.len() == 3).map(|c| [c[0], c[1], c[2], 0xFF]).collect(),
+            b"tRNS" => transparent = Some(body.to_vec()),
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+
+    let header = header.ok_or_else(|| "PNG has no IHDR chunk".to_owned())?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let depth = header[8] as usize;
+    let color = header[9];
+    if header[12] != 0 {
+        return Err("Interlaced PNGs are not supported".to_owned());
+    }
+    let channels = match color {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("Unknown PNG color type {}", color)),
+    };
+    if ![1, 2, 4, 8, 16].contains(&depth) {
+        return Err(format!("Unsupported PNG bit depth {}", depth));
+    }
+    if idat.len() < 2 {
+        return Err("PNG has no image data".to_owned());
+    }
+
+    let raw = inflate(&idat[2..])?;
+    let bits_per_pixel = depth * channels;
+    let stride = (width * bits_per_pixel).div_ceil(8);
+    let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+    if raw.len() < (stride + 1) * height {
+        return Err("PNG image data is truncated".to_owned());
+    }
+
+    let mut rows: Vec<Vec<u8>> = Vec::with_capacity(height);
+    for y in 0..height {
+        let line = &raw[y * (stride + 1)..(y + 1) * (stride + 1)];
+        let filter = line[0];
+        let mut row = line[1..].to_vec();
+        let zero = vec![0; stride];
+        let prev = rows.last().unwrap_or(&zero);
+        for x in 0..stride {
+            let a = if x >= bytes_per_pixel { row[x - bytes_per_pixel] } else { 0 };
+            let b = prev[x];
+            let c = if x >= bytes_per_pixel { prev[x - bytes_per_pixel] } else { 0 };
+            let add = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(format!("Unknown PNG filter {}", filter)),
+            };
+            row[x] = row[x].wrapping_add(add);
+        }
+        rows.push(row);
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in rows.iter() {
+        for x in 0..width {
+            let sample = |channel: usize| -> u16 {
+                let idx = x * channels + channel;
+                if depth == 16 {
+                    u16::from_be_bytes([row[idx * 2], row[idx * 2 + 1]])
+                } else if depth == 8 {
+                    u16::from(row[idx])
+                } else {
+                    let bit = idx * depth;
+                    let shift = 8 - depth - bit % 8;
+                    u16::from((row[bit / 8] >> shift) & ((1 << depth) - 1) as u8)
+                }
+            };
+            // Scales a sample of the image's depth to 0..=255.
+            let scale = |value: u16| -> u8 {
+                match depth {
+                    16 => (value >> 8) as u8,
+                    8 => value as u8,
+                    _ => (u32::from(value) * 255 / ((1 << depth) - 1)) as u8,
+                }
+            };
+            let pixel = match color {
+                0 => {
+                    let value = sample(0);
+                    let alpha = match transparent {
+                        Some(ref t) if t.len() >= 2 && u16::from_be_bytes([t[0], t[1]]) == value => 0,
+                        _ => 0xFF,
+                    };
+                    let gray = scale(value);
+                    [gray, gray, gray, alpha]
+                }
+                2 => [scale(sample(0)), scale(sample(1)), scale(sample(2)), 0xFF],
+                3 => {
+                    let idx = sample(0) as usize;
+                    let mut entry = *palette
+                        .get(idx)
+                        .ok_or_else(|| format!("PNG palette has no entry {}", idx))?;
+                    if let Some(alpha) = transparent.as_ref().and_then(|t| t.get(idx)) {
+                        entry[3] = *alpha;
+                    }
+                    entry
+                }
+                4 => {
+                    let gray = scale(sample(0));
+                    [gray, gray, gray, scale(sample(1))]
+                }
+                _ => [scale(sample(0)), scale(sample(1)), scale(sample(2)), scale(sample(3))],
+            };
+            pixels.push(pixel);
+        }
+    }
+
+    Ok(Image { width, height, pixels })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for idx in 0..count {
+            let byte = *self.data.get(self.pos).ok_or_else(|| "Truncated deflate stream".to_owned())?;
+            value |= u32::from((byte >> self.bit) & 1) << idx;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// Canonical Huffman code as (symbols ordered by code, count of codes per length).
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = i32::from(self.counts[len]);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err("Invalid Huffman code in deflate stream".to_owned())
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader { data, pos: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or_else(|| "Truncated deflate stream".to_owned())?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let start = reader.pos + 4;
+                let block = data
+                    .get(start..start + len)
+                    .ok_or_else(|| "Truncated deflate stream".to_owned())?;
+                out.extend_from_slice(block);
+                reader.pos = start + len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (idx, len) in lengths.iter_mut().enumerate() {
+                    *len = match idx {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8,
+                    };
+                }
+                let lit = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5; 30]);
+                inflate_block(&mut reader, &mut out, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = read_dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &lit, &dist)?;
+            }
+            _ => return Err("Invalid deflate block type".to_owned()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let hlit = reader.bits(5)? as usize + 257;
+    let hdist = reader.bits(5)? as usize + 1;
+    let hclen = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &idx in ORDER.iter().take(hclen) {
+        code_lengths[idx] = reader.bits(3)? as u8;
+    }
+    let code_huffman = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let symbol = code_huffman.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *lengths.last().ok_or_else(|| "Invalid deflate code lengths".to_owned())?;
+                (prev, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > hlit + hdist {
+        return Err("Invalid deflate code lengths".to_owned());
+    }
+    Ok((Huffman::new(&lengths[..hlit]), Huffman::new(&lengths[hlit..])))
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = lit.decode(reader)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let idx = symbol - 257;
+            if idx >= LENGTH_BASE.len() {
+                return Err("Invalid deflate length code".to_owned());
+            }
+            let len = LENGTH_BASE[idx] as usize + reader.bits(u32::from(LENGTH_EXTRA[idx]))? as usize;
+            let didx = dist.decode(reader)? as usize;
+            if didx >= DIST_BASE.len() {
+                return Err("Invalid deflate distance code".to_owned());
+            }
+            let distance = DIST_BASE[didx] as usize + reader.bits(u32::from(DIST_EXTRA[didx]))? as usize;
+            if distance > out.len() {
+                return Err("Deflate distance reaches before the start of the data".to_owned());
+            }
+            let start = out.len() - distance;
+            for offset in 0..len {
+                let byte = out[start + offset];
+                out.push(byte);
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use json::{JsonError, JsonValue};
+
+use std::io;
+use std::io::prelude::*;
+
+// The largest body a client may send; anything claiming more is refused
+// rather than allocated.
+pub const MAX_BODY: usize = 1 << 20;
+
+// Both the debug adapter and language server protocols frame their JSON
+// bodies with an HTTP-like `Content-Length` header. A body that is not valid
+// JSON comes back as the inner error, so the caller can answer it and read
+// on; only the framing itself failing is an I/O error.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Result<JsonValue, JsonError>>> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        let mut parts = header.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            length = value.parse().ok();
+        }
+    }
+
+    let length = length.unwrap_or(0);
+    if length > MAX_BODY {
+        let message = format!("Content-Length {} is more than the {} bytes a message may have", length, MAX_BODY);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let text = String::from_utf8_lossy(&body);
+    Ok(Some(JsonValue::parse(&text)))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &JsonValue) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
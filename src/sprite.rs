@@ -0,0 +1,228 @@
+use assembler::{resolve_include, Item, SourceItem, SourceLoc};
+use output::array_name;
+use png::{self, Image};
+
+use std::fmt::Write;
+use std::fs;
+
+// Sprites sliced out of images, so artists' PNG and PBM files can go into a
+// program as they are:
+//
+//     INCSPRITE "ship.png", 8, 6          // 8x6 sprites for DRW Vx, Vy, 6
+//     INCSPRITE "boss.png", 16, 16        // SCHIP 16x16, for DRW Vx, Vy, 0
+//     INCSPRITE "tiles.png", 8, 8, 2      // XO-CHIP, two planes
+//
+// The image is cut into sprites left to right, then top to bottom, and the
+// data goes where the line is, labelled with the file name (`SHIP`) and,
+// when there is more than one sprite, each sprite by number (`SHIP_0`).
+// Sprites are 8 wide and up to 15 high, as one DRW draws them, or 16x16.
+//
+// One plane takes black and white pixels only; white is lit, and so is a 1
+// in a PBM file. Two planes take the grays 00, 55, AA and FF, whose bits
+// are the pixel in plane 1 and plane 2; each sprite's plane 2 rows follow
+// its plane 1 rows, the way XO-CHIP's DRW reads them.
+
+const MAX_HEIGHT: usize = 15;
+const GRAYS: [u8; 4] = [0x00, 0x55, 0xAA, 0xFF];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteSize {
+    pub width: usize,
+    pub height: usize,
+    pub planes: usize,
+}
+
+impl SpriteSize {
+    pub fn new(width: usize, height: usize, planes: usize) -> Result<SpriteSize, String> {
+        if !(width == 8 && (1..=MAX_HEIGHT).contains(&height) || width == 16 && height == 16) {
+            return Err(format!("Sprites are 8 wide and 1 to {} high, or 16x16, not {}x{}", MAX_HEIGHT, width, height));
+        }
+        if planes != 1 && planes != 2 {
+            return Err(format!("Sprites have 1 or 2 planes, not {}", planes));
+        }
+        Ok(SpriteSize { width, height, planes })
+    }
+
+    // `8x6`
+    pub fn parse(text: &str, planes: usize) -> Result<SpriteSize, String> {
+        let lower = text.to_lowercase();
+        let parsed = lower.split_once('x').and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+        let (width, height) = parsed.ok_or_else(|| format!("Expected a sprite size like 8x6, got {}", text))?;
+        SpriteSize::new(width, height, planes)
+    }
+}
+
+// A plain PBM, P1 or P4, as an image with 1s white and 0s black.
+fn decode_pbm(data: &[u8]) -> Result<Image, String> {
+    let mut pos = 2;
+    let mut header = Vec::new();
+    while header.len() < 2 {
+        while pos < data.len() && (data[pos].is_ascii_whitespace() || data[pos] == b'#') {
+            if data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && data[pos].is_ascii_digit() {
+            pos += 1;
+        }
+        let number = std::str::from_utf8(&data[start..pos]).ok().and_then(|text| text.parse::<usize>().ok());
+        header.push(number.ok_or("PBM header is missing its size")?);
+    }
+    let (width, height) = (header[0], header[1]);
+    // A size too big to count has more pixels than any file holds.
+    let count = width.checked_mul(height).ok_or("PBM image data is truncated")?;
+    let bits: Vec<bool> = if data[1] == b'4' {
+        let stride = width.div_ceil(8);
+        let body = stride
+            .checked_mul(height)
+            .and_then(|len| data.get(pos + 1..)?.get(..len))
+            .ok_or("PBM image data is truncated")?;
+        (0..count).map(|idx| body[idx / width * stride + idx % width / 8] & 0x80 >> (idx % width % 8) != 0).collect()
+    } else {
+        let body: Vec<u8> = data[pos..].iter().cloned().filter(|byte| !byte.is_ascii_whitespace()).collect();
+        if body.len() < count || body.iter().any(|&byte| byte != b'0' && byte != b'1') {
+            return Err("PBM image data must be a 0 or 1 for each pixel".to_owned());
+        }
+        body.iter().take(count).map(|&byte| byte == b'1').collect()
+    };
+    let pixels = bits.into_iter().map(|lit| if lit { [0xFF; 4] } else { [0x00, 0x00, 0x00, 0xFF] }).collect();
+    Ok(Image { width, height, pixels })
+}
+
+pub fn decode(data: &[u8]) -> Result<Image, String> {
+    if data.starts_with(b"P1") || data.starts_with(b"P4") {
+        decode_pbm(data)
+    } else if data.starts_with(b"\x89PNG") {
+        png::decode(data)
+    } else {
+        Err("Not a PNG or PBM file".to_owned())
+    }
+}
+
+// The planes a pixel is lit in, bit 0 for plane 1.
+fn pixel_planes(image: &Image, x: usize, y: usize, planes: usize) -> Result<u8, String> {
+    let [r, g, b, a] = image.pixel(x, y);
+    let gray = match a {
+        0 => Some(0),
+        0xFF if r == g && g == b => GRAYS.iter().position(|&gray| gray == r),
+        _ => None,
+    };
+    match (gray, planes) {
+        (Some(0), 1) => Ok(0),
+        (Some(3), 1) => Ok(1),
+        (Some(bits), 2) => Ok(bits as u8),
+        (_, 1) => Err(format!("Pixel ({}, {}) is neither black nor white", x, y)),
+        _ => Err(format!("Pixel ({}, {}) is not one of the grays 00, 55, AA and FF", x, y)),
+    }
+}
+
+// The image's sprites, in order, each as the bytes DRW reads.
+pub fn slice(image: &Image, size: SpriteSize) -> Result<Vec<Vec<u8>>, String> {
+    if image.width == 0 || image.height == 0 || !image.width.is_multiple_of(size.width) || !image.height.is_multiple_of(size.height) {
+        return Err(format!(
+            "The image is {}x{}, which is not a whole number of {}x{} sprites",
+            image.width, image.height, size.width, size.height
+        ));
+    }
+    let mut sprites = Vec::new();
+    for top in (0..image.height).step_by(size.height) {
+        for left in (0..image.width).step_by(size.width) {
+            let mut sprite = Vec::new();
+            for plane in 0..size.planes {
+                for y in top..top + size.height {
+                    for byte_left in (left..left + size.width).step_by(8) {
+                        let mut byte = 0u8;
+                        for x in byte_left..byte_left + 8 {
+                            if pixel_planes(image, x, y, size.planes)? & 1 << plane != 0 {
+                                byte |= 0x80 >> (x - byte_left);
+                            }
+                        }
+                        sprite.push(byte);
+                    }
+                }
+            }
+            sprites.push(sprite);
+        }
+    }
+    Ok(sprites)
+}
+
+// Reads and slices an image file.
+pub fn import(path: &str, size: SpriteSize) -> Result<Vec<Vec<u8>>, String> {
+    let data = fs::read(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+    decode(&data).and_then(|image| slice(&image, size)).map_err(|err| format!("{}: {}", path, err))
+}
+
+// The labels for an image's sprites: the name for the first, and numbered
+// ones for each when there are several.
+fn labels(name: &str, idx: usize, count: usize) -> Vec<String> {
+    let mut labels = Vec::new();
+    if idx == 0 {
+        labels.push(name.to_owned());
+    }
+    if count > 1 {
+        labels.push(format!("{}_{}", name, idx));
+    }
+    labels
+}
+
+// The sprites as an Octo file, for `--sprite-import`.
+pub fn to_octo(path: &str, size: SpriteSize, sprites: &[Vec<u8>]) -> String {
+    let name = array_name(path).to_lowercase();
+    let mut out = String::new();
+    let planes = if size.planes == 1 { "1 plane" } else { "2 planes" };
+    writeln!(out, "# {}x{} sprites from {}, {}", size.width, size.height, path, planes).unwrap();
+    for (idx, sprite) in sprites.iter().enumerate() {
+        for label in labels(&name, idx, sprites.len()) {
+            writeln!(out, ": {}", label).unwrap();
+        }
+        for row in sprite.chunks(size.width / 8) {
+            let bytes: Vec<String> = row.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            writeln!(out, "  {}", bytes.join(" ")).unwrap();
+        }
+    }
+    out
+}
+
+pub fn is_sprite_line(ln: &str) -> bool {
+    ln.split_whitespace().next().is_some_and(|word| word.eq_ignore_ascii_case("INCSPRITE"))
+}
+
+// `INCSPRITE "file", W, H[, PLANES]`, with the file found the way INCLUDE
+// finds one.
+pub fn line(ln: &str, loc: &SourceLoc) -> Result<Vec<SourceItem>, String> {
+    let code = ln.split("//").next().unwrap_or("").trim();
+    let usage = || "Expected INCSPRITE \"file\", WIDTH, HEIGHT[, PLANES]".to_owned();
+    let args: Vec<&str> = code[9..].split(',').map(|arg| arg.trim()).collect();
+    let number = |text: &str| text.parse::<usize>().map_err(|_| usage());
+    let (file, size) = match args.as_slice() {
+        [file, width, height] => (file, SpriteSize::new(number(width)?, number(height)?, 1)?),
+        [file, width, height, planes] => (file, SpriteSize::new(number(width)?, number(height)?, number(planes)?)?),
+        _ => return Err(usage()),
+    };
+    let file = file.strip_prefix('"').and_then(|file| file.strip_suffix('"')).ok_or_else(usage)?;
+    let path = resolve_include(&loc.file, file);
+    let sprites = import(&path, size)?;
+
+    let name = array_name(file);
+    let mut items = Vec::new();
+    let mut push = |item: Item| {
+        items.push(SourceItem {
+            loc: loc.clone(),
+            text: ln.to_owned(),
+            item,
+        })
+    };
+    for (idx, sprite) in sprites.iter().enumerate() {
+        for label in labels(&name, idx, sprites.len()) {
+            push(Item::Label(label));
+        }
+        push(Item::Data(sprite.clone()));
+    }
+    Ok(items)
+}
@@ -0,0 +1,37 @@
+// The standard library: routines shipped inside the compiler and included
+// by name, `INCLUDE <math>`, instead of by path. Each file's comments give
+// the registers its routines take, return and change.
+//
+//     math     MUL8, DIV8 and SUM16
+//     print    PRINT_DEC and PRINT_HEX, with the built-in font
+//     text     DRAW_TEXT, with the program's own glyphs
+//     font     SMALL, a 3x5 FONT of capitals, digits and punctuation for TEXT
+
+// Included files are named `<std>/math.chip8`, which no file on disk has.
+const PREFIX: &str = "<std>/";
+
+const FILES: &[(&str, &str)] = &[
+    ("math", include_str!("stdlib/math.chip8")),
+    ("print", include_str!("stdlib/print.chip8")),
+    ("text", include_str!("stdlib/text.chip8")),
+    ("font", include_str!("stdlib/font.chip8")),
+];
+
+pub fn names() -> Vec<&'static str> {
+    FILES.iter().map(|file| file.0).collect()
+}
+
+// The path a library file is included as.
+pub fn path(name: &str) -> String {
+    format!("{}{}.chip8", PREFIX, name)
+}
+
+// The source of a library file, if `path` names one.
+pub fn source(path: &str) -> Option<&'static str> {
+    let name = path.strip_prefix(PREFIX)?.strip_suffix(".chip8")?;
+    FILES.iter().find(|file| file.0 == name).map(|file| file.1)
+}
+
+pub fn is_library_path(path: &str) -> bool {
+    path.starts_with(PREFIX)
+}
@@ -0,0 +1,362 @@
+use assembler::{Diagnostic, Item, SourceItem, SourceLoc};
+use instructions::flow::{Jump, SkipIfEqual, SkipIfKey, SkipIfNotEqual, SkipIfNotKey};
+use instructions::loads::Load;
+use instructions::math::{Sub, SubN};
+use instructions::parameters::OpParam;
+use instructions::*;
+
+// Structured control flow, lowered to skips and jumps between generated labels:
+//
+//     IF V0 == 0x1 THEN CALL LEFT      // guards a single instruction
+//     IF V0 != V1 THEN                 // or a block, with an optional ELSE
+//         ...
+//     ELSE
+//         ...
+//     END
+//     LOOP
+//         WHILE V2 != 0x0              // leaves the loop once the condition fails
+//         IF V3 KEY THEN BREAK
+//         ...
+//     AGAIN
+//
+// Conditions compare a register with a register or a 0x constant using ==,
+// !=, <, >, <= or >=, or test a key with `VX KEY` / `VX -KEY`. The ordering
+// comparisons are computed in VF, so they clobber it.
+
+// Generated labels start with a character no hand-written label uses, so they
+// can be left out of symbol lists and routine names.
+pub const GENERATED_PREFIX: char = '@';
+
+pub fn is_generated_label(name: &str) -> bool {
+    name.starts_with(GENERATED_PREFIX)
+}
+
+const KEYWORDS: [&str; 7] = ["IF", "ELSE", "END", "LOOP", "AGAIN", "WHILE", "BREAK"];
+
+fn keyword(ln: &str) -> Option<String> {
+    let code = ln.split("//").next().unwrap_or("");
+    let first = code.split_whitespace().next()?.to_uppercase();
+    if KEYWORDS.contains(&first.as_str()) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+pub fn is_block_line(ln: &str) -> bool {
+    keyword(ln).is_some()
+}
+
+const VF: OpParam = OpParam::Register(0xF);
+
+#[derive(Clone, Debug)]
+pub enum Condition {
+    Equal(OpParam, OpParam),
+    NotEqual(OpParam, OpParam),
+    Less(OpParam, OpParam),
+    Greater(OpParam, OpParam),
+    LessEqual(OpParam, OpParam),
+    GreaterEqual(OpParam, OpParam),
+    Key(OpParam),
+    NotKey(OpParam),
+}
+
+impl Condition {
+    // Builds a condition from already parsed operands; `right` is ignored for
+    // the key tests.
+    pub fn new(left: OpParam, op: &str, right: OpParam) -> Result<Condition, String> {
+        let left = match left {
+            reg @ OpParam::Register(_) => reg,
+            _ => return Err("A condition must start with a register".to_owned()),
+        };
+        if op.eq_ignore_ascii_case("KEY") {
+            return Ok(Condition::Key(left));
+        } else if op.eq_ignore_ascii_case("-KEY") {
+            return Ok(Condition::NotKey(left));
+        }
+        let right = match right {
+            reg @ OpParam::Register(_) => reg,
+            OpParam::Variable(value) if value <= 0xFF => OpParam::Variable(value),
+            _ => return Err("A register can only be compared with a register or a constant up to 0xFF".to_owned()),
+        };
+        match op {
+            "==" => Ok(Condition::Equal(left, right)),
+            "!=" => Ok(Condition::NotEqual(left, right)),
+            "<" => Ok(Condition::Less(left, right)),
+            ">" => Ok(Condition::Greater(left, right)),
+            "<=" => Ok(Condition::LessEqual(left, right)),
+            ">=" => Ok(Condition::GreaterEqual(left, right)),
+            _ => Err(format!("Unknown comparison {}", op)),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            [reg, op] => Condition::new(OpParam::parse(reg), op, OpParam::Blank),
+            [left, op, right] => Condition::new(OpParam::parse(left), op, OpParam::parse(right)),
+            _ => Err("Expected `VX op value`, `VX KEY` or `VX -KEY`".to_owned()),
+        }
+        .map_err(|err| format!("{}: {}", err, text))
+    }
+
+    pub fn negate(&self) -> Condition {
+        match self.clone() {
+            Condition::Equal(left, right) => Condition::NotEqual(left, right),
+            Condition::NotEqual(left, right) => Condition::Equal(left, right),
+            Condition::Less(left, right) => Condition::GreaterEqual(left, right),
+            Condition::GreaterEqual(left, right) => Condition::Less(left, right),
+            Condition::Greater(left, right) => Condition::LessEqual(left, right),
+            Condition::LessEqual(left, right) => Condition::Greater(left, right),
+            Condition::Key(reg) => Condition::NotKey(reg),
+            Condition::NotKey(reg) => Condition::Key(reg),
+        }
+    }
+
+    // Instructions that end in skipping the next one when the condition holds.
+    // Ordering comparisons subtract in VF and test the NOT borrow flag.
+    pub fn skip(&self) -> Vec<Instruction> {
+        let borrow_test = |left: &OpParam, right: &OpParam, reversed: bool, holds_on: u16| {
+            let subtract = if reversed {
+                Instruction::SubN(SubN::new(VF, left.clone()))
+            } else {
+                Instruction::Sub(Sub::new(VF, left.clone()))
+            };
+            vec![
+                Instruction::Load(Load::new(VF, right.clone())),
+                subtract,
+                Instruction::SkipIfEqual(SkipIfEqual::new(VF, OpParam::Variable(holds_on))),
+            ]
+        };
+        match self.clone() {
+            Condition::Equal(left, right) => vec![Instruction::SkipIfEqual(SkipIfEqual::new(left, right))],
+            Condition::NotEqual(left, right) => vec![Instruction::SkipIfNotEqual(SkipIfNotEqual::new(left, right))],
+            // VF = left - right, flag set when left >= right.
+            Condition::Less(ref left, ref right) => borrow_test(left, right, true, 0),
+            Condition::GreaterEqual(ref left, ref right) => borrow_test(left, right, true, 1),
+            // VF = right - left, flag set when right >= left.
+            Condition::Greater(ref left, ref right) => borrow_test(left, right, false, 0),
+            Condition::LessEqual(ref left, ref right) => borrow_test(left, right, false, 1),
+            Condition::Key(reg) => vec![Instruction::SkipIfKey(SkipIfKey::new(reg))],
+            Condition::NotKey(reg) => vec![Instruction::SkipIfNotKey(SkipIfNotKey::new(reg))],
+        }
+    }
+
+    // Runs `body`, a single instruction, only when the condition holds.
+    pub fn guard(&self, body: Instruction) -> Vec<Instruction> {
+        let mut instrs = self.negate().skip();
+        instrs.push(body);
+        instrs
+    }
+}
+
+fn jump(label: &str) -> Item {
+    Item::Instr(Instruction::Jump(Jump::new(OpParam::Label(label.to_owned()))))
+}
+
+fn instrs(instrs: Vec<Instruction>) -> Vec<Item> {
+    instrs.into_iter().map(Item::Instr).collect()
+}
+
+enum Block {
+    If { else_label: String, end_label: String, has_else: bool },
+    Loop { start_label: String, end_label: String },
+}
+
+struct OpenBlock {
+    block: Block,
+    loc: SourceLoc,
+}
+
+// The blocks open while parsing a program. Files share one instance so that
+// generated labels stay unique across includes, but a block must be closed
+// in the file that opened it: `depth` is the number of blocks that were open
+// when the current file started.
+pub struct Blocks {
+    next_id: usize,
+    open: Vec<OpenBlock>,
+}
+
+impl Default for Blocks {
+    fn default() -> Blocks {
+        Blocks::new()
+    }
+}
+
+impl Blocks {
+    pub fn new() -> Blocks {
+        Blocks {
+            next_id: 0,
+            open: Vec::new(),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn open_if(&mut self, condition: &Condition, loc: &SourceLoc) -> Vec<Item> {
+        let id = self.next_id();
+        let else_label = format!("{}IF{}_ELSE", GENERATED_PREFIX, id);
+        let end_label = format!("{}IF{}_END", GENERATED_PREFIX, id);
+        let mut items = instrs(condition.skip());
+        items.push(jump(&else_label));
+        self.open.push(OpenBlock {
+            block: Block::If { else_label, end_label, has_else: false },
+            loc: loc.clone(),
+        });
+        items
+    }
+
+    pub fn else_branch(&mut self, depth: usize) -> Result<Vec<Item>, String> {
+        match self.innermost(depth) {
+            Some(&mut OpenBlock { block: Block::If { ref else_label, ref end_label, ref mut has_else }, .. }) => {
+                if *has_else {
+                    return Err("IF already has an ELSE".to_owned());
+                }
+                *has_else = true;
+                Ok(vec![jump(end_label), Item::Label(else_label.clone())])
+            }
+            _ => Err("ELSE without IF".to_owned()),
+        }
+    }
+
+    pub fn end_if(&mut self, depth: usize) -> Result<Vec<Item>, String> {
+        match self.innermost(depth) {
+            Some(&mut OpenBlock { block: Block::If { .. }, .. }) => {}
+            Some(_) => return Err("END cannot close a LOOP; use AGAIN".to_owned()),
+            None => return Err("END without IF".to_owned()),
+        }
+        let mut items = Vec::new();
+        if let Some(OpenBlock { block: Block::If { else_label, end_label, has_else }, .. }) = self.open.pop() {
+            if !has_else {
+                items.push(Item::Label(else_label));
+            }
+            items.push(Item::Label(end_label));
+        }
+        Ok(items)
+    }
+
+    pub fn open_loop(&mut self, loc: &SourceLoc) -> Vec<Item> {
+        let id = self.next_id();
+        let start_label = format!("{}LOOP{}", GENERATED_PREFIX, id);
+        let end_label = format!("{}LOOP{}_END", GENERATED_PREFIX, id);
+        let items = vec![Item::Label(start_label.clone())];
+        self.open.push(OpenBlock {
+            block: Block::Loop { start_label, end_label },
+            loc: loc.clone(),
+        });
+        items
+    }
+
+    pub fn again(&mut self, depth: usize) -> Result<Vec<Item>, String> {
+        match self.innermost(depth) {
+            Some(&mut OpenBlock { block: Block::Loop { .. }, .. }) => {}
+            Some(_) => return Err("AGAIN cannot close an IF; use END".to_owned()),
+            None => return Err("AGAIN without LOOP".to_owned()),
+        }
+        match self.open.pop() {
+            Some(OpenBlock { block: Block::Loop { start_label, end_label }, .. }) => {
+                Ok(vec![jump(&start_label), Item::Label(end_label)])
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    // Leaves the innermost loop unless the condition holds.
+    pub fn exit_unless(&self, condition: &Condition, depth: usize) -> Result<Vec<Item>, String> {
+        let end_label = self.loop_end(depth).ok_or_else(|| "WHILE outside of a LOOP".to_owned())?;
+        let mut items = instrs(condition.skip());
+        items.push(jump(&end_label));
+        Ok(items)
+    }
+
+    pub fn break_loop(&self, depth: usize) -> Result<Instruction, String> {
+        let end_label = self.loop_end(depth).ok_or_else(|| "BREAK outside of a LOOP".to_owned())?;
+        Ok(Instruction::Jump(Jump::new(OpParam::Label(end_label))))
+    }
+
+    // Lowers one block line of the assembly dialect.
+    pub fn line(&mut self, ln: &str, loc: &SourceLoc, depth: usize, items: &mut Vec<SourceItem>) -> Result<(), String> {
+        let code = ln.split("//").next().unwrap_or("").trim().to_uppercase();
+        let word = keyword(&code).unwrap_or_default();
+        let rest = code[word.len()..].trim();
+        if !rest.is_empty() && !["IF", "WHILE"].contains(&word.as_str()) {
+            return Err(format!("{} takes no arguments", word));
+        }
+
+        let lowered = match word.as_str() {
+            "IF" => {
+                let words: Vec<&str> = rest.split_whitespace().collect();
+                let then = words
+                    .iter()
+                    .position(|&word| word == "THEN")
+                    .ok_or_else(|| "Expected THEN after the IF condition".to_owned())?;
+                let condition = Condition::parse(&words[..then].join(" "))?;
+                let body = words[then + 1..].join(" ");
+                if body.is_empty() {
+                    self.open_if(&condition, loc)
+                } else {
+                    let body = if body == "BREAK" {
+                        self.break_loop(depth)?
+                    } else if is_instr_line(&body) {
+                        Instruction::parse_args(&body).map_err(|ParseError(message)| message)?
+                    } else {
+                        return Err(format!("THEN must be followed by a single instruction, got {}", body));
+                    };
+                    instrs(condition.guard(body))
+                }
+            }
+            "ELSE" => self.else_branch(depth)?,
+            "END" => self.end_if(depth)?,
+            "LOOP" => self.open_loop(loc),
+            "AGAIN" => self.again(depth)?,
+            "WHILE" => self.exit_unless(&Condition::parse(rest)?, depth)?,
+            _ => vec![Item::Instr(self.break_loop(depth)?)],
+        };
+        for item in lowered {
+            items.push(SourceItem {
+                loc: loc.clone(),
+                text: ln.to_owned(),
+                item,
+            });
+        }
+        Ok(())
+    }
+
+    // Reports every block the current file left open.
+    pub fn close_file(&mut self, depth: usize, errors: &mut Vec<Diagnostic>) {
+        while self.open.len() > depth {
+            if let Some(open) = self.open.pop() {
+                let message = match open.block {
+                    Block::If { .. } => "IF without END",
+                    Block::Loop { .. } => "LOOP without AGAIN",
+                };
+                errors.push(Diagnostic {
+                    loc: open.loc,
+                    message: message.to_owned(),
+                });
+            }
+        }
+    }
+
+    fn innermost(&mut self, depth: usize) -> Option<&mut OpenBlock> {
+        if self.open.len() > depth {
+            self.open.last_mut()
+        } else {
+            None
+        }
+    }
+
+    fn loop_end(&self, depth: usize) -> Option<String> {
+        self.open[depth..].iter().rev().find_map(|open| match open.block {
+            Block::Loop { ref end_label, .. } => Some(end_label.clone()),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,108 @@
+use assembler::{addresses, Assembly, Item};
+use json::JsonValue;
+use structured::is_generated_label;
+
+use std::fmt::Write;
+
+// Symbol files and line maps for other emulators and debuggers. A symbol
+// file gives every label's address and the value of every Octo `:const`:
+//
+//     plain    MAIN=0x200, one per line, constants as decimal numbers
+//     json     {"labels": {"MAIN": 512}, "constants": {"STEP": 2}}
+//     sym      0200 MAIN, the `ADDR NAME` lines of `.sym` files; labels only
+//
+// A line map gives the source line each instruction came from, as
+// `0x200 file:line` lines or a JSON array.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolFormat {
+    Plain,
+    Json,
+    Sym,
+}
+
+impl SymbolFormat {
+    pub fn parse(name: &str) -> Option<SymbolFormat> {
+        match name {
+            "plain" => Some(SymbolFormat::Plain),
+            "json" => Some(SymbolFormat::Json),
+            "sym" => Some(SymbolFormat::Sym),
+            _ => None,
+        }
+    }
+
+    pub fn for_path(path: &str) -> SymbolFormat {
+        if path.ends_with(".json") {
+            SymbolFormat::Json
+        } else if path.ends_with(".sym") {
+            SymbolFormat::Sym
+        } else {
+            SymbolFormat::Plain
+        }
+    }
+}
+
+// The labels written in the source, by address.
+fn labels(assembly: &Assembly) -> Vec<(u16, &str)> {
+    let mut labels: Vec<(u16, &str)> = assembly
+        .items
+        .iter()
+        .zip(addresses(&assembly.items))
+        .filter_map(|(item, addr)| match item.item {
+            Item::Label(ref name) if !is_generated_label(name) => Some((addr, name.as_str())),
+            _ => None,
+        })
+        .collect();
+    labels.sort();
+    labels
+}
+
+pub fn symbols(assembly: &Assembly, format: SymbolFormat) -> String {
+    let labels = labels(assembly);
+    let mut out = String::new();
+    match format {
+        SymbolFormat::Plain => {
+            for (addr, name) in labels {
+                writeln!(out, "{}=0x{:03X}", name, addr).unwrap();
+            }
+            for &(ref name, value) in assembly.constants.iter() {
+                writeln!(out, "{}={}", name, value).unwrap();
+            }
+        }
+        SymbolFormat::Json => {
+            let labels = labels.into_iter().map(|(addr, name)| (name, JsonValue::from(addr))).collect();
+            let constants = assembly.constants.iter().map(|constant| (constant.0.as_str(), JsonValue::from(i64::from(constant.1)))).collect();
+            let json = JsonValue::object(vec![("labels", JsonValue::object(labels)), ("constants", JsonValue::object(constants))]);
+            writeln!(out, "{}", json).unwrap();
+        }
+        SymbolFormat::Sym => {
+            for (addr, name) in labels {
+                writeln!(out, "{:04X} {}", addr, name).unwrap();
+            }
+        }
+    }
+    out
+}
+
+pub fn line_map(assembly: &Assembly, json: bool) -> String {
+    let mut out = String::new();
+    if json {
+        let entries: Vec<JsonValue> = assembly
+            .line_map
+            .iter()
+            .map(|entry| {
+                JsonValue::object(vec![
+                    ("addr", entry.addr.into()),
+                    ("file", entry.loc.file.as_str().into()),
+                    ("line", entry.loc.line.into()),
+                ])
+            })
+            .collect();
+        writeln!(out, "{}", JsonValue::from(entries)).unwrap();
+    } else {
+        for entry in assembly.line_map.iter() {
+            writeln!(out, "0x{:03X} {}", entry.addr, entry.loc).unwrap();
+        }
+    }
+    out
+}
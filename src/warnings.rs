@@ -0,0 +1,381 @@
+use assembler::{Diagnostic, Item, SourceItem};
+use callgraph;
+use expr::is_variable_line;
+use instructions::{Instruction, InstructionOps};
+use ir::{effects, reg, Program, Regs, ALL_REGS, REG_I};
+use structured::is_generated_label;
+use opcode::Op;
+
+use std::collections::BTreeSet;
+
+// Dataflow checks over the control-flow graph that point at likely mistakes
+// without stopping the build:
+//
+//     LD VF, 0x1
+//     ADD V1, V2      // the carry replaces the 1
+//     SE VF, 0x1      // warning: VF holds the carry from the line above
+//
+// Besides a VF value lost to a flag, they report registers read before
+// anything writes them and writes nothing reads. Only lines written as
+// plain instructions are checked for those; code generated from blocks,
+// expressions or other syntaxes reads and writes registers on its own terms.
+//
+// A line compiled from an expression is checked for using a register as
+// scratch that a later instruction still reads as if it held its old value.
+//
+// Every skip is also checked for passing over something other than a single
+// instruction: a label, part of a line that makes several instructions, or
+// data.
+
+fn is_written_out(item: &SourceItem) -> bool {
+    matches!(item.item, Item::Instr(_)) && Instruction::parse_args(&item.text).is_ok()
+}
+
+fn reg_names(regs: Regs) -> String {
+    let mut names: Vec<String> = (0..16).filter(|&x| regs & reg(x) != 0).map(|x| format!("V{:X}", x)).collect();
+    if regs & REG_I != 0 {
+        names.push("I".to_owned());
+    }
+    names.join(", ")
+}
+
+// What is in VF at a point of the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Flag {
+    // A value an instruction put there on purpose, not read yet.
+    Set(usize),
+    // The flag an instruction left, and the unread value it replaced.
+    Clobbered(usize, Option<usize>),
+}
+
+// Instructions that name VF as their destination rather than setting it as
+// a flag.
+fn sets_vf(op: &Op) -> bool {
+    match *op {
+        Op::LoadImm(x, _) | Op::AddImm(x, _) | Op::LoadReg(x, _) | Op::Or(x, _) | Op::And(x, _) | Op::Xor(x, _) => x == 0xF,
+        Op::Rand(x, _) | Op::LoadFromTimer(x) | Op::WaitKey(x) => x == 0xF,
+        Op::LoadRegs(x) => x == 0xF,
+        _ => false,
+    }
+}
+
+fn sets_flag(op: &Op) -> bool {
+    matches!(*op, Op::AddReg(..) | Op::Sub(..) | Op::SubN(..) | Op::ShiftLeft(..) | Op::ShiftRight(..) | Op::Draw(..))
+}
+
+// The registers an instruction means to write, leaving out flags.
+fn explicit_writes(op: &Op) -> Regs {
+    let (_, written) = effects(op);
+    match *op {
+        Op::WaitKey(_) => 0,
+        Op::AddReg(x, _) | Op::Sub(x, _) | Op::SubN(x, _) | Op::ShiftLeft(x, _) | Op::ShiftRight(x, _) => reg(x),
+        Op::Draw(..) => 0,
+        _ => written,
+    }
+}
+
+// Whether the instruction really reads the registers `effects` reports,
+// rather than standing in for code elsewhere.
+fn reads_exactly(op: &Op) -> bool {
+    !matches!(*op, Op::Return | Op::Call(_) | Op::Sys(_) | Op::Unknown(_))
+}
+
+pub fn check(items: &[SourceItem]) -> Vec<Diagnostic> {
+    let program = Program::new(items.to_vec());
+    let mut warnings = Vec::new();
+    stale_flags(&program, &mut warnings);
+    unwritten_reads(&program, &mut warnings);
+    dead_writes(&program, &mut warnings);
+    scratch_reads(&program, &mut warnings);
+    skips(&program, &mut warnings);
+    warnings.extend(callgraph::recursion(&program));
+    warnings.sort_by(|a, b| (&a.loc.file, a.loc.line).cmp(&(&b.loc.file, b.loc.line)));
+    warnings
+}
+
+fn warn(program: &Program, item: usize, message: String, warnings: &mut Vec<Diagnostic>) {
+    warnings.push(Diagnostic {
+        loc: program.items[item].loc.clone(),
+        message,
+    });
+}
+
+// Runs a forward analysis to a fixed point from the given state at the
+// start of each block; `step` moves a state across one item.
+fn forward<S, F>(program: &Program, starts: Vec<S>, join: fn(&mut S, &S), step: &F) -> Vec<S>
+where
+    S: Clone + PartialEq,
+    F: Fn(usize, &mut S),
+{
+    let mut starts = starts;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for idx in 0..program.blocks.len() {
+            let mut state = starts[idx].clone();
+            for item in program.blocks[idx].items.clone() {
+                step(item, &mut state);
+            }
+            for &(succ, _) in program.blocks[idx].succs.iter() {
+                let mut joined = starts[succ].clone();
+                join(&mut joined, &state);
+                if joined != starts[succ] {
+                    starts[succ] = joined;
+                    changed = true;
+                }
+            }
+        }
+    }
+    starts
+}
+
+fn stale_flags(program: &Program, warnings: &mut Vec<Diagnostic>) {
+    let step = |item: usize, state: &mut BTreeSet<Flag>| {
+        let op = match program.ops[item] {
+            Some(op) => op,
+            None => return,
+        };
+        if !reads_exactly(&op) {
+            state.clear();
+            return;
+        }
+        let (read, written) = effects(&op);
+        if read & reg(0xF) != 0 {
+            *state = state
+                .iter()
+                .map(|&flag| match flag {
+                    Flag::Set(def) | Flag::Clobbered(def, _) => Flag::Clobbered(def, None),
+                })
+                .collect();
+        }
+        if written & reg(0xF) != 0 {
+            if sets_vf(&op) {
+                *state = [Flag::Set(item)].iter().cloned().collect();
+            } else if sets_flag(&op) {
+                let mut flags: BTreeSet<Flag> = state
+                    .iter()
+                    .map(|&flag| match flag {
+                        Flag::Set(def) => Flag::Clobbered(item, Some(def)),
+                        Flag::Clobbered(_, lost) => Flag::Clobbered(item, lost),
+                    })
+                    .collect();
+                if flags.is_empty() {
+                    flags.insert(Flag::Clobbered(item, None));
+                }
+                *state = flags;
+            }
+        }
+    };
+    let join = |into: &mut BTreeSet<Flag>, from: &BTreeSet<Flag>| into.extend(from.iter().cloned());
+    let starts = forward(program, vec![BTreeSet::new(); program.blocks.len()], join, &step);
+
+    for (idx, block) in program.blocks.iter().enumerate() {
+        let mut state = starts[idx].clone();
+        for item in block.items.clone() {
+            if let Some(op) = program.ops[item] {
+                let (read, _) = effects(&op);
+                if reads_exactly(&op) && read & reg(0xF) != 0 && is_written_out(&program.items[item]) {
+                    let lost = state.iter().find_map(|&flag| match flag {
+                        Flag::Clobbered(def, Some(lost)) => Some((def, lost)),
+                        _ => None,
+                    });
+                    if let Some((def, lost)) = lost {
+                        let message = format!(
+                            "VF holds the flag from line {}, which replaced the value line {} put there before anything read it",
+                            program.items[def].loc.line, program.items[lost].loc.line
+                        );
+                        warn(program, item, message, warnings);
+                    }
+                }
+            }
+            step(item, &mut state);
+        }
+    }
+}
+
+fn unwritten_reads(program: &Program, warnings: &mut Vec<Diagnostic>) {
+    // The registers that may not have been written yet. A CALL is assumed
+    // to write everything.
+    let step = |item: usize, state: &mut Regs| {
+        if let Some(op) = program.ops[item] {
+            if reads_exactly(&op) {
+                *state &= !effects(&op).1;
+            } else if let Op::Call(_) = op {
+                *state = 0;
+            }
+        }
+    };
+    let join = |into: &mut Regs, from: &Regs| *into |= *from;
+    let mut starts = vec![0; program.blocks.len()];
+    if let Some(first) = starts.first_mut() {
+        *first = ALL_REGS;
+    }
+    // Routines start with whatever their callers had not written.
+    loop {
+        starts = forward(program, starts, join, &step);
+        let mut changed = false;
+        for (idx, block) in program.blocks.iter().enumerate() {
+            let mut state = starts[idx];
+            for item in block.items.clone() {
+                if let Some(Op::Call(addr)) = program.ops[item] {
+                    if let Some(target) = program.blocks.iter().position(|block| block.addr == addr) {
+                        changed |= starts[target] | state != starts[target];
+                        starts[target] |= state;
+                    }
+                }
+                step(item, &mut state);
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for (idx, block) in program.blocks.iter().enumerate() {
+        let mut state = starts[idx];
+        for item in block.items.clone() {
+            if let Some(op) = program.ops[item] {
+                let unwritten = effects(&op).0 & state;
+                if reads_exactly(&op) && unwritten != 0 && is_written_out(&program.items[item]) {
+                    warn(program, item, format!("Reads {} before anything writes it", reg_names(unwritten)), warnings);
+                }
+            }
+            step(item, &mut state);
+        }
+    }
+}
+
+fn dead_writes(program: &Program, warnings: &mut Vec<Diagnostic>) {
+    let live = program.live_out();
+    for (item, op) in program.ops.iter().enumerate() {
+        if let Some(op) = *op {
+            let written = explicit_writes(&op);
+            if written != 0 && written & live[item] == 0 && is_written_out(&program.items[item]) {
+                warn(program, item, format!("Writes {}, which nothing reads afterwards", reg_names(written)), warnings);
+            }
+        }
+    }
+}
+
+fn skips(program: &Program, warnings: &mut Vec<Diagnostic>) {
+    let items = &program.items;
+    for (idx, op) in program.ops.iter().enumerate() {
+        if !op.is_some_and(|op| op.is_skip()) {
+            continue;
+        }
+        let mut next = idx + 1;
+        while next < items.len() {
+            match items[next].item {
+                Item::Label(ref name) => {
+                    if !is_generated_label(name) {
+                        let message = format!("Skips over label {}, so the instruction after it runs or not depending on how it was reached", name);
+                        warn(program, idx, message, warnings);
+                    }
+                    next += 1;
+                }
+                _ => break,
+            }
+        }
+        // Data may be split over several items, one per byte.
+        let data: Vec<u8> = items[next..]
+            .iter()
+            .map_while(|item| match item.item {
+                Item::Data(ref bytes) => Some(bytes.iter().cloned()),
+                _ => None,
+            })
+            .flatten()
+            .take(2)
+            .collect();
+        let message = match items.get(next).map(|item| &item.item) {
+            Some(&Item::Data(_)) if data == [0xF0, 0x00] => "Skips only the first half of a four-byte XO-CHIP i := long".to_owned(),
+            Some(&Item::Data(_)) => "Skips over data, which runs as an instruction when the skip is not taken".to_owned(),
+            Some(&Item::Instr(_)) => {
+                let loc = &items[next].loc;
+                let count = items[next..]
+                    .iter()
+                    .take_while(|item| &item.loc == loc)
+                    .filter(|item| matches!(item.item, Item::Instr(_)))
+                    .count();
+                if loc == &items[idx].loc || count < 2 {
+                    continue;
+                }
+                format!("Skips only the first of the {} instructions line {} makes", count, loc.line)
+            }
+            _ => continue,
+        };
+        warn(program, idx, message, warnings);
+    }
+}
+
+// The registers an instruction that names them may read after each item,
+// before anything writes them. Unlike `Program::live_out`, RET, CALL and
+// code that leaves the program are not taken to read everything.
+fn read_later(program: &Program) -> Vec<Regs> {
+    let uses = |item: usize| match program.ops[item] {
+        Some(op) if reads_exactly(&op) => effects(&op),
+        _ => (0, 0),
+    };
+    let mut block_in = vec![0; program.blocks.len()];
+    let block_out = |idx: usize, block_in: &[Regs]| {
+        program.blocks[idx].succs.iter().fold(0, |out, &(succ, _)| out | block_in[succ])
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for idx in (0..program.blocks.len()).rev() {
+            let live = program.blocks[idx].items.clone().rev().fold(block_out(idx, &block_in), |live, item| {
+                let (read, written) = uses(item);
+                (live & !written) | read
+            });
+            if live != block_in[idx] {
+                block_in[idx] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut later = vec![0; program.items.len()];
+    for (idx, block) in program.blocks.iter().enumerate() {
+        let mut out = block_out(idx, &block_in);
+        for item in block.items.clone().rev() {
+            later[item] = out;
+            let (read, written) = uses(item);
+            out = (out & !written) | read;
+        }
+    }
+    later
+}
+
+fn scratch_reads(program: &Program, warnings: &mut Vec<Diagnostic>) {
+    let later = read_later(program);
+    let items = &program.items;
+    let mut start = 0;
+    while start < items.len() {
+        let first = start;
+        start += items[first..].iter().take_while(|item| item.loc == items[first].loc && item.text == items[first].text).count();
+        if !is_variable_line(&items[first].text) {
+            continue;
+        }
+        let line: Vec<(usize, Op)> = (first..start).filter_map(|item| program.ops[item].map(|op| (item, op))).collect();
+        let (last, last_op) = match line.last() {
+            Some(&last) => last,
+            None => continue,
+        };
+
+        // Registers whose first use on the line is a write, other than the
+        // one the last instruction assigns.
+        let mut seen = 0;
+        let mut scratch = 0;
+        for &(_, op) in line.iter() {
+            let read = if reads_exactly(&op) { effects(&op).0 } else { 0 };
+            scratch |= explicit_writes(&op) & !read & !seen;
+            seen |= read | explicit_writes(&op);
+        }
+        let target = explicit_writes(&last_op);
+        let clobbered = scratch & !target & !(reg(0) | reg(0xF) | REG_I) & later[last];
+        if clobbered != 0 {
+            let message = format!("Uses {} as scratch, but it is read afterwards as if it still held its old value", reg_names(clobbered));
+            warn(program, last, message, warnings);
+        }
+    }
+}
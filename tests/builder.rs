@@ -0,0 +1,95 @@
+extern crate chip8_rust_compiler;
+
+use chip8_rust_compiler::assembler::Item;
+use chip8_rust_compiler::builder::*;
+use chip8_rust_compiler::{assemble, Instruction, InstructionOps, Options};
+
+// A loop that draws the sprite under TABLE, which comes after it.
+fn draw_loop() -> Builder {
+    let mut prog = Builder::new();
+    prog.cls();
+    prog.ld(V0, 5).ld(V1, V0);
+    prog.label("loop");
+    prog.ld_i("table");
+    prog.drw(V0, V1, 4);
+    prog.add(V0, 1).se(V0, 0x20).jp("loop");
+    prog.ld_b(V0).ld_from_mem(V2).ld_key(V3).shr(V3).add_i(V3);
+    prog.call(0x300u16).ret();
+    prog.label("table").data(&[0xF0, 0x90, 0x90, 0xF0]);
+    prog
+}
+
+const SOURCE: &str = "\
+CLS
+LD V0, 0x5
+LD V1, V0
+LOOP:
+LD I, TABLE
+DRW V0, V1, 0x4
+ADD V0, 0x1
+SE V0, 0x20
+JP LOOP
+LD B, V0
+LD V2, [I]
+LD V3, K
+SHR V3
+ADD I, V3
+CALL 0x300
+RET
+TABLE:
+";
+
+#[test]
+fn builds_the_rom_the_source_would() {
+    let built = draw_loop().build(&Options::default()).unwrap();
+    let assembled = assemble(SOURCE, &Options::default()).unwrap();
+    let mut code = assembled.code.clone();
+    code.extend_from_slice(&[0xF0, 0x90, 0x90, 0xF0]);
+    assert_eq!(code, built.code);
+    assert_eq!(assembled.labels, built.labels);
+    assert_eq!(Some(0x21E), built.label("TABLE"));
+}
+
+#[test]
+fn makes_the_instructions_the_parser_makes() {
+    let prog = draw_loop();
+    let built: Vec<&Instruction> = prog
+        .items()
+        .iter()
+        .filter_map(|item| match item.item {
+            Item::Instr(ref instr) => Some(instr),
+            _ => None,
+        })
+        .collect();
+    let parsed: Vec<Instruction> = SOURCE
+        .lines()
+        .filter(|ln| !ln.ends_with(':'))
+        .map(|ln| Instruction::parse_args(ln).unwrap())
+        .collect();
+    assert_eq!(parsed.iter().collect::<Vec<_>>(), built);
+}
+
+#[test]
+fn reports_problems_by_call() {
+    let mut prog = Builder::new();
+    prog.label("start").jp("nowhere");
+    let errors = prog.build(&Options::default()).unwrap_err();
+    assert_eq!("<builder>:2: Unknown label NOWHERE", errors.to_string());
+}
+
+#[test]
+fn reports_operands_out_of_range() {
+    let mut prog = Builder::new();
+    prog.label("start");
+    prog.drw(V0, V1, 16);
+    prog.jp(0x1234u16);
+    prog.ld(Reg(0x10), 1);
+    prog.jp("start");
+    assert_eq!(2, prog.items().len());
+    let errors = prog.build(&Options::default()).unwrap_err();
+    let expected = "\
+<builder>:2: DRW draws up to 15 rows, not 16
+<builder>:3: Address 0x1234 does not fit in 12 bits
+<builder>:4: Register 0x10 does not exist; registers are V0 to VF";
+    assert_eq!(expected, errors.to_string());
+}
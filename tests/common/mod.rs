@@ -0,0 +1,11 @@
+use std::process::{Command, Output};
+
+// Runs the compiler from the crate directory, so tests can name their
+// fixtures as tests/TOPIC/FILE.
+pub fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(args)
+        .output()
+        .unwrap()
+}
@@ -0,0 +1,89 @@
+extern crate chip8_rust_compiler;
+
+use chip8_rust_compiler::interpreter::MEMORY_SIZE;
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Sends raw input to the adapter and returns the message bodies it writes.
+fn exchange(input: &[u8]) -> Vec<String> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .arg("--dap")
+        .current_dir(root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    let mut actual = Vec::new();
+    let mut rest = String::from_utf8(output.stdout).unwrap();
+    while let Some(split) = rest.find("\r\n\r\n") {
+        let length: usize = rest[..split].trim_start_matches("Content-Length:").trim().parse().unwrap();
+        let body_start = split + 4;
+        actual.push(rest[body_start..body_start + length].to_owned());
+        rest = rest[body_start + length..].to_owned();
+    }
+    actual
+}
+
+// Transcripts interleave `-->` client messages with the `<--` messages the
+// adapter is expected to send back, one compact JSON body per line.
+fn run_transcript(name: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let transcript = fs::read_to_string(root.join("tests/dap").join(name)).unwrap();
+
+    let mut input = Vec::new();
+    let mut expected = Vec::new();
+    for ln in transcript.lines() {
+        if let Some(body) = ln.strip_prefix("--> ") {
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        } else if let Some(body) = ln.strip_prefix("<-- ") {
+            expected.push(body.to_owned());
+        }
+    }
+
+    assert_eq!(expected, exchange(&input));
+}
+
+#[test]
+fn breakpoints() {
+    run_transcript("breakpoints.txt");
+}
+
+#[test]
+fn launch_error() {
+    run_transcript("launch_error.txt");
+}
+
+#[test]
+fn step_back() {
+    run_transcript("step_back.txt");
+}
+
+#[test]
+fn memory_bounds() {
+    run_transcript("memory.txt");
+}
+
+// However many instructions a client asks for, it gets at most what memory
+// holds, and a body larger than any message may be is not read at all.
+#[test]
+fn oversized_requests() {
+    let mut input = Vec::new();
+    for body in [
+        r#"{"seq":1,"type":"request","command":"launch","arguments":{"program":"src/roms/tapereader.chip8","stopOnEntry":true}}"#,
+        r#"{"seq":2,"type":"request","command":"disassemble","arguments":{"memoryReference":"0x200","instructionCount":200000000}}"#,
+    ] {
+        write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    }
+    write!(input, "Content-Length: 4000000000\r\n\r\n").unwrap();
+
+    let replies = exchange(&input);
+    let listing = replies.iter().find(|reply| reply.contains(r#""command":"disassemble""#)).unwrap();
+    assert_eq!(MEMORY_SIZE / 2, listing.matches(r#""instruction":"#).count());
+}
@@ -0,0 +1,34 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn dot(args: &[&str]) -> String {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .current_dir(root)
+        .args(args)
+        .args(["--dot", "-"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// Skips branch two ways, and the jump out of the program leaves the graph.
+#[test]
+fn error_routine() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let expected = fs::read_to_string(root.join("tests/dot/tapereader_err.dot")).unwrap();
+    assert_eq!(expected, dot(&["src/roms/tapereader.chip8", "--dot-label", "ERR"]));
+}
+
+// Every block of a whole program is drawn, data included.
+#[test]
+fn whole_program() {
+    let graph = dot(&["src/roms/pong.c8c"]);
+    assert!(graph.starts_with("digraph \"src/roms/pong.c8c\" {"));
+    assert!(graph.contains("[label=\"skip\"]"));
+    assert!(graph.contains("PADDLE:\\l0x"));
+    assert!(graph.contains("bytes of data"));
+    assert!(graph.ends_with("}\n"));
+}
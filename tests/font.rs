@@ -0,0 +1,28 @@
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn reports_font_and_text_mistakes() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("font_errors.c8");
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .current_dir(root)
+        .arg("tests/font/errors.chip8")
+        .arg("-o")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let expected = "\
+tests/font/errors.chip8:2: TEXT needs a FONT declared before it
+tests/font/errors.chip8:4: Glyphs can be up to 8 wide and 15 high, not 9x5
+tests/font/errors.chip8:5: ENDFONT without a FONT
+tests/font/errors.chip8:9: Expected 3 pixels of # or ., got ##
+tests/font/errors.chip8:10: FONT BLOCKY already has a glyph for 'A'
+tests/font/errors.chip8:13: FONT BLOCKY has no glyph for 'B'
+tests/font/errors.chip8:14: TEXT cannot draw at VF, which DRW changes
+tests/font/errors.chip8:15: No FONT named THIN
+tests/font/errors.chip8:17: FONT OPEN is missing its ENDFONT
+";
+    assert_eq!(expected, String::from_utf8(output.stderr).unwrap());
+}
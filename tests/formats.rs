@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Assembles tests/formats/program.chip8 to program.EXT, with any extra
+// arguments, and compares the output with the golden file of that name.
+fn check(ext: &str, args: &[&str], golden: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("program.{}", ext));
+    let status = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .current_dir(root)
+        .arg("tests/formats/program.chip8")
+        .args(args)
+        .arg("-o")
+        .arg(&out)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let expected = fs::read(root.join("tests/formats").join(golden)).unwrap();
+    assert_eq!(String::from_utf8_lossy(&expected), String::from_utf8_lossy(&fs::read(&out).unwrap()));
+    fs::remove_file(out).unwrap();
+}
+
+#[test]
+fn raw() {
+    check("c8", &[], "program.c8");
+}
+
+#[test]
+fn intel_hex() {
+    check("hex", &[], "program.hex");
+}
+
+#[test]
+fn s_records() {
+    check("srec", &[], "program.srec");
+}
+
+#[test]
+fn hex_dump() {
+    check("txt", &[], "program.txt");
+}
+
+#[test]
+fn c_array() {
+    check("c", &[], "program.c");
+}
+
+#[test]
+fn rust_array() {
+    check("rs", &[], "program.rs");
+}
+
+#[test]
+fn octo_bundle() {
+    check("json", &[], "program.json");
+}
+
+#[test]
+fn format_overrides_extension() {
+    check("bin", &["--format", "ihex"], "program.hex");
+}
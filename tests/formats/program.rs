@@ -0,0 +1,4 @@
+pub const PROGRAM: [u8; 18] = [
+    0x00, 0xE0, 0x60, 0x01, 0x61, 0x02, 0xA2, 0x0E, 0xD0, 0x11, 0x70, 0x01,
+    0x12, 0x08, 0xA0, 0x80, 0x00, 0xEE,
+];
@@ -0,0 +1,63 @@
+extern crate chip8_rust_compiler;
+
+use chip8_rust_compiler::assembler::assemble_source_as;
+use chip8_rust_compiler::instructions::loads::Load;
+use chip8_rust_compiler::{assemble, assemble_file, Instruction, InstructionOps, OpParam, Options, Syntax};
+
+#[test]
+fn assembles_source_into_a_rom() {
+    let rom = assemble("LD V0, 0x5\nLOOP:\nJP LOOP\n", &Options::default()).unwrap();
+    assert_eq!(vec![0x60, 0x05, 0x12, 0x02], rom.code);
+    assert_eq!(vec![("LOOP".to_owned(), 0x202)], rom.labels);
+    assert_eq!(Some(0x202), rom.label("loop"));
+}
+
+#[test]
+fn reports_every_error() {
+    let errors = assemble("LD V0, 0x5\nLD VX, 0x1\nDRW V1\n", &Options::default()).unwrap_err();
+    assert_eq!(2, errors.0.len());
+    assert!(errors.to_string().starts_with("<source>:2: "), "{}", errors);
+}
+
+#[test]
+fn reports_half_typed_and_non_ascii_lines() {
+    let errors = assemble("MAIN:\nLD V\n", &Options::default()).unwrap_err();
+    assert!(errors.to_string().starts_with("<source>:2: "), "{}", errors);
+    let rom = assemble("ÉTÉ:\nJP ÉTÉ\n", &Options::default()).unwrap();
+    assert_eq!(vec![0x12, 0x00], rom.code);
+}
+
+#[test]
+fn keeps_expressions_off_named_registers() {
+    let named = "VAR X : BYTE\nX = X + 1\nLD V1, V2\nLD V3, V4\nLD V5, V6\nLD V7, V8\nLD V9, VA\nLD VB, VC\n";
+    let errors = assemble(named, &Options::default()).unwrap_err();
+    assert!(errors.to_string().contains("pick the ones they may use with REGISTERS"), "{}", errors);
+    assert!(assemble(&format!("REGISTERS VC-VE\n{}", named), &Options::default()).is_ok());
+}
+
+#[test]
+fn rejects_programs_too_big_for_memory() {
+    let nested = "struct A { byte x[255]; }\nstruct B { A a[255]; }\nB c[2];\nvoid main() {}\n";
+    let errors = assemble_source_as("nested.c8c", nested, Syntax::Lang).err().unwrap();
+    assert_eq!("A[255] does not fit in memory", errors[0].message);
+
+    let arrays: String = (0..17).map(|idx| format!("byte a{}[255];\n", idx)).collect::<String>() + "void main() {}\n";
+    let errors = assemble_source_as("arrays.c8c", &arrays, Syntax::Lang).err().unwrap();
+    assert_eq!(1, errors.len());
+    assert_eq!("The program takes 4341 bytes, more than the 3584 there is room for", errors[0].message);
+    assert_eq!(15, errors[0].loc.line);
+}
+
+#[test]
+fn assembles_files_in_any_syntax() {
+    let native = assemble_file("tests/roms/functions.chip8", &Options::default()).unwrap();
+    let octo = assemble_file("tests/roms/octo.8o", &Options::default()).unwrap();
+    assert!(native.label("DONE").is_some());
+    assert!(!octo.code.is_empty());
+}
+
+#[test]
+fn builds_instructions_directly() {
+    let load = Instruction::Load(Load::new(OpParam::Register(3), OpParam::Variable(0x42)));
+    assert_eq!(0x6342, load.to_opcode());
+}
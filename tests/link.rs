@@ -0,0 +1,143 @@
+extern crate chip8_rust_compiler;
+
+mod common;
+
+use chip8_rust_compiler::assembler::parse_source;
+use chip8_rust_compiler::linker;
+use chip8_rust_compiler::object::{self, Object};
+use common::run;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+// A directory of its own for each test's objects.
+fn tmp_dir(test: &str) -> PathBuf {
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR")).join("link").join(test);
+    fs::create_dir_all(&tmp).unwrap();
+    tmp
+}
+
+// Compiles tests/link/NAME.EXT to an object file, returning its path.
+fn compile(source: &str, tmp: &Path) -> PathBuf {
+    let name = source.split('.').next().unwrap();
+    let object = tmp.join(format!("{}.o8", name));
+    let output = run(&[&format!("tests/link/{}", source), "-c", "-o", object.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    object
+}
+
+// Links the objects of the given sources and returns the output.
+fn link(sources: &[&str], tmp: &Path, out: &str, extra: &[&str]) -> Output {
+    let objects: Vec<PathBuf> = sources.iter().map(|source| compile(source, tmp)).collect();
+    let mut args = Vec::new();
+    for object in objects.iter() {
+        args.push("--link");
+        args.push(object.to_str().unwrap());
+    }
+    args.extend_from_slice(extra);
+    args.push("-o");
+    args.push(out);
+    run(&args)
+}
+
+#[test]
+fn links_modules_into_a_rom_and_map() {
+    let tmp = tmp_dir("game");
+    let rom = tmp.join("game.txt");
+    let map = tmp.join("game.map");
+    let output = link(&["main.chip8", "draw.chip8", "sprites.8o"], &tmp, rom.to_str().unwrap(), &["--map", map.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    assert_eq!(fs::read_to_string(root.join("tests/link/game.txt")).unwrap(), fs::read_to_string(&rom).unwrap());
+    let map = fs::read_to_string(&map).unwrap().replace(tmp.to_str().unwrap(), "TMP");
+    assert_eq!(fs::read_to_string(root.join("tests/link/game.map")).unwrap(), map);
+}
+
+#[test]
+fn reports_unresolved_and_duplicate_symbols() {
+    let tmp = tmp_dir("broken");
+    let rom = tmp.join("broken.c8");
+    let output = link(&["main.chip8", "draw.chip8", "twice.chip8"], &tmp, rom.to_str().unwrap(), &[]);
+    assert!(!output.status.success());
+    let expected = "DRAW is exported by both TMP/draw.o8 and TMP/twice.o8\nTMP/draw.o8 imports SHIP, which no module exports\n";
+    assert_eq!(expected, String::from_utf8(output.stderr).unwrap().replace(tmp.to_str().unwrap(), "TMP"));
+}
+
+#[test]
+fn pulls_only_needed_archive_members() {
+    let tmp = tmp_dir("archive");
+    let members: Vec<PathBuf> = ["draw.chip8", "twice.chip8", "sprites.8o"].iter().map(|source| compile(source, &tmp)).collect();
+    let library = tmp.join("lib.c8a");
+    let mut args = Vec::new();
+    for member in members.iter() {
+        args.push("--archive");
+        args.push(member.to_str().unwrap());
+    }
+    args.push("-o");
+    args.push(library.to_str().unwrap());
+    assert!(run(&args).status.success());
+
+    let main = compile("main.chip8", &tmp);
+    let rom = tmp.join("game.txt");
+    let map = tmp.join("game.map");
+    let output = run(&[
+        "--link",
+        main.to_str().unwrap(),
+        "--link",
+        library.to_str().unwrap(),
+        "--map",
+        map.to_str().unwrap(),
+        "-o",
+        rom.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // The same ROM as linking the needed objects directly, without TWICE's
+    // second DRAW.
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    assert_eq!(fs::read_to_string(root.join("tests/link/game.txt")).unwrap(), fs::read_to_string(&rom).unwrap());
+    let map = fs::read_to_string(&map).unwrap();
+    assert!(map.contains("lib.c8a(draw.o8)") && map.contains("lib.c8a(sprites.o8)"));
+    assert!(!map.contains("twice.o8"));
+}
+
+#[test]
+fn rejects_exports_outside_the_code() {
+    let tmp = tmp_dir("export");
+    let object = compile("draw.chip8", &tmp);
+    let text = fs::read_to_string(&object).unwrap();
+    let export = text.lines().find(|ln| ln.starts_with("EXPORT DRAW ")).unwrap();
+    fs::write(&object, text.replace(export, "EXPORT DRAW FFFF")).unwrap();
+
+    let output = run(&["--link", object.to_str().unwrap(), "-o", tmp.join("export.c8").to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Export DRAW at FFFF is outside the code"), "{}", stderr);
+}
+
+// Objects built in memory skip the parser's check, so the linker makes its own.
+#[test]
+fn linker_checks_export_offsets() {
+    let object = Object {
+        source: "made.chip8".to_owned(),
+        code: vec![0x00, 0xEE],
+        exports: vec![("FAR".to_owned(), 0xFFFF)],
+        imports: Vec::new(),
+        relocs: Vec::new(),
+    };
+    let errors = linker::link(&[("made.o8".to_owned(), object)]).err().unwrap();
+    assert_eq!(vec!["made.o8 exports FAR at FFFF, outside its code".to_owned()], errors);
+}
+
+// A module past the end of memory has labels no address field can hold.
+#[test]
+fn rejects_modules_too_big_for_memory() {
+    let source = format!(": main jump finish\n{}\n: finish jump finish\n", vec!["0x00"; 3840].join(" "));
+    let (items, errors) = parse_source("big.8o", &source);
+    assert!(errors.is_empty());
+    let errors = object::compile_items("big.8o", items).err().unwrap();
+    assert_eq!(1, errors.len());
+    assert_eq!("The program takes 3844 bytes, more than the 3584 there is room for", errors[0].message);
+}
@@ -0,0 +1,31 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Assembles tests/listing/NAME.chip8 and compares its listing with NAME.lst.
+fn check(name: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let out = tmp.join(format!("{}.c8", name));
+    let listing = tmp.join(format!("{}.lst", name));
+    let status = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .current_dir(root)
+        .arg(format!("tests/listing/{}.chip8", name))
+        .arg("--listing")
+        .arg(&listing)
+        .arg("-o")
+        .arg(&out)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let expected = fs::read_to_string(root.join(format!("tests/listing/{}.lst", name))).unwrap();
+    assert_eq!(expected, fs::read_to_string(&listing).unwrap());
+    fs::remove_file(out).unwrap();
+    fs::remove_file(listing).unwrap();
+}
+
+#[test]
+fn program() {
+    check("program");
+}
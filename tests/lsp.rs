@@ -0,0 +1,66 @@
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Transcripts interleave `-->` client messages with the `<--` messages the
+// server is expected to send back, one compact JSON body per line. `$ROOT`
+// stands for the crate directory in document URIs.
+fn run_transcript(name: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let transcript = fs::read_to_string(root.join("tests/lsp").join(name)).unwrap();
+    let transcript = transcript.replace("$ROOT", &root.to_string_lossy());
+
+    let mut input = Vec::new();
+    let mut expected = Vec::new();
+    for ln in transcript.lines() {
+        if let Some(body) = ln.strip_prefix("--> ") {
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        } else if let Some(body) = ln.strip_prefix("<-- ") {
+            expected.push(body.to_owned());
+        }
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .arg("--lsp")
+        .current_dir(root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let mut actual = Vec::new();
+    let mut rest = output.stdout;
+    while let Some(split) = rest.windows(4).position(|window| window == b"\r\n\r\n") {
+        let header = String::from_utf8(rest[..split].to_vec()).unwrap();
+        let length: usize = header.trim_start_matches("Content-Length:").trim().parse().unwrap();
+        let body_start = split + 4;
+        actual.push(String::from_utf8(rest[body_start..body_start + length].to_vec()).unwrap());
+        rest = rest[body_start + length..].to_vec();
+    }
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn navigation() {
+    run_transcript("navigation.txt");
+}
+
+#[test]
+fn partial_input() {
+    run_transcript("partial.txt");
+}
+
+#[test]
+fn constants() {
+    run_transcript("constants.txt");
+}
+
+#[test]
+fn bad_body() {
+    run_transcript("bad_body.txt");
+}
@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn build(program: &str, name: &str, optimize: bool) -> Vec<u8> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let mut command = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"));
+    command.current_dir(root).arg(program).arg("-o").arg(&out);
+    if optimize {
+        command.arg("-O");
+    }
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let rom = fs::read(&out).unwrap();
+    fs::remove_file(&out).unwrap();
+    rom
+}
+
+// peephole.test checks both builds behave the same; this checks that the
+// rewrites happened: a LD, an ADD's constant load, the unreachable LD and a
+// RET go.
+#[test]
+fn peephole_rewrites_shrink_the_program() {
+    let plain = build("tests/roms/peephole.chip8", "peephole.c8", false);
+    let optimized = build("tests/roms/peephole.chip8", "peephole_o.c8", true);
+    assert_eq!(plain.len() - 8, optimized.len());
+    assert_eq!(&optimized[2..4], &[0x7A, 0x01]);
+}
+
+// The tape reader addresses its own code by number, so nothing may move.
+#[test]
+fn numeric_addresses_keep_code_in_place() {
+    let plain = build("src/roms/tapereader.chip8", "tapereader.c8", false);
+    let optimized = build("src/roms/tapereader.chip8", "tapereader_o.c8", true);
+    assert_eq!(plain, optimized);
+}
@@ -0,0 +1,64 @@
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Plays tests/play/crash.chip8 with the given commands and returns the lines
+// after each screen.
+fn play(commands: &str) -> Vec<String> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .args(["tests/play/crash.chip8", "--play", "--seed", "1"])
+        .current_dir(root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(commands.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter(|ln| !ln.chars().all(|c| c == '.' || c == '#'))
+        .map(|ln| ln.to_owned())
+        .collect()
+}
+
+#[test]
+fn rewinds_to_before_a_crash() {
+    let lines = play("run 30\nrun\nback\nrun 0\nback 2\n");
+    assert_eq!(
+        vec![
+            "Frame 0, PC 0x200",
+            "Frame 19, PC 0x210",
+            "Stopped at 0x210 in frame 19: RET with an empty stack",
+            "Frame 19, PC 0x210",
+            "Stopped at 0x210 in frame 19: RET with an empty stack; use back to rewind",
+            "Frame 19, PC 0x20E",
+            "Frame 19, PC 0x20E",
+            "Frame 17, PC 0x20A",
+        ],
+        lines
+    );
+}
+
+#[test]
+fn reports_bad_commands() {
+    let lines = play("press G\nback\nrun x\nfly\npress 3\nquit\nrun\n");
+    assert_eq!(
+        vec![
+            "Frame 0, PC 0x200",
+            "Frame 0, PC 0x200",
+            "G is not a key; keys are 0 to F",
+            "Frame 0, PC 0x200",
+            "Only 0 frames can be rewound",
+            "Frame 0, PC 0x200",
+            "run takes a number of frames, not x",
+            "Frame 0, PC 0x200",
+            "Unknown command: fly",
+            "Frame 0, PC 0x200",
+        ],
+        lines
+    );
+}
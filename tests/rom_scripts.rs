@@ -0,0 +1,39 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Runs every input script in tests/roms through `--test`.
+fn run_scripts(optimize: bool) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut scripts: Vec<String> = fs::read_dir(root.join("tests/roms"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "test"))
+        .map(|path| path.strip_prefix(root).unwrap().to_string_lossy().into_owned())
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"));
+    command.current_dir(root);
+    if optimize {
+        command.arg("-O");
+    }
+    for script in scripts.iter() {
+        command.arg("--test").arg(script);
+    }
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn rom_scripts() {
+    run_scripts(false);
+}
+
+// The same scripts against the -O builds, so every rewrite is checked by
+// running it next to the original.
+#[test]
+fn rom_scripts_optimized() {
+    run_scripts(true);
+}
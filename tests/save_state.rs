@@ -0,0 +1,89 @@
+extern crate chip8_rust_compiler;
+
+mod common;
+
+use chip8_rust_compiler::interpreter::state::History;
+use chip8_rust_compiler::interpreter::{Machine, MEMORY_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8_rust_compiler::{assemble_file, Options};
+
+use std::fs;
+use std::path::Path;
+
+fn run(args: &[&str]) -> String {
+    let output = common::run(args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// Saving part way through and resuming must trace exactly like running straight through.
+#[test]
+fn resume_matches_uninterrupted_run() {
+    let state = Path::new(env!("CARGO_TARGET_TMPDIR")).join("resume.c8s");
+    let state = state.to_str().unwrap();
+    let program = "tests/roms/random.chip8";
+
+    let full = run(&[program, "--seed", "9", "--cycles", "40", "--trace", "-", "--trace-format", "json"]);
+    run(&[program, "--seed", "9", "--cycles", "15", "--save-state", state]);
+    let resumed = run(&[program, "--load-state", state, "--cycles", "25", "--trace", "-", "--trace-format", "json"]);
+
+    let tail: Vec<&str> = full.lines().skip(15).collect();
+    assert_eq!(tail, resumed.lines().collect::<Vec<_>>());
+    fs::remove_file(state).unwrap();
+}
+
+// Asking to rewind further back than anything ran saves the starting state.
+#[test]
+fn rewind_without_history_saves_the_start() {
+    let state = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rewind.c8s");
+    let state = state.to_str().unwrap();
+    run(&["tests/roms/random.chip8", "--cycles", "0", "--save-state", state, "--rewind", "2"]);
+    let machine = Machine::load_state(&fs::read(state).unwrap()).unwrap();
+    assert_eq!(0, machine.cycles);
+    fs::remove_file(state).unwrap();
+}
+
+#[test]
+fn rejects_impossible_states() {
+    let mut machine = Machine::with_seed(1);
+    machine.key_wait = Some(0xF);
+    let saved = machine.save_state();
+    assert!(Machine::load_state(&saved).is_ok());
+
+    // The key being waited for comes after the stack, timers, screen and keys.
+    let key_wait = 5 + MEMORY_SIZE + 16 + 2 + 2 + 1 + 2 + SCREEN_WIDTH * SCREEN_HEIGHT / 8 + 2;
+    assert_eq!(0xF, saved[key_wait]);
+    let mut bad_key = saved.clone();
+    bad_key[key_wait] = 0x20;
+    assert!(Machine::load_state(&bad_key).is_err());
+
+    machine.key_wait = None;
+    machine.stack = vec![0x200; 17];
+    assert!(Machine::load_state(&machine.save_state()).is_err());
+}
+
+// History keeps only what each step changed, and must still give back every
+// state it holds exactly, including the memory and pixels that were written.
+#[test]
+fn history_restores_recent_states() {
+    let rom = assemble_file("tests/roms/stdlib_print.chip8", &Options::default()).unwrap();
+    let mut machine = Machine::with_seed(3);
+    machine.load_rom(&rom.code).unwrap();
+    let mut history = History::new(50);
+    let mut states = Vec::new();
+    // Long enough to drop the first few steps but keep the BCD store and the draws.
+    for _ in 0..55 {
+        history.push(&machine);
+        states.push(machine.save_state());
+        machine.step().unwrap();
+    }
+
+    assert_eq!(50, history.len());
+    for (count, state) in states.iter().rev().take(50).enumerate() {
+        assert!(history.back(count).unwrap().save_state() == *state, "state {} back", count);
+    }
+    assert!(history.back(50).is_none());
+    for state in states.iter().rev().take(50) {
+        assert!(history.pop().unwrap().save_state() == *state);
+    }
+    assert!(history.pop().is_none());
+}
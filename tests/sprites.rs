@@ -0,0 +1,60 @@
+mod common;
+
+use common::run;
+
+use std::fs;
+use std::path::Path;
+
+fn golden(name: &str) -> String {
+    fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sprites").join(name)).unwrap()
+}
+
+#[test]
+fn includes_one_plane_schip_and_xo_chip_sprites() {
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("sprites.txt");
+    let output = run(&["tests/sprites/program.chip8", "-o", out.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(golden("program.txt"), fs::read_to_string(&out).unwrap());
+}
+
+#[test]
+fn imports_sprites_as_octo_data() {
+    let output = run(&["--sprite-import", "tests/sprites/ship.pbm", "--sprite-size", "8x6"]);
+    assert!(output.status.success());
+    assert_eq!(golden("ship.8o"), String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn rejects_images_that_are_not_sprites() {
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("sprite_errors.c8");
+    let output = run(&["tests/sprites/errors.chip8", "-o", out.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let expected = "\
+tests/sprites/errors.chip8:2: tests/sprites/smudge.png: Pixel (5, 2) is neither black nor white
+tests/sprites/errors.chip8:3: tests/sprites/tile.png: Pixel (2, 0) is neither black nor white
+tests/sprites/errors.chip8:4: Sprites are 8 wide and 1 to 15 high, or 16x16, not 8x16
+tests/sprites/errors.chip8:5: tests/sprites/ship.pbm: The image is 8x12, which is not a whole number of 8x5 sprites
+tests/sprites/errors.chip8:6: Sprites have 1 or 2 planes, not 3
+tests/sprites/errors.chip8:7: Could not read tests/sprites/missing.png: No such file or directory (os error 2)
+tests/sprites/errors.chip8:8: Expected INCSPRITE \"file\", WIDTH, HEIGHT[, PLANES]
+";
+    assert_eq!(expected, String::from_utf8(output.stderr).unwrap());
+}
+
+// A PBM header can claim more pixels than there are, or than a usize counts.
+#[test]
+fn rejects_pbm_sizes_the_data_does_not_have() {
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let images = [
+        ("huge.pbm", "P4\n99999999999 99999999999\n"),
+        ("huge1.pbm", "P1\n99999999999 99999999999\n0 1\n"),
+        ("short.pbm", "P4\n8 6\nab"),
+    ];
+    for (name, data) in images {
+        let path = tmp.join(name);
+        fs::write(&path, data).unwrap();
+        let output = run(&["--sprite-import", path.to_str().unwrap(), "--sprite-size", "8x8"]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8(output.stderr).unwrap().ends_with(": PBM image data is truncated\n"), "{}", name);
+    }
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use std::path::Path;
+use std::process::Output;
+
+// Assembles tests/stack/FILE with the given extra arguments.
+fn assemble(file: &str, args: &[&str]) -> Output {
+    let program = format!("tests/stack/{}", file);
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.c8", file));
+    let mut all = vec![program.as_str()];
+    all.extend_from_slice(args);
+    all.extend_from_slice(&["-o", out.to_str().unwrap()]);
+    common::run(&all)
+}
+
+#[test]
+fn deep_calls_fit_the_default_stack() {
+    let output = assemble("deep.chip8", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn deep_calls_overflow_the_vip_stack() {
+    let output = assemble("deep.chip8", &["--stack-limit", "vip"]);
+    assert!(!output.status.success());
+    let expected = "tests/stack/deep.chip8:46: Calls nest 13 deep, more than the 12 the stack holds: 0x200 -> LEVEL1 -> LEVEL2 -> \
+                    LEVEL3 -> LEVEL4 -> LEVEL5 -> LEVEL6 -> LEVEL7 -> LEVEL8 -> LEVEL9 -> LEVEL10 -> LEVEL11 -> LEVEL12 -> LEVEL13\n";
+    assert_eq!(expected, String::from_utf8(output.stderr).unwrap());
+}
+
+// FUNC and lang calls are checked against --stack-limit like any other CALL.
+#[test]
+fn function_calls_follow_the_stack_limit() {
+    assert!(assemble("funcs.chip8", &["--stack-limit", "3"]).status.success());
+    let output = assemble("funcs.chip8", &["--stack-limit", "2"]);
+    assert!(!output.status.success());
+    let expected = "tests/stack/funcs.chip8:9: Calls nest 3 deep, more than the 2 the stack holds: 0x200 -> ONE -> TWO -> THREE\n";
+    assert_eq!(expected, String::from_utf8(output.stderr).unwrap());
+
+    assert!(assemble("calls.c8c", &["--stack-limit", "3"]).status.success());
+    let output = assemble("calls.c8c", &["--stack-limit", "2"]);
+    assert!(!output.status.success());
+    let expected = "tests/stack/calls.c8c:9: Calls nest 3 deep, more than the 2 the stack holds: 0x200 -> MAIN -> ONE -> TWO\n";
+    assert_eq!(expected, String::from_utf8(output.stderr).unwrap());
+}
@@ -0,0 +1,65 @@
+extern crate chip8_rust_compiler;
+
+use chip8_rust_compiler::assembler::{assemble_source_as, Syntax};
+use chip8_rust_compiler::symbols::{symbols, SymbolFormat};
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Assembles tests/symbols/program.chip8 with the given arguments, which
+// write to OUT, and compares what was written with the golden file.
+fn check(args: &[&str], golden: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let out = tmp.join(golden);
+    let rom = tmp.join(format!("{}.c8", golden));
+    let args: Vec<&str> = args.iter().map(|&arg| if arg == "OUT" { out.to_str().unwrap() } else { arg }).collect();
+    let status = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .current_dir(root)
+        .arg("tests/symbols/program.chip8")
+        .args(&args)
+        .arg("-o")
+        .arg(&rom)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let expected = fs::read_to_string(root.join("tests/symbols").join(golden)).unwrap();
+    assert_eq!(expected, fs::read_to_string(&out).unwrap());
+    fs::remove_file(out).unwrap();
+    fs::remove_file(rom).unwrap();
+}
+
+#[test]
+fn plain() {
+    check(&["--symbols", "OUT"], "program.txt");
+}
+
+#[test]
+fn json() {
+    check(&["--symbols", "OUT"], "program.json");
+}
+
+#[test]
+fn sym() {
+    check(&["--symbols", "OUT"], "program.sym");
+}
+
+#[test]
+fn format_overrides_extension() {
+    check(&["--symbols", "OUT", "--symbols-format", "sym"], "program.sym.txt");
+}
+
+#[test]
+fn line_map() {
+    check(&["--line-map", "OUT"], "program.map");
+}
+
+// Constants are kept from parsing, so a source never saved has them too.
+#[test]
+fn constants_of_unsaved_octo_source() {
+    let source = ":const STEP 2\n:const FAST STEP\n: main\n  jump main\n";
+    let assembly = assemble_source_as("unsaved.8o", source, Syntax::Octo).ok().unwrap();
+    assert_eq!("MAIN=0x200\nSTEP=2\nFAST=2\n", symbols(&assembly, SymbolFormat::Plain));
+}
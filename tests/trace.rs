@@ -0,0 +1,78 @@
+mod common;
+
+use common::run;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// Traces 20 cycles of tests/trace/NAME.chip8 to stdout with the given extra arguments.
+fn trace(name: &str, args: &[&str]) -> String {
+    let program = format!("tests/trace/{}.chip8", name);
+    let mut all = vec![program.as_str(), "--seed", "1", "--cycles", "20", "--trace", "-"];
+    all.extend_from_slice(args);
+    let output = run(&all);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn golden(name: &str) -> String {
+    fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/trace").join(name)).unwrap()
+}
+
+// The program counter column of a text trace.
+fn pcs(text: &str) -> Vec<String> {
+    text.lines().map(|ln| ln.split_whitespace().nth(1).unwrap().to_owned()).collect()
+}
+
+#[test]
+fn writes_text() {
+    assert_eq!(golden("loop.txt"), trace("loop", &[]));
+}
+
+#[test]
+fn writes_json_lines() {
+    assert_eq!(golden("loop.jsonl"), trace("loop", &["--trace-format", "json"]));
+}
+
+#[test]
+fn traces_only_the_given_range() {
+    let text = trace("loop", &["--trace-range", "0x200:0x204"]);
+    assert_eq!(vec!["0x200", "0x202", "0x202", "0x202"], pcs(&text));
+}
+
+#[test]
+fn traces_only_the_given_label() {
+    let text = trace("loop", &["--trace-label", "STEP"]);
+    assert_eq!(vec!["0x20C", "0x20E", "0x20C", "0x20E", "0x20C", "0x20E"], pcs(&text));
+}
+
+// Writes the JSON trace of each program and diffs the two.
+fn diff(left: &str, right: &str) -> Output {
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR")).join("trace");
+    fs::create_dir_all(&tmp).unwrap();
+    let mut paths = Vec::new();
+    for name in [left, right].iter() {
+        let path = tmp.join(format!("{}.jsonl", name));
+        fs::write(&path, trace(name, &["--trace-format", "json"])).unwrap();
+        paths.push(path.to_str().unwrap().to_owned());
+    }
+    run(&["--trace-diff", &paths[0], &paths[1]])
+}
+
+// STEP moved, so the LD I and CALL operands and I itself differ as numbers
+// but not as labels.
+#[test]
+fn diff_lines_up_moved_code_by_label() {
+    let output = diff("loop", "moved");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert_eq!("Traces are identical\n", String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn diff_reports_the_first_difference() {
+    let output = diff("loop", "changed");
+    assert!(!output.status.success());
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.starts_with("Traces diverge at record 6 (asm):\n"), "{}", report);
+}
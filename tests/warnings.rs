@@ -0,0 +1,41 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Assembles tests/warnings/NAME.chip8 and compares what it prints on stderr
+// with NAME.txt.
+fn check(name: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.c8", name));
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8-rust-compiler"))
+        .current_dir(root)
+        .arg(format!("tests/warnings/{}.chip8", name))
+        .arg("-o")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "warnings must not fail the build");
+    let expected = fs::read_to_string(root.join(format!("tests/warnings/{}.txt", name))).unwrap();
+    assert_eq!(expected, String::from_utf8(output.stderr).unwrap());
+    fs::remove_file(out).unwrap();
+}
+
+#[test]
+fn dataflow() {
+    check("dataflow");
+}
+
+#[test]
+fn skips() {
+    check("skips");
+}
+
+#[test]
+fn recursion() {
+    check("recursion");
+}
+
+#[test]
+fn scratch() {
+    check("scratch");
+}